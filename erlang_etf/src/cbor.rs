@@ -0,0 +1,469 @@
+//! Lossless conversions between terms and CBOR values.
+//!
+//! Every term reads back as the term it was written from. Terms which CBOR
+//! can say directly are written as such:
+//!
+//! | Erlang          | CBOR                                                |
+//! |-----------------|-----------------------------------------------------|
+//! | integers        | integers, or bignums (tags 2 and 3) beyond 64 bits  |
+//! | floats          | floats                                              |
+//! | `true`, `false` | `true`, `false`                                     |
+//! | binaries        | byte strings                                        |
+//! | proper lists    | arrays                                              |
+//! | maps            | maps                                                |
+//!
+//! and the rest as a tagged value, with the tag numbers below. These are
+//! private values spelling `ERL` followed by a number. They are not
+//! registered with IANA, so other CBOR readers will not know them, and both
+//! ends must agree on them.
+//!
+//! | Erlang                | Tag                    | Content                                |
+//! |-----------------------|------------------------|----------------------------------------|
+//! | other atoms           | [`ATOM_TAG`]           | name as text                           |
+//! | bitstrings            | [`BITSTRING_TAG`]      | `[data, bits]`                         |
+//! | tuples                | [`TUPLE_TAG`]          | array of elements                      |
+//! | improper lists        | [`IMPROPER_LIST_TAG`]  | `[elements, tail]`                     |
+//! | pids                  | [`PID_TAG`]            | `[node, id, serial, creation]`         |
+//! | ports                 | [`PORT_TAG`]           | `[node, id, creation]`                 |
+//! | references            | [`REFERENCE_TAG`]      | `[node, ids, creation]`                |
+//! | funs                  | [`FUN_TAG`]            | see below                              |
+//!
+//! Node, module and function names are text. An external fun is written as
+//! `[module, function, arity]`, a new internal fun as `[module, arity, pid,
+//! free_vars, index, uniq, old_index, old_uniq]`, and an old one as `[module,
+//! pid, free_vars, index, uniq]`.
+//!
+//! When reading CBOR which was not written from a term, text strings read as
+//! binaries and `null` as the atom `null`.
+
+use ciborium::value::{Integer, Value};
+
+use crate::num_bigint::{BigInt, Sign};
+use crate::term::*;
+
+/// Tag of an atom other than `true` or `false`.
+pub const ATOM_TAG: u64 = 0x45524C01;
+
+/// Tag of a bitstring whose length is not a whole number of bytes.
+pub const BITSTRING_TAG: u64 = 0x45524C02;
+
+/// Tag of a tuple.
+pub const TUPLE_TAG: u64 = 0x45524C03;
+
+/// Tag of an improper list.
+pub const IMPROPER_LIST_TAG: u64 = 0x45524C04;
+
+/// Tag of a pid.
+pub const PID_TAG: u64 = 0x45524C05;
+
+/// Tag of a port.
+pub const PORT_TAG: u64 = 0x45524C06;
+
+/// Tag of a reference.
+pub const REFERENCE_TAG: u64 = 0x45524C07;
+
+/// Tag of a fun.
+pub const FUN_TAG: u64 = 0x45524C08;
+
+const POSITIVE_BIGNUM_TAG: u64 = 2;
+const NEGATIVE_BIGNUM_TAG: u64 = 3;
+
+/// Errors which can occur when reading terms from CBOR
+#[derive(Debug, thiserror::Error)]
+pub enum CborError {
+    #[error(transparent)]
+    Read(#[from] ciborium::de::Error<std::io::Error>),
+
+    #[error("invalid content for tag {tag}: {value:?}")]
+    InvalidTag { tag: u64, value: Value },
+
+    #[error("unsupported value: {0:?}")]
+    Unsupported(Value),
+}
+
+/// Converts `term` into a CBOR value.
+pub fn to_value(term: &Term) -> Value {
+    match term {
+        Term::Number(Number::FixInteger(x)) => Value::Integer(x.value.into()),
+        Term::Number(Number::Bignum(x)) => integer_value(&x.value),
+        Term::Number(Number::Float(x)) => Value::Float(x.value),
+        Term::Atom(x) => match x.name() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            name => Value::Tag(ATOM_TAG, Box::new(Value::Text(name.to_string()))),
+        },
+        Term::Bitstring(x) if x.is_binary() => Value::Bytes(x.data.to_vec()),
+        Term::Bitstring(x) => tag(
+            BITSTRING_TAG,
+            vec![Value::Bytes(x.data.to_vec()), Value::Integer(x.bits.into())],
+        ),
+        Term::Nil(_) => Value::Array(Vec::new()),
+        Term::List(x) if x.is_improper_list() => tag(
+            IMPROPER_LIST_TAG,
+            vec![Value::Array(values(&x.elements)), to_value(&x.tail)],
+        ),
+        Term::List(x) => Value::Array(values(&x.elements)),
+        Term::Tuple(x) => tag(TUPLE_TAG, values(&x.elements)),
+        Term::Map(x) => Value::Map(
+            x.pairs
+                .iter()
+                .map(|(key, value)| (to_value(key), to_value(value)))
+                .collect(),
+        ),
+        Term::Pid(x) => pid_value(x),
+        Term::Port(x) => tag(
+            PORT_TAG,
+            vec![
+                text(&x.node),
+                Value::Integer(x.id.into()),
+                Value::Integer(x.creation.into()),
+            ],
+        ),
+        Term::Reference(x) => tag(
+            REFERENCE_TAG,
+            vec![
+                text(&x.node),
+                Value::Array(x.id.iter().map(|&id| Value::Integer(id.into())).collect()),
+                Value::Integer(x.creation.into()),
+            ],
+        ),
+        Term::Fun(Fun::ExternalFun(x)) => tag(
+            FUN_TAG,
+            vec![
+                text(&x.module),
+                text(&x.function),
+                Value::Integer(x.arity.into()),
+            ],
+        ),
+        Term::Fun(Fun::InternalFun(InternalFun::Old {
+            module,
+            pid,
+            free_vars,
+            index,
+            uniq,
+        })) => tag(
+            FUN_TAG,
+            vec![
+                text(module),
+                pid_value(pid),
+                Value::Array(values(free_vars)),
+                Value::Integer((*index).into()),
+                Value::Integer((*uniq).into()),
+            ],
+        ),
+        Term::Fun(Fun::InternalFun(InternalFun::New {
+            module,
+            arity,
+            pid,
+            free_vars,
+            index,
+            uniq,
+            old_index,
+            old_uniq,
+        })) => tag(
+            FUN_TAG,
+            vec![
+                text(module),
+                Value::Integer((*arity).into()),
+                pid_value(pid),
+                Value::Array(values(free_vars)),
+                Value::Integer((*index).into()),
+                Value::Bytes(uniq.to_vec()),
+                Value::Integer((*old_index).into()),
+                Value::Integer((*old_uniq).into()),
+            ],
+        ),
+    }
+}
+
+/// Converts a CBOR value into a term.
+pub fn from_value(value: &Value) -> Result<Term, CborError> {
+    Ok(match value {
+        Value::Integer(x) => integer_term(BigInt::from(i128::from(*x))),
+        Value::Float(x) => Term::from(*x),
+        Value::Bool(x) => Term::from(Atom::from(if *x { "true" } else { "false" })),
+        Value::Null => Term::from(Atom::from("null")),
+        Value::Bytes(x) => Term::from(Bitstring::from(x.as_slice())),
+        Value::Text(x) => Term::from(Bitstring::from(x.as_bytes())),
+        Value::Array(x) if x.is_empty() => Term::from(Nil),
+        Value::Array(x) => Term::from(List::from(terms(x)?)),
+        Value::Map(x) => Term::from(Map::from(
+            x.iter()
+                .map(|(key, value)| Ok((from_value(key)?, from_value(value)?)))
+                .collect::<Result<Vec<_>, CborError>>()?,
+        )),
+        Value::Tag(tag, content) => {
+            tagged_term(*tag, content)?.ok_or_else(|| CborError::InvalidTag {
+                tag: *tag,
+                value: (**content).clone(),
+            })?
+        }
+        _ => return Err(CborError::Unsupported(value.clone())),
+    })
+}
+
+/// Writes `term` as CBOR.
+pub fn to_vec(term: &Term) -> Vec<u8> {
+    let mut buf = Vec::new();
+    ciborium::ser::into_writer(&to_value(term), &mut buf).expect("writing to a Vec cannot fail");
+    buf
+}
+
+/// Reads a term from CBOR.
+pub fn from_slice(bytes: &[u8]) -> Result<Term, CborError> {
+    let value: Value = ciborium::de::from_reader(bytes)?;
+    from_value(&value)
+}
+
+fn tag(tag: u64, content: Vec<Value>) -> Value {
+    Value::Tag(tag, Box::new(Value::Array(content)))
+}
+
+fn text(atom: &Atom) -> Value {
+    Value::Text(atom.name().to_string())
+}
+
+fn values(terms: &[Term]) -> Vec<Value> {
+    terms.iter().map(to_value).collect()
+}
+
+fn terms(values: &[Value]) -> Result<Vec<Term>, CborError> {
+    values.iter().map(from_value).collect()
+}
+
+fn pid_value(pid: &Pid) -> Value {
+    tag(
+        PID_TAG,
+        vec![
+            text(&pid.node),
+            Value::Integer(pid.id.into()),
+            Value::Integer(pid.serial.into()),
+            Value::Integer(pid.creation.into()),
+        ],
+    )
+}
+
+fn integer_value(value: &BigInt) -> Value {
+    if let Some(integer) = i128::try_from(value)
+        .ok()
+        .and_then(|x| Integer::try_from(x).ok())
+    {
+        return Value::Integer(integer);
+    }
+    // A negative bignum holds `-1 - n`, as its magnitude less one.
+    match value.sign() {
+        Sign::Minus => {
+            let (_, bytes) = (-value - 1u8).to_bytes_be();
+            Value::Tag(NEGATIVE_BIGNUM_TAG, Box::new(Value::Bytes(bytes)))
+        }
+        _ => {
+            let (_, bytes) = value.to_bytes_be();
+            Value::Tag(POSITIVE_BIGNUM_TAG, Box::new(Value::Bytes(bytes)))
+        }
+    }
+}
+
+fn integer_term(value: BigInt) -> Term {
+    match i32::try_from(&value) {
+        Ok(value) => Term::from(value),
+        Err(_) => Term::from(Number::from(Bignum { value })),
+    }
+}
+
+fn u32_value(value: &Value) -> Option<u32> {
+    value.as_integer().and_then(|x| u32::try_from(x).ok())
+}
+
+fn i32_value(value: &Value) -> Option<i32> {
+    value.as_integer().and_then(|x| i32::try_from(x).ok())
+}
+
+fn u8_value(value: &Value) -> Option<u8> {
+    value.as_integer().and_then(|x| u8::try_from(x).ok())
+}
+
+fn atom_value(value: &Value) -> Option<Atom> {
+    value.as_text().map(Atom::from)
+}
+
+fn pid(value: &Value) -> Option<Pid> {
+    match value {
+        Value::Tag(PID_TAG, content) => pid_fields(content),
+        _ => None,
+    }
+}
+
+fn pid_fields(content: &Value) -> Option<Pid> {
+    match content.as_array()?.as_slice() {
+        [node, id, serial, creation] => Some(Pid::new(
+            atom_value(node)?,
+            u32_value(id)?,
+            u32_value(serial)?,
+            u32_value(creation)?,
+        )),
+        _ => None,
+    }
+}
+
+/// Reads the term a tagged value was written from, or returns `None` if its
+/// content is not what the tag calls for.
+fn tagged_term(tag: u64, content: &Value) -> Result<Option<Term>, CborError> {
+    let fields = content.as_array().map(Vec::as_slice).unwrap_or_default();
+    Ok(match (tag, fields) {
+        (POSITIVE_BIGNUM_TAG, _) => content
+            .as_bytes()
+            .map(|bytes| integer_term(BigInt::from_bytes_be(Sign::Plus, bytes))),
+        (NEGATIVE_BIGNUM_TAG, _) => content
+            .as_bytes()
+            .map(|bytes| integer_term(-BigInt::from_bytes_be(Sign::Plus, bytes) - 1u8)),
+        (ATOM_TAG, _) => atom_value(content).map(Term::from),
+        // Whole bytes are plain byte strings, so the last byte holds 1 to 7
+        // bits.
+        (BITSTRING_TAG, [data, bits]) => data
+            .as_bytes()
+            .filter(|data| !data.is_empty())
+            .zip(u8_value(bits).filter(|bits| (1..=7).contains(bits)))
+            .map(|(data, bits)| Term::from(Bitstring::from((data.clone(), bits)))),
+        (TUPLE_TAG, _) => match content {
+            Value::Array(elements) => Some(Term::from(Tuple::from(terms(elements)?))),
+            _ => None,
+        },
+        (IMPROPER_LIST_TAG, [Value::Array(elements), tail]) => Some(Term::from(List::from((
+            terms(elements)?,
+            from_value(tail)?,
+        )))),
+        (PID_TAG, _) => pid_fields(content).map(Term::from),
+        (PORT_TAG, [node, id, creation]) => (|| {
+            let id = id.as_integer().and_then(|x| u64::try_from(x).ok())?;
+            Some(Term::from(Port::new(
+                atom_value(node)?,
+                id,
+                u32_value(creation)?,
+            )))
+        })(),
+        (REFERENCE_TAG, [node, Value::Array(id), creation]) => (|| {
+            let id = id.iter().map(u32_value).collect::<Option<Vec<_>>>()?;
+            Some(Term::from(Reference::new(
+                atom_value(node)?,
+                id,
+                u32_value(creation)?,
+            )))
+        })(),
+        (FUN_TAG, [module, function, arity]) => (|| {
+            Some(Term::from(Fun::from(ExternalFun {
+                module: atom_value(module)?,
+                function: atom_value(function)?,
+                arity: u8_value(arity)?,
+            })))
+        })(),
+        (FUN_TAG, [module, pid_value, Value::Array(free_vars), index, uniq]) => {
+            let free_vars = terms(free_vars)?;
+            (|| {
+                Some(Term::from(Fun::from(InternalFun::Old {
+                    module: atom_value(module)?,
+                    pid: pid(pid_value)?,
+                    free_vars,
+                    index: i32_value(index)?,
+                    uniq: i32_value(uniq)?,
+                })))
+            })()
+        }
+        (
+            FUN_TAG,
+            [module, arity, pid_value, Value::Array(free_vars), index, uniq, old_index, old_uniq],
+        ) => {
+            let free_vars = terms(free_vars)?;
+            (|| {
+                Some(Term::from(Fun::from(InternalFun::New {
+                    module: atom_value(module)?,
+                    arity: u8_value(arity)?,
+                    pid: pid(pid_value)?,
+                    free_vars,
+                    index: u32_value(index)?,
+                    uniq: uniq.as_bytes()?.as_slice().try_into().ok()?,
+                    old_index: i32_value(old_index)?,
+                    old_uniq: i32_value(old_uniq)?,
+                })))
+            })()
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::term;
+
+    #[test]
+    fn it_round_trips_every_term() {
+        let pid = Pid::new("node@host", 1, 2, 3);
+        let terms = vec![
+            term("{ok, 'Quoted atom', true, null, 1, -2.5, 18446744073709551616, -18446744073709551617}"),
+            term("[340282366920938463463374607431768211456, -340282366920938463463374607431768211457]"),
+            term("#{1 => one, {a} => [<<\"é\"/utf8>>, <<255, 0>>, <<5:3>>], <<>> => \"\"}"),
+            term("[[], [1, 2 | tail], <<>>]"),
+            Term::from(pid.clone()),
+            Term::from(Port::new("node@host", 7, 3)),
+            Term::from(Reference::new("node@host", vec![1, 2, 3], 3)),
+            Term::from(Fun::from(ExternalFun::from(("lists", "map", 2)))),
+            Term::from(Fun::from(InternalFun::Old {
+                module: Atom::from("erl_eval"),
+                pid: pid.clone(),
+                free_vars: vec![],
+                index: 1,
+                uniq: -7,
+            })),
+            Term::from(Fun::from(InternalFun::New {
+                module: Atom::from("erl_eval"),
+                arity: 1,
+                pid,
+                free_vars: vec![term("{env, [x]}")],
+                index: 5,
+                uniq: [7; 16],
+                old_index: 5,
+                old_uniq: 99,
+            })),
+        ];
+        for t in terms {
+            assert_eq!(from_slice(&to_vec(&t)).unwrap(), t);
+        }
+    }
+
+    #[test]
+    fn it_reads_plain_cbor() {
+        let value = Value::Map(vec![
+            (Value::Text("name".into()), Value::Text("svc".into())),
+            (Value::Text("tags".into()), Value::Array(vec![Value::Null])),
+        ]);
+        assert_eq!(
+            from_value(&value).unwrap(),
+            term("#{<<\"name\">> => <<\"svc\">>, <<\"tags\">> => [null]}")
+        );
+        let invalid = Value::Tag(PID_TAG, Box::new(Value::Array(vec![])));
+        assert!(matches!(
+            from_value(&invalid),
+            Err(CborError::InvalidTag { tag: PID_TAG, .. })
+        ));
+    }
+
+    #[test]
+    fn it_rejects_malformed_bitstrings() {
+        for (data, bits) in [(vec![], 3), (vec![1, 2], 0), (vec![1, 2], 8)] {
+            let invalid = Value::Tag(
+                BITSTRING_TAG,
+                Box::new(Value::Array(vec![
+                    Value::Bytes(data),
+                    Value::Integer(bits.into()),
+                ])),
+            );
+            assert!(matches!(
+                from_value(&invalid),
+                Err(CborError::InvalidTag {
+                    tag: BITSTRING_TAG,
+                    ..
+                })
+            ));
+        }
+    }
+}
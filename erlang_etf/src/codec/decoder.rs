@@ -1,11 +1,13 @@
 use async_recursion::async_recursion;
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use libflate::zlib;
+use byteorder::{BigEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
 
-use std::io::{Read, Write};
+use std::io::Read;
 
+use crate::bytes::Bytes;
 use crate::codec::external as ext;
-// use crate::dist::{AtomCache, AtomCacheError, AtomRef};
+use crate::dist::{AtomCache, AtomCacheRefEntry, AtomRef, DistHeader};
+use crate::env::{AtomEncoding, Env};
 use crate::task::Process;
 use crate::term::*;
 
@@ -93,14 +95,102 @@ pub type DecodeResult = Result<Term, DecodeError>;
 //     atom: &'a Atom,
 // }
 
+/// Options limiting what a decoder will accept from untrusted input, see
+/// [`binary_to_term/2`](https://www.erlang.org/doc/man/erlang.html#binary_to_term-2)
+/// in the Erlang docs.
+#[derive(Clone, Debug, Default)]
+pub struct DecodeOptions {
+    max_depth: Option<usize>,
+    max_bytes: Option<usize>,
+    max_elements: Option<usize>,
+    safe: Option<Env>,
+}
+impl DecodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits how deeply tuples, lists, maps and funs may be nested.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Limits the number of bytes read, counting decompressed bytes for
+    /// compressed terms.
+    pub fn max_bytes(mut self, max_bytes: usize) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Limits the number of elements in any one tuple, list, map or fun
+    /// environment.
+    pub fn max_elements(mut self, max_elements: usize) -> Self {
+        self.max_elements = Some(max_elements);
+        self
+    }
+
+    /// Equivalent to the `safe` option: atoms which do not already exist in
+    /// the atom table of `env` are rejected instead of being created.
+    pub fn safe(mut self, env: &Env) -> Self {
+        self.safe = Some(env.clone());
+        self
+    }
+
+    pub fn get_max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    pub fn get_max_bytes(&self) -> Option<usize> {
+        self.max_bytes
+    }
+
+    pub fn get_max_elements(&self) -> Option<usize> {
+        self.max_elements
+    }
+
+    pub fn is_safe(&self) -> bool {
+        self.safe.is_some()
+    }
+
+    fn check_atom(&self, encoding: AtomEncoding, name: &[u8]) -> Result<(), DecodeError> {
+        if let Some(env) = &self.safe {
+            let exists = match encoding {
+                AtomEncoding::Latin1 => env.lookup_atom_latin1(name).is_some(),
+                AtomEncoding::Utf8 => env.lookup_atom_utf8(name).is_some(),
+            };
+            if !exists {
+                let name = match encoding {
+                    AtomEncoding::Latin1 => name.iter().map(|&b| char::from(b)).collect(),
+                    AtomEncoding::Utf8 => String::from_utf8_lossy(name).into_owned(),
+                };
+                return Err(DecodeError::UnsafeAtom { name });
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct ReadContext<'a> {
     pub process: &'a Process,
     pub atom_cache: &'a AtomCache,
-    atom_cache_refs: Vec<AtomRef>,
+    pub options: DecodeOptions,
 }
 impl<'a> ReadContext<'a> {
     pub fn new(process: &'a Process, atom_cache: &'a AtomCache) -> Self {
-        Self { process, atom_cache, atom_cache_refs: vec![], }
+        Self::with_options(process, atom_cache, DecodeOptions::default())
+    }
+
+    pub fn with_options(
+        process: &'a Process,
+        atom_cache: &'a AtomCache,
+        options: DecodeOptions,
+    ) -> Self {
+        Self {
+            process,
+            atom_cache,
+            options,
+        }
     }
 
     pub async fn bump_all_reds(&self) {
@@ -114,18 +204,36 @@ impl<'a> ReadContext<'a> {
 
 pub struct YieldableDecoder<R> {
     // process: Process,
-    reader: R,
+    reader: aux::CountingReader<R>,
     // atom_cache_refs: Vec<AtomCacheRef<'a>>,
-    // atom_cache_refs: Vec<AtomRef>,
+    atom_cache_refs: Vec<AtomRef>,
     // atom_cache: Option<AtomCache>,
     buf: Vec<u8>,
+    depth: usize,
+}
+impl YieldableDecoder<std::io::Empty> {
+    /// Creates a decoder that reads from `bytes`.
+    ///
+    /// Binaries in the decoded terms are slices of `bytes` rather than
+    /// copies. Bit binaries and the contents of compressed terms are still
+    /// copied.
+    pub fn from_bytes(bytes: Bytes) -> Self {
+        Self {
+            reader: aux::CountingReader::from_bytes(bytes),
+            atom_cache_refs: Vec::new(),
+            buf: vec![],
+            depth: 0,
+        }
+    }
 }
 impl<R: Read> YieldableDecoder<R> {
     // pub fn new(process: Process, reader: R, atom_cache: Option<AtomCache>) -> Self {
     pub fn new(reader: R) -> Self {
         Self {
-            reader,
+            reader: aux::CountingReader::new(reader),
+            atom_cache_refs: Vec::new(),
             buf: vec![],
+            depth: 0,
         }
         //     Self {
         //     process,
@@ -136,6 +244,23 @@ impl<R: Read> YieldableDecoder<R> {
         // }
     }
 
+    /// Creates a decoder for the body of a distribution message whose atom
+    /// cache header has already been read.
+    pub(crate) fn with_atom_cache_refs(reader: R, atom_cache_refs: Vec<AtomRef>) -> Self {
+        let mut decoder = Self::new(reader);
+        decoder.atom_cache_refs = atom_cache_refs;
+        decoder
+    }
+
+    pub(crate) fn atom_cache_refs(&self) -> &[AtomRef] {
+        &self.atom_cache_refs
+    }
+
+    /// Returns the number of bytes consumed from the reader so far.
+    pub fn bytes_read(&self) -> usize {
+        self.reader.count()
+    }
+
     pub async fn read_external_term(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
         let version = self.reader.read_u8()?;
@@ -155,8 +280,13 @@ impl<R: Read> YieldableDecoder<R> {
 
     async fn read_compressed_term(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
-        let _uncompressed_size = self.reader.read_u32::<BigEndian>()? as usize;
-        let zlib_decoder = zlib::Decoder::new(&mut self.reader)?;
+        let uncompressed_size = self.reader.read_u32::<BigEndian>()? as usize;
+        if let Some(max_bytes) = ctx.options.max_bytes {
+            if uncompressed_size > max_bytes {
+                return Err(DecodeError::ByteLimitExceeded { max_bytes });
+            }
+        }
+        let zlib_decoder = ZlibDecoder::new(&mut self.reader);
         // FIXME: support compressed atom cache decoding
         let mut decoder = YieldableDecoder::new(zlib_decoder);
         decoder.read_internal_term(ctx).await
@@ -164,11 +294,16 @@ impl<R: Read> YieldableDecoder<R> {
 
     pub async fn read_internal_term(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
+        self.check_bytes(ctx, 1)?;
         let tag = self.reader.read_u8()?;
-        self.read_internal_term_with_tag(tag).await
+        self.read_internal_term_with_tag(ctx, tag).await
     }
 
-    async fn read_internal_term_with_tag(&mut self, ctx: &ReadContext<'_>, tag: u8) -> DecodeResult {
+    async fn read_internal_term_with_tag(
+        &mut self,
+        ctx: &ReadContext<'_>,
+        tag: u8,
+    ) -> DecodeResult {
         match tag {
             ext::SMALL_INTEGER_EXT => self.read_small_integer_ext(ctx),
             ext::INTEGER_EXT => self.read_integer_ext(ctx),
@@ -200,18 +335,12 @@ impl<R: Read> YieldableDecoder<R> {
             ext::SMALL_ATOM_UTF8_EXT => self.read_small_atom_utf8_ext(ctx),
             ext::V4_PORT_EXT => self.read_v4_port_ext(ctx).await,
 
-            ext::DIST_HEADER => self.read_dist_header(ctx).await,
-            // ext::DIST_FRAG_HEADER => self.read_dist_frag_header().await,
-            // ext::DIST_FRAG_CONT => self.read_dist_frag_cont().await,
             ext::ATOM_CACHE_REF => self.read_atom_cache_ref(ctx),
 
-            _ => {
-                println!("tag was {:?}", tag);
-                Err(DecodeError::Io(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "expected internal term",
-                )))
-            }
+            _ => Err(DecodeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "expected internal term",
+            ))),
         }
     }
 
@@ -270,7 +399,11 @@ impl<R: Read> YieldableDecoder<R> {
                 "expected internal small integer",
             ))),
         }?;
-        aux::term_into_i32(term).and_then(|x| Ok(x as u8))
+        let value = aux::term_into_i32(term)?;
+        u8::try_from(value).map_err(|_| DecodeError::OutOfRange {
+            value,
+            range: 0..256,
+        })
     }
 
     fn read_small_integer_ext(&mut self, _ctx: &ReadContext<'_>) -> DecodeResult {
@@ -295,18 +428,20 @@ impl<R: Read> YieldableDecoder<R> {
         Ok(Term::from(Number::from(Float::try_from(value)?)))
     }
 
-    fn read_atom_ext(&mut self, _ctx: &ReadContext<'_>) -> DecodeResult {
+    fn read_atom_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         let len = self.reader.read_u16::<BigEndian>()?;
         self.buf.resize(len as usize, 0);
         self.reader.read_exact(&mut self.buf)?;
+        ctx.options.check_atom(AtomEncoding::Latin1, &self.buf)?;
         let name = aux::latin1_bytes_to_string(&self.buf)?;
         Ok(Term::from(Atom::from(name)))
     }
 
-    fn read_small_atom_ext(&mut self, _ctx: &ReadContext<'_>) -> DecodeResult {
+    fn read_small_atom_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         let len = self.reader.read_u8()?;
         self.buf.resize(len as usize, 0);
         self.reader.read_exact(&mut self.buf)?;
+        ctx.options.check_atom(AtomEncoding::Latin1, &self.buf)?;
         let name = aux::latin1_bytes_to_string(&self.buf)?;
         Ok(Term::from(Atom::from(name)))
     }
@@ -386,10 +521,12 @@ impl<R: Read> YieldableDecoder<R> {
     async fn read_small_tuple_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
         let arity = self.reader.read_u8()? as usize;
+        self.enter_container(ctx, arity)?;
         let mut elements = Vec::with_capacity(arity);
         for _ in 0..arity {
             elements.push(self.read_internal_term(ctx).await?);
         }
+        self.leave_container();
         Ok(Term::from(Tuple::from(elements)))
     }
 
@@ -397,10 +534,12 @@ impl<R: Read> YieldableDecoder<R> {
     async fn read_large_tuple_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
         let arity = self.reader.read_u32::<BigEndian>()? as usize;
-        let mut elements = Vec::with_capacity(arity);
+        self.enter_container(ctx, arity)?;
+        let mut elements = Vec::with_capacity(aux::capacity(arity));
         for _ in 0..arity {
             elements.push(self.read_internal_term(ctx).await?);
         }
+        self.leave_container();
         Ok(Term::from(Tuple::from(elements)))
     }
 
@@ -411,6 +550,8 @@ impl<R: Read> YieldableDecoder<R> {
     async fn read_string_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
         let len = self.reader.read_u16::<BigEndian>()? as usize;
+        self.check_elements(ctx, len)?;
+        self.check_bytes(ctx, len)?;
         let mut elements = Vec::with_capacity(len);
         for i in 0..len {
             elements.push(Term::from(Number::from(FixInteger::from(i32::from(
@@ -427,28 +568,36 @@ impl<R: Read> YieldableDecoder<R> {
     async fn read_list_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
         let len = self.reader.read_u32::<BigEndian>()? as usize;
-        let mut elements = Vec::with_capacity(len);
+        self.enter_container(ctx, len)?;
+        let mut elements = Vec::with_capacity(aux::capacity(len));
         for _ in 0..len {
             elements.push(self.read_internal_term(ctx).await?);
         }
         let tail = self.read_internal_term(ctx).await?;
+        self.leave_container();
         Ok(Term::from(List::from((elements, tail))))
     }
 
     async fn read_binary_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
         let len = self.reader.read_u32::<BigEndian>()? as usize;
-        let mut buf = vec![0; len];
-        self.reader.read_exact(&mut buf)?;
-        Ok(Term::from(Bitstring::from(buf)))
+        self.check_bytes(ctx, len)?;
+        let data = match self.reader.read_bytes(len)? {
+            Some(data) => data,
+            None => Bytes::from(aux::read_vec(&mut self.reader, len)?),
+        };
+        Ok(Term::from(Bitstring::from(data)))
     }
 
     async fn read_bit_binary_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
         let len = self.reader.read_u32::<BigEndian>()? as usize;
         let tail_bits = self.reader.read_u8()?;
-        let mut buf = vec![0; len];
-        self.reader.read_exact(&mut buf)?;
+        if (len == 0 && tail_bits != 0) || (len != 0 && !(1..=8).contains(&tail_bits)) {
+            return Err(DecodeError::InvalidTailBits { len, tail_bits });
+        }
+        self.check_bytes(ctx, len)?;
+        let mut buf = aux::read_vec(&mut self.reader, len)?;
         if !buf.is_empty() {
             let tail = buf[len - 1] >> (8 - tail_bits);
             buf[len - 1] = tail;
@@ -470,9 +619,9 @@ impl<R: Read> YieldableDecoder<R> {
         ctx.bump_reds(1).await;
         let n = self.reader.read_u32::<BigEndian>()? as usize;
         let sign = self.reader.read_u8()?;
-        self.buf.resize(n, 0);
-        self.reader.read_exact(&mut self.buf)?;
-        let value = BigInt::from_bytes_le(aux::byte_to_sign(sign)?, &self.buf);
+        self.check_bytes(ctx, n)?;
+        let buf = aux::read_vec(&mut self.reader, n)?;
+        let value = BigInt::from_bytes_le(aux::byte_to_sign(sign)?, &buf);
         Ok(Term::from(Number::from(Bignum { value })))
     }
 
@@ -489,10 +638,12 @@ impl<R: Read> YieldableDecoder<R> {
         let old_index = self.read_internal_i32(ctx).await?;
         let old_uniq = self.read_internal_i32(ctx).await?;
         let pid = self.read_internal_pid(ctx).await?;
-        let mut free_vars = Vec::with_capacity(num_free as usize);
+        self.enter_container(ctx, num_free as usize)?;
+        let mut free_vars = Vec::with_capacity(aux::capacity(num_free as usize));
         for _ in 0..num_free {
             free_vars.push(self.read_internal_term(ctx).await?);
         }
+        self.leave_container();
         Ok(Term::from(Fun::from(InternalFun::New {
             module,
             arity,
@@ -521,12 +672,15 @@ impl<R: Read> YieldableDecoder<R> {
     async fn read_map_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         ctx.bump_reds(1).await;
         let arity = self.reader.read_u32::<BigEndian>()? as usize;
-        let mut pairs = Vec::with_capacity(arity);
+        self.enter_container(ctx, arity)?;
+        self.check_bytes(ctx, arity.saturating_mul(2))?;
+        let mut pairs = Vec::with_capacity(aux::capacity(arity));
         for _ in 0..arity {
             let key = self.read_internal_term(ctx).await?;
             let val = self.read_internal_term(ctx).await?;
             pairs.push((key, val));
         }
+        self.leave_container();
         Ok(Term::from(Map::from(pairs)))
     }
 
@@ -538,10 +692,12 @@ impl<R: Read> YieldableDecoder<R> {
         let module = self.read_internal_atom(ctx).await?;
         let index = self.read_internal_i32(ctx).await?;
         let uniq = self.read_internal_i32(ctx).await?;
-        let mut free_vars = Vec::with_capacity(num_free as usize);
+        self.enter_container(ctx, num_free as usize)?;
+        let mut free_vars = Vec::with_capacity(aux::capacity(num_free as usize));
         for _ in 0..num_free {
             free_vars.push(self.read_internal_term(ctx).await?);
         }
+        self.leave_container();
         Ok(Term::from(Fun::from(InternalFun::Old {
             module,
             pid,
@@ -551,19 +707,21 @@ impl<R: Read> YieldableDecoder<R> {
         })))
     }
 
-    fn read_atom_utf8_ext(&mut self, _ctx: &ReadContext<'_>) -> DecodeResult {
+    fn read_atom_utf8_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         let len = self.reader.read_u16::<BigEndian>()?;
         self.buf.resize(len as usize, 0);
         self.reader.read_exact(&mut self.buf)?;
+        ctx.options.check_atom(AtomEncoding::Utf8, &self.buf)?;
         let name =
             std::str::from_utf8(&self.buf).or_else(|e| aux::invalid_data_error(e.to_string()))?;
         Ok(Term::from(Atom::from(name)))
     }
 
-    fn read_small_atom_utf8_ext(&mut self, _ctx: &ReadContext<'_>) -> DecodeResult {
+    fn read_small_atom_utf8_ext(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
         let len = self.reader.read_u8()?;
         self.buf.resize(len as usize, 0);
         self.reader.read_exact(&mut self.buf)?;
+        ctx.options.check_atom(AtomEncoding::Utf8, &self.buf)?;
         let name =
             std::str::from_utf8(&self.buf).or_else(|e| aux::invalid_data_error(e.to_string()))?;
         Ok(Term::from(Atom::from(name)))
@@ -577,137 +735,241 @@ impl<R: Read> YieldableDecoder<R> {
         Ok(Term::from(Port::new(node, id, creation)))
     }
 
-    async fn read_dist_header(&mut self, ctx: &ReadContext<'_>) -> DecodeResult {
-        let number_of_atom_cache_refs = self.reader.read_u8()?;
-        // self.reader.read_
-        if number_of_atom_cache_refs > 0 {
-            let flags_len = (number_of_atom_cache_refs as usize / 2) + 1;
-            let mut flags_buf = vec![0; flags_len];
-            self.reader.read_exact(&mut flags_buf)?;
-            use bitter::{BigEndianReader, BitReader};
-            let mut flags_reader = BigEndianReader::new(&flags_buf[..]);
-            // #[derive(Debug)]
-            // struct AtomCacheEntry {
-            //     atom_cache_reference_index: usize,
-            //     new_cache_entry_flag: bool,
-            //     segment_index: u64,
-            //     update: AtomCacheRefUpdate,
-            // }
-            // #[derive(Debug)]
-            // enum AtomCacheRefUpdate {
-            //     Old {
-            //         internal_segment_index: u64,
-            //     },
-            //     New {
-            //         internal_segment_index: u64,
-            //         atom_text: Vec<u8>,
-            //     }
-            // }
-            // let mut entries = Vec::with_capacity(number_of_atom_cache_refs as usize);
-            let flags_buf_last_byte = flags_buf.last().unwrap();
-            let long_atoms = if number_of_atom_cache_refs % 2 == 0 {
-                (flags_buf_last_byte & 1) == 1
-            } else {
-                ((flags_buf_last_byte >> 4) & 1) == 1
-            };
-            let atom_cache = self.atom_cache.clone().unwrap();
-            // println!("bits remaining: {:?}", flags_reader.bits_remaining());
-            for atom_cache_reference_index in 0..(number_of_atom_cache_refs as usize) {
-                let new_cache_entry_flag = flags_reader.read_bit_unchecked();
-                let segment_index = flags_reader.read_bits_unchecked(3);
-                // let _currently_unused = flags_reader.read_bits_max_unchecked(3);
-                // let long_atoms = flags_reader.read_bit_unchecked();
-                let internal_segment_index = self.reader.read_u8()? as u64;
-                if new_cache_entry_flag {
-                    let atom_len = if long_atoms { self.reader.read_u16::<BigEndian>()? as usize } else { self.reader.read_u8()? as usize };
-                    let mut atom_text = vec![0; atom_len];
-                    self.reader.read_exact(&mut atom_text)?;
-                    // FIXME: support UTF-8 and Latin1 based on DFLAG_UTF8_ATOMS
-                    let atom_ref = AtomRef::new(Atom::from(String::from_utf8(atom_text).or_else(|e| aux::invalid_data_error(e.to_string()))?));
-                    // aux::latin1_bytes_to_string(&self.buf)?;
-                    let internal_index = ((segment_index << 8) | internal_segment_index) as usize;
-                    atom_cache.insert(internal_index, atom_ref.clone())?;
-                    self.atom_cache_refs.push(atom_ref);
-                    // atom_cache.get(internal_index).unwrap().cloned();
-                    // self.atom_cache_refs.push(&atom_cache.get(internal_index).unwrap().as_ref().unwrap());
-                // let internal_segment_index = match {
-                //     AtomCacheRefUpdate::New {}
-                // };
-                // let internal_index = entry.segment_index
-                // self.atom_cache_refs.push(AtomCacheRef {})
-                //     entries.push(AtomCacheEntry {
-                //         atom_cache_reference_index,
-                //         new_cache_entry_flag,
-                //         segment_index,
-                //         update: AtomCacheRefUpdate::New { internal_segment_index, atom_text, },
-                //     });
+    /// Reads the version magic, tag and atom cache header of a `DIST_HEADER`,
+    /// updating `ctx.atom_cache` with its new entries. The control message
+    /// and payload follow.
+    pub async fn read_dist_header(
+        &mut self,
+        ctx: &ReadContext<'_>,
+    ) -> Result<DistHeader, DecodeError> {
+        ctx.bump_reds(1).await;
+        let version = self.reader.read_u8()?;
+        if version != ext::VERSION_MAGIC {
+            return Err(DecodeError::UnsupportedVersion { version });
+        }
+        let tag = self.reader.read_u8()?;
+        if tag != ext::DIST_HEADER {
+            return Err(DecodeError::UnknownTag { tag });
+        }
+        self.read_atom_cache_header(ctx).await
+    }
+
+    /// Reads the sequence id, fragment id and atom cache header of a
+    /// `DIST_FRAG_HEADER`, whose tag has already been consumed. The bytes
+    /// which follow are the first part of the fragmented message.
+    pub(crate) async fn read_dist_frag_header(
+        &mut self,
+        ctx: &ReadContext<'_>,
+    ) -> Result<(u64, u64, DistHeader), DecodeError> {
+        ctx.bump_reds(1).await;
+        let sequence_id = self.reader.read_u64::<BigEndian>()?;
+        let fragment_id = self.reader.read_u64::<BigEndian>()?;
+        let header = self.read_atom_cache_header(ctx).await?;
+        Ok((sequence_id, fragment_id, header))
+    }
+
+    /// Reads the sequence id and fragment id of a `DIST_FRAG_CONT`, whose tag
+    /// has already been consumed.
+    pub(crate) fn read_dist_frag_cont(&mut self) -> Result<(u64, u64), DecodeError> {
+        let sequence_id = self.reader.read_u64::<BigEndian>()?;
+        let fragment_id = self.reader.read_u64::<BigEndian>()?;
+        Ok((sequence_id, fragment_id))
+    }
+
+    async fn read_atom_cache_header(
+        &mut self,
+        ctx: &ReadContext<'_>,
+    ) -> Result<DistHeader, DecodeError> {
+        self.atom_cache_refs.clear();
+        let number_of_atom_cache_refs = self.reader.read_u8()? as usize;
+        let mut header = DistHeader {
+            long_atoms: false,
+            atom_cache_ref_entries: Vec::with_capacity(number_of_atom_cache_refs),
+        };
+        if number_of_atom_cache_refs == 0 {
+            return Ok(header);
+        }
+        let mut flags_buf = vec![0; number_of_atom_cache_refs / 2 + 1];
+        self.reader.read_exact(&mut flags_buf)?;
+        // Each entry has a half byte of flags, starting from the least
+        // significant half of the first byte, followed by a half byte with
+        // the long atoms flag.
+        let flags_of = |i: usize| (flags_buf[i / 2] >> ((i % 2) * 4)) & 0x0f;
+        header.long_atoms = (flags_of(number_of_atom_cache_refs) & 1) == 1;
+        for i in 0..number_of_atom_cache_refs {
+            let flags = flags_of(i);
+            let new_cache_entry_flag = (flags & 0b1000) != 0;
+            let segment_index = (flags & 0b0111) as usize;
+            let internal_index = (segment_index << 8) | self.reader.read_u8()? as usize;
+            if new_cache_entry_flag {
+                let atom_len = if header.long_atoms {
+                    self.reader.read_u16::<BigEndian>()? as usize
                 } else {
-                    let internal_index = ((segment_index << 8) | internal_segment_index) as usize;
-                    let atom_ref = atom_cache.get(internal_index)?.ok_or_else(|| aux::invalid_data_error::<DecodeResult>(format!("atom cache not found: {:?}", internal_index)).unwrap_err())?;
-                    self.atom_cache_refs.push(atom_ref);
-                    // self.atom_cache_refs.push(&atom_cache.get(internal_index).unwrap().as_ref().unwrap());
-                    // entries.push(AtomCacheEntry {
-                    //     atom_cache_reference_index,
-                    //     new_cache_entry_flag,
-                    //     segment_index,
-                    //     update: AtomCacheRefUpdate::Old { internal_segment_index, },
-                    // });
-                }
-                // flags.push(Flag {
-                //     atom_cache_reference_index,
-                //     new_cache_entry_flag,
-                //     segment_index,
-                //     long_atoms,
-                // });
-                // println!("[flags:{:?}] {:?} {:?} {:?}", i, new_cache_entry_flag, segment_index, long_atoms);
+                    self.reader.read_u8()? as usize
+                };
+                let mut atom_text = vec![0; atom_len];
+                self.reader.read_exact(&mut atom_text)?;
+                ctx.options.check_atom(AtomEncoding::Utf8, &atom_text)?;
+                // FIXME: support UTF-8 and Latin1 based on DFLAG_UTF8_ATOMS
+                let name = std::str::from_utf8(&atom_text)
+                    .or_else(|e| aux::invalid_data_error(e.to_string()))?;
+                let atom_ref = AtomRef::new(Atom::from(name));
+                ctx.atom_cache.insert(internal_index, atom_ref.clone())?;
+                self.atom_cache_refs.push(atom_ref);
+                header
+                    .atom_cache_ref_entries
+                    .push(AtomCacheRefEntry::new(internal_index, atom_text));
+            } else {
+                let atom_ref = ctx.atom_cache.get(internal_index)?.ok_or_else(|| {
+                    aux::invalid_data_error::<()>(format!(
+                        "atom cache not found: {:?}",
+                        internal_index
+                    ))
+                    .unwrap_err()
+                })?;
+                self.atom_cache_refs.push(atom_ref);
+                header
+                    .atom_cache_ref_entries
+                    .push(AtomCacheRefEntry::old(internal_index));
+            }
+        }
+        Ok(header)
+    }
+
+    /// Fails unless `len` more bytes may be read without going over the
+    /// `max_bytes` limit. Length prefixes are checked this way before
+    /// anything is allocated for them.
+    fn check_bytes(&self, ctx: &ReadContext<'_>, len: usize) -> Result<(), DecodeError> {
+        if let Some(max_bytes) = ctx.options.max_bytes {
+            if self.reader.count().saturating_add(len) > max_bytes {
+                return Err(DecodeError::ByteLimitExceeded { max_bytes });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_elements(&self, ctx: &ReadContext<'_>, len: usize) -> Result<(), DecodeError> {
+        if let Some(max_elements) = ctx.options.max_elements {
+            if len > max_elements {
+                return Err(DecodeError::ElementLimitExceeded { len, max_elements });
             }
-            println!("atom_cache_refs = {:?}", self.atom_cache_refs);
-            println!("atom_cache = {:?}", self.atom_cache);
-            // println!("bits remaining: {:?}", flags_reader.approx_bytes_remaining());
-            // println!("flags = {:?}", entries);
-            // for entry in entries {
-            //     println!("{:?}", entry);
-            // }
-            // for entry in entries {
-            //     let mut atom_cache = self.atom_cache.unwrap();
-            //     let internal_segment_index = match {
-            //         AtomCacheRefUpdate::New {}
-            //     };
-            //     let internal_index = entry.segment_index
-            //     self.atom_cache_refs.push(AtomCacheRef {})
-            // }
-        }
-        Ok(Term::Nil(Nil))
-    }
-
-    // async fn read_dist_frag_header(&mut self) -> DecodeResult {
-    //     let sequence_id = self.reader.read_u64::<BigEndian>()?;
-    //     let fragment_id = self.reader.read_u64::<BigEndian>()?;
-    //     let number_of_atom_cache_refs = self.reader.read_u8()?;
-    //     let flags_len = if number_of_atom_cache_refs == 0 {
-    //         0
-    //     } else {
-    //         (number_of_atom_cache_refs as usize / 2) + 1
-    //     };
-    //     let mut flags_buf = vec![0; flags_len];
-    //     self.reader.read_exact(&mut flags_buf)?;
-    //     Ok(Term::Nil(Nil))
-    // }
-
-    fn read_atom_cache_ref(&mut self) -> DecodeResult {
+        }
+        Ok(())
+    }
+
+    fn enter_container(&mut self, ctx: &ReadContext<'_>, len: usize) -> Result<(), DecodeError> {
+        self.check_elements(ctx, len)?;
+        // Every element takes up at least one byte.
+        self.check_bytes(ctx, len)?;
+        if let Some(max_depth) = ctx.options.max_depth {
+            if self.depth >= max_depth {
+                return Err(DecodeError::DepthLimitExceeded { max_depth });
+            }
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn leave_container(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn read_atom_cache_ref(&mut self, _ctx: &ReadContext<'_>) -> DecodeResult {
         let atom_cache_reference_index = self.reader.read_u8()? as usize;
         if let Some(atom_ref) = self.atom_cache_refs.get(atom_cache_reference_index) {
             let atom = atom_ref.to_owned_atom();
             Ok(Term::from(atom))
         } else {
-            aux::invalid_data_error(format!("no atom cache ref index found {:?}", atom_cache_reference_index)).map_err(|err| err.into())
+            aux::invalid_data_error(format!(
+                "no atom cache ref index found {:?}",
+                atom_cache_reference_index
+            ))
+            .map_err(|err| err.into())
         }
     }
 }
 
 mod aux {
+    use crate::bytes::Bytes;
     use crate::term::Sign;
 
+    /// Keeps track of how many bytes have been read from the inner reader.
+    ///
+    /// When created from `Bytes`, the inner reader is unused and reads come
+    /// from the `Bytes` instead, so that [`read_bytes`](Self::read_bytes)
+    /// can hand out slices of it.
+    pub struct CountingReader<R> {
+        inner: R,
+        count: usize,
+        source: Option<Bytes>,
+    }
+    impl<R> CountingReader<R> {
+        pub fn new(inner: R) -> Self {
+            Self {
+                inner,
+                count: 0,
+                source: None,
+            }
+        }
+
+        pub fn count(&self) -> usize {
+            self.count
+        }
+
+        /// Returns the next `len` bytes without copying them, or `None` if
+        /// the reader was not created from `Bytes`.
+        pub fn read_bytes(&mut self, len: usize) -> std::io::Result<Option<Bytes>> {
+            let Some(source) = &self.source else {
+                return Ok(None);
+            };
+            if source.len() - self.count < len {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            let bytes = source.slice(self.count..self.count + len);
+            self.count += len;
+            Ok(Some(bytes))
+        }
+    }
+    impl CountingReader<std::io::Empty> {
+        pub fn from_bytes(source: Bytes) -> Self {
+            Self {
+                inner: std::io::empty(),
+                count: 0,
+                source: Some(source),
+            }
+        }
+    }
+    impl<R: std::io::Read> std::io::Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = match &self.source {
+                Some(source) => std::io::Read::read(&mut &source[self.count..], buf)?,
+                None => self.inner.read(buf)?,
+            };
+            self.count += n;
+            Ok(n)
+        }
+    }
+
+    /// Most elements allocated up front for a length prefix. The input may
+    /// not hold as many as it claims, so longer collections grow as their
+    /// elements are read instead.
+    const MAX_PREALLOCATED: usize = 1024;
+
+    pub fn capacity(len: usize) -> usize {
+        len.min(MAX_PREALLOCATED)
+    }
+
+    /// Reads exactly `len` bytes, growing the buffer as they arrive rather
+    /// than allocating all of `len` before the first one.
+    pub fn read_vec<R: std::io::Read>(reader: &mut R, len: usize) -> std::io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::Read::take(reader, len as u64), &mut buf)?;
+        if buf.len() < len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(buf)
+    }
+
     pub fn byte_to_sign(b: u8) -> std::io::Result<Sign> {
         match b {
             0 => Ok(Sign::Plus),
@@ -724,14 +986,8 @@ mod aux {
     }
 
     pub fn latin1_bytes_to_string(buf: &[u8]) -> std::io::Result<String> {
-        // FIXME: Supports Latin1 characters
-        std::str::from_utf8(buf)
-            .or_else(|e| other_error(e.to_string()))
-            .map(ToString::to_string)
-    }
-
-    pub fn other_error<T>(message: String) -> std::io::Result<T> {
-        Err(std::io::Error::new(std::io::ErrorKind::Other, message))
+        // Latin-1 code points are the byte values themselves.
+        Ok(buf.iter().map(|&b| char::from(b)).collect())
     }
 
     pub fn term_into_atom(t: crate::Term) -> Result<crate::term::Atom, super::DecodeError> {
@@ -758,3 +1014,242 @@ mod aux {
             })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{pin_mut, Cassette};
+
+    fn decode_with_options(options: DecodeOptions, buf: &[u8]) -> DecodeResult {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::with_options(&process, &atom_cache, options);
+        let mut decoder = YieldableDecoder::new(buf);
+        let future = decoder.read_external_term(&ctx);
+        pin_mut!(future);
+        Cassette::new(future).block_on()
+    }
+
+    fn nested_lists(depth: usize) -> Vec<u8> {
+        let mut buf = vec![ext::VERSION_MAGIC];
+        for _ in 0..depth {
+            buf.extend_from_slice(&[ext::LIST_EXT, 0, 0, 0, 1]);
+        }
+        buf.push(ext::NIL_EXT);
+        buf.extend(std::iter::repeat_n(ext::NIL_EXT, depth));
+        buf
+    }
+
+    #[test]
+    fn it_reads_dist_header_flags() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        atom_cache
+            .insert(1034, AtomRef::new(Atom::from("atom1034")))
+            .unwrap();
+        atom_cache
+            .insert(5, AtomRef::new(Atom::from("atom5")))
+            .unwrap();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let buf = [
+            131, 68, 5, 4, 137, 9, 10, 5, 236, 3, 114, 101, 103, 9, 4, 99, 97, 108, 108, 238, 13,
+            115, 101, 116, 95, 103, 101, 116, 95, 115, 116, 97, 116, 101,
+        ];
+        let mut decoder = YieldableDecoder::new(&buf[..]);
+        let header = {
+            let future = decoder.read_dist_header(&ctx);
+            pin_mut!(future);
+            Cassette::new(future).block_on().unwrap()
+        };
+        assert!(!header.long_atoms);
+        assert_eq!(
+            header.atom_cache_ref_entries[0],
+            AtomCacheRefEntry::old(1034)
+        );
+        assert_eq!(
+            header.atom_cache_ref_entries[2],
+            AtomCacheRefEntry::new(492, b"reg".to_vec())
+        );
+        let names: Vec<_> = decoder
+            .atom_cache_refs()
+            .iter()
+            .map(|atom_ref| atom_ref.to_owned_atom().name().to_string())
+            .collect();
+        assert_eq!(names, ["atom1034", "atom5", "reg", "call", "set_get_state"]);
+        assert!(atom_cache.get(492).unwrap().is_some());
+        assert!(atom_cache.get(494).unwrap().is_some());
+    }
+
+    #[test]
+    fn it_limits_depth() {
+        let buf = nested_lists(8);
+        assert!(decode_with_options(DecodeOptions::new().max_depth(8), &buf).is_ok());
+        assert!(matches!(
+            decode_with_options(DecodeOptions::new().max_depth(7), &buf),
+            Err(DecodeError::DepthLimitExceeded { max_depth: 7 })
+        ));
+    }
+
+    #[test]
+    fn it_checks_length_prefixes_before_allocating() {
+        let binary = [
+            ext::VERSION_MAGIC,
+            ext::BINARY_EXT,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            1,
+        ];
+        assert!(matches!(
+            decode_with_options(DecodeOptions::new().max_bytes(1024), &binary),
+            Err(DecodeError::ByteLimitExceeded { max_bytes: 1024 })
+        ));
+        let list = [
+            ext::VERSION_MAGIC,
+            ext::LIST_EXT,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            ext::NIL_EXT,
+        ];
+        assert!(matches!(
+            decode_with_options(DecodeOptions::new().max_bytes(1024), &list),
+            Err(DecodeError::ByteLimitExceeded { max_bytes: 1024 })
+        ));
+        assert!(matches!(
+            decode_with_options(DecodeOptions::new().max_elements(16), &list),
+            Err(DecodeError::ElementLimitExceeded {
+                len: 0xffff_ffff,
+                max_elements: 16
+            })
+        ));
+    }
+
+    #[test]
+    fn it_does_not_trust_length_prefixes_without_limits() {
+        let prefixed = |tag| [ext::VERSION_MAGIC, tag, 0x0f, 0xff, 0xff, 0xff];
+        for tag in [
+            ext::LIST_EXT,
+            ext::LARGE_TUPLE_EXT,
+            ext::MAP_EXT,
+            ext::BINARY_EXT,
+        ] {
+            assert!(matches!(
+                decode_with_options(DecodeOptions::default(), &prefixed(tag)),
+                Err(DecodeError::Io(_))
+            ));
+        }
+        let mut big = prefixed(ext::LARGE_BIG_EXT).to_vec();
+        big.push(0);
+        assert!(matches!(
+            decode_with_options(DecodeOptions::default(), &big),
+            Err(DecodeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn it_rejects_invalid_tail_bits() {
+        for (len, tail_bits) in [(1, 0), (1, 9), (0, 3)] {
+            let mut buf = vec![
+                ext::VERSION_MAGIC,
+                ext::BIT_BINARY_EXT,
+                0,
+                0,
+                0,
+                len,
+                tail_bits,
+            ];
+            buf.resize(buf.len() + len as usize, 0xff);
+            assert!(matches!(
+                decode_with_options(DecodeOptions::new(), &buf),
+                Err(DecodeError::InvalidTailBits { .. })
+            ));
+        }
+        let empty = [ext::VERSION_MAGIC, ext::BIT_BINARY_EXT, 0, 0, 0, 0, 0];
+        assert!(decode_with_options(DecodeOptions::new(), &empty).is_ok());
+    }
+
+    #[test]
+    fn it_only_accepts_existing_atoms_when_safe() {
+        let env = Env::new();
+        env.make_atom_utf8(&b"known"[..]);
+        let options = DecodeOptions::new().safe(&env);
+        let known = [
+            ext::VERSION_MAGIC,
+            ext::SMALL_ATOM_UTF8_EXT,
+            5,
+            b'k',
+            b'n',
+            b'o',
+            b'w',
+            b'n',
+        ];
+        assert_eq!(
+            decode_with_options(options.clone(), &known).unwrap(),
+            Term::from(Atom::from("known"))
+        );
+        let unknown = [
+            ext::VERSION_MAGIC,
+            ext::SMALL_ATOM_UTF8_EXT,
+            3,
+            b'n',
+            b'e',
+            b'w',
+        ];
+        assert!(matches!(
+            decode_with_options(options, &unknown),
+            Err(DecodeError::UnsafeAtom { name }) if name == "new"
+        ));
+    }
+
+    #[test]
+    fn it_slices_binaries_from_bytes() {
+        // {<<1,2,3>>, <<4,5>>}
+        let input = Bytes::from(vec![
+            ext::VERSION_MAGIC,
+            ext::SMALL_TUPLE_EXT,
+            2,
+            ext::BINARY_EXT,
+            0,
+            0,
+            0,
+            3,
+            1,
+            2,
+            3,
+            ext::BINARY_EXT,
+            0,
+            0,
+            0,
+            2,
+            4,
+            5,
+        ]);
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let mut decoder = YieldableDecoder::from_bytes(input.clone());
+        let term = {
+            let future = decoder.read_external_term(&ctx);
+            pin_mut!(future);
+            Cassette::new(future).block_on().unwrap()
+        };
+        assert_eq!(decoder.bytes_read(), input.len());
+        let Term::Tuple(tuple) = term else {
+            panic!("expected a tuple");
+        };
+        let offsets: Vec<_> = tuple
+            .elements
+            .iter()
+            .map(|element| match element {
+                Term::Bitstring(bitstring) => {
+                    bitstring.data.as_ptr() as usize - input.as_ptr() as usize
+                }
+                _ => panic!("expected a binary"),
+            })
+            .collect();
+        assert_eq!(offsets, [8, 16]);
+    }
+}
@@ -1,41 +1,1549 @@
-pub enum TermVisitorHint {
-    Continue,
-    Skip,
-    Halt,
+use async_recursion::async_recursion;
+use byteorder::{BigEndian, WriteBytesExt};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use std::io::Write;
+
+use crate::codec::dist::DistributionFlags;
+use crate::codec::external as ext;
+use crate::dist::AtomCacheRefEntry;
+use crate::task::Process;
+use crate::term::*;
+
+use super::error::EncodeError;
+
+pub type EncodeResult = Result<(), EncodeError>;
+
+/// Number of binary bytes written between reduction bumps.
+const BYTES_PER_REDUCTION_BLOCK: usize = 4096;
+
+/// Options controlling how terms are encoded, see
+/// [`term_to_binary/2`](https://www.erlang.org/doc/man/erlang.html#term_to_binary-2)
+/// in the Erlang docs.
+#[derive(Clone, Debug)]
+pub struct EncodeOptions {
+    compression_level: u8,
+    compression_threshold: usize,
+    deterministic: bool,
+    flags: DistributionFlags,
+}
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            compression_level: 0,
+            compression_threshold: 0,
+            deterministic: false,
+            flags: DistributionFlags::DFLAG_DIST_MANDATORY,
+        }
+    }
 }
+impl EncodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The distribution flags negotiated with the peer, which decide the
+    /// wire forms of atoms, pids, ports, references, funs, floats and bit
+    /// binaries. Terms the peer cannot represent fail to encode with
+    /// [`EncodeError::UnsupportedByPeer`].
+    ///
+    /// Defaults to the flags that are mandatory since OTP 26, which is what
+    /// `term_to_binary/1` assumes.
+    pub fn flags(mut self, flags: DistributionFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Equivalent to `{compressed, Level}`: `0` writes terms uncompressed, as
+    /// OTP does, and `1` to `9` are zlib compression levels, `9` being the
+    /// slowest and smallest. The compressed bytes need not match the ones OTP
+    /// writes at the same level, but each side reads the other's.
+    pub fn compressed(mut self, level: u8) -> Self {
+        self.compression_level = level;
+        self
+    }
+
+    /// Terms whose uncompressed encoding is smaller than `threshold` bytes
+    /// are always written uncompressed.
+    pub fn compression_threshold(mut self, threshold: usize) -> Self {
+        self.compression_threshold = threshold;
+        self
+    }
+
+    /// Equivalent to `deterministic`: map pairs are written sorted by key in
+    /// map key order, so equal maps always encode to the same bytes.
+    pub fn deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    pub fn get_compression_level(&self) -> u8 {
+        self.compression_level
+    }
+
+    pub fn get_compression_threshold(&self) -> usize {
+        self.compression_threshold
+    }
 
-pub trait TermVisitor {
-    type Return;
-    type Error;
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
 
-    fn visit_outer_term(&mut self, task: &mut Task, term: &Term) -> Result<TermVisitorHint, Self::Error>;
-    fn visit_inner_term(&mut self, task: &mut Task, parent: Option<&Term>, child: &Term, hint: InnerTermHint) -> TermVisitorHint;
-    fn visit_term_final(&mut self, task: &mut Task, term: &Term) -> TermVisitorHint;
-    fn return_final(&mut self, task: &mut Task) -> TaskState<(), Self::Return>;
+    pub fn get_flags(&self) -> DistributionFlags {
+        self.flags
+    }
 }
 
-pub trait ReductionCountingTask {
-    
+pub struct WriteContext<'a> {
+    pub process: &'a Process,
+    pub options: EncodeOptions,
 }
+impl<'a> WriteContext<'a> {
+    pub fn new(process: &'a Process) -> Self {
+        Self::with_options(process, EncodeOptions::default())
+    }
 
-pub struct ListEncoder {
-    task: &mut ReductionCountingTask,
-    list: &List,
-    iter: ListIterator,
+    pub fn with_options(process: &'a Process, options: EncodeOptions) -> Self {
+        Self { process, options }
+    }
+
+    pub async fn bump_all_reds(&self) {
+        self.process.bump_all_reds().await;
+    }
+
+    pub async fn bump_reds(&self, gc: isize) {
+        self.process.bump_reds(gc).await;
+    }
+}
+
+pub struct YieldableEncoder<W> {
+    writer: W,
+    atom_cache_refs: Option<AtomCacheRefBuilder>,
 }
+impl<W: Write> YieldableEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            atom_cache_refs: None,
+        }
+    }
+
+    /// Creates an encoder which writes the atoms in `atom_cache_refs` as
+    /// ATOM_CACHE_REF, for the body of a distribution message whose header
+    /// was written by [`YieldableEncoder::write_atom_cache_header`].
+    pub fn with_atom_cache_refs(writer: W, atom_cache_refs: AtomCacheRefBuilder) -> Self {
+        Self {
+            writer,
+            atom_cache_refs: Some(atom_cache_refs),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Returns the exact number of bytes
+    /// [`YieldableEncoder::write_internal_term`] would write for `term`,
+    /// including the atoms this encoder writes as ATOM_CACHE_REF.
+    pub fn internal_size(
+        &self,
+        options: &EncodeOptions,
+        term: &Term,
+    ) -> Result<usize, EncodeError> {
+        let counter = SizeCounter {
+            options,
+            atom_cache_refs: self.atom_cache_refs.as_ref(),
+        };
+        counter.internal_size(term)
+    }
+
+    /// Writes a distribution message as a DIST_HEADER followed by `control`
+    /// and `payload`, with the atoms of both looked up in `atom_cache`, the
+    /// output atom cache of the connection.
+    pub async fn write_dist_message(
+        &mut self,
+        ctx: &WriteContext<'_>,
+        atom_cache: &mut AtomCache,
+        control: &Term,
+        payload: Option<&Term>,
+    ) -> EncodeResult {
+        let terms: Vec<&Term> = std::iter::once(control).chain(payload).collect();
+        self.writer.write_u8(ext::VERSION_MAGIC)?;
+        self.writer.write_u8(ext::DIST_HEADER)?;
+        let atom_cache_refs = self
+            .write_atom_cache_header(ctx, atom_cache, &terms)
+            .await?;
+        let previous = self.atom_cache_refs.replace(atom_cache_refs);
+        let mut result = Ok(());
+        for term in terms {
+            result = self.write_internal_term(ctx, term).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        self.atom_cache_refs = previous;
+        result
+    }
+
+    /// Writes everything which follows the tag of a DIST_HEADER up to the
+    /// control message: the number of atom cache references, their flags and
+    /// the new or old entries for the atoms found in `terms`.
+    ///
+    /// Returns the references to use when writing `terms`, see
+    /// [`YieldableEncoder::with_atom_cache_refs`].
+    pub async fn write_atom_cache_header(
+        &mut self,
+        ctx: &WriteContext<'_>,
+        atom_cache: &mut AtomCache,
+        terms: &[&Term],
+    ) -> Result<AtomCacheRefBuilder, EncodeError> {
+        let atom_cache_refs = AtomCacheRefBuilder::new();
+        for term in terms {
+            aux::collect_atoms(ctx, &atom_cache_refs, term).await;
+        }
+        let header = atom_cache_refs.finalize(atom_cache);
+        let entries = &header.atom_cache_ref_entries;
+        self.writer.write_u8(entries.len() as u8)?;
+        if entries.is_empty() {
+            return Ok(atom_cache_refs);
+        }
+        // Each entry has a half byte of flags, starting from the least
+        // significant half of the first byte, followed by a half byte with
+        // the long atoms flag.
+        let mut flags = vec![0; entries.len() / 2 + 1];
+        let nibbles = entries
+            .iter()
+            .map(|entry| match entry {
+                AtomCacheRefEntry::New { index, .. } => 0b1000 | (index >> 8) as u8,
+                AtomCacheRefEntry::Old { index } => (index >> 8) as u8,
+            })
+            .chain(std::iter::once(u8::from(header.long_atoms)));
+        for (i, nibble) in nibbles.enumerate() {
+            flags[i / 2] |= nibble << ((i % 2) * 4);
+        }
+        self.writer.write_all(&flags)?;
+        for entry in entries.iter() {
+            match entry {
+                AtomCacheRefEntry::New { index, atom_text } => {
+                    self.writer.write_u8(*index as u8)?;
+                    if header.long_atoms {
+                        self.writer.write_u16::<BigEndian>(atom_text.len() as u16)?;
+                    } else {
+                        self.writer.write_u8(atom_text.len() as u8)?;
+                    }
+                    self.writer.write_all(atom_text)?;
+                }
+                AtomCacheRefEntry::Old { index } => {
+                    self.writer.write_u8(*index as u8)?;
+                }
+            }
+        }
+        Ok(atom_cache_refs)
+    }
+
+    pub async fn write_external_term(
+        &mut self,
+        ctx: &WriteContext<'_>,
+        term: &Term,
+    ) -> EncodeResult {
+        ctx.bump_reds(1).await;
+        let level = ctx.options.get_compression_level();
+        if level > 9 {
+            return Err(EncodeError::InvalidCompressionLevel { level });
+        }
+        if level == 0 {
+            self.writer.write_u8(ext::VERSION_MAGIC)?;
+            return self.write_internal_term(ctx, term).await;
+        }
+        let mut uncompressed = YieldableEncoder::new(Vec::new());
+        uncompressed.write_internal_term(ctx, term).await?;
+        let uncompressed = uncompressed.into_inner();
+        self.writer.write_u8(ext::VERSION_MAGIC)?;
+        if uncompressed.len() >= ctx.options.get_compression_threshold()
+            && self
+                .write_compressed_term(ctx, term, level, &uncompressed)
+                .await?
+        {
+            return Ok(());
+        }
+        self.write_bytes(ctx, &uncompressed).await
+    }
+
+    /// Writes `uncompressed` as a COMPRESSED term, unless doing so would not
+    /// make it any smaller. Returns whether the compressed form was written.
+    async fn write_compressed_term(
+        &mut self,
+        ctx: &WriteContext<'_>,
+        term: &Term,
+        level: u8,
+        uncompressed: &[u8],
+    ) -> Result<bool, EncodeError> {
+        let uncompressed_size =
+            aux::check_len(uncompressed.len(), u32::MAX as usize, || term.clone())?;
+        let zlib_encoder = ZlibEncoder::new(Vec::new(), Compression::new(u32::from(level)));
+        let mut compressor = YieldableEncoder::new(zlib_encoder);
+        compressor.write_bytes(ctx, uncompressed).await?;
+        let compressed = compressor.into_inner().finish()?;
+        if 5 + compressed.len() >= uncompressed.len() {
+            return Ok(false);
+        }
+        self.writer.write_u8(ext::COMPRESSED)?;
+        self.writer
+            .write_u32::<BigEndian>(uncompressed_size as u32)?;
+        self.write_bytes(ctx, &compressed).await?;
+        Ok(true)
+    }
+
+    pub async fn write_internal_term(
+        &mut self,
+        ctx: &WriteContext<'_>,
+        term: &Term,
+    ) -> EncodeResult {
+        ctx.bump_reds(1).await;
+        match term {
+            Term::Number(x) => self.write_number(ctx, x),
+            Term::Atom(x) => self.write_atom(ctx, x),
+            Term::Reference(x) => self.write_reference(ctx, x),
+            Term::Fun(x) => self.write_fun(ctx, x).await,
+            Term::Port(x) => self.write_port(ctx, x),
+            Term::Pid(x) => self.write_pid(ctx, x),
+            Term::Tuple(x) => self.write_tuple(ctx, x).await,
+            Term::Map(x) => self.write_map_ext(ctx, x).await,
+            Term::Nil(_) => self.write_nil_ext(ctx),
+            Term::List(x) => self.write_list(ctx, x).await,
+            Term::Bitstring(x) => self.write_bitstring(ctx, x).await,
+        }
+    }
+
+    fn write_number(&mut self, ctx: &WriteContext<'_>, number: &Number) -> EncodeResult {
+        match number {
+            Number::FixInteger(x) => self.write_integer(ctx, x.value),
+            Number::Bignum(x) => match i32::try_from(&x.value) {
+                Ok(value) => self.write_integer(ctx, value),
+                Err(_) => self.write_big(ctx, x),
+            },
+            Number::Float(x) if has_flag(&ctx.options, DistributionFlags::DFLAG_NEW_FLOATS) => {
+                self.write_new_float_ext(ctx, x.value)
+            }
+            Number::Float(x) => self.write_float_ext(ctx, x.value),
+        }
+    }
+
+    /// Writes an integer using the smallest of SMALL_INTEGER_EXT and INTEGER_EXT.
+    fn write_integer(&mut self, ctx: &WriteContext<'_>, value: i32) -> EncodeResult {
+        match u8::try_from(value) {
+            Ok(value) => self.write_small_integer_ext(ctx, value),
+            Err(_) => self.write_integer_ext(ctx, value),
+        }
+    }
+
+    fn write_small_integer_ext(&mut self, _ctx: &WriteContext<'_>, value: u8) -> EncodeResult {
+        self.writer.write_u8(ext::SMALL_INTEGER_EXT)?;
+        self.writer.write_u8(value)?;
+        Ok(())
+    }
+
+    fn write_integer_ext(&mut self, _ctx: &WriteContext<'_>, value: i32) -> EncodeResult {
+        self.writer.write_u8(ext::INTEGER_EXT)?;
+        self.writer.write_i32::<BigEndian>(value)?;
+        Ok(())
+    }
+
+    fn write_big(&mut self, _ctx: &WriteContext<'_>, bignum: &Bignum) -> EncodeResult {
+        let (sign, digits) = bignum.value.to_bytes_le();
+        let sign = aux::sign_to_byte(sign);
+        if let Ok(n) = u8::try_from(digits.len()) {
+            self.writer.write_u8(ext::SMALL_BIG_EXT)?;
+            self.writer.write_u8(n)?;
+        } else {
+            let n = aux::check_len(digits.len(), u32::MAX as usize, || {
+                Number::from(bignum.clone()).into()
+            })?;
+            self.writer.write_u8(ext::LARGE_BIG_EXT)?;
+            self.writer.write_u32::<BigEndian>(n as u32)?;
+        }
+        self.writer.write_u8(sign)?;
+        self.writer.write_all(&digits)?;
+        Ok(())
+    }
+
+    fn write_new_float_ext(&mut self, _ctx: &WriteContext<'_>, value: f64) -> EncodeResult {
+        self.writer.write_u8(ext::NEW_FLOAT_EXT)?;
+        self.writer.write_f64::<BigEndian>(value)?;
+        Ok(())
+    }
+
+    /// Writes a float as the 31-byte, NUL-padded `"%.20e"` string that
+    /// peers without DFLAG_NEW_FLOATS expect.
+    fn write_float_ext(&mut self, _ctx: &WriteContext<'_>, value: f64) -> EncodeResult {
+        let formatted = format!("{:.20e}", value);
+        let (mantissa, exponent) = formatted.split_once('e').unwrap_or((&formatted, "0"));
+        let exponent: i32 = exponent.parse().unwrap_or(0);
+        let sign = if exponent < 0 { '-' } else { '+' };
+        let text = format!("{}e{}{:02}", mantissa, sign, exponent.abs());
+        let mut buf = [0; 31];
+        buf[..text.len()].copy_from_slice(text.as_bytes());
+        self.writer.write_u8(ext::FLOAT_EXT)?;
+        self.writer.write_all(&buf)?;
+        Ok(())
+    }
+
+    /// Writes an atom as SMALL_ATOM_UTF8_EXT when its name fits in 255 bytes,
+    /// otherwise as ATOM_UTF8_EXT. Peers without DFLAG_UTF8_ATOMS get
+    /// Latin-1 ATOM_EXT, or SMALL_ATOM_EXT with DFLAG_SMALL_ATOM_TAGS.
+    fn write_atom(&mut self, ctx: &WriteContext<'_>, atom: &Atom) -> EncodeResult {
+        let atom_cache_ref = self
+            .atom_cache_refs
+            .as_ref()
+            .and_then(|atom_cache_refs| atom_cache_refs.get(atom));
+        if let Some(atom_cache_ref) = atom_cache_ref {
+            self.writer.write_u8(ext::ATOM_CACHE_REF)?;
+            self.writer.write_u8(atom_cache_ref.index)?;
+            return Ok(());
+        }
+        if !has_flag(&ctx.options, DistributionFlags::DFLAG_UTF8_ATOMS) {
+            return self.write_latin1_atom(ctx, atom);
+        }
+        let name = atom.name().as_bytes();
+        if let Ok(len) = u8::try_from(name.len()) {
+            self.writer.write_u8(ext::SMALL_ATOM_UTF8_EXT)?;
+            self.writer.write_u8(len)?;
+        } else {
+            let len = aux::check_len(name.len(), u16::MAX as usize, || atom.clone().into())?;
+            self.writer.write_u8(ext::ATOM_UTF8_EXT)?;
+            self.writer.write_u16::<BigEndian>(len as u16)?;
+        }
+        self.writer.write_all(name)?;
+        Ok(())
+    }
+
+    fn write_latin1_atom(&mut self, ctx: &WriteContext<'_>, atom: &Atom) -> EncodeResult {
+        let name = atom
+            .name()
+            .chars()
+            .map(u8::try_from)
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| EncodeError::UnsupportedByPeer {
+                value: atom.clone().into(),
+                flag: DistributionFlags::DFLAG_UTF8_ATOMS,
+            })?;
+        match u8::try_from(name.len()) {
+            Ok(len) if has_flag(&ctx.options, DistributionFlags::DFLAG_SMALL_ATOM_TAGS) => {
+                self.writer.write_u8(ext::SMALL_ATOM_EXT)?;
+                self.writer.write_u8(len)?;
+            }
+            _ => {
+                let len = aux::check_len(name.len(), u16::MAX as usize, || atom.clone().into())?;
+                self.writer.write_u8(ext::ATOM_EXT)?;
+                self.writer.write_u16::<BigEndian>(len as u16)?;
+            }
+        }
+        self.writer.write_all(&name)?;
+        Ok(())
+    }
+
+    /// Writes a reference as NEWER_REFERENCE_EXT, or as NEW_REFERENCE_EXT
+    /// for peers without DFLAG_BIG_CREATION.
+    fn write_reference(&mut self, ctx: &WriteContext<'_>, reference: &Reference) -> EncodeResult {
+        // Only node container version 4 allows more than three id words.
+        if reference.id.len() > 3 {
+            require_flag(&ctx.options, DistributionFlags::DFLAG_V4_NC, || {
+                reference.clone().into()
+            })?;
+        }
+        let len = aux::check_len(reference.id.len(), u16::MAX as usize, || {
+            reference.clone().into()
+        })?;
+        if has_flag(&ctx.options, DistributionFlags::DFLAG_BIG_CREATION) {
+            self.writer.write_u8(ext::NEWER_REFERENCE_EXT)?;
+            self.writer.write_u16::<BigEndian>(len as u16)?;
+            self.write_atom(ctx, &reference.node)?;
+            self.writer.write_u32::<BigEndian>(reference.creation)?;
+        } else {
+            let creation = small_creation(reference.creation, || reference.clone().into())?;
+            self.writer.write_u8(ext::NEW_REFERENCE_EXT)?;
+            self.writer.write_u16::<BigEndian>(len as u16)?;
+            self.write_atom(ctx, &reference.node)?;
+            self.writer.write_u8(creation)?;
+        }
+        for id in reference.id.iter() {
+            self.writer.write_u32::<BigEndian>(*id)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a port as NEW_PORT_EXT when its id fits in 32 bits, otherwise
+    /// as V4_PORT_EXT, just like the BEAM does. Peers without
+    /// DFLAG_BIG_CREATION get PORT_EXT, and ids that need V4_PORT_EXT fail
+    /// to encode for peers without DFLAG_V4_NC.
+    fn write_port(&mut self, ctx: &WriteContext<'_>, port: &Port) -> EncodeResult {
+        match u32::try_from(port.id) {
+            Ok(id) if has_flag(&ctx.options, DistributionFlags::DFLAG_BIG_CREATION) => {
+                self.write_new_port_ext(ctx, &port.node, id, port.creation)
+            }
+            Ok(id) => {
+                let creation = small_creation(port.creation, || port.clone().into())?;
+                self.writer.write_u8(ext::PORT_EXT)?;
+                self.write_atom(ctx, &port.node)?;
+                self.writer.write_u32::<BigEndian>(id)?;
+                self.writer.write_u8(creation)?;
+                Ok(())
+            }
+            Err(_) => {
+                require_flag(&ctx.options, DistributionFlags::DFLAG_V4_NC, || {
+                    port.clone().into()
+                })?;
+                self.write_v4_port_ext(ctx, port)
+            }
+        }
+    }
+
+    fn write_new_port_ext(
+        &mut self,
+        ctx: &WriteContext<'_>,
+        node: &Atom,
+        id: u32,
+        creation: u32,
+    ) -> EncodeResult {
+        self.writer.write_u8(ext::NEW_PORT_EXT)?;
+        self.write_atom(ctx, node)?;
+        self.writer.write_u32::<BigEndian>(id)?;
+        self.writer.write_u32::<BigEndian>(creation)?;
+        Ok(())
+    }
+
+    fn write_v4_port_ext(&mut self, ctx: &WriteContext<'_>, port: &Port) -> EncodeResult {
+        self.writer.write_u8(ext::V4_PORT_EXT)?;
+        self.write_atom(ctx, &port.node)?;
+        self.writer.write_u64::<BigEndian>(port.id)?;
+        self.writer.write_u32::<BigEndian>(port.creation)?;
+        Ok(())
+    }
+
+    /// Writes a pid as NEW_PID_EXT, or as PID_EXT for peers without
+    /// DFLAG_BIG_CREATION. Without DFLAG_V4_NC only 15 bits of the id and
+    /// 13 bits of the serial can be sent.
+    fn write_pid(&mut self, ctx: &WriteContext<'_>, pid: &Pid) -> EncodeResult {
+        if pid.id >= 1 << 15 || pid.serial >= 1 << 13 {
+            require_flag(&ctx.options, DistributionFlags::DFLAG_V4_NC, || {
+                pid.clone().into()
+            })?;
+        }
+        if has_flag(&ctx.options, DistributionFlags::DFLAG_BIG_CREATION) {
+            return self.write_new_pid_ext(ctx, pid);
+        }
+        let creation = small_creation(pid.creation, || pid.clone().into())?;
+        self.writer.write_u8(ext::PID_EXT)?;
+        self.write_atom(ctx, &pid.node)?;
+        self.writer.write_u32::<BigEndian>(pid.id)?;
+        self.writer.write_u32::<BigEndian>(pid.serial)?;
+        self.writer.write_u8(creation)?;
+        Ok(())
+    }
+
+    fn write_new_pid_ext(&mut self, ctx: &WriteContext<'_>, pid: &Pid) -> EncodeResult {
+        self.writer.write_u8(ext::NEW_PID_EXT)?;
+        self.write_atom(ctx, &pid.node)?;
+        self.writer.write_u32::<BigEndian>(pid.id)?;
+        self.writer.write_u32::<BigEndian>(pid.serial)?;
+        self.writer.write_u32::<BigEndian>(pid.creation)?;
+        Ok(())
+    }
+
+    #[async_recursion(?Send)]
+    async fn write_tuple(&mut self, ctx: &WriteContext<'_>, tuple: &Tuple) -> EncodeResult {
+        if let Ok(arity) = u8::try_from(tuple.elements.len()) {
+            self.writer.write_u8(ext::SMALL_TUPLE_EXT)?;
+            self.writer.write_u8(arity)?;
+        } else {
+            let arity = aux::check_len(tuple.elements.len(), u32::MAX as usize, || {
+                tuple.clone().into()
+            })?;
+            self.writer.write_u8(ext::LARGE_TUPLE_EXT)?;
+            self.writer.write_u32::<BigEndian>(arity as u32)?;
+        }
+        for element in tuple.elements.iter() {
+            self.write_internal_term(ctx, element).await?;
+        }
+        Ok(())
+    }
+
+    #[async_recursion(?Send)]
+    async fn write_map_ext(&mut self, ctx: &WriteContext<'_>, map: &Map) -> EncodeResult {
+        require_flag(&ctx.options, DistributionFlags::DFLAG_MAP_TAG, || {
+            map.clone().into()
+        })?;
+        let arity = aux::check_len(map.pairs.len(), u32::MAX as usize, || map.clone().into())?;
+        self.writer.write_u8(ext::MAP_EXT)?;
+        self.writer.write_u32::<BigEndian>(arity as u32)?;
+        if ctx.options.is_deterministic() {
+            for (key, val) in sorted_pairs(map) {
+                self.write_internal_term(ctx, key).await?;
+                self.write_internal_term(ctx, val).await?;
+            }
+        } else {
+            for (key, val) in map.pairs.iter() {
+                self.write_internal_term(ctx, key).await?;
+                self.write_internal_term(ctx, val).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_nil_ext(&mut self, _ctx: &WriteContext<'_>) -> EncodeResult {
+        self.writer.write_u8(ext::NIL_EXT)?;
+        Ok(())
+    }
+
+    /// Writes a list as STRING_EXT when it is a proper list of at most
+    /// 65535 bytes, otherwise as LIST_EXT.
+    #[async_recursion(?Send)]
+    async fn write_list(&mut self, ctx: &WriteContext<'_>, list: &List) -> EncodeResult {
+        if list.elements.is_empty() {
+            self.write_internal_term(ctx, &list.tail).await
+        } else if let Some(bytes) = aux::list_to_string_bytes(list) {
+            self.write_string_ext(ctx, &bytes).await
+        } else {
+            self.write_list_ext(ctx, list).await
+        }
+    }
+
+    async fn write_string_ext(&mut self, ctx: &WriteContext<'_>, bytes: &[u8]) -> EncodeResult {
+        self.writer.write_u8(ext::STRING_EXT)?;
+        self.writer.write_u16::<BigEndian>(bytes.len() as u16)?;
+        self.write_bytes(ctx, bytes).await
+    }
+
+    async fn write_list_ext(&mut self, ctx: &WriteContext<'_>, list: &List) -> EncodeResult {
+        let len = aux::check_len(list.elements.len(), u32::MAX as usize, || {
+            list.clone().into()
+        })?;
+        self.writer.write_u8(ext::LIST_EXT)?;
+        self.writer.write_u32::<BigEndian>(len as u32)?;
+        for element in list.elements.iter() {
+            self.write_internal_term(ctx, element).await?;
+        }
+        self.write_internal_term(ctx, &list.tail).await
+    }
+
+    async fn write_bitstring(
+        &mut self,
+        ctx: &WriteContext<'_>,
+        bitstring: &Bitstring,
+    ) -> EncodeResult {
+        let len = aux::check_len(bitstring.data.len(), u32::MAX as usize, || {
+            bitstring.clone().into()
+        })?;
+        if bitstring.is_binary() {
+            self.writer.write_u8(ext::BINARY_EXT)?;
+            self.writer.write_u32::<BigEndian>(len as u32)?;
+            self.write_bytes(ctx, &bitstring.data).await?;
+        } else if !has_flag(&ctx.options, DistributionFlags::DFLAG_BIT_BINARIES) {
+            // Peers without bit binaries get `{Binary, Bits}`, with the bits
+            // in the last byte left-aligned.
+            let tail_bits = bitstring.bits % 8;
+            let mut data = bitstring.data.to_vec();
+            if let Some(last) = data.last_mut() {
+                *last <<= 8 - tail_bits;
+            }
+            self.writer.write_u8(ext::SMALL_TUPLE_EXT)?;
+            self.writer.write_u8(2)?;
+            self.writer.write_u8(ext::BINARY_EXT)?;
+            self.writer.write_u32::<BigEndian>(len as u32)?;
+            self.write_bytes(ctx, &data).await?;
+            self.write_small_integer_ext(ctx, tail_bits)?;
+        } else {
+            // The decoder keeps the trailing bits right-aligned in the last
+            // byte, whereas the wire format expects them left-aligned.
+            let tail_bits = bitstring.bits % 8;
+            let (last, init) =
+                bitstring
+                    .data
+                    .split_last()
+                    .ok_or_else(|| EncodeError::UnsupportedTerm {
+                        value: bitstring.clone().into(),
+                    })?;
+            self.writer.write_u8(ext::BIT_BINARY_EXT)?;
+            self.writer.write_u32::<BigEndian>(len as u32)?;
+            self.writer.write_u8(tail_bits)?;
+            self.write_bytes(ctx, init).await?;
+            self.writer.write_u8(last << (8 - tail_bits))?;
+        }
+        Ok(())
+    }
+
+    /// Writes raw bytes, bumping reductions once per block so that large
+    /// binaries and strings can be split across timeslices.
+    async fn write_bytes(&mut self, ctx: &WriteContext<'_>, bytes: &[u8]) -> EncodeResult {
+        for (i, block) in bytes.chunks(BYTES_PER_REDUCTION_BLOCK).enumerate() {
+            if i > 0 {
+                ctx.bump_reds(5).await;
+            }
+            self.writer.write_all(block)?;
+        }
+        Ok(())
+    }
+
+    async fn write_fun(&mut self, ctx: &WriteContext<'_>, fun: &Fun) -> EncodeResult {
+        match fun {
+            Fun::ExternalFun(x) => self.write_export_ext(ctx, x),
+            Fun::InternalFun(x @ InternalFun::New { .. }) => self.write_new_fun_ext(ctx, x).await,
+            Fun::InternalFun(x @ InternalFun::Old { .. }) => self.write_fun_ext(ctx, x).await,
+        }
+    }
+
+    /// Writes an external fun as EXPORT_EXT, or as the tuple
+    /// `{Module, Function, Arity}` for peers without DFLAG_EXPORT_PTR_TAG.
+    fn write_export_ext(&mut self, ctx: &WriteContext<'_>, fun: &ExternalFun) -> EncodeResult {
+        if !has_flag(&ctx.options, DistributionFlags::DFLAG_EXPORT_PTR_TAG) {
+            self.writer.write_u8(ext::SMALL_TUPLE_EXT)?;
+            self.writer.write_u8(3)?;
+        } else {
+            self.writer.write_u8(ext::EXPORT_EXT)?;
+        }
+        self.write_atom(ctx, &fun.module)?;
+        self.write_atom(ctx, &fun.function)?;
+        self.write_small_integer_ext(ctx, fun.arity)
+    }
 
-pub struct StringEncoder {
+    #[async_recursion(?Send)]
+    async fn write_new_fun_ext(
+        &mut self,
+        ctx: &WriteContext<'_>,
+        fun: &InternalFun,
+    ) -> EncodeResult {
+        if let InternalFun::New {
+            module,
+            arity,
+            pid,
+            free_vars,
+            index,
+            uniq,
+            old_index,
+            old_uniq,
+        } = fun
+        {
+            require_flag(&ctx.options, DistributionFlags::DFLAG_NEW_FUN_TAGS, || {
+                Fun::from(fun.clone()).into()
+            })?;
+            let num_free = aux::check_len(free_vars.len(), u32::MAX as usize, || {
+                Fun::from(fun.clone()).into()
+            })?;
+            // The size field covers the whole encoding, including itself, so
+            // the body has to be written out before it can be known.
+            let mut body = YieldableEncoder {
+                writer: Vec::new(),
+                atom_cache_refs: self.atom_cache_refs.clone(),
+            };
+            body.writer.write_u8(*arity)?;
+            body.writer.write_all(uniq)?;
+            body.writer.write_u32::<BigEndian>(*index)?;
+            body.writer.write_u32::<BigEndian>(num_free as u32)?;
+            body.write_atom(ctx, module)?;
+            body.write_integer(ctx, *old_index)?;
+            body.write_integer(ctx, *old_uniq)?;
+            body.write_pid(ctx, pid)?;
+            for free_var in free_vars.iter() {
+                body.write_internal_term(ctx, free_var).await?;
+            }
+            let body = body.into_inner();
+            let size = aux::check_len(body.len() + 4, u32::MAX as usize, || {
+                Fun::from(fun.clone()).into()
+            })?;
+            self.writer.write_u8(ext::NEW_FUN_EXT)?;
+            self.writer.write_u32::<BigEndian>(size as u32)?;
+            self.writer.write_all(&body)?;
+        }
+        Ok(())
+    }
 
+    #[async_recursion(?Send)]
+    async fn write_fun_ext(&mut self, ctx: &WriteContext<'_>, fun: &InternalFun) -> EncodeResult {
+        if let InternalFun::Old {
+            module,
+            pid,
+            free_vars,
+            index,
+            uniq,
+        } = fun
+        {
+            let num_free = aux::check_len(free_vars.len(), u32::MAX as usize, || {
+                Fun::from(fun.clone()).into()
+            })?;
+            self.writer.write_u8(ext::FUN_EXT)?;
+            self.writer.write_u32::<BigEndian>(num_free as u32)?;
+            self.write_pid(ctx, pid)?;
+            self.write_atom(ctx, module)?;
+            self.write_integer(ctx, *index)?;
+            self.write_integer(ctx, *uniq)?;
+            for free_var in free_vars.iter() {
+                self.write_internal_term(ctx, free_var).await?;
+            }
+        }
+        Ok(())
+    }
 }
 
-pub trait Encoder: Task {
-    type Ok;
-    type Error: std::error::Error;
+fn has_flag(options: &EncodeOptions, flag: DistributionFlags) -> bool {
+    options.get_flags().contains(flag)
+}
+
+fn require_flag<F>(options: &EncodeOptions, flag: DistributionFlags, value: F) -> EncodeResult
+where
+    F: FnOnce() -> Term,
+{
+    if has_flag(options, flag) {
+        Ok(())
+    } else {
+        Err(EncodeError::UnsupportedByPeer {
+            value: value(),
+            flag,
+        })
+    }
+}
+
+/// Peers without DFLAG_BIG_CREATION only take a one-byte creation.
+fn small_creation<F>(creation: u32, value: F) -> Result<u8, EncodeError>
+where
+    F: FnOnce() -> Term,
+{
+    u8::try_from(creation).map_err(|_| EncodeError::UnsupportedByPeer {
+        value: value(),
+        flag: DistributionFlags::DFLAG_BIG_CREATION,
+    })
+}
+
+/// Returns the exact number of bytes
+/// [`YieldableEncoder::write_external_term`] would write for `term`, without
+/// encoding it, or the error encoding it would fail with.
+///
+/// With compression enabled this is the size of the uncompressed encoding,
+/// which the encoder only replaces with a compressed one when that is smaller.
+pub fn external_size(term: &Term, options: &EncodeOptions) -> Result<usize, EncodeError> {
+    let level = options.get_compression_level();
+    if level > 9 {
+        return Err(EncodeError::InvalidCompressionLevel { level });
+    }
+    let counter = SizeCounter {
+        options,
+        atom_cache_refs: None,
+    };
+    Ok(1 + counter.internal_size(term)?)
+}
+
+/// Adds up what the `write_*` methods of [`YieldableEncoder`] would write,
+/// picking the same wire forms and failing with the same errors.
+struct SizeCounter<'a> {
+    options: &'a EncodeOptions,
+    atom_cache_refs: Option<&'a AtomCacheRefBuilder>,
+}
+impl SizeCounter<'_> {
+    /// Sizes are additive, so terms are visited with an explicit stack
+    /// rather than by recursion, which keeps deeply nested terms off the
+    /// call stack.
+    fn internal_size(&self, term: &Term) -> Result<usize, EncodeError> {
+        let mut size = 0;
+        let mut stack = vec![term];
+        while let Some(term) = stack.pop() {
+            size += self.shallow_size(term, &mut stack)?;
+        }
+        Ok(size)
+    }
+
+    /// Returns the size of `term` without its elements, which are pushed on
+    /// `stack` in reverse so that they are visited in encoding order.
+    fn shallow_size<'t>(
+        &self,
+        term: &'t Term,
+        stack: &mut Vec<&'t Term>,
+    ) -> Result<usize, EncodeError> {
+        match term {
+            Term::Number(x) => self.number_size(x),
+            Term::Atom(x) => self.atom_size(x),
+            Term::Reference(x) => self.reference_size(x),
+            Term::Fun(x) => self.fun_size(x, stack),
+            Term::Port(x) => self.port_size(x),
+            Term::Pid(x) => self.pid_size(x),
+            Term::Tuple(x) => {
+                stack.extend(x.elements.iter().rev());
+                if x.elements.len() <= u8::MAX as usize {
+                    return Ok(2);
+                }
+                aux::check_len(x.elements.len(), u32::MAX as usize, || x.clone().into())?;
+                Ok(5)
+            }
+            Term::Map(x) => {
+                require_flag(self.options, DistributionFlags::DFLAG_MAP_TAG, || {
+                    x.clone().into()
+                })?;
+                aux::check_len(x.pairs.len(), u32::MAX as usize, || x.clone().into())?;
+                for (key, val) in x.pairs.iter().rev() {
+                    stack.push(val);
+                    stack.push(key);
+                }
+                Ok(5)
+            }
+            Term::Nil(_) => Ok(1),
+            Term::List(x) => {
+                if x.elements.is_empty() {
+                    stack.push(&x.tail);
+                    Ok(0)
+                } else if let Some(bytes) = aux::list_to_string_bytes(x) {
+                    Ok(3 + bytes.len())
+                } else {
+                    aux::check_len(x.elements.len(), u32::MAX as usize, || x.clone().into())?;
+                    stack.push(&x.tail);
+                    stack.extend(x.elements.iter().rev());
+                    Ok(5)
+                }
+            }
+            Term::Bitstring(x) => self.bitstring_size(x),
+        }
+    }
+
+    fn number_size(&self, number: &Number) -> Result<usize, EncodeError> {
+        match number {
+            Number::FixInteger(x) => Ok(integer_size(x.value)),
+            Number::Bignum(x) => match i32::try_from(&x.value) {
+                Ok(value) => Ok(integer_size(value)),
+                Err(_) => {
+                    let n = x.value.bits().div_ceil(8) as usize;
+                    if n <= u8::MAX as usize {
+                        return Ok(3 + n);
+                    }
+                    aux::check_len(n, u32::MAX as usize, || number.clone().into())?;
+                    Ok(6 + n)
+                }
+            },
+            Number::Float(_) if has_flag(self.options, DistributionFlags::DFLAG_NEW_FLOATS) => {
+                Ok(9)
+            }
+            Number::Float(_) => Ok(32),
+        }
+    }
+
+    fn atom_size(&self, atom: &Atom) -> Result<usize, EncodeError> {
+        let cached = self
+            .atom_cache_refs
+            .is_some_and(|atom_cache_refs| atom_cache_refs.get(atom).is_some());
+        if cached {
+            return Ok(2);
+        }
+        let (len, small_tags) = if has_flag(self.options, DistributionFlags::DFLAG_UTF8_ATOMS) {
+            (atom.name().len(), true)
+        } else if atom.name().chars().all(|c| u8::try_from(c).is_ok()) {
+            // Latin-1 names take one byte per character.
+            let small_tags = has_flag(self.options, DistributionFlags::DFLAG_SMALL_ATOM_TAGS);
+            (atom.name().chars().count(), small_tags)
+        } else {
+            return Err(EncodeError::UnsupportedByPeer {
+                value: atom.clone().into(),
+                flag: DistributionFlags::DFLAG_UTF8_ATOMS,
+            });
+        };
+        if small_tags && len <= u8::MAX as usize {
+            return Ok(2 + len);
+        }
+        aux::check_len(len, u16::MAX as usize, || atom.clone().into())?;
+        Ok(3 + len)
+    }
+
+    fn reference_size(&self, reference: &Reference) -> Result<usize, EncodeError> {
+        if reference.id.len() > 3 {
+            require_flag(self.options, DistributionFlags::DFLAG_V4_NC, || {
+                reference.clone().into()
+            })?;
+        }
+        let len = aux::check_len(reference.id.len(), u16::MAX as usize, || {
+            reference.clone().into()
+        })?;
+        let creation = if has_flag(self.options, DistributionFlags::DFLAG_BIG_CREATION) {
+            4
+        } else {
+            small_creation(reference.creation, || reference.clone().into())?;
+            1
+        };
+        Ok(3 + self.atom_size(&reference.node)? + creation + 4 * len)
+    }
 
-    type Result: std::result::Result<Self::Ok, Self::Error>;
+    fn port_size(&self, port: &Port) -> Result<usize, EncodeError> {
+        let id_and_creation = match u32::try_from(port.id) {
+            Ok(_) if has_flag(self.options, DistributionFlags::DFLAG_BIG_CREATION) => 8,
+            Ok(_) => {
+                small_creation(port.creation, || port.clone().into())?;
+                5
+            }
+            Err(_) => {
+                require_flag(self.options, DistributionFlags::DFLAG_V4_NC, || {
+                    port.clone().into()
+                })?;
+                12
+            }
+        };
+        Ok(1 + self.atom_size(&port.node)? + id_and_creation)
+    }
+
+    fn pid_size(&self, pid: &Pid) -> Result<usize, EncodeError> {
+        if pid.id >= 1 << 15 || pid.serial >= 1 << 13 {
+            require_flag(self.options, DistributionFlags::DFLAG_V4_NC, || {
+                pid.clone().into()
+            })?;
+        }
+        let creation = if has_flag(self.options, DistributionFlags::DFLAG_BIG_CREATION) {
+            4
+        } else {
+            small_creation(pid.creation, || pid.clone().into())?;
+            1
+        };
+        Ok(9 + self.atom_size(&pid.node)? + creation)
+    }
+
+    fn bitstring_size(&self, bitstring: &Bitstring) -> Result<usize, EncodeError> {
+        let len = aux::check_len(bitstring.data.len(), u32::MAX as usize, || {
+            bitstring.clone().into()
+        })?;
+        if bitstring.is_binary() {
+            Ok(5 + len)
+        } else if !has_flag(self.options, DistributionFlags::DFLAG_BIT_BINARIES) {
+            // A SMALL_TUPLE_EXT of a BINARY_EXT and a SMALL_INTEGER_EXT.
+            Ok(2 + 5 + len + 2)
+        } else if len == 0 {
+            Err(EncodeError::UnsupportedTerm {
+                value: bitstring.clone().into(),
+            })
+        } else {
+            Ok(6 + len)
+        }
+    }
+
+    fn fun_size<'t>(&self, fun: &'t Fun, stack: &mut Vec<&'t Term>) -> Result<usize, EncodeError> {
+        let internal = match fun {
+            Fun::ExternalFun(x) => {
+                let tag = if has_flag(self.options, DistributionFlags::DFLAG_EXPORT_PTR_TAG) {
+                    1
+                } else {
+                    2
+                };
+                return Ok(tag + self.atom_size(&x.module)? + self.atom_size(&x.function)? + 2);
+            }
+            Fun::InternalFun(x) => x,
+        };
+        let to_term = || Fun::from(internal.clone()).into();
+        match internal {
+            InternalFun::New {
+                module,
+                pid,
+                free_vars,
+                old_index,
+                old_uniq,
+                ..
+            } => {
+                require_flag(self.options, DistributionFlags::DFLAG_NEW_FUN_TAGS, to_term)?;
+                aux::check_len(free_vars.len(), u32::MAX as usize, to_term)?;
+                // The size field is checked against the whole body, so the
+                // free variables are sized here rather than on the stack.
+                let mut body = 1 + 16 + 4 + 4;
+                body += self.atom_size(module)?;
+                body += integer_size(*old_index) + integer_size(*old_uniq);
+                body += self.pid_size(pid)?;
+                for free_var in free_vars.iter() {
+                    body += self.internal_size(free_var)?;
+                }
+                aux::check_len(body + 4, u32::MAX as usize, to_term)?;
+                Ok(1 + 4 + body)
+            }
+            InternalFun::Old {
+                module,
+                pid,
+                free_vars,
+                index,
+                uniq,
+            } => {
+                aux::check_len(free_vars.len(), u32::MAX as usize, to_term)?;
+                stack.extend(free_vars.iter().rev());
+                Ok(1 + 4
+                    + self.pid_size(pid)?
+                    + self.atom_size(module)?
+                    + integer_size(*index)
+                    + integer_size(*uniq))
+            }
+        }
+    }
+}
+
+/// The size of an integer written by [`YieldableEncoder::write_integer`].
+fn integer_size(value: i32) -> usize {
+    if u8::try_from(value).is_ok() {
+        2
+    } else {
+        5
+    }
+}
+
+mod aux {
+    use super::{async_recursion, WriteContext};
+    use crate::term::{AtomCacheRefBuilder, Fun, InternalFun, List, Number, Sign, Term};
+
+    /// Inserts every atom which will be written for `term` into
+    /// `atom_cache_refs`, including the node names of pids, ports and
+    /// references.
+    #[async_recursion(?Send)]
+    pub async fn collect_atoms(
+        ctx: &WriteContext<'_>,
+        atom_cache_refs: &AtomCacheRefBuilder,
+        term: &Term,
+    ) {
+        ctx.bump_reds(1).await;
+        match term {
+            Term::Atom(x) => {
+                atom_cache_refs.insert(x);
+            }
+            Term::Reference(x) => {
+                atom_cache_refs.insert(&x.node);
+            }
+            Term::Port(x) => {
+                atom_cache_refs.insert(&x.node);
+            }
+            Term::Pid(x) => {
+                atom_cache_refs.insert(&x.node);
+            }
+            Term::Fun(Fun::ExternalFun(x)) => {
+                atom_cache_refs.insert(&x.module);
+                atom_cache_refs.insert(&x.function);
+            }
+            Term::Fun(Fun::InternalFun(
+                InternalFun::New {
+                    module,
+                    pid,
+                    free_vars,
+                    ..
+                }
+                | InternalFun::Old {
+                    module,
+                    pid,
+                    free_vars,
+                    ..
+                },
+            )) => {
+                atom_cache_refs.insert(module);
+                atom_cache_refs.insert(&pid.node);
+                for free_var in free_vars.iter() {
+                    collect_atoms(ctx, atom_cache_refs, free_var).await;
+                }
+            }
+            Term::Tuple(x) => {
+                for element in x.elements.iter() {
+                    collect_atoms(ctx, atom_cache_refs, element).await;
+                }
+            }
+            Term::Map(x) => {
+                for (key, val) in x.pairs.iter() {
+                    collect_atoms(ctx, atom_cache_refs, key).await;
+                    collect_atoms(ctx, atom_cache_refs, val).await;
+                }
+            }
+            Term::List(x) => {
+                for element in x.elements.iter() {
+                    collect_atoms(ctx, atom_cache_refs, element).await;
+                }
+                collect_atoms(ctx, atom_cache_refs, &x.tail).await;
+            }
+            Term::Number(_) | Term::Nil(_) | Term::Bitstring(_) => {}
+        }
+    }
+
+    pub fn sign_to_byte(sign: Sign) -> u8 {
+        match sign {
+            Sign::Minus => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn check_len<F>(len: usize, max: usize, value: F) -> Result<usize, super::EncodeError>
+    where
+        F: FnOnce() -> Term,
+    {
+        if len <= max {
+            Ok(len)
+        } else {
+            Err(super::EncodeError::TooLarge {
+                value: value(),
+                len,
+                max,
+            })
+        }
+    }
+
+    /// Returns the bytes of `list` if it can be written as a STRING_EXT.
+    pub fn list_to_string_bytes(list: &List) -> Option<Vec<u8>> {
+        if list.is_improper_list() || list.elements.len() > u16::MAX as usize {
+            return None;
+        }
+        list.elements
+            .iter()
+            .map(|element| match element {
+                Term::Number(Number::FixInteger(x)) => u8::try_from(x.value).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+}
 
-    fn encode_list(&mut self, list: &List) -> Result;
-    fn encode_list_element(&mut self, list: &List, element: &Term) -> Result;
-    fn encode_list_tail(&mut self, list: &List, tail: &Term) -> Result;
-    fn encode_string(&mut self, list: &List) -> Result;
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decoder::ReadContext;
+    use crate::dist::AtomCache;
+    use crate::test_util::{atom, decode, encode, encode_with, encode_with_options, int};
+    use crate::{pin_mut, Cassette, Process};
+
+    fn encode_dist(
+        output_cache: &mut crate::term::AtomCache,
+        control: &Term,
+        payload: Option<&Term>,
+    ) -> Vec<u8> {
+        let process = Process::blocking();
+        let ctx = WriteContext::new(&process);
+        let mut encoder = YieldableEncoder::new(Vec::new());
+        {
+            let future = encoder.write_dist_message(&ctx, output_cache, control, payload);
+            pin_mut!(future);
+            Cassette::new(future).block_on().unwrap();
+        }
+        encoder.into_inner()
+    }
+
+    fn decode_dist(atom_cache: &AtomCache, buf: &[u8]) -> (Term, Option<Term>) {
+        let process = Process::blocking();
+        let ctx = ReadContext::new(&process, atom_cache);
+        let future = crate::dist::DistMessage::decode(&ctx, buf);
+        pin_mut!(future);
+        let message = Cassette::new(future).block_on().unwrap();
+        (message.control, message.payload)
+    }
+
+    #[test]
+    fn it_encodes_like_term_to_binary() {
+        assert_eq!(encode(&int(1)), vec![131, 97, 1]);
+        assert_eq!(encode(&int(256)), vec![131, 98, 0, 0, 1, 0]);
+        assert_eq!(encode(&int(-1)), vec![131, 98, 255, 255, 255, 255]);
+        let big = Term::from(Number::from(Bignum::from(1u64 << 40)));
+        assert_eq!(encode(&big), vec![131, 110, 6, 0, 0, 0, 0, 0, 0, 1]);
+        let float = Term::from(Number::from(Float::try_from(1.5).unwrap()));
+        assert_eq!(encode(&float), vec![131, 70, 63, 248, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(encode(&atom("ok")), vec![131, 119, 2, 111, 107]);
+        assert_eq!(encode(&Term::from(Tuple::empty())), vec![131, 104, 0]);
+        assert_eq!(encode(&Term::from(Nil)), vec![131, 106]);
+        let string = Term::from(List::from(vec![int(97), int(98), int(99)]));
+        assert_eq!(encode(&string), vec![131, 107, 0, 3, 97, 98, 99]);
+        let improper = Term::from(List::from((vec![atom("a")], atom("b"))));
+        assert_eq!(
+            encode(&improper),
+            vec![131, 108, 0, 0, 0, 1, 119, 1, 97, 119, 1, 98]
+        );
+        let map = Term::from(Map::from(vec![(atom("a"), int(1))]));
+        assert_eq!(encode(&map), vec![131, 116, 0, 0, 0, 1, 119, 1, 97, 97, 1]);
+        let binary = Term::from(Bitstring::from(vec![1, 2]));
+        assert_eq!(encode(&binary), vec![131, 109, 0, 0, 0, 2, 1, 2]);
+        let bits = Term::from(Bitstring::from((vec![1], 3)));
+        assert_eq!(encode(&bits), vec![131, 77, 0, 0, 0, 1, 3, 32]);
+        let export = Term::from(Fun::from(ExternalFun::from(("erlang", "self", 0))));
+        assert_eq!(encode(&export), b"\x83qw\x06erlangw\x04selfa\x00".to_vec());
+    }
+
+    #[test]
+    fn it_chooses_atom_ext_by_length() {
+        let long_name = "a".repeat(256);
+        let buf = encode(&atom(&long_name));
+        assert_eq!(&buf[..4], &[131, 118, 1, 0]);
+        assert_eq!(decode(&buf), atom(&long_name));
+    }
+
+    fn every_term() -> Vec<Term> {
+        let pid = Pid::new("nonode@nohost", 79, 0, 1660000000);
+        vec![
+            int(i32::MIN),
+            Term::from(Number::from(Bignum::from(-(1i128 << 100)))),
+            Term::from(Number::from(Float::try_from(-0.25).unwrap())),
+            atom("Ω"),
+            Term::from(Reference::new("nonode@nohost", vec![1, 2, 3], 1660000000)),
+            Term::from(Port::new("nonode@nohost", 5, 1660000000)),
+            Term::from(Port::new("nonode@nohost", 1 << 40, 1660000000)),
+            Term::from(pid.clone()),
+            Term::from(Tuple::from((0..300).map(int).collect::<Vec<_>>())),
+            Term::from(List::from(vec![int(1000), atom("x")])),
+            Term::from(List::from((vec![int(1)], int(2)))),
+            Term::from(Map::from(vec![(atom("k"), Term::from(Nil))])),
+            Term::from(Bitstring::from((vec![1, 2, 5], 3))),
+            Term::from(Fun::from(InternalFun::New {
+                module: Atom::from("erl_eval"),
+                arity: 2,
+                pid: pid.clone(),
+                free_vars: vec![atom("free"), int(7)],
+                index: 45,
+                uniq: [7; 16],
+                old_index: 45,
+                old_uniq: 82050010,
+            })),
+            Term::from(Fun::from(InternalFun::Old {
+                module: Atom::from("erl_eval"),
+                pid,
+                free_vars: vec![int(1)],
+                index: 3,
+                uniq: -12345,
+            })),
+        ]
+    }
+
+    #[test]
+    fn it_round_trips_every_term() {
+        for term in every_term() {
+            assert_eq!(decode(&encode(&term)), term);
+        }
+    }
+
+    #[test]
+    fn it_computes_external_sizes_without_encoding() {
+        let process = Process::blocking();
+        let mut terms = every_term();
+        terms.extend([
+            atom(&"a".repeat(300)),
+            atom("caf\u{e9}"),
+            Term::from(List::from(vec![int(104), int(105)])),
+            Term::from(List::from((vec![], int(1)))),
+            Term::from(Fun::from(ExternalFun {
+                module: Atom::from("lists"),
+                function: Atom::from("map"),
+                arity: 2,
+            })),
+            Term::from(Pid::new("a", 1, 2, 3)),
+        ]);
+        let all = DistributionFlags::DFLAG_DIST_MANDATORY;
+        let old_peer = all
+            - DistributionFlags::DFLAG_UTF8_ATOMS
+            - DistributionFlags::DFLAG_BIG_CREATION
+            - DistributionFlags::DFLAG_EXPORT_PTR_TAG
+            - DistributionFlags::DFLAG_NEW_FLOATS
+            - DistributionFlags::DFLAG_BIT_BINARIES;
+        for flags in [
+            all,
+            all | DistributionFlags::DFLAG_SMALL_ATOM_TAGS,
+            old_peer,
+        ] {
+            let options = EncodeOptions::new().flags(flags);
+            for term in terms.iter() {
+                let encoded = encode_with_options(&process, options.clone(), term);
+                let size = external_size(term, &options);
+                match (encoded, size) {
+                    (Ok((buf, _)), Ok(size)) => assert_eq!(size, buf.len(), "{}", term),
+                    (Err(_), Err(_)) => {}
+                    (encoded, size) => panic!("{}: {:?} and {:?}", term, encoded, size),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_counts_atom_cache_refs_in_internal_sizes() {
+        let process = Process::blocking();
+        let ctx = WriteContext::new(&process);
+        let atom_cache_refs = AtomCacheRefBuilder::new();
+        atom_cache_refs.insert(&Atom::from("foo"));
+        let term = Term::from(Tuple::from(vec![atom("foo"), atom("bar")]));
+        let mut encoder = YieldableEncoder::with_atom_cache_refs(Vec::new(), atom_cache_refs);
+        let size = encoder.internal_size(&ctx.options, &term).unwrap();
+        {
+            let future = encoder.write_internal_term(&ctx, &term);
+            pin_mut!(future);
+            Cassette::new(future).block_on().unwrap();
+        }
+        assert_eq!(size, encoder.into_inner().len());
+        assert_eq!(size, 2 + 2 + 5);
+    }
+
+    #[test]
+    fn it_writes_atom_cache_refs_in_dist_messages() {
+        let mut output_cache = crate::term::AtomCache::new();
+        let atom_cache = AtomCache::new();
+        let pid = Term::from(Pid::new("nonode@nohost", 1, 2, 3));
+        let control = Term::from(Tuple::from(vec![atom("foo"), pid]));
+        let payload = Term::from(List::from(vec![atom("foo"), atom("bar")]));
+        let buf = encode_dist(&mut output_cache, &control, Some(&payload));
+        assert_eq!(buf[..3], [131, ext::DIST_HEADER, 3]);
+        assert_eq!(buf.iter().filter(|&&b| b == ext::ATOM_CACHE_REF).count(), 4);
+        assert_eq!(
+            decode_dist(&atom_cache, &buf),
+            (control.clone(), Some(payload.clone()))
+        );
+
+        // The second time around every entry is old, so no atom text is sent.
+        let again = encode_dist(&mut output_cache, &control, Some(&payload));
+        assert!(again.len() < buf.len());
+        assert!(!again.windows(3).any(|w| w == b"foo"));
+        assert_eq!(decode_dist(&atom_cache, &again), (control, Some(payload)));
+    }
+
+    #[test]
+    fn it_writes_long_atoms_in_dist_headers() {
+        let mut output_cache = crate::term::AtomCache::new();
+        let atom_cache = AtomCache::new();
+        let control = atom(&"a".repeat(300));
+        let buf = encode_dist(&mut output_cache, &control, None);
+        assert_eq!(decode_dist(&atom_cache, &buf), (control, None));
+    }
+
+    #[test]
+    fn it_yields_on_large_lists() {
+        let list = Term::from(List::from(
+            (0..20_000).map(|x| int(x + 1000)).collect::<Vec<_>>(),
+        ));
+        let expected = encode(&list);
+        let process = Process::yielding();
+        let (buf, yields) = encode_with(&process, &list);
+        assert_eq!(buf, expected);
+        assert!(yields >= 4);
+        assert!((1..=100).contains(&process.get_timeslice_pct()));
+    }
+
+    #[test]
+    fn it_yields_on_large_binaries() {
+        let binary = Term::from(Bitstring::from(vec![7; 8 * 1024 * 1024]));
+        let expected = encode(&binary);
+        let (buf, yields) = encode_with(&Process::yielding(), &binary);
+        assert_eq!(buf, expected);
+        assert!(yields > 0);
+    }
+
+    #[test]
+    fn it_compresses_above_the_threshold() {
+        let list = Term::from(List::from(vec![atom("repeat"); 1000]));
+        let uncompressed = encode(&list);
+        let options = EncodeOptions::new().compressed(6);
+        let (buf, _) = encode_with_options(&Process::blocking(), options, &list).unwrap();
+        assert_eq!(&buf[..2], &[131, 80]);
+        let size = u32::from_be_bytes([buf[2], buf[3], buf[4], buf[5]]) as usize;
+        assert_eq!(size, uncompressed.len() - 1);
+        assert!(buf.len() < uncompressed.len());
+        assert_eq!(decode(&buf), list);
+
+        let options = EncodeOptions::new()
+            .compressed(9)
+            .compression_threshold(uncompressed.len());
+        let (buf, _) = encode_with_options(&Process::blocking(), options, &list).unwrap();
+        assert_eq!(buf, uncompressed);
+
+        let options = EncodeOptions::new().compressed(0);
+        let (buf, _) = encode_with_options(&Process::blocking(), options, &list).unwrap();
+        assert_eq!(buf, uncompressed);
+    }
+
+    #[test]
+    fn it_uses_zlib_compression_levels() {
+        let list = Term::from(List::from(
+            (0..4096).map(|i| int(i * 7919 % 1009)).collect::<Vec<_>>(),
+        ));
+        let sizes: Vec<_> = [1, 6, 9]
+            .into_iter()
+            .map(|level| {
+                let options = EncodeOptions::new().compressed(level);
+                let (buf, _) = encode_with_options(&Process::blocking(), options, &list).unwrap();
+                assert_eq!(decode(&buf), list);
+                buf.len()
+            })
+            .collect();
+        assert!(sizes[0] >= sizes[1] && sizes[1] >= sizes[2]);
+    }
+
+    #[test]
+    fn it_skips_compression_when_it_does_not_help() {
+        let term = atom("ok");
+        for level in [0, 1, 9] {
+            let options = EncodeOptions::new().compressed(level);
+            let (buf, _) = encode_with_options(&Process::blocking(), options, &term).unwrap();
+            assert_eq!(buf, encode(&term));
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_compression_levels() {
+        let options = EncodeOptions::new().compressed(10);
+        assert!(matches!(
+            encode_with_options(&Process::blocking(), options, &atom("ok")),
+            Err(EncodeError::InvalidCompressionLevel { level: 10 })
+        ));
+    }
+
+    #[test]
+    fn it_sorts_map_keys_when_deterministic() {
+        let one_float = Term::from(Number::from(Float::try_from(1.0).unwrap()));
+        let map = Term::from(Map::from(vec![
+            (atom("b"), int(1)),
+            (one_float.clone(), atom("x")),
+            (int(1), atom("y")),
+        ]));
+        let reversed = Term::from(Map::from(vec![
+            (int(1), atom("y")),
+            (one_float, atom("x")),
+            (atom("b"), int(1)),
+        ]));
+        let options = EncodeOptions::new().deterministic();
+        let process = Process::blocking();
+        let (buf, _) = encode_with_options(&process, options.clone(), &map).unwrap();
+        // term_to_binary(#{b => 1, 1.0 => x, 1 => y}, [deterministic])
+        let mut expected = vec![131, 116, 0, 0, 0, 3, 97, 1, 119, 1, b'y'];
+        expected.extend_from_slice(&[70, 63, 240, 0, 0, 0, 0, 0, 0, 119, 1, b'x']);
+        expected.extend_from_slice(&[119, 1, b'b', 97, 1]);
+        assert_eq!(buf, expected);
+        let (other, _) = encode_with_options(&process, options, &reversed).unwrap();
+        assert_eq!(other, expected);
+        assert_ne!(encode(&map), expected);
+    }
+
+    #[test]
+    fn it_picks_wire_forms_from_peer_flags() {
+        let process = Process::blocking();
+        let flags = DistributionFlags::DFLAG_DIST_MANDATORY
+            - DistributionFlags::DFLAG_UTF8_ATOMS
+            - DistributionFlags::DFLAG_BIG_CREATION
+            - DistributionFlags::DFLAG_NEW_FLOATS;
+        let options = EncodeOptions::new().flags(flags);
+        let term = Term::from(Tuple::from(vec![
+            atom("caf\u{e9}"),
+            Term::from(Pid::new("a", 1, 2, 3)),
+            Term::from(Number::from(Float::try_from(1.5).unwrap())),
+        ]));
+        let (buf, _) = encode_with_options(&process, options, &term).unwrap();
+        let mut expected = vec![131, 104, 3, 100, 0, 4, b'c', b'a', b'f', 0xe9];
+        expected.extend_from_slice(&[103, 100, 0, 1, b'a', 0, 0, 0, 1, 0, 0, 0, 2, 3]);
+        expected.push(99);
+        expected.extend_from_slice(b"1.50000000000000000000e+00");
+        expected.extend_from_slice(&[0; 5]);
+        assert_eq!(buf, expected);
+        assert_eq!(decode(&buf), term);
+    }
+
+    #[test]
+    fn it_rejects_terms_the_peer_cannot_decode() {
+        let process = Process::blocking();
+        let flags = DistributionFlags::DFLAG_DIST_MANDATORY - DistributionFlags::DFLAG_UTF8_ATOMS;
+        let options = EncodeOptions::new().flags(flags);
+        assert!(matches!(
+            encode_with_options(&process, options, &atom("\u{3bb}")),
+            Err(EncodeError::UnsupportedByPeer {
+                flag: DistributionFlags::DFLAG_UTF8_ATOMS,
+                ..
+            })
+        ));
+
+        let flags = DistributionFlags::DFLAG_DIST_MANDATORY - DistributionFlags::DFLAG_MAP_TAG;
+        let options = EncodeOptions::new().flags(flags);
+        assert!(matches!(
+            encode_with_options(&process, options, &Term::from(Map::empty())),
+            Err(EncodeError::UnsupportedByPeer {
+                flag: DistributionFlags::DFLAG_MAP_TAG,
+                ..
+            })
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::codec::dist::DistributionFlags;
+use crate::dist::AtomCacheError;
 use crate::term::Term;
 
 /// Errors which can occur when decoding a term
@@ -9,8 +11,9 @@ pub enum DecodeError {
     // #[error("BitReader error")]
     // BitReader(#[from] bitreader::BitReaderError),
 
-    // #[error("atom cache error")]
-    // AtomCacheError(#[from] AtomCacheError),
+    #[error("atom cache error")]
+    AtomCacheError(#[from] AtomCacheError),
+
     #[error("the format version {version} is unsupported")]
     UnsupportedVersion { version: u8 },
 
@@ -28,4 +31,47 @@ pub enum DecodeError {
 
     #[error("tried to convert non-finite float")]
     NonFiniteFloat,
+
+    #[error("term is nested deeper than the maximum depth of {max_depth}")]
+    DepthLimitExceeded { max_depth: usize },
+
+    #[error("term is larger than the maximum of {max_bytes} bytes")]
+    ByteLimitExceeded { max_bytes: usize },
+
+    #[error("container of {len} elements is larger than the maximum of {max_elements}")]
+    ElementLimitExceeded { len: usize, max_elements: usize },
+
+    #[error("atom {name:?} does not exist and safe decoding is enabled")]
+    UnsafeAtom { name: String },
+
+    #[error("bitstring of {len} bytes cannot have {tail_bits} bits in its last byte")]
+    InvalidTailBits { len: usize, tail_bits: u8 },
+
+    #[error("fragment {fragment_id} of sequence {sequence_id} is invalid")]
+    InvalidFragment { sequence_id: u64, fragment_id: u64 },
+
+    #[error("partially received messages exceed the maximum of {max_bytes} bytes")]
+    FragmentLimitExceeded { max_bytes: usize },
+}
+
+/// Errors which can occur when encoding a term
+#[derive(Debug, thiserror::Error)]
+pub enum EncodeError {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+
+    #[error("{value} is too large to encode: length {len} exceeds {max}")]
+    TooLarge { value: Term, len: usize, max: usize },
+
+    #[error("{value} cannot be encoded as an external term")]
+    UnsupportedTerm { value: Term },
+
+    #[error("compression level {level} is not in the range 0..=9")]
+    InvalidCompressionLevel { level: u8 },
+
+    #[error("{value} cannot be encoded for a peer without {flag:?}")]
+    UnsupportedByPeer {
+        value: Term,
+        flag: DistributionFlags,
+    },
 }
@@ -0,0 +1,364 @@
+//! Length-prefixed external term frames over tokio I/O.
+//!
+//! Frames are laid out like the `{packet, N}` option of `gen_tcp` and
+//! `open_port/2`: a big-endian length of 1, 2 or 4 bytes followed by that
+//! many bytes of external term format.
+
+use bytes::{Buf, BufMut, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::decoder::{DecodeOptions, ReadContext, YieldableDecoder};
+use super::encoder::{EncodeOptions, WriteContext, YieldableEncoder};
+use super::error::{DecodeError, EncodeError};
+use crate::dist::AtomCache;
+use crate::term::Term;
+use crate::{pin_mut, Cassette, Process};
+
+/// The default for [`TermCodec::max_frame_length`], matching tokio's
+/// `LengthDelimitedCodec`.
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// The size of the length prefix of each frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Packet {
+    One,
+    Two,
+    Four,
+}
+impl Packet {
+    pub fn header_len(&self) -> usize {
+        match self {
+            Self::One => 1,
+            Self::Two => 2,
+            Self::Four => 4,
+        }
+    }
+
+    /// Returns the largest frame length the prefix can hold.
+    pub fn max_len(&self) -> usize {
+        match self {
+            Self::One => u8::MAX as usize,
+            Self::Two => u16::MAX as usize,
+            Self::Four => u32::MAX as usize,
+        }
+    }
+
+    fn get_len(&self, mut header: &[u8]) -> usize {
+        match self {
+            Self::One => header.get_u8() as usize,
+            Self::Two => header.get_u16() as usize,
+            Self::Four => header.get_u32() as usize,
+        }
+    }
+
+    fn put_len(&self, dst: &mut BytesMut, len: usize) {
+        match self {
+            Self::One => dst.put_u8(len as u8),
+            Self::Two => dst.put_u16(len as u16),
+            Self::Four => dst.put_u32(len as u32),
+        }
+    }
+
+    fn check_len(&self, term: &Term, len: usize) -> Result<(), EncodeError> {
+        if len > self.max_len() {
+            return Err(EncodeError::TooLarge {
+                value: term.clone(),
+                len,
+                max: self.max_len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A [`tokio_util::codec`] decoder and encoder for length-prefixed external
+/// terms.
+///
+/// The codec runs the yieldable decoder and encoder to completion on a
+/// blocking process, so it never yields for reductions. Use
+/// [`read_term`] and [`write_term`] to share a process with other work.
+///
+/// Frames longer than [`DEFAULT_MAX_FRAME_LENGTH`] are rejected unless
+/// [`TermCodec::max_frame_length`] raises the limit.
+#[derive(Clone, Debug)]
+pub struct TermCodec {
+    packet: Packet,
+    max_frame_length: usize,
+    decode_options: DecodeOptions,
+    encode_options: EncodeOptions,
+}
+impl TermCodec {
+    pub fn new(packet: Packet) -> Self {
+        Self {
+            packet,
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+            decode_options: DecodeOptions::default(),
+            encode_options: EncodeOptions::default(),
+        }
+    }
+
+    /// Sets the longest frame the decoder buffers. A `max_bytes` decode
+    /// option below this limit takes precedence.
+    pub fn max_frame_length(mut self, max_frame_length: usize) -> Self {
+        self.max_frame_length = max_frame_length;
+        self
+    }
+
+    pub fn decode_options(mut self, options: DecodeOptions) -> Self {
+        self.decode_options = options;
+        self
+    }
+
+    pub fn encode_options(mut self, options: EncodeOptions) -> Self {
+        self.encode_options = options;
+        self
+    }
+
+    pub fn get_packet(&self) -> Packet {
+        self.packet
+    }
+
+    pub fn get_max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+}
+
+impl tokio_util::codec::Decoder for TermCodec {
+    type Item = Term;
+    type Error = DecodeError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Term>, DecodeError> {
+        let header_len = self.packet.header_len();
+        if src.len() < header_len {
+            return Ok(None);
+        }
+        let len = self.packet.get_len(&src[..header_len]);
+        if len > self.max_frame_length {
+            return Err(DecodeError::ByteLimitExceeded {
+                max_bytes: self.max_frame_length,
+            });
+        }
+        check_frame_len(&self.decode_options, len)?;
+        if src.len() < header_len + len {
+            src.reserve(header_len + len - src.len());
+            return Ok(None);
+        }
+        src.advance(header_len);
+        let frame = src.split_to(len).freeze();
+
+        // Atom cache references only occur in distribution messages, so plain
+        // term frames are decoded against an empty cache.
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::with_options(&process, &atom_cache, self.decode_options.clone());
+        let mut decoder = YieldableDecoder::from_bytes(frame);
+        let future = decoder.read_external_term(&ctx);
+        pin_mut!(future);
+        Cassette::new(future).block_on().map(Some)
+    }
+}
+
+impl tokio_util::codec::Encoder<&Term> for TermCodec {
+    type Error = EncodeError;
+
+    fn encode(&mut self, term: &Term, dst: &mut BytesMut) -> Result<(), EncodeError> {
+        let process = Process::blocking();
+        let ctx = WriteContext::with_options(&process, self.encode_options.clone());
+        let mut encoder = YieldableEncoder::new(Vec::new());
+        {
+            let future = encoder.write_external_term(&ctx, term);
+            pin_mut!(future);
+            Cassette::new(future).block_on()?;
+        }
+        let buf = encoder.into_inner();
+        self.packet.check_len(term, buf.len())?;
+        dst.reserve(self.packet.header_len() + buf.len());
+        self.packet.put_len(dst, buf.len());
+        dst.extend_from_slice(&buf);
+        Ok(())
+    }
+}
+
+impl tokio_util::codec::Encoder<Term> for TermCodec {
+    type Error = EncodeError;
+
+    fn encode(&mut self, term: Term, dst: &mut BytesMut) -> Result<(), EncodeError> {
+        self.encode(&term, dst)
+    }
+}
+
+/// Reads one length-prefixed external term from `reader`.
+pub async fn read_term<R>(
+    ctx: &ReadContext<'_>,
+    reader: &mut R,
+    packet: Packet,
+) -> Result<Term, DecodeError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0; 4];
+    let header = &mut header[..packet.header_len()];
+    reader.read_exact(header).await?;
+    let len = packet.get_len(header);
+    check_frame_len(&ctx.options, len)?;
+    // The length prefix is untrusted, so the frame buffer only grows as
+    // bytes actually arrive.
+    let mut frame = Vec::new();
+    reader.take(len as u64).read_to_end(&mut frame).await?;
+    if frame.len() < len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    let mut decoder = YieldableDecoder::new(frame.as_slice());
+    decoder.read_external_term(ctx).await
+}
+
+/// Writes `term` to `writer` as one length-prefixed external term.
+pub async fn write_term<W>(
+    ctx: &WriteContext<'_>,
+    writer: &mut W,
+    packet: Packet,
+    term: &Term,
+) -> Result<(), EncodeError>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut encoder = YieldableEncoder::new(Vec::new());
+    encoder.write_external_term(ctx, term).await?;
+    let buf = encoder.into_inner();
+    packet.check_len(term, buf.len())?;
+    let mut frame = BytesMut::with_capacity(packet.header_len() + buf.len());
+    packet.put_len(&mut frame, buf.len());
+    frame.extend_from_slice(&buf);
+    writer.write_all(&frame).await?;
+    Ok(())
+}
+
+fn check_frame_len(options: &DecodeOptions, len: usize) -> Result<(), DecodeError> {
+    match options.get_max_bytes() {
+        Some(max_bytes) if len > max_bytes => Err(DecodeError::ByteLimitExceeded { max_bytes }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::{Atom, Bitstring, Tuple};
+    use tokio_util::codec::{Decoder, Encoder};
+
+    fn sample() -> Term {
+        Term::from(Tuple::from(vec![
+            Term::from(Atom::from("ok")),
+            Term::from(Bitstring::from(vec![1, 2, 3])),
+        ]))
+    }
+
+    #[test]
+    fn it_is_send() {
+        fn is_send<T: Send>() {}
+        is_send::<TermCodec>();
+    }
+
+    #[test]
+    fn it_round_trips_frames() {
+        for packet in [Packet::One, Packet::Two, Packet::Four] {
+            let mut codec = TermCodec::new(packet);
+            let mut buf = BytesMut::new();
+            codec.encode(&sample(), &mut buf).unwrap();
+            codec
+                .encode(Term::from(Atom::from("done")), &mut buf)
+                .unwrap();
+
+            let mut src = BytesMut::new();
+            src.extend_from_slice(&buf[..3]);
+            assert!(codec.decode(&mut src).unwrap().is_none());
+            src.extend_from_slice(&buf[3..]);
+            assert_eq!(codec.decode(&mut src).unwrap(), Some(sample()));
+            assert_eq!(
+                codec.decode(&mut src).unwrap(),
+                Some(Term::from(Atom::from("done")))
+            );
+            assert!(codec.decode(&mut src).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn it_checks_frame_lengths() {
+        let mut codec = TermCodec::new(Packet::One);
+        let term = Term::from(Bitstring::from(vec![0; 300]));
+        assert!(matches!(
+            codec.encode(&term, &mut BytesMut::new()),
+            Err(EncodeError::TooLarge {
+                len: 306,
+                max: 255,
+                ..
+            })
+        ));
+
+        let mut codec =
+            TermCodec::new(Packet::Four).decode_options(DecodeOptions::new().max_bytes(1024));
+        let mut src = BytesMut::from(&[0, 1, 0, 0, 131][..]);
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(DecodeError::ByteLimitExceeded { max_bytes: 1024 })
+        ));
+    }
+
+    #[test]
+    fn it_limits_frame_lengths_by_default() {
+        let mut codec = TermCodec::new(Packet::Four);
+        assert_eq!(codec.get_max_frame_length(), DEFAULT_MAX_FRAME_LENGTH);
+        let mut src = BytesMut::from(&[0xff, 0xff, 0xff, 0xff, 131][..]);
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(DecodeError::ByteLimitExceeded {
+                max_bytes: DEFAULT_MAX_FRAME_LENGTH
+            })
+        ));
+
+        let mut codec = TermCodec::new(Packet::Two).max_frame_length(4);
+        let mut buf = BytesMut::new();
+        codec.encode(&sample(), &mut buf).unwrap();
+        assert!(matches!(
+            codec.decode(&mut buf),
+            Err(DecodeError::ByteLimitExceeded { max_bytes: 4 })
+        ));
+    }
+
+    #[test]
+    fn it_does_not_trust_length_prefixes_when_reading() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let buf = [0xff, 0xff, 0xff, 0xff, 131, 106];
+        let mut reader = &buf[..];
+        let future = read_term(&ctx, &mut reader, Packet::Four);
+        pin_mut!(future);
+        assert!(matches!(
+            Cassette::new(future).block_on(),
+            Err(DecodeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn it_reads_and_writes_terms() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let term = sample();
+        let mut buf = Vec::new();
+        {
+            let ctx = WriteContext::new(&process);
+            let future = write_term(&ctx, &mut buf, Packet::Two, &term);
+            pin_mut!(future);
+            Cassette::new(future).block_on().unwrap();
+        }
+        assert_eq!(&buf[..2], &[0, 15]);
+        assert_eq!(buf.len(), 17);
+
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let mut reader = buf.as_slice();
+        let future = read_term(&ctx, &mut reader, Packet::Two);
+        pin_mut!(future);
+        assert_eq!(Cassette::new(future).block_on().unwrap(), term);
+    }
+}
@@ -1,4 +1,8 @@
-// pub mod decoder;
-mod dist;
+pub mod decoder;
+pub mod dist;
+pub mod encoder;
 pub mod error;
 pub(crate) mod external;
+#[cfg(feature = "tokio")]
+pub mod framed;
+pub mod push;
@@ -0,0 +1,556 @@
+use byteorder::{BigEndian, ByteOrder};
+use flate2::{Decompress, FlushDecompress, Status};
+
+use super::decoder::{ReadContext, YieldableDecoder};
+use super::error::DecodeError;
+use super::external as ext;
+use crate::term::Term;
+
+/// The outcome of handing bytes to a [`PushDecoder`].
+#[derive(Debug)]
+pub enum PushResult {
+    /// At least this many more bytes are needed before the next term is
+    /// complete.
+    NeedMore(usize),
+    /// A term was decoded from this many bytes at the front of the buffer,
+    /// like the `used` option of `binary_to_term/2`.
+    Term(Term, usize),
+    Error(DecodeError),
+}
+
+/// A decoder for terms that arrive in arbitrary chunks, such as reads from a
+/// socket.
+///
+/// Bytes passed to [`feed`](Self::feed) are buffered and scanned
+/// incrementally, so the work done on earlier chunks is kept between calls.
+/// Once a whole term is buffered it is decoded and removed from the front of
+/// the buffer; any bytes after it stay buffered for the next term, which can
+/// be taken with [`next_term`](Self::next_term).
+///
+/// Compressed terms are only scanned up to their length prefix, after which
+/// the zlib stream is inflated as its bytes arrive.
+///
+/// After an error the buffered bytes are discarded, since the position of the
+/// next term is unknown.
+#[derive(Debug, Default)]
+pub struct PushDecoder {
+    buf: Vec<u8>,
+    start: usize,
+    scanner: Scanner,
+    inflater: Option<Inflater>,
+}
+impl PushDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of bytes buffered but not yet decoded.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buf.len() - self.start
+    }
+
+    /// Appends `chunk` to the buffer and tries to decode the next term.
+    pub async fn feed(&mut self, ctx: &ReadContext<'_>, chunk: &[u8]) -> PushResult {
+        if self.start > 0 && self.start * 2 >= self.buf.len() {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(chunk);
+        self.next_term(ctx).await
+    }
+
+    /// Tries to decode the next term from the bytes already buffered.
+    pub async fn next_term(&mut self, ctx: &ReadContext<'_>) -> PushResult {
+        match self.decode(ctx).await {
+            Ok(Some((term, used))) => {
+                self.start += used;
+                if self.start == self.buf.len() {
+                    self.buf.clear();
+                    self.start = 0;
+                }
+                self.scanner = Scanner::default();
+                self.inflater = None;
+                PushResult::Term(term, used)
+            }
+            Ok(None) => PushResult::NeedMore(self.scanner.need),
+            Err(error) => {
+                self.buf.clear();
+                self.start = 0;
+                self.scanner = Scanner::default();
+                self.inflater = None;
+                PushResult::Error(error)
+            }
+        }
+    }
+
+    async fn decode(
+        &mut self,
+        ctx: &ReadContext<'_>,
+    ) -> Result<Option<(Term, usize)>, DecodeError> {
+        let buf = &self.buf[self.start..];
+        if !self.scanner.scan(ctx, buf).await? {
+            if let Some(max_bytes) = ctx.options.get_max_bytes() {
+                if buf.len() + self.scanner.need > max_bytes {
+                    return Err(DecodeError::ByteLimitExceeded { max_bytes });
+                }
+            }
+            return Ok(None);
+        }
+        if self.scanner.compressed {
+            return self.decode_compressed(ctx).await;
+        }
+        let used = self.scanner.pos;
+        let mut decoder = YieldableDecoder::new(&buf[..used]);
+        let term = decoder.read_external_term(ctx).await?;
+        Ok(Some((term, used)))
+    }
+
+    async fn decode_compressed(
+        &mut self,
+        ctx: &ReadContext<'_>,
+    ) -> Result<Option<(Term, usize)>, DecodeError> {
+        let buf = &self.buf[self.start..];
+        let inflater = self.inflater.get_or_insert_with(|| Inflater {
+            uncompressed_size: BigEndian::read_u32(&buf[2..6]) as usize,
+            decompress: Decompress::new(true),
+            uncompressed: Vec::new(),
+        });
+        if !inflater.inflate(&buf[6..])? {
+            self.scanner.need = 1;
+            return Ok(None);
+        }
+        let used = 6 + inflater.decompress.total_in() as usize;
+        let mut decoder = YieldableDecoder::new(inflater.uncompressed.as_slice());
+        let term = decoder.read_internal_term(ctx).await?;
+        Ok(Some((term, used)))
+    }
+}
+
+/// The state of a compressed term being inflated, kept between feeds so that
+/// each compressed byte is only inflated once.
+#[derive(Debug)]
+struct Inflater {
+    /// The size declared by the term, already checked against the byte limit.
+    uncompressed_size: usize,
+    decompress: Decompress,
+    uncompressed: Vec<u8>,
+}
+impl Inflater {
+    /// Inflates the part of `compressed` not seen by earlier calls, returning
+    /// whether the zlib stream is complete.
+    fn inflate(&mut self, compressed: &[u8]) -> Result<bool, DecodeError> {
+        let mut chunk = [0; 8 * 1024];
+        loop {
+            let total_in = self.decompress.total_in();
+            let total_out = self.decompress.total_out();
+            let status = self
+                .decompress
+                .decompress(
+                    &compressed[total_in as usize..],
+                    &mut chunk,
+                    FlushDecompress::None,
+                )
+                .map_err(std::io::Error::from)?;
+            let read = self.decompress.total_in() - total_in;
+            let written = (self.decompress.total_out() - total_out) as usize;
+            if self.uncompressed.len() + written > self.uncompressed_size {
+                return Err(size_mismatch());
+            }
+            self.uncompressed.extend_from_slice(&chunk[..written]);
+            match status {
+                Status::StreamEnd if self.uncompressed.len() == self.uncompressed_size => {
+                    return Ok(true)
+                }
+                Status::StreamEnd => return Err(size_mismatch()),
+                _ if read == 0 && written == 0 => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+}
+
+fn size_mismatch() -> DecodeError {
+    DecodeError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "uncompressed size does not match the compressed term",
+    ))
+}
+
+/// Work left to do before the term being scanned is complete.
+#[derive(Debug)]
+enum Pending {
+    Terms(u64),
+    Bytes(usize),
+}
+
+/// Finds where an external term ends without decoding it.
+#[derive(Debug, Default)]
+struct Scanner {
+    /// The number of bytes scanned so far.
+    pos: usize,
+    /// The number of bytes still needed when the last scan stopped short.
+    need: usize,
+    compressed: bool,
+    stack: Vec<Pending>,
+}
+impl Scanner {
+    /// Scans as far as `buf` allows, returning whether the term is complete.
+    async fn scan(&mut self, ctx: &ReadContext<'_>, buf: &[u8]) -> Result<bool, DecodeError> {
+        if self.pos == 0 {
+            if buf.len() < 2 {
+                return Ok(self.need_more(2 - buf.len()));
+            }
+            if buf[0] != ext::VERSION_MAGIC {
+                return Err(DecodeError::UnsupportedVersion { version: buf[0] });
+            }
+            if buf[1] == ext::COMPRESSED {
+                if buf.len() < 6 {
+                    return Ok(self.need_more(6 - buf.len()));
+                }
+                let uncompressed_size = BigEndian::read_u32(&buf[2..6]) as usize;
+                if let Some(max_bytes) = ctx.options.get_max_bytes() {
+                    if uncompressed_size > max_bytes {
+                        return Err(DecodeError::ByteLimitExceeded { max_bytes });
+                    }
+                }
+                self.pos = 6;
+                self.compressed = true;
+                return Ok(true);
+            }
+            self.pos = 1;
+            self.stack.push(Pending::Terms(1));
+        }
+        while let Some(pending) = self.stack.last_mut() {
+            let available = buf.len() - self.pos;
+            match pending {
+                Pending::Bytes(len) => {
+                    let len = *len;
+                    if available < len {
+                        return Ok(self.need_more(len - available));
+                    }
+                    self.pos += len;
+                    self.stack.pop();
+                }
+                Pending::Terms(0) => {
+                    self.stack.pop();
+                }
+                Pending::Terms(count) => {
+                    if available == 0 {
+                        return Ok(self.need_more(1));
+                    }
+                    let tag = buf[self.pos];
+                    let header_len = header_len(tag)?;
+                    if available < 1 + header_len {
+                        return Ok(self.need_more(1 + header_len - available));
+                    }
+                    *count -= 1;
+                    let header = &buf[self.pos + 1..self.pos + 1 + header_len];
+                    self.pos += 1 + header_len;
+                    self.push_contents(tag, header)?;
+                    ctx.bump_reds(1).await;
+                }
+            }
+        }
+        self.need = 0;
+        Ok(true)
+    }
+
+    fn need_more(&mut self, need: usize) -> bool {
+        self.need = need;
+        false
+    }
+
+    /// Pushes what follows the tag and fixed-size header of a term.
+    fn push_contents(&mut self, tag: u8, header: &[u8]) -> Result<(), DecodeError> {
+        let u16_at = |i: usize| BigEndian::read_u16(&header[i..]) as usize;
+        let u32_at = |i: usize| BigEndian::read_u32(&header[i..]) as usize;
+        // Items on the stack are handled last first, so a node followed by
+        // fixed-size fields pushes the fields before the node.
+        match tag {
+            ext::SMALL_INTEGER_EXT | ext::ATOM_CACHE_REF => self.bytes(1),
+            ext::INTEGER_EXT => self.bytes(4),
+            ext::FLOAT_EXT => self.bytes(31),
+            ext::NEW_FLOAT_EXT => self.bytes(8),
+            ext::ATOM_EXT | ext::ATOM_UTF8_EXT | ext::STRING_EXT => self.bytes(u16_at(0)),
+            ext::SMALL_ATOM_EXT | ext::SMALL_ATOM_UTF8_EXT => self.bytes(header[0] as usize),
+            ext::NIL_EXT => {}
+            ext::PID_EXT => self.node_then(9),
+            ext::NEW_PID_EXT | ext::V4_PORT_EXT => self.node_then(12),
+            ext::PORT_EXT | ext::REFERENCE_EXT => self.node_then(5),
+            ext::NEW_PORT_EXT => self.node_then(8),
+            ext::NEW_REFERENCE_EXT => self.node_then(1 + 4 * u16_at(0)),
+            ext::NEWER_REFERENCE_EXT => self.node_then(4 + 4 * u16_at(0)),
+            ext::SMALL_TUPLE_EXT => self.terms(header[0] as u64),
+            ext::LARGE_TUPLE_EXT => self.terms(u32_at(0) as u64),
+            ext::LIST_EXT => self.terms(u32_at(0) as u64 + 1),
+            ext::MAP_EXT => self.terms(2 * u32_at(0) as u64),
+            ext::BINARY_EXT => self.bytes(u32_at(0)),
+            ext::BIT_BINARY_EXT => self.bytes(u32_at(0)),
+            ext::SMALL_BIG_EXT => self.bytes(header[0] as usize),
+            ext::LARGE_BIG_EXT => self.bytes(u32_at(0)),
+            ext::NEW_FUN_EXT => {
+                // The size includes the size field itself.
+                let size = u32_at(0);
+                if size < 4 {
+                    return Err(DecodeError::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid fun size",
+                    )));
+                }
+                self.bytes(size - 4)
+            }
+            ext::EXPORT_EXT => self.terms(3),
+            ext::FUN_EXT => self.terms(4 + u32_at(0) as u64),
+            tag => return Err(DecodeError::UnknownTag { tag }),
+        }
+        Ok(())
+    }
+
+    fn bytes(&mut self, len: usize) {
+        if len > 0 {
+            self.stack.push(Pending::Bytes(len));
+        }
+    }
+
+    fn terms(&mut self, count: u64) {
+        if count > 0 {
+            self.stack.push(Pending::Terms(count));
+        }
+    }
+
+    fn node_then(&mut self, len: usize) {
+        self.bytes(len);
+        self.terms(1);
+    }
+}
+
+/// Returns how many bytes after `tag` are needed to know the rest of the
+/// term's layout.
+fn header_len(tag: u8) -> Result<usize, DecodeError> {
+    Ok(match tag {
+        ext::SMALL_INTEGER_EXT
+        | ext::INTEGER_EXT
+        | ext::FLOAT_EXT
+        | ext::NEW_FLOAT_EXT
+        | ext::NIL_EXT
+        | ext::ATOM_CACHE_REF
+        | ext::PID_EXT
+        | ext::NEW_PID_EXT
+        | ext::PORT_EXT
+        | ext::NEW_PORT_EXT
+        | ext::V4_PORT_EXT
+        | ext::REFERENCE_EXT
+        | ext::EXPORT_EXT => 0,
+        ext::SMALL_ATOM_EXT | ext::SMALL_ATOM_UTF8_EXT | ext::SMALL_TUPLE_EXT => 1,
+        ext::ATOM_EXT
+        | ext::ATOM_UTF8_EXT
+        | ext::STRING_EXT
+        | ext::NEW_REFERENCE_EXT
+        | ext::NEWER_REFERENCE_EXT => 2,
+        // The sign byte of a small big comes before its digits.
+        ext::SMALL_BIG_EXT => 2,
+        ext::LARGE_TUPLE_EXT
+        | ext::LIST_EXT
+        | ext::MAP_EXT
+        | ext::BINARY_EXT
+        | ext::NEW_FUN_EXT
+        | ext::FUN_EXT => 4,
+        // The bit count and sign bytes follow the length.
+        ext::BIT_BINARY_EXT | ext::LARGE_BIG_EXT => 5,
+        tag => return Err(DecodeError::UnknownTag { tag }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decoder::DecodeOptions;
+    use crate::codec::encoder::{EncodeOptions, WriteContext, YieldableEncoder};
+    use crate::dist::AtomCache;
+    use crate::term::{Atom, Bitstring, List, Tuple};
+    use crate::{pin_mut, Cassette, Process};
+
+    fn feed(decoder: &mut PushDecoder, ctx: &ReadContext<'_>, chunk: &[u8]) -> PushResult {
+        let future = decoder.feed(ctx, chunk);
+        pin_mut!(future);
+        Cassette::new(future).block_on()
+    }
+
+    fn next_term(decoder: &mut PushDecoder, ctx: &ReadContext<'_>) -> PushResult {
+        let future = decoder.next_term(ctx);
+        pin_mut!(future);
+        Cassette::new(future).block_on()
+    }
+
+    fn encode(options: EncodeOptions, term: &Term) -> Vec<u8> {
+        let process = Process::blocking();
+        let ctx = WriteContext::with_options(&process, options);
+        let mut encoder = YieldableEncoder::new(Vec::new());
+        {
+            let future = encoder.write_external_term(&ctx, term);
+            pin_mut!(future);
+            Cassette::new(future).block_on().unwrap();
+        }
+        encoder.into_inner()
+    }
+
+    fn sample() -> Term {
+        Term::from(Tuple::from(vec![
+            Term::from(Atom::from("ok")),
+            Term::from(Bitstring::from(vec![7; 100])),
+            Term::from(List::from(vec![
+                Term::from(Atom::from("a")),
+                Term::from(Atom::from("b")),
+            ])),
+        ]))
+    }
+
+    #[test]
+    fn it_decodes_a_term_fed_byte_by_byte() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let buf = encode(EncodeOptions::new(), &sample());
+        let mut decoder = PushDecoder::new();
+        for (i, byte) in buf.iter().enumerate() {
+            match feed(&mut decoder, &ctx, &[*byte]) {
+                PushResult::NeedMore(n) => assert!(i + 1 < buf.len() && n >= 1),
+                PushResult::Term(term, used) => {
+                    assert_eq!(i + 1, buf.len());
+                    assert_eq!(used, buf.len());
+                    assert_eq!(term, sample());
+                }
+                PushResult::Error(error) => panic!("{}", error),
+            }
+        }
+        assert_eq!(decoder.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn it_reports_how_many_bytes_are_missing() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let mut decoder = PushDecoder::new();
+        assert!(matches!(
+            feed(&mut decoder, &ctx, &[131, 109, 0, 0]),
+            PushResult::NeedMore(2)
+        ));
+        assert!(matches!(
+            feed(&mut decoder, &ctx, &[0, 10, 1, 2]),
+            PushResult::NeedMore(8)
+        ));
+    }
+
+    #[test]
+    fn it_decodes_concatenated_terms() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let first = encode(EncodeOptions::new(), &sample());
+        let second = encode(EncodeOptions::new(), &Term::from(Atom::from("done")));
+        let mut buf = first.clone();
+        buf.extend_from_slice(&second);
+        buf.extend_from_slice(&[131, 97]);
+
+        let mut decoder = PushDecoder::new();
+        assert!(matches!(
+            feed(&mut decoder, &ctx, &buf),
+            PushResult::Term(term, used) if term == sample() && used == first.len()
+        ));
+        assert!(matches!(
+            next_term(&mut decoder, &ctx),
+            PushResult::Term(term, used) if term == Term::from(Atom::from("done")) && used == second.len()
+        ));
+        assert!(matches!(
+            next_term(&mut decoder, &ctx),
+            PushResult::NeedMore(1)
+        ));
+        assert_eq!(decoder.buffered_bytes(), 2);
+    }
+
+    #[test]
+    fn it_decodes_compressed_terms_split_across_chunks() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let mut buf = encode(EncodeOptions::new().compressed(6), &sample());
+        assert_eq!(buf[1], ext::COMPRESSED);
+        let len = buf.len();
+        buf.extend_from_slice(&encode(
+            EncodeOptions::new(),
+            &Term::from(Atom::from("done")),
+        ));
+
+        let mut decoder = PushDecoder::new();
+        assert!(matches!(
+            feed(&mut decoder, &ctx, &buf[..len - 1]),
+            PushResult::NeedMore(_)
+        ));
+        assert!(matches!(
+            feed(&mut decoder, &ctx, &buf[len - 1..]),
+            PushResult::Term(term, used) if term == sample() && used == len
+        ));
+        assert!(matches!(
+            next_term(&mut decoder, &ctx),
+            PushResult::Term(term, _) if term == Term::from(Atom::from("done"))
+        ));
+    }
+
+    #[test]
+    fn it_inflates_compressed_terms_fed_byte_by_byte() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let buf = encode(EncodeOptions::new().compressed(6), &sample());
+        let mut decoder = PushDecoder::new();
+        for byte in &buf[..buf.len() - 1] {
+            assert!(matches!(
+                feed(&mut decoder, &ctx, &[*byte]),
+                PushResult::NeedMore(_)
+            ));
+        }
+        let inflated = decoder.inflater.as_ref().unwrap().uncompressed.len();
+        assert!(inflated > 0);
+        assert!(matches!(
+            feed(&mut decoder, &ctx, &buf[buf.len() - 1..]),
+            PushResult::Term(term, used) if term == sample() && used == buf.len()
+        ));
+    }
+
+    #[test]
+    fn it_rejects_compressed_terms_of_the_wrong_size() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let buf = encode(EncodeOptions::new().compressed(6), &sample());
+        let size = BigEndian::read_u32(&buf[2..6]);
+        for wrong_size in [size - 1, size + 1] {
+            let mut buf = buf.clone();
+            BigEndian::write_u32(&mut buf[2..6], wrong_size);
+            let mut decoder = PushDecoder::new();
+            assert!(matches!(
+                feed(&mut decoder, &ctx, &buf),
+                PushResult::Error(DecodeError::Io(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn it_applies_decode_options() {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let options = DecodeOptions::new().max_bytes(64);
+        let ctx = ReadContext::with_options(&process, &atom_cache, options);
+        let mut decoder = PushDecoder::new();
+        assert!(matches!(
+            feed(&mut decoder, &ctx, &[131, 109, 255, 255, 255, 255]),
+            PushResult::Error(DecodeError::ByteLimitExceeded { max_bytes: 64 })
+        ));
+        assert_eq!(decoder.buffered_bytes(), 0);
+        assert!(matches!(
+            feed(&mut decoder, &ctx, &[131, 200]),
+            PushResult::Error(DecodeError::UnknownTag { tag: 200 })
+        ));
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::{BTreeMap, HashMap};
+
+use byteorder::ReadBytesExt;
+
+use crate::codec::decoder::{ReadContext, YieldableDecoder};
+use crate::codec::dist::DistributionFlags;
+use crate::codec::error::DecodeError;
+use crate::codec::external as ext;
+
+use super::{AtomRef, DistHeader, DistMessage};
+
+/// Puts fragmented distribution messages back together, see
+/// [Distribution Header for Fragmented Messages](https://www.erlang.org/doc/apps/erts/erl_ext_dist.html#distribution-header-for-fragmented-messages)
+/// in the Erlang docs.
+///
+/// One reassembler should be used per connection, as sequence ids are only
+/// unique within a connection.
+#[derive(Debug)]
+pub struct FragmentReassembler {
+    max_buffered_bytes: usize,
+    buffered_bytes: usize,
+    partial: HashMap<u64, PartialMessage>,
+}
+
+/// Bytes counted against `max_buffered_bytes` for each held fragment on top
+/// of its data, for the bookkeeping of the fragment and its message. Without
+/// it, empty fragments with fresh sequence or fragment ids would be free.
+const FRAGMENT_OVERHEAD: usize =
+    std::mem::size_of::<(u64, PartialMessage)>() + std::mem::size_of::<(u64, Vec<u8>)>();
+
+#[derive(Debug, Default)]
+struct PartialMessage {
+    header: Option<DistHeader>,
+    atom_cache_refs: Vec<AtomRef>,
+    num_fragments: Option<u64>,
+    fragments: BTreeMap<u64, Vec<u8>>,
+    len: usize,
+    buffered_bytes: usize,
+}
+impl PartialMessage {
+    fn is_complete(&self) -> bool {
+        self.num_fragments == Some(self.fragments.len() as u64)
+    }
+}
+
+impl FragmentReassembler {
+    /// Creates a reassembler which holds at most `max_buffered_bytes` of
+    /// partially received messages at once.
+    pub fn new(max_buffered_bytes: usize) -> Self {
+        Self {
+            max_buffered_bytes,
+            buffered_bytes: 0,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of bytes held for messages which are not complete,
+    /// including a fixed overhead for each fragment.
+    pub fn buffered_bytes(&self) -> usize {
+        self.buffered_bytes
+    }
+
+    /// Returns the number of messages which are not complete.
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+
+    /// Drops the fragments received so far for `sequence_id`.
+    pub fn discard(&mut self, sequence_id: u64) -> bool {
+        if let Some(message) = self.partial.remove(&sequence_id) {
+            self.buffered_bytes -= message.buffered_bytes;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Handles one distribution frame, without its packet length prefix.
+    ///
+    /// The atom cache updates of a `DIST_HEADER` or `DIST_FRAG_HEADER` are
+    /// applied to `ctx.atom_cache` as soon as the frame is read. Returns the
+    /// message once its last fragment has been received, in whichever order
+    /// the fragments arrived.
+    pub async fn push(
+        &mut self,
+        ctx: &ReadContext<'_>,
+        frame: &[u8],
+    ) -> Result<Option<DistMessage>, DecodeError> {
+        let mut body = frame;
+        let version = body.read_u8()?;
+        if version != ext::VERSION_MAGIC {
+            return Err(DecodeError::UnsupportedVersion { version });
+        }
+        let tag = body.read_u8()?;
+        let mut decoder = YieldableDecoder::new(body);
+        let (sequence_id, fragment_id, header) = match tag {
+            ext::DIST_HEADER => return DistMessage::decode(ctx, frame).await.map(Some),
+            ext::DIST_FRAG_HEADER => {
+                let (sequence_id, fragment_id, header) = decoder.read_dist_frag_header(ctx).await?;
+                (sequence_id, fragment_id, Some(header))
+            }
+            ext::DIST_FRAG_CONT => {
+                let (sequence_id, fragment_id) = decoder.read_dist_frag_cont()?;
+                (sequence_id, fragment_id, None)
+            }
+            _ => return Err(DecodeError::UnknownTag { tag }),
+        };
+        let data = &body[decoder.bytes_read()..];
+        let invalid = DecodeError::InvalidFragment {
+            sequence_id,
+            fragment_id,
+        };
+        if fragment_id == 0 {
+            return Err(invalid);
+        }
+        let cost = data.len() + FRAGMENT_OVERHEAD;
+        if self.buffered_bytes + cost > self.max_buffered_bytes {
+            self.discard(sequence_id);
+            return Err(DecodeError::FragmentLimitExceeded {
+                max_bytes: self.max_buffered_bytes,
+            });
+        }
+
+        let message = self.partial.entry(sequence_id).or_default();
+        if header.is_some() {
+            // The header carries the highest fragment id, counting down to 1.
+            let out_of_range = message.fragments.keys().any(|&id| id >= fragment_id);
+            if message.num_fragments.is_some() || out_of_range {
+                self.discard(sequence_id);
+                return Err(invalid);
+            }
+            message.num_fragments = Some(fragment_id);
+            message.header = header;
+            message.atom_cache_refs = decoder.atom_cache_refs().to_vec();
+        } else if message.num_fragments.is_some_and(|n| fragment_id >= n)
+            || message.fragments.contains_key(&fragment_id)
+        {
+            self.discard(sequence_id);
+            return Err(invalid);
+        }
+        message.fragments.insert(fragment_id, data.to_vec());
+        message.len += data.len();
+        message.buffered_bytes += cost;
+        self.buffered_bytes += cost;
+
+        if !message.is_complete() {
+            return Ok(None);
+        }
+        let message = self.partial.remove(&sequence_id).unwrap();
+        self.buffered_bytes -= message.buffered_bytes;
+        let mut buf = Vec::with_capacity(message.len);
+        for (_, fragment) in message.fragments.into_iter().rev() {
+            ctx.bump_reds(1).await;
+            buf.extend_from_slice(&fragment);
+        }
+        let mut decoder = YieldableDecoder::with_atom_cache_refs(&buf[..], message.atom_cache_refs);
+        let (control, payload) = DistMessage::read_body(ctx, &mut decoder, buf.len()).await?;
+        Ok(Some(DistMessage {
+            header: message.header.unwrap(),
+            control,
+            payload,
+        }))
+    }
+}
+
+/// The fragment size used by ERTS, see `ERTS_DIST_FRAGMENT_SIZE` in
+/// [erts/emulator/beam/dist.h](https://github.com/erlang/otp/blob/OTP-25.0.3/erts/emulator/beam/dist.h).
+pub const DEFAULT_FRAGMENT_SIZE: usize = 64 * 1024;
+
+/// Splits outgoing distribution messages into a `DIST_FRAG_HEADER` frame
+/// followed by `DIST_FRAG_CONT` frames, so that other traffic such as ticks
+/// can be sent in between.
+///
+/// One fragmenter should be used per connection, as sequence ids are only
+/// unique within a connection.
+#[derive(Clone, Debug)]
+pub struct Fragmenter {
+    flags: DistributionFlags,
+    fragment_size: usize,
+    next_sequence_id: u64,
+}
+impl Fragmenter {
+    /// Creates a fragmenter for a peer which negotiated `flags`. Messages are
+    /// only fragmented if they include `DFLAG_FRAGMENTS`.
+    pub fn new(flags: DistributionFlags) -> Self {
+        Self {
+            flags,
+            fragment_size: DEFAULT_FRAGMENT_SIZE,
+            next_sequence_id: 1,
+        }
+    }
+
+    /// Sets the largest number of message bytes carried by one frame.
+    pub fn fragment_size(mut self, fragment_size: usize) -> Self {
+        assert!(fragment_size > 0, "fragment size must not be zero");
+        self.fragment_size = fragment_size;
+        self
+    }
+
+    pub fn get_fragment_size(&self) -> usize {
+        self.fragment_size
+    }
+
+    /// Splits a message into frames, without packet length prefixes.
+    ///
+    /// `atom_cache_header` is everything which follows the tag of a
+    /// `DIST_HEADER`, starting with the number of atom cache references, and
+    /// `message` is the encoded control message followed by the encoded
+    /// payload, if any. A single `DIST_HEADER` frame is produced if the
+    /// message fits in one fragment or the peer does not support fragments.
+    pub fn fragment<'a>(
+        &mut self,
+        atom_cache_header: &'a [u8],
+        message: &'a [u8],
+    ) -> Fragments<'a> {
+        let num_fragments = if self.flags.contains(DistributionFlags::DFLAG_FRAGMENTS) {
+            message.len().div_ceil(self.fragment_size).max(1)
+        } else {
+            1
+        };
+        let sequence_id = if num_fragments > 1 {
+            let sequence_id = self.next_sequence_id;
+            self.next_sequence_id = self.next_sequence_id.wrapping_add(1);
+            sequence_id
+        } else {
+            0
+        };
+        Fragments {
+            atom_cache_header,
+            message,
+            sequence_id,
+            fragment_size: self.fragment_size,
+            fragment_id: num_fragments as u64,
+            num_fragments: num_fragments as u64,
+        }
+    }
+}
+
+/// Iterator over the frames of a message, see [`Fragmenter::fragment`].
+#[derive(Clone, Debug)]
+pub struct Fragments<'a> {
+    atom_cache_header: &'a [u8],
+    message: &'a [u8],
+    sequence_id: u64,
+    fragment_size: usize,
+    fragment_id: u64,
+    num_fragments: u64,
+}
+impl<'a> Fragments<'a> {
+    /// Returns the sequence id of a fragmented message, or `0` if the message
+    /// is sent as a single `DIST_HEADER` frame.
+    pub fn sequence_id(&self) -> u64 {
+        self.sequence_id
+    }
+}
+impl<'a> Iterator for Fragments<'a> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.fragment_id == 0 {
+            return None;
+        }
+        let first = self.fragment_id == self.num_fragments;
+        let data = if self.fragment_id == 1 {
+            std::mem::take(&mut self.message)
+        } else {
+            let (data, rest) = self.message.split_at(self.fragment_size);
+            self.message = rest;
+            data
+        };
+        let mut frame = Vec::with_capacity(18 + self.atom_cache_header.len() + data.len());
+        frame.push(ext::VERSION_MAGIC);
+        if self.num_fragments == 1 {
+            frame.push(ext::DIST_HEADER);
+        } else {
+            frame.push(if first {
+                ext::DIST_FRAG_HEADER
+            } else {
+                ext::DIST_FRAG_CONT
+            });
+            frame.extend_from_slice(&self.sequence_id.to_be_bytes());
+            frame.extend_from_slice(&self.fragment_id.to_be_bytes());
+        }
+        if first {
+            frame.extend_from_slice(self.atom_cache_header);
+        }
+        frame.extend_from_slice(data);
+        self.fragment_id -= 1;
+        Some(frame)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.fragment_id as usize, Some(self.fragment_id as usize))
+    }
+}
+impl<'a> ExactSizeIterator for Fragments<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dist::{AtomCache, AtomCacheRefEntry};
+    use crate::term::{Atom, Term};
+    use crate::{pin_mut, Cassette, Process};
+
+    const SEQUENCE_ID: u64 = 7;
+
+    fn push(
+        reassembler: &mut FragmentReassembler,
+        atom_cache: &AtomCache,
+        frame: &[u8],
+    ) -> Result<Option<DistMessage>, DecodeError> {
+        let process = Process::blocking();
+        let ctx = ReadContext::new(&process, atom_cache);
+        let future = reassembler.push(&ctx, frame);
+        pin_mut!(future);
+        Cassette::new(future).block_on()
+    }
+
+    fn frag_header(fragment_id: u64, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![ext::VERSION_MAGIC, ext::DIST_FRAG_HEADER];
+        frame.extend_from_slice(&SEQUENCE_ID.to_be_bytes());
+        frame.extend_from_slice(&fragment_id.to_be_bytes());
+        // One new atom cache entry, `foo`, in segment 0 at index 3.
+        frame.extend_from_slice(&[1, 0x08, 3, 3, b'f', b'o', b'o']);
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn frag_cont(fragment_id: u64, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![ext::VERSION_MAGIC, ext::DIST_FRAG_CONT];
+        frame.extend_from_slice(&SEQUENCE_ID.to_be_bytes());
+        frame.extend_from_slice(&fragment_id.to_be_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    /// `{foo, 1}` as the control message and `[1, 2, 3]` as the payload, split
+    /// into three fragments.
+    fn fragments() -> Vec<Vec<u8>> {
+        vec![
+            frag_header(3, &[ext::SMALL_TUPLE_EXT, 2, ext::ATOM_CACHE_REF]),
+            frag_cont(2, &[0, ext::SMALL_INTEGER_EXT, 1]),
+            frag_cont(1, &[ext::STRING_EXT, 0, 3, 1, 2, 3]),
+        ]
+    }
+
+    fn expected() -> DistMessage {
+        DistMessage {
+            header: DistHeader {
+                long_atoms: false,
+                atom_cache_ref_entries: vec![AtomCacheRefEntry::new(3, b"foo".to_vec())],
+            },
+            control: Term::from(crate::term::Tuple::from(vec![
+                Term::from(Atom::from("foo")),
+                Term::from(crate::term::Number::from(crate::term::FixInteger::from(1))),
+            ])),
+            payload: Some(Term::from(crate::term::List::from(
+                [1, 2, 3]
+                    .into_iter()
+                    .map(|i| {
+                        Term::from(crate::term::Number::from(crate::term::FixInteger::from(i)))
+                    })
+                    .collect::<Vec<_>>(),
+            ))),
+        }
+    }
+
+    #[test]
+    fn it_reassembles_fragments_in_order() {
+        let atom_cache = AtomCache::new();
+        let mut reassembler = FragmentReassembler::new(1024);
+        let [first, second, third]: [Vec<u8>; 3] = fragments().try_into().unwrap();
+        assert_eq!(push(&mut reassembler, &atom_cache, &first).unwrap(), None);
+        assert!(atom_cache.get(3).unwrap().is_some());
+        assert_eq!(push(&mut reassembler, &atom_cache, &second).unwrap(), None);
+        assert_eq!(
+            push(&mut reassembler, &atom_cache, &third).unwrap(),
+            Some(expected())
+        );
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn it_reassembles_fragments_out_of_order() {
+        let atom_cache = AtomCache::new();
+        let mut reassembler = FragmentReassembler::new(1024);
+        let [first, second, third]: [Vec<u8>; 3] = fragments().try_into().unwrap();
+        assert_eq!(push(&mut reassembler, &atom_cache, &third).unwrap(), None);
+        assert_eq!(push(&mut reassembler, &atom_cache, &first).unwrap(), None);
+        assert_eq!(
+            push(&mut reassembler, &atom_cache, &second).unwrap(),
+            Some(expected())
+        );
+    }
+
+    #[test]
+    fn it_passes_through_unfragmented_messages() {
+        let atom_cache = AtomCache::new();
+        let mut reassembler = FragmentReassembler::new(1024);
+        let frame = [
+            ext::VERSION_MAGIC,
+            ext::DIST_HEADER,
+            0,
+            ext::SMALL_INTEGER_EXT,
+            1,
+        ];
+        let message = push(&mut reassembler, &atom_cache, &frame)
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.payload, None);
+    }
+
+    #[test]
+    fn it_limits_buffered_bytes() {
+        let atom_cache = AtomCache::new();
+        let max_bytes = 4 + FRAGMENT_OVERHEAD;
+        let mut reassembler = FragmentReassembler::new(max_bytes);
+        let [first, second, _]: [Vec<u8>; 3] = fragments().try_into().unwrap();
+        assert_eq!(push(&mut reassembler, &atom_cache, &first).unwrap(), None);
+        assert_eq!(reassembler.buffered_bytes(), 3 + FRAGMENT_OVERHEAD);
+        assert!(matches!(
+            push(&mut reassembler, &atom_cache, &second),
+            Err(DecodeError::FragmentLimitExceeded { max_bytes: m }) if m == max_bytes
+        ));
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.buffered_bytes(), 0);
+    }
+
+    #[test]
+    fn it_counts_empty_fragments_against_the_limit() {
+        let atom_cache = AtomCache::new();
+        let mut reassembler = FragmentReassembler::new(16 * FRAGMENT_OVERHEAD);
+        let mut result = Ok(None);
+        for sequence_id in 0..1000u64 {
+            let mut frame = vec![ext::VERSION_MAGIC, ext::DIST_FRAG_CONT];
+            frame.extend_from_slice(&sequence_id.to_be_bytes());
+            frame.extend_from_slice(&1u64.to_be_bytes());
+            result = push(&mut reassembler, &atom_cache, &frame);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert!(matches!(
+            result,
+            Err(DecodeError::FragmentLimitExceeded { .. })
+        ));
+        assert_eq!(reassembler.pending(), 16);
+    }
+
+    #[test]
+    fn it_round_trips_fragmented_messages() {
+        let atom_cache = AtomCache::new();
+        let mut reassembler = FragmentReassembler::new(1024);
+        let mut fragmenter = Fragmenter::new(DistributionFlags::DFLAG_FRAGMENTS).fragment_size(2);
+        let message = [
+            ext::SMALL_TUPLE_EXT,
+            2,
+            ext::ATOM_CACHE_REF,
+            0,
+            ext::NIL_EXT,
+        ];
+        let frames: Vec<_> = fragmenter
+            .fragment(&[1, 0x08, 3, 3, b'f', b'o', b'o'], &message)
+            .collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0][1], ext::DIST_FRAG_HEADER);
+        assert_eq!(frames[1][1], ext::DIST_FRAG_CONT);
+        let mut reassembled = None;
+        for frame in frames.iter().rev() {
+            reassembled = push(&mut reassembler, &atom_cache, frame).unwrap();
+        }
+        assert_eq!(
+            reassembled.unwrap().control,
+            Term::from(crate::term::Tuple::from(vec![
+                Term::from(Atom::from("foo")),
+                Term::from(crate::term::Nil),
+            ]))
+        );
+    }
+
+    #[test]
+    fn it_uses_new_sequence_ids() {
+        let mut fragmenter = Fragmenter::new(DistributionFlags::DFLAG_FRAGMENTS).fragment_size(1);
+        let first = fragmenter.fragment(&[0], &[ext::NIL_EXT, ext::NIL_EXT]);
+        let second = fragmenter.fragment(&[0], &[ext::NIL_EXT, ext::NIL_EXT]);
+        assert_ne!(first.sequence_id(), second.sequence_id());
+        assert_eq!(fragmenter.fragment(&[0], &[ext::NIL_EXT]).sequence_id(), 0);
+    }
+
+    #[test]
+    fn it_sends_a_single_frame_without_dflag_fragments() {
+        let mut fragmenter =
+            Fragmenter::new(DistributionFlags::DFLAG_DIST_HDR_ATOM_CACHE).fragment_size(1);
+        let message = [ext::SMALL_INTEGER_EXT, 1, ext::NIL_EXT];
+        let frames: Vec<_> = fragmenter.fragment(&[0], &message).collect();
+        assert_eq!(
+            frames,
+            [vec![
+                ext::VERSION_MAGIC,
+                ext::DIST_HEADER,
+                0,
+                ext::SMALL_INTEGER_EXT,
+                1,
+                ext::NIL_EXT
+            ]]
+        );
+    }
+
+    #[test]
+    fn it_rejects_duplicate_fragments() {
+        let atom_cache = AtomCache::new();
+        let mut reassembler = FragmentReassembler::new(1024);
+        let second = &fragments()[1];
+        assert_eq!(push(&mut reassembler, &atom_cache, second).unwrap(), None);
+        assert!(matches!(
+            push(&mut reassembler, &atom_cache, second),
+            Err(DecodeError::InvalidFragment {
+                sequence_id: SEQUENCE_ID,
+                fragment_id: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn it_rejects_a_header_whose_fragment_id_was_already_received() {
+        let atom_cache = AtomCache::new();
+        let mut reassembler = FragmentReassembler::new(1024);
+        let cont = frag_cont(3, &[ext::SMALL_TUPLE_EXT, 2, ext::ATOM_CACHE_REF]);
+        let [header, ..]: [Vec<u8>; 3] = fragments().try_into().unwrap();
+        assert_eq!(push(&mut reassembler, &atom_cache, &cont).unwrap(), None);
+        assert!(matches!(
+            push(&mut reassembler, &atom_cache, &header),
+            Err(DecodeError::InvalidFragment {
+                sequence_id: SEQUENCE_ID,
+                fragment_id: 3
+            })
+        ));
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.buffered_bytes(), 0);
+    }
+}
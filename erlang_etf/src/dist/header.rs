@@ -1,23 +1,20 @@
 use std::fmt;
 
-use super::AtomCacheRefEntry;
-
+/// An entry in the atom cache part of a distribution header, see
+/// [Normal Distribution Header](https://www.erlang.org/doc/apps/erts/erl_ext_dist.html#normal-distribution-header)
+/// in the Erlang docs.
 #[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
-pub enum Dist {
-    DistHeader(DistHeader),
-    // DistFragHeader(DistFragHeader),
-    // DistFragCont(DistFragCont),
+pub enum AtomCacheRefEntry {
+    New { index: usize, atom_text: Vec<u8> },
+    Old { index: usize },
 }
-impl fmt::Display for Dist {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Dist::DistHeader(ref x) => x.fmt(f),
-        }
+impl AtomCacheRefEntry {
+    pub fn new(index: usize, atom_text: Vec<u8>) -> Self {
+        Self::New { index, atom_text }
     }
-}
-impl From<DistHeader> for Dist {
-    fn from(x: DistHeader) -> Self {
-        Dist::DistHeader(x)
+
+    pub fn old(index: usize) -> Self {
+        Self::Old { index }
     }
 }
 
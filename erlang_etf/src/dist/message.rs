@@ -0,0 +1,100 @@
+use crate::codec::decoder::{ReadContext, YieldableDecoder};
+use crate::codec::error::DecodeError;
+use crate::term::Term;
+
+use super::DistHeader;
+
+/// A message sent between connected nodes, see
+/// [Protocol between Connected Nodes](https://www.erlang.org/doc/apps/erts/erl_dist_protocol.html#protocol-between-connected-nodes)
+/// in the Erlang docs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DistMessage {
+    pub header: DistHeader,
+    pub control: Term,
+    pub payload: Option<Term>,
+}
+impl DistMessage {
+    /// Decodes a `DIST_HEADER` frame, without its packet length prefix.
+    ///
+    /// New entries in the header are stored in `ctx.atom_cache`, the atom
+    /// cache of the connection, and old entries are looked up in it.
+    pub async fn decode(ctx: &ReadContext<'_>, frame: &[u8]) -> Result<Self, DecodeError> {
+        let mut decoder = YieldableDecoder::new(frame);
+        let header = decoder.read_dist_header(ctx).await?;
+        let (control, payload) = Self::read_body(ctx, &mut decoder, frame.len()).await?;
+        Ok(Self {
+            header,
+            control,
+            payload,
+        })
+    }
+
+    /// Reads the control message and, if there are bytes left out of `len`,
+    /// the payload.
+    pub(crate) async fn read_body(
+        ctx: &ReadContext<'_>,
+        decoder: &mut YieldableDecoder<&[u8]>,
+        len: usize,
+    ) -> Result<(Term, Option<Term>), DecodeError> {
+        let control = decoder.read_internal_term(ctx).await?;
+        let payload = if decoder.bytes_read() < len {
+            Some(decoder.read_internal_term(ctx).await?)
+        } else {
+            None
+        };
+        Ok((control, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dist::{AtomCache, AtomCacheRefEntry};
+    use crate::term::{Atom, Tuple};
+    use crate::{pin_mut, Cassette, Process};
+
+    fn decode(atom_cache: &AtomCache, frame: &[u8]) -> Result<DistMessage, DecodeError> {
+        let process = Process::blocking();
+        let ctx = ReadContext::new(&process, atom_cache);
+        let future = DistMessage::decode(&ctx, frame);
+        pin_mut!(future);
+        Cassette::new(future).block_on()
+    }
+
+    #[test]
+    fn it_decodes_header_control_and_payload() {
+        let atom_cache = AtomCache::new();
+        // A new entry for `foo` at index 3, the control message `{foo}` and
+        // the payload `foo`.
+        let frame = [
+            131, 68, 1, 0x08, 3, 3, b'f', b'o', b'o', 104, 1, 82, 0, 82, 0,
+        ];
+        let message = decode(&atom_cache, &frame).unwrap();
+        let foo = Term::from(Atom::from("foo"));
+        assert_eq!(
+            message.header.atom_cache_ref_entries,
+            [AtomCacheRefEntry::new(3, b"foo".to_vec())]
+        );
+        assert_eq!(message.control, Term::from(Tuple::from(vec![foo.clone()])));
+        assert_eq!(message.payload, Some(foo.clone()));
+
+        // The entry is now in the connection's atom cache.
+        let frame = [131, 68, 1, 0x00, 3, 82, 0];
+        let message = decode(&atom_cache, &frame).unwrap();
+        assert_eq!(
+            message.header.atom_cache_ref_entries,
+            [AtomCacheRefEntry::old(3)]
+        );
+        assert_eq!(message.control, foo);
+        assert_eq!(message.payload, None);
+    }
+
+    #[test]
+    fn it_rejects_other_frames() {
+        let atom_cache = AtomCache::new();
+        assert!(matches!(
+            decode(&atom_cache, &[131, 106]),
+            Err(DecodeError::UnknownTag { tag: 106 })
+        ));
+    }
+}
@@ -5,6 +5,14 @@ use std::sync::Arc;
 use crate::codec::external::ERTS_ATOM_CACHE_SIZE;
 use crate::term::Atom;
 
+mod fragment;
+mod header;
+mod message;
+
+pub use fragment::*;
+pub use header::*;
+pub use message::*;
+
 #[derive(Clone, Debug)]
 pub struct AtomRef(Rc<Atom>);
 impl AtomRef {
@@ -35,6 +35,20 @@ impl Env {
         self.make_atom_int(AtomEncoding::Utf8, atom_name)
     }
 
+    /// Returns the atom named `atom_name` if it already exists, without
+    /// creating it.
+    pub fn lookup_atom(&self, atom_name: &[u8]) -> Option<Atom> {
+        self.lookup_atom_utf8(atom_name)
+    }
+
+    pub fn lookup_atom_latin1(&self, atom_name: &[u8]) -> Option<Atom> {
+        self.atom_table.read().lookup(AtomEncoding::Latin1, atom_name).cloned()
+    }
+
+    pub fn lookup_atom_utf8(&self, atom_name: &[u8]) -> Option<Atom> {
+        self.atom_table.read().lookup(AtomEncoding::Utf8, atom_name).cloned()
+    }
+
     fn make_atom_int<T: Into<Cow<'static, [u8]>>>(&self, atom_encoding: AtomEncoding, atom_name: T) -> Atom {
         let atom_name = atom_name.into();
         if let Some(atom) = self.atom_table.read().raw_get(atom_encoding, &atom_name) {
@@ -388,6 +402,12 @@ where
         self.map.get(&atom_read_key)
     }
 
+    /// Returns the interned atom named `atom_name`, if any.
+    pub fn lookup<'a>(&'a self, atom_encoding: AtomEncoding, atom_name: &'a [u8]) -> Option<&'a Atom> {
+        let atom_read_key = AtomKey::new(atom_encoding, atom_name);
+        self.map.get(&atom_read_key)
+    }
+
     fn raw_get_or_intern(&mut self, atom_encoding: AtomEncoding, atom_name: Cow<'static, [u8]>) -> Atom {
         let atom_write_key_encoded_size = {
             let atom_read_key = AtomKey::new(atom_encoding, &*atom_name);
@@ -0,0 +1,615 @@
+//! Conversions between terms and JSON values, in one of two [`Mode`]s.
+//!
+//! In [`Mode::Idiomatic`], terms map to JSON as `jason` and `jsx` would
+//! write them, and JSON reads back as those libraries decode it:
+//!
+//! | Erlang                          | JSON                                 |
+//! |---------------------------------|--------------------------------------|
+//! | integers, floats                | numbers                              |
+//! | `true`, `false`, `null`         | `true`, `false`, `null`              |
+//! | other atoms                     | strings                              |
+//! | UTF-8 binaries                  | strings                              |
+//! | proper lists, tuples            | arrays                               |
+//! | maps                            | objects                              |
+//!
+//! Strings read back as binaries, arrays as lists and objects as maps with
+//! binary keys. Map keys which are atoms or numbers are written as their
+//! text. Anything else, such as a pid, or an integer too large for a JSON
+//! number, is written as a string of its Erlang text, as `~tw` writes it.
+//!
+//! In [`Mode::Lossless`], every term reads back as the term it was written
+//! from. Booleans, numbers, UTF-8 binaries, proper lists and maps with UTF-8
+//! binary keys are written as above, and other terms as an object with a
+//! single `$`-prefixed key naming what it is:
+//!
+//! | Erlang                          | JSON                                             |
+//! |---------------------------------|--------------------------------------------------|
+//! | atom `ok`                       | `{"$atom": "ok"}`                                |
+//! | integer beyond 64 bits          | `{"$integer": "36893488147419103232"}`           |
+//! | binary which is not UTF-8       | `{"$binary": "AP8="}` (base64)                   |
+//! | bitstring `<<1, 2:3>>`          | `{"$bitstring": {"data": "AQI=", "bits": 3}}`    |
+//! | tuple `{a, 1}`                  | `{"$tuple": [{"$atom": "a"}, 1]}`                |
+//! | other maps                      | `{"$map": [[key, value], ...]}`                  |
+//! | improper list `[1 \| 2]`        | `{"$list": [1], "$tail": 2}`                     |
+//! | pid                             | `{"$pid": {"node", "id", "serial", "creation"}}` |
+//! | port                            | `{"$port": {"node", "id", "creation"}}`          |
+//! | reference                       | `{"$ref": {"node", "id", "creation"}}`           |
+//! | fun                             | `{"$fun": {"module", ...}}`                      |
+//!
+//! Maps whose keys start with `$` are always written as `{"$map": ...}`,
+//! so that they are not mistaken for one of these objects.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{Number as JsonNumber, Value};
+
+use crate::num_bigint::BigInt;
+use crate::term::*;
+
+/// How terms map to JSON.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Terms map to JSON as Erlang and Elixir JSON libraries map them, at
+    /// the cost of losing what JSON cannot say, such as whether a string
+    /// was an atom or a binary.
+    #[default]
+    Idiomatic,
+
+    /// Every term survives a round trip through JSON, using tagged objects
+    /// for what JSON cannot say directly.
+    Lossless,
+}
+
+/// Errors which can occur when reading terms from JSON
+#[derive(Debug, thiserror::Error)]
+pub enum JsonError {
+    #[error(transparent)]
+    Syntax(#[from] serde_json::Error),
+
+    #[error("invalid {tag} object: {value}")]
+    InvalidTag { tag: &'static str, value: Value },
+}
+
+/// Converts `term` into a JSON value.
+pub fn to_value(term: &Term, mode: Mode) -> Value {
+    match mode {
+        Mode::Idiomatic => idiomatic_value(term),
+        Mode::Lossless => lossless_value(term),
+    }
+}
+
+/// Converts a JSON value into a term.
+pub fn from_value(value: &Value, mode: Mode) -> Result<Term, JsonError> {
+    Ok(match value {
+        Value::Null => Term::from(Atom::from("null")),
+        Value::Bool(b) => Term::from(Atom::from(if *b { "true" } else { "false" })),
+        Value::Number(n) => number_term(n),
+        Value::String(s) => Term::from(Bitstring::from(s.as_bytes())),
+        Value::Array(elements) => list(
+            elements
+                .iter()
+                .map(|element| from_value(element, mode))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(object) => {
+            if mode == Mode::Lossless {
+                if let Some(term) = tagged_term(object)? {
+                    return Ok(term);
+                }
+            }
+            let pairs = object
+                .iter()
+                .map(|(key, value)| {
+                    let key = Term::from(Bitstring::from(key.as_bytes()));
+                    Ok((key, from_value(value, mode)?))
+                })
+                .collect::<Result<Vec<_>, JsonError>>()?;
+            Term::from(Map::from(pairs))
+        }
+    })
+}
+
+/// Writes `term` as JSON text.
+pub fn to_string(term: &Term, mode: Mode) -> String {
+    to_value(term, mode).to_string()
+}
+
+/// Reads a term from JSON text.
+pub fn from_str(text: &str, mode: Mode) -> Result<Term, JsonError> {
+    let value: Value = serde_json::from_str(text)?;
+    from_value(&value, mode)
+}
+
+fn list(elements: Vec<Term>) -> Term {
+    if elements.is_empty() {
+        Term::from(Nil)
+    } else {
+        Term::from(List::from(elements))
+    }
+}
+
+fn number_term(n: &JsonNumber) -> Term {
+    if let Some(i) = n.as_i64() {
+        Term::from(i)
+    } else if let Some(u) = n.as_u64() {
+        Term::from(u)
+    } else {
+        Term::from(n.as_f64().unwrap_or_default())
+    }
+}
+
+fn integer_term(value: BigInt) -> Term {
+    match i32::try_from(&value) {
+        Ok(value) => Term::from(value),
+        Err(_) => Term::from(Number::from(Bignum { value })),
+    }
+}
+
+/// Returns `value` as a JSON number, if it fits in 64 bits.
+fn json_integer(value: &BigInt) -> Option<Value> {
+    if let Ok(i) = i64::try_from(value) {
+        Some(Value::from(i))
+    } else {
+        u64::try_from(value).ok().map(Value::from)
+    }
+}
+
+fn json_number(number: &Number) -> Option<Value> {
+    match number {
+        Number::FixInteger(x) => Some(Value::from(x.value)),
+        Number::Bignum(x) => json_integer(&x.value),
+        Number::Float(x) => JsonNumber::from_f64(x.value).map(Value::Number),
+    }
+}
+
+/// Returns the Erlang text of `term`, as `~tw` writes it.
+fn erlang_text(term: &Term) -> String {
+    let options = PrettyOptions::new()
+        .line_length(0)
+        .strings(false)
+        .unicode(true);
+    pretty(term, &options)
+}
+
+fn utf8_binary(term: &Term) -> Option<&str> {
+    match term {
+        Term::Bitstring(x) if x.is_binary() => std::str::from_utf8(&x.data).ok(),
+        _ => None,
+    }
+}
+
+fn idiomatic_value(term: &Term) -> Value {
+    match term {
+        Term::Number(x) => json_number(x).unwrap_or_else(|| Value::from(erlang_text(term))),
+        Term::Atom(x) => match x.name() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            "null" => Value::Null,
+            name => Value::from(name),
+        },
+        Term::Bitstring(_) => match utf8_binary(term) {
+            Some(s) => Value::from(s),
+            None => Value::from(erlang_text(term)),
+        },
+        Term::Nil(_) => Value::Array(Vec::new()),
+        Term::List(x) if !x.is_improper_list() => {
+            Value::Array(x.elements.iter().map(idiomatic_value).collect())
+        }
+        Term::Tuple(x) => Value::Array(x.elements.iter().map(idiomatic_value).collect()),
+        Term::Map(x) => Value::Object(
+            x.pairs
+                .iter()
+                .map(|(key, value)| {
+                    let key = match (key, utf8_binary(key)) {
+                        (_, Some(s)) => s.to_string(),
+                        (Term::Atom(atom), None) => atom.name().to_string(),
+                        (key, None) => erlang_text(key),
+                    };
+                    (key, idiomatic_value(value))
+                })
+                .collect(),
+        ),
+        _ => Value::from(erlang_text(term)),
+    }
+}
+
+fn tag(name: &str, value: Value) -> Value {
+    let mut object = serde_json::Map::new();
+    object.insert(name.to_string(), value);
+    Value::Object(object)
+}
+
+fn lossless_value(term: &Term) -> Value {
+    match term {
+        Term::Number(Number::Bignum(x)) => json_integer(&x.value)
+            .unwrap_or_else(|| tag("$integer", Value::from(x.value.to_string()))),
+        Term::Number(x) => json_number(x).unwrap_or(Value::Null),
+        Term::Atom(x) => match x.name() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            "null" => Value::Null,
+            name => tag("$atom", Value::from(name)),
+        },
+        Term::Bitstring(x) => match utf8_binary(term) {
+            Some(s) => Value::from(s),
+            None if x.is_binary() => tag("$binary", Value::from(BASE64.encode(&x.data))),
+            None => tag(
+                "$bitstring",
+                serde_json::json!({ "data": BASE64.encode(&x.data), "bits": x.bits }),
+            ),
+        },
+        Term::Nil(_) => Value::Array(Vec::new()),
+        Term::List(x) => {
+            let elements = Value::Array(x.elements.iter().map(lossless_value).collect());
+            if x.is_improper_list() {
+                serde_json::json!({ "$list": elements, "$tail": lossless_value(&x.tail) })
+            } else {
+                elements
+            }
+        }
+        Term::Tuple(x) => tag(
+            "$tuple",
+            Value::Array(x.elements.iter().map(lossless_value).collect()),
+        ),
+        Term::Map(x) => {
+            let plain = x
+                .pairs
+                .iter()
+                .all(|(key, _)| utf8_binary(key).is_some_and(|s| !s.starts_with('$')));
+            if plain {
+                Value::Object(
+                    x.pairs
+                        .iter()
+                        .map(|(key, value)| {
+                            let key = utf8_binary(key).unwrap_or_default().to_string();
+                            (key, lossless_value(value))
+                        })
+                        .collect(),
+                )
+            } else {
+                let pairs = x
+                    .pairs
+                    .iter()
+                    .map(|(key, value)| {
+                        Value::Array(vec![lossless_value(key), lossless_value(value)])
+                    })
+                    .collect();
+                tag("$map", Value::Array(pairs))
+            }
+        }
+        Term::Pid(x) => tag("$pid", pid_value(x)),
+        Term::Port(x) => tag(
+            "$port",
+            serde_json::json!({ "node": x.node.name(), "id": x.id, "creation": x.creation }),
+        ),
+        Term::Reference(x) => tag(
+            "$ref",
+            serde_json::json!({ "node": x.node.name(), "id": x.id, "creation": x.creation }),
+        ),
+        Term::Fun(Fun::ExternalFun(x)) => tag(
+            "$fun",
+            serde_json::json!({
+                "module": x.module.name(),
+                "function": x.function.name(),
+                "arity": x.arity,
+            }),
+        ),
+        Term::Fun(Fun::InternalFun(x)) => {
+            let fun = match x {
+                InternalFun::Old {
+                    module,
+                    pid,
+                    free_vars,
+                    index,
+                    uniq,
+                } => serde_json::json!({
+                    "module": module.name(),
+                    "pid": pid_value(pid),
+                    "free_vars": free_vars.iter().map(lossless_value).collect::<Vec<_>>(),
+                    "index": index,
+                    "uniq": uniq,
+                }),
+                InternalFun::New {
+                    module,
+                    arity,
+                    pid,
+                    index,
+                    uniq,
+                    old_index,
+                    old_uniq,
+                    free_vars,
+                } => serde_json::json!({
+                    "module": module.name(),
+                    "arity": arity,
+                    "pid": pid_value(pid),
+                    "free_vars": free_vars.iter().map(lossless_value).collect::<Vec<_>>(),
+                    "index": index,
+                    "uniq": BASE64.encode(uniq),
+                    "old_index": old_index,
+                    "old_uniq": old_uniq,
+                }),
+            };
+            tag("$fun", fun)
+        }
+    }
+}
+
+fn pid_value(pid: &Pid) -> Value {
+    serde_json::json!({
+        "node": pid.node.name(),
+        "id": pid.id,
+        "serial": pid.serial,
+        "creation": pid.creation,
+    })
+}
+
+/// Reads the object a term was written as in [`Mode::Lossless`], or returns
+/// `None` if `object` is not one.
+fn tagged_term(object: &serde_json::Map<String, Value>) -> Result<Option<Term>, JsonError> {
+    if object.len() == 2 {
+        if let (Some(Value::Array(elements)), Some(tail)) =
+            (object.get("$list"), object.get("$tail"))
+        {
+            let elements = elements
+                .iter()
+                .map(|element| from_value(element, Mode::Lossless))
+                .collect::<Result<Vec<_>, _>>()?;
+            let tail = from_value(tail, Mode::Lossless)?;
+            return Ok(Some(Term::from(List::from((elements, tail)))));
+        }
+    }
+    let (key, value) = match object.iter().next() {
+        Some(entry) if object.len() == 1 => entry,
+        _ => return Ok(None),
+    };
+    let tag: &'static str = match key.as_str() {
+        "$atom" => "$atom",
+        "$integer" => "$integer",
+        "$binary" => "$binary",
+        "$bitstring" => "$bitstring",
+        "$tuple" => "$tuple",
+        "$map" => "$map",
+        "$pid" => "$pid",
+        "$port" => "$port",
+        "$ref" => "$ref",
+        "$fun" => "$fun",
+        _ => return Ok(None),
+    };
+    let invalid = || JsonError::InvalidTag {
+        tag,
+        value: value.clone(),
+    };
+    let term = match tag {
+        "$atom" => value.as_str().map(|name| Term::from(Atom::from(name))),
+        "$integer" => value
+            .as_str()
+            .and_then(|digits| digits.parse::<BigInt>().ok())
+            .map(integer_term),
+        "$binary" => value
+            .as_str()
+            .and_then(|data| BASE64.decode(data).ok())
+            .map(|data| Term::from(Bitstring::from(data))),
+        "$bitstring" => {
+            let data = value["data"]
+                .as_str()
+                .and_then(|data| BASE64.decode(data).ok());
+            // Whole bytes are `$binary`, so the last byte holds 1 to 7 bits.
+            let bits = value["bits"]
+                .as_u64()
+                .filter(|bits| (1..=7).contains(bits))
+                .map(|bits| bits as u8);
+            data.filter(|data| !data.is_empty())
+                .zip(bits)
+                .map(|(data, bits)| Term::from(Bitstring::from((data, bits))))
+        }
+        "$tuple" => match value {
+            Value::Array(elements) => Some(Term::from(Tuple::from(terms(elements)?))),
+            _ => None,
+        },
+        "$map" => match value {
+            Value::Array(pairs) => pairs
+                .iter()
+                .map(|pair| match pair.as_array().map(Vec::as_slice) {
+                    Some([key, value]) => Ok(Some((
+                        from_value(key, Mode::Lossless)?,
+                        from_value(value, Mode::Lossless)?,
+                    ))),
+                    _ => Ok(None),
+                })
+                .collect::<Result<Option<Vec<_>>, JsonError>>()?
+                .map(|pairs| Term::from(Map::from(pairs))),
+            _ => None,
+        },
+        "$pid" => pid(value).map(Term::from),
+        "$port" => (|| {
+            let port = Port::new(
+                value["node"].as_str()?,
+                value["id"].as_u64()?,
+                u32_field(value, "creation")?,
+            );
+            Some(Term::from(port))
+        })(),
+        "$ref" => (|| {
+            let id = value["id"]
+                .as_array()?
+                .iter()
+                .map(|id| id.as_u64().and_then(|id| u32::try_from(id).ok()))
+                .collect::<Option<Vec<_>>>()?;
+            let reference =
+                Reference::new(value["node"].as_str()?, id, u32_field(value, "creation")?);
+            Some(Term::from(reference))
+        })(),
+        _ => fun(value)?,
+    };
+    term.map(Some).ok_or_else(invalid)
+}
+
+fn terms(values: &[Value]) -> Result<Vec<Term>, JsonError> {
+    values
+        .iter()
+        .map(|value| from_value(value, Mode::Lossless))
+        .collect()
+}
+
+fn u32_field(value: &Value, name: &str) -> Option<u32> {
+    value[name].as_u64().and_then(|n| u32::try_from(n).ok())
+}
+
+fn i32_field(value: &Value, name: &str) -> Option<i32> {
+    value[name].as_i64().and_then(|n| i32::try_from(n).ok())
+}
+
+fn pid(value: &Value) -> Option<Pid> {
+    Some(Pid::new(
+        value["node"].as_str()?,
+        u32_field(value, "id")?,
+        u32_field(value, "serial")?,
+        u32_field(value, "creation")?,
+    ))
+}
+
+fn fun(value: &Value) -> Result<Option<Term>, JsonError> {
+    let module = match value["module"].as_str() {
+        Some(module) => Atom::from(module),
+        None => return Ok(None),
+    };
+    if let Some(function) = value["function"].as_str() {
+        let arity = value["arity"].as_u64().and_then(|a| u8::try_from(a).ok());
+        return Ok(arity.map(|arity| {
+            Term::from(Fun::from(ExternalFun {
+                module,
+                function: Atom::from(function),
+                arity,
+            }))
+        }));
+    }
+    let free_vars = match &value["free_vars"] {
+        Value::Array(free_vars) => terms(free_vars)?,
+        _ => return Ok(None),
+    };
+    let fun = (|| {
+        let pid = pid(&value["pid"])?;
+        if value.get("arity").is_none() {
+            return Some(InternalFun::Old {
+                module,
+                pid,
+                free_vars,
+                index: i32_field(value, "index")?,
+                uniq: i32_field(value, "uniq")?,
+            });
+        }
+        let uniq = value["uniq"]
+            .as_str()
+            .and_then(|uniq| BASE64.decode(uniq).ok())?;
+        Some(InternalFun::New {
+            module,
+            arity: value["arity"].as_u64().and_then(|a| u8::try_from(a).ok())?,
+            pid,
+            free_vars,
+            index: u32_field(value, "index")?,
+            uniq: uniq.try_into().ok()?,
+            old_index: i32_field(value, "old_index")?,
+            old_uniq: i32_field(value, "old_uniq")?,
+        })
+    })();
+    Ok(fun.map(|fun| Term::from(Fun::from(fun))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::term;
+    use serde_json::json;
+
+    #[test]
+    fn it_maps_terms_idiomatically() {
+        let t = term("#{<<\"name\">> => <<\"svc\">>, status => ok, port => 8080, ratio => 0.5, tags => [a, <<\"b\">>], pair => {1, null}, enabled => true, id => 99999999999999999999999}");
+        assert_eq!(
+            to_value(&t, Mode::Idiomatic),
+            json!({
+                "name": "svc",
+                "status": "ok",
+                "port": 8080,
+                "ratio": 0.5,
+                "tags": ["a", "b"],
+                "pair": [1, null],
+                "enabled": true,
+                "id": "99999999999999999999999",
+            })
+        );
+        let pid = Term::from(Pid::new("node@host", 1, 2, 3));
+        let odd = Term::from(Tuple::from(vec![
+            pid,
+            term("[1 | 2]"),
+            term("<<255, 1:2>>"),
+        ]));
+        assert_eq!(
+            to_value(&odd, Mode::Idiomatic),
+            json!(["<'node@host'.1.2>", "[1|2]", "<<255,1:2>>"])
+        );
+        assert_eq!(
+            from_str(
+                r#"{"a": [1, 2.5, "x", null, {}], "b": []}"#,
+                Mode::Idiomatic
+            )
+            .unwrap(),
+            term("#{<<\"a\">> => [1, 2.5, <<\"x\">>, null, #{}], <<\"b\">> => []}")
+        );
+    }
+
+    #[test]
+    fn it_round_trips_every_term_losslessly() {
+        let pid = Pid::new("node@host", 1, 2, 3);
+        let terms = vec![
+            term("{ok, 'Quoted atom', 1, -2.5, 36893488147419103232, [], [1, 2 | tail]}"),
+            term("#{<<\"plain\">> => <<\"text\">>, <<\"$atom\">> => <<\"not a tag\">>}"),
+            term("#{1 => one, {a} => [<<\"é\"/utf8>>, <<255, 0>>, <<5:3>>], <<>> => \"\"}"),
+            Term::from(pid.clone()),
+            Term::from(Port::new("node@host", 7, 3)),
+            Term::from(Reference::new("node@host", vec![1, 2, 3], 3)),
+            Term::from(Fun::from(ExternalFun::from(("lists", "map", 2)))),
+            Term::from(Fun::from(InternalFun::New {
+                module: Atom::from("erl_eval"),
+                arity: 1,
+                pid,
+                free_vars: vec![term("{env, [x]}")],
+                index: 5,
+                uniq: [7; 16],
+                old_index: 5,
+                old_uniq: 99,
+            })),
+        ];
+        for t in terms {
+            let text = to_string(&t, Mode::Lossless);
+            assert_eq!(from_str(&text, Mode::Lossless).unwrap(), t, "{}", text);
+        }
+        assert_eq!(
+            to_value(&term("{a, <<255>>, [1 | 2]}"), Mode::Lossless),
+            json!({"$tuple": [{"$atom": "a"}, {"$binary": "/w=="}, {"$list": [1], "$tail": 2}]})
+        );
+        let error = from_str(r#"{"$pid": {"node": "a"}}"#, Mode::Lossless).unwrap_err();
+        assert_eq!(error.to_string(), r#"invalid $pid object: {"node":"a"}"#);
+    }
+
+    #[test]
+    fn it_rejects_malformed_bitstrings() {
+        for text in [
+            r#"{"$bitstring": {"data": "", "bits": 3}}"#,
+            r#"{"$bitstring": {"data": "AQI=", "bits": 0}}"#,
+            r#"{"$bitstring": {"data": "AQI=", "bits": 8}}"#,
+            r#"{"$bitstring": {"data": "AQI=", "bits": 200}}"#,
+        ] {
+            assert!(
+                matches!(
+                    from_str(text, Mode::Lossless),
+                    Err(JsonError::InvalidTag {
+                        tag: "$bitstring",
+                        ..
+                    })
+                ),
+                "{}",
+                text
+            );
+        }
+    }
+}
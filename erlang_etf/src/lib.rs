@@ -1,10 +1,23 @@
+// Lets derived conversions name `::erlang_etf` from inside this crate too.
+extern crate self as erlang_etf;
+
 pub use num_bigint;
 
+#[cfg(feature = "cbor")]
+pub mod cbor;
 pub mod codec;
 pub mod dist;
 pub mod env;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+#[cfg(feature = "serde")]
+pub mod serde;
 pub mod task;
 pub mod term;
+#[cfg(test)]
+mod test_util;
 
 // pub use codec::*;
 pub use task::*;
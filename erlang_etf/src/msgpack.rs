@@ -0,0 +1,521 @@
+//! Lossless conversions between terms and MessagePack values.
+//!
+//! Every term reads back as the term it was written from. Terms which
+//! MessagePack can say directly are written as such:
+//!
+//! | Erlang          | MessagePack                    |
+//! |-----------------|--------------------------------|
+//! | 64-bit integers | integers                       |
+//! | floats          | 64-bit floats                  |
+//! | `true`, `false` | `true`, `false`                |
+//! | binaries        | binaries                       |
+//! | proper lists    | arrays                         |
+//! | maps            | maps                           |
+//!
+//! and the rest as an extension value, with the application types below,
+//! which both ends must agree on:
+//!
+//! | Erlang           | Type                   | Data                                     |
+//! |------------------|------------------------|------------------------------------------|
+//! | other atoms      | [`ATOM_EXT`]           | name as UTF-8                            |
+//! | other integers   | [`INTEGER_EXT`]        | big-endian two's complement              |
+//! | bitstrings       | [`BITSTRING_EXT`]      | bits used in the last byte, then bytes   |
+//! | tuples           | [`TUPLE_EXT`]          | array of elements                        |
+//! | improper lists   | [`IMPROPER_LIST_EXT`]  | `[elements, tail]`                       |
+//! | pids             | [`PID_EXT`]            | `[node, id, serial, creation]`           |
+//! | ports            | [`PORT_EXT`]           | `[node, id, creation]`                   |
+//! | references       | [`REFERENCE_EXT`]      | `[node, ids, creation]`                  |
+//! | funs             | [`FUN_EXT`]            | see below                                |
+//!
+//! Where the data is an array, it is that array written as MessagePack, and
+//! node, module and function names are strings. An external fun is written
+//! as `[module, function, arity]`, a new internal fun as `[module, arity,
+//! pid, free_vars, index, uniq, old_index, old_uniq]`, and an old one as
+//! `[module, pid, free_vars, index, uniq]`.
+//!
+//! When reading MessagePack which was not written from a term, strings read
+//! as binaries and `nil` as the atom `null`.
+
+use rmpv::{Value, ValueRef};
+
+use crate::num_bigint::BigInt;
+use crate::term::*;
+
+/// Deepest nesting of arrays, maps and extension values read into a term.
+const MAX_DEPTH: usize = 128;
+
+/// Extension type of an atom other than `true` or `false`.
+pub const ATOM_EXT: i8 = 1;
+
+/// Extension type of an integer which does not fit in 64 bits.
+pub const INTEGER_EXT: i8 = 2;
+
+/// Extension type of a bitstring whose length is not a whole number of bytes.
+pub const BITSTRING_EXT: i8 = 3;
+
+/// Extension type of a tuple.
+pub const TUPLE_EXT: i8 = 4;
+
+/// Extension type of an improper list.
+pub const IMPROPER_LIST_EXT: i8 = 5;
+
+/// Extension type of a pid.
+pub const PID_EXT: i8 = 6;
+
+/// Extension type of a port.
+pub const PORT_EXT: i8 = 7;
+
+/// Extension type of a reference.
+pub const REFERENCE_EXT: i8 = 8;
+
+/// Extension type of a fun.
+pub const FUN_EXT: i8 = 9;
+
+/// Errors which can occur when reading terms from MessagePack
+#[derive(Debug, thiserror::Error)]
+pub enum MsgpackError {
+    #[error(transparent)]
+    Read(#[from] rmpv::decode::Error),
+
+    #[error("invalid data for extension type {ext_type}: {data:?}")]
+    InvalidExt { ext_type: i8, data: Vec<u8> },
+
+    #[error("unsupported value: {0}")]
+    Unsupported(Value),
+
+    #[error("nesting exceeds the maximum depth of {max_depth}")]
+    DepthLimitExceeded { max_depth: usize },
+}
+
+/// Converts `term` into a MessagePack value.
+pub fn to_value(term: &Term) -> Value {
+    match term {
+        Term::Number(Number::FixInteger(x)) => Value::from(x.value),
+        Term::Number(Number::Bignum(x)) => {
+            if let Ok(i) = i64::try_from(&x.value) {
+                Value::from(i)
+            } else if let Ok(u) = u64::try_from(&x.value) {
+                Value::from(u)
+            } else {
+                Value::Ext(INTEGER_EXT, x.value.to_signed_bytes_be())
+            }
+        }
+        Term::Number(Number::Float(x)) => Value::F64(x.value),
+        Term::Atom(x) => match x.name() {
+            "true" => Value::Boolean(true),
+            "false" => Value::Boolean(false),
+            name => Value::Ext(ATOM_EXT, name.as_bytes().to_vec()),
+        },
+        Term::Bitstring(x) if x.is_binary() => Value::Binary(x.data.to_vec()),
+        Term::Bitstring(x) => {
+            let mut data = vec![x.bits];
+            data.extend_from_slice(&x.data);
+            Value::Ext(BITSTRING_EXT, data)
+        }
+        Term::Nil(_) => Value::Array(Vec::new()),
+        Term::List(x) if x.is_improper_list() => ext(
+            IMPROPER_LIST_EXT,
+            vec![Value::Array(values(&x.elements)), to_value(&x.tail)],
+        ),
+        Term::List(x) => Value::Array(values(&x.elements)),
+        Term::Tuple(x) => ext(TUPLE_EXT, values(&x.elements)),
+        Term::Map(x) => Value::Map(
+            x.pairs
+                .iter()
+                .map(|(key, value)| (to_value(key), to_value(value)))
+                .collect(),
+        ),
+        Term::Pid(x) => pid_value(x),
+        Term::Port(x) => ext(
+            PORT_EXT,
+            vec![text(&x.node), Value::from(x.id), Value::from(x.creation)],
+        ),
+        Term::Reference(x) => ext(
+            REFERENCE_EXT,
+            vec![
+                text(&x.node),
+                Value::Array(x.id.iter().map(|&id| Value::from(id)).collect()),
+                Value::from(x.creation),
+            ],
+        ),
+        Term::Fun(Fun::ExternalFun(x)) => ext(
+            FUN_EXT,
+            vec![text(&x.module), text(&x.function), Value::from(x.arity)],
+        ),
+        Term::Fun(Fun::InternalFun(InternalFun::Old {
+            module,
+            pid,
+            free_vars,
+            index,
+            uniq,
+        })) => ext(
+            FUN_EXT,
+            vec![
+                text(module),
+                pid_value(pid),
+                Value::Array(values(free_vars)),
+                Value::from(*index),
+                Value::from(*uniq),
+            ],
+        ),
+        Term::Fun(Fun::InternalFun(InternalFun::New {
+            module,
+            arity,
+            pid,
+            free_vars,
+            index,
+            uniq,
+            old_index,
+            old_uniq,
+        })) => ext(
+            FUN_EXT,
+            vec![
+                text(module),
+                Value::from(*arity),
+                pid_value(pid),
+                Value::Array(values(free_vars)),
+                Value::from(*index),
+                Value::Binary(uniq.to_vec()),
+                Value::from(*old_index),
+                Value::from(*old_uniq),
+            ],
+        ),
+    }
+}
+
+/// Converts a MessagePack value into a term.
+pub fn from_value(value: &Value) -> Result<Term, MsgpackError> {
+    value_term(&value.as_ref(), 0)
+}
+
+/// Writes `term` as MessagePack.
+pub fn to_vec(term: &Term) -> Vec<u8> {
+    let mut buf = Vec::new();
+    rmpv::encode::write_value(&mut buf, &to_value(term)).expect("writing to a Vec cannot fail");
+    buf
+}
+
+/// Reads a term from MessagePack.
+pub fn from_slice(mut bytes: &[u8]) -> Result<Term, MsgpackError> {
+    let value = rmpv::decode::read_value_ref(&mut bytes)?;
+    value_term(&value, 0)
+}
+
+/// Returns an extension value whose data is `fields` written as an array.
+fn ext(ext_type: i8, fields: Vec<Value>) -> Value {
+    let mut data = Vec::new();
+    rmpv::encode::write_value(&mut data, &Value::Array(fields))
+        .expect("writing to a Vec cannot fail");
+    Value::Ext(ext_type, data)
+}
+
+fn text(atom: &Atom) -> Value {
+    Value::from(atom.name())
+}
+
+fn values(terms: &[Term]) -> Vec<Value> {
+    terms.iter().map(to_value).collect()
+}
+
+fn pid_value(pid: &Pid) -> Value {
+    ext(
+        PID_EXT,
+        vec![
+            text(&pid.node),
+            Value::from(pid.id),
+            Value::from(pid.serial),
+            Value::from(pid.creation),
+        ],
+    )
+}
+
+/// Reads a term from a value nested `depth` levels deep.
+fn value_term(value: &ValueRef, depth: usize) -> Result<Term, MsgpackError> {
+    if depth > MAX_DEPTH {
+        return Err(MsgpackError::DepthLimitExceeded {
+            max_depth: MAX_DEPTH,
+        });
+    }
+    Ok(match value {
+        ValueRef::Nil => Term::from(Atom::from("null")),
+        ValueRef::Boolean(x) => Term::from(Atom::from(if *x { "true" } else { "false" })),
+        ValueRef::Integer(x) => match (x.as_i64(), x.as_u64()) {
+            (Some(i), _) => Term::from(i),
+            (_, Some(u)) => Term::from(u),
+            _ => return Err(MsgpackError::Unsupported(value.to_owned())),
+        },
+        ValueRef::F32(x) => Term::from(f64::from(*x)),
+        ValueRef::F64(x) => Term::from(*x),
+        ValueRef::String(x) => Term::from(Bitstring::from(x.as_bytes())),
+        ValueRef::Binary(x) => Term::from(Bitstring::from(*x)),
+        ValueRef::Array(x) if x.is_empty() => Term::from(Nil),
+        ValueRef::Array(x) => Term::from(List::from(terms(x, depth + 1)?)),
+        ValueRef::Map(x) => Term::from(Map::from(
+            x.iter()
+                .map(|(key, value)| {
+                    Ok((value_term(key, depth + 1)?, value_term(value, depth + 1)?))
+                })
+                .collect::<Result<Vec<_>, MsgpackError>>()?,
+        )),
+        ValueRef::Ext(ext_type, data) => {
+            ext_term(*ext_type, data, depth + 1)?.ok_or_else(|| MsgpackError::InvalidExt {
+                ext_type: *ext_type,
+                data: data.to_vec(),
+            })?
+        }
+    })
+}
+
+fn terms(values: &[ValueRef], depth: usize) -> Result<Vec<Term>, MsgpackError> {
+    values
+        .iter()
+        .map(|value| value_term(value, depth))
+        .collect()
+}
+
+fn u32_value(value: &ValueRef) -> Option<u32> {
+    value.as_u64().and_then(|x| u32::try_from(x).ok())
+}
+
+fn i32_value(value: &ValueRef) -> Option<i32> {
+    match value {
+        ValueRef::Integer(x) => x.as_i64().and_then(|x| i32::try_from(x).ok()),
+        _ => None,
+    }
+}
+
+fn u8_value(value: &ValueRef) -> Option<u8> {
+    value.as_u64().and_then(|x| u8::try_from(x).ok())
+}
+
+fn atom_value(value: &ValueRef) -> Option<Atom> {
+    match value {
+        ValueRef::String(x) => x.as_str().map(Atom::from),
+        _ => None,
+    }
+}
+
+/// Reads the array of fields an extension value's data was written from,
+/// borrowing strings and binaries from `data`.
+fn fields<'a>(mut data: &'a [u8]) -> Option<Vec<ValueRef<'a>>> {
+    match rmpv::decode::read_value_ref(&mut data) {
+        Ok(ValueRef::Array(fields)) if data.is_empty() => Some(fields),
+        _ => None,
+    }
+}
+
+fn pid(value: &ValueRef) -> Option<Pid> {
+    match value {
+        ValueRef::Ext(PID_EXT, data) => pid_fields(&fields(data)?),
+        _ => None,
+    }
+}
+
+fn pid_fields(fields: &[ValueRef]) -> Option<Pid> {
+    match fields {
+        [node, id, serial, creation] => Some(Pid::new(
+            atom_value(node)?,
+            u32_value(id)?,
+            u32_value(serial)?,
+            u32_value(creation)?,
+        )),
+        _ => None,
+    }
+}
+
+/// Reads the term an extension value was written from, or returns `None` if
+/// its data is not what the type calls for.
+fn ext_term(ext_type: i8, data: &[u8], depth: usize) -> Result<Option<Term>, MsgpackError> {
+    let term = match ext_type {
+        ATOM_EXT => std::str::from_utf8(data)
+            .ok()
+            .map(|name| Term::from(Atom::from(name))),
+        INTEGER_EXT if !data.is_empty() => {
+            let value = BigInt::from_signed_bytes_be(data);
+            Some(match i32::try_from(&value) {
+                Ok(value) => Term::from(value),
+                Err(_) => Term::from(Number::from(Bignum { value })),
+            })
+        }
+        // Whole bytes are plain binaries, so the last byte holds 1 to 7 bits.
+        BITSTRING_EXT => data
+            .split_first()
+            .filter(|(bits, data)| !data.is_empty() && (1..=7).contains(*bits))
+            .map(|(&bits, data)| Term::from(Bitstring::from((data.to_vec(), bits)))),
+        _ => match fields(data) {
+            Some(fields) => fields_term(ext_type, &fields, depth)?,
+            None => None,
+        },
+    };
+    Ok(term)
+}
+
+fn fields_term(
+    ext_type: i8,
+    fields: &[ValueRef],
+    depth: usize,
+) -> Result<Option<Term>, MsgpackError> {
+    Ok(match (ext_type, fields) {
+        (TUPLE_EXT, elements) => Some(Term::from(Tuple::from(terms(elements, depth)?))),
+        (IMPROPER_LIST_EXT, [ValueRef::Array(elements), tail]) => Some(Term::from(List::from((
+            terms(elements, depth + 1)?,
+            value_term(tail, depth)?,
+        )))),
+        (PID_EXT, _) => pid_fields(fields).map(Term::from),
+        (PORT_EXT, [node, id, creation]) => (|| {
+            Some(Term::from(Port::new(
+                atom_value(node)?,
+                id.as_u64()?,
+                u32_value(creation)?,
+            )))
+        })(),
+        (REFERENCE_EXT, [node, ValueRef::Array(id), creation]) => (|| {
+            let id = id.iter().map(u32_value).collect::<Option<Vec<_>>>()?;
+            Some(Term::from(Reference::new(
+                atom_value(node)?,
+                id,
+                u32_value(creation)?,
+            )))
+        })(),
+        (FUN_EXT, [module, function, arity]) => (|| {
+            Some(Term::from(Fun::from(ExternalFun {
+                module: atom_value(module)?,
+                function: atom_value(function)?,
+                arity: u8_value(arity)?,
+            })))
+        })(),
+        (FUN_EXT, [module, pid_value, ValueRef::Array(free_vars), index, uniq]) => {
+            let free_vars = terms(free_vars, depth + 1)?;
+            (|| {
+                Some(Term::from(Fun::from(InternalFun::Old {
+                    module: atom_value(module)?,
+                    pid: pid(pid_value)?,
+                    free_vars,
+                    index: i32_value(index)?,
+                    uniq: i32_value(uniq)?,
+                })))
+            })()
+        }
+        (
+            FUN_EXT,
+            [module, arity, pid_value, ValueRef::Array(free_vars), index, uniq, old_index, old_uniq],
+        ) => {
+            let free_vars = terms(free_vars, depth + 1)?;
+            (|| {
+                Some(Term::from(Fun::from(InternalFun::New {
+                    module: atom_value(module)?,
+                    arity: u8_value(arity)?,
+                    pid: pid(pid_value)?,
+                    free_vars,
+                    index: u32_value(index)?,
+                    uniq: match uniq {
+                        ValueRef::Binary(x) => (*x).try_into().ok()?,
+                        _ => return None,
+                    },
+                    old_index: i32_value(old_index)?,
+                    old_uniq: i32_value(old_uniq)?,
+                })))
+            })()
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::term;
+
+    #[test]
+    fn it_round_trips_every_term() {
+        let pid = Pid::new("node@host", 1, 2, 3);
+        let terms = vec![
+            term("{ok, 'Quoted atom', true, null, 1, -2.5, 18446744073709551615, -9223372036854775809}"),
+            term("[340282366920938463463374607431768211456, -340282366920938463463374607431768211457]"),
+            term("#{1 => one, {a} => [<<\"é\"/utf8>>, <<255, 0>>, <<5:3>>], <<>> => \"\"}"),
+            term("[[], {}, [1, 2 | tail], <<>>]"),
+            Term::from(pid.clone()),
+            Term::from(Port::new("node@host", 7, 3)),
+            Term::from(Reference::new("node@host", vec![1, 2, 3], 3)),
+            Term::from(Fun::from(ExternalFun::from(("lists", "map", 2)))),
+            Term::from(Fun::from(InternalFun::Old {
+                module: Atom::from("erl_eval"),
+                pid: pid.clone(),
+                free_vars: vec![],
+                index: 1,
+                uniq: -7,
+            })),
+            Term::from(Fun::from(InternalFun::New {
+                module: Atom::from("erl_eval"),
+                arity: 1,
+                pid,
+                free_vars: vec![term("{env, [x]}")],
+                index: 5,
+                uniq: [7; 16],
+                old_index: 5,
+                old_uniq: 99,
+            })),
+        ];
+        for t in terms {
+            assert_eq!(from_slice(&to_vec(&t)).unwrap(), t);
+        }
+    }
+
+    #[test]
+    fn it_reads_plain_msgpack() {
+        let value = Value::Map(vec![
+            (Value::from("name"), Value::from("svc")),
+            (Value::from("tags"), Value::Array(vec![Value::Nil])),
+        ]);
+        assert_eq!(
+            from_value(&value).unwrap(),
+            term("#{<<\"name\">> => <<\"svc\">>, <<\"tags\">> => [null]}")
+        );
+        let invalid = Value::Ext(PID_EXT, vec![0xc0]);
+        assert!(matches!(
+            from_value(&invalid),
+            Err(MsgpackError::InvalidExt {
+                ext_type: PID_EXT,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn it_rejects_malformed_bitstrings() {
+        for data in [vec![], vec![3], vec![0, 1, 2], vec![8, 1, 2]] {
+            let invalid = Value::Ext(BITSTRING_EXT, data);
+            assert!(matches!(
+                from_value(&invalid),
+                Err(MsgpackError::InvalidExt {
+                    ext_type: BITSTRING_EXT,
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn it_limits_nesting() {
+        let mut nested = term("ok");
+        for _ in 0..MAX_DEPTH / 2 {
+            nested = Term::from(Tuple::from(vec![nested]));
+        }
+        assert_eq!(from_slice(&to_vec(&nested)).unwrap(), nested);
+
+        // Built from the inside out: each level is a tuple extension value
+        // whose data is a one element array holding the next level.
+        let mut reversed = vec![0x90];
+        for _ in 0..100_000 {
+            let len = reversed.len() as u32;
+            reversed.push(TUPLE_EXT as u8);
+            reversed.extend(len.to_le_bytes());
+            reversed.extend([0xc9, 0x91]);
+        }
+        let bytes: Vec<u8> = reversed.into_iter().rev().skip(1).collect();
+        assert!(matches!(
+            from_slice(&bytes),
+            Err(MsgpackError::DepthLimitExceeded { max_depth: 128 })
+        ));
+    }
+}
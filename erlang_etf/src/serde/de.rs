@@ -0,0 +1,929 @@
+use byteorder::{BigEndian, ByteOrder};
+use flate2::read::ZlibDecoder;
+use serde::de::{self, value::SeqDeserializer, DeserializeOwned, DeserializeSeed, Visitor};
+use serde::Deserialize;
+
+use std::borrow::Cow;
+use std::io::Read;
+
+use super::error::Error;
+use crate::codec::external as ext;
+
+type Result<T> = std::result::Result<T, Error>;
+
+const MAX_DEPTH: usize = 128;
+
+/// Deserializes a `T` from an external term, including the version byte,
+/// borrowing strings and bytes from `bytes` where possible.
+///
+/// Compressed terms cannot be borrowed from; use [`from_reader`] for those.
+pub fn from_slice<'de, T>(bytes: &'de [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    let mut deserializer = Deserializer::new(check_version(bytes)?);
+    if deserializer.peek_tag()? == ext::COMPRESSED {
+        return Err(Error::UnexpectedTag {
+            tag: ext::COMPRESSED,
+            expected: "an uncompressed term",
+        });
+    }
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+/// Deserializes a `T` from an external term, including the version byte,
+/// read to the end of `reader`. Compressed terms are inflated first.
+pub fn from_reader<R, T>(mut reader: R) -> Result<T>
+where
+    R: Read,
+    T: DeserializeOwned,
+{
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    let input = check_version(&buf)?;
+    if input.first() != Some(&ext::COMPRESSED) {
+        return from_slice(&buf);
+    }
+    let mut deserializer = Deserializer::new(input);
+    deserializer.take(1)?;
+    let len = deserializer.parse_u32()? as usize;
+    let mut inflated = Vec::with_capacity(len.min(1 << 20));
+    ZlibDecoder::new(deserializer.input)
+        .take(len as u64 + 1)
+        .read_to_end(&mut inflated)?;
+    if inflated.len() != len {
+        return Err(Error::Message(format!(
+            "compressed term inflated to {} bytes instead of {}",
+            inflated.len(),
+            len
+        )));
+    }
+    let mut deserializer = Deserializer::new(&inflated);
+    let value = T::deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+fn check_version(bytes: &[u8]) -> Result<&[u8]> {
+    match bytes.split_first() {
+        Some((&ext::VERSION_MAGIC, rest)) => Ok(rest),
+        Some((&version, _)) => Err(Error::UnsupportedVersion { version }),
+        None => Err(Error::Eof),
+    }
+}
+
+/// A serde deserializer which reads a term in the external term format,
+/// without the leading version byte, straight from a byte slice.
+///
+/// See the [module documentation](super) for how terms map to Rust values.
+#[derive(Debug)]
+pub struct Deserializer<'de> {
+    input: &'de [u8],
+    depth: usize,
+}
+impl<'de> Deserializer<'de> {
+    pub fn new(input: &'de [u8]) -> Self {
+        Self { input, depth: 0 }
+    }
+
+    /// Checks that the whole input has been consumed.
+    pub fn end(&self) -> Result<()> {
+        match self.input.len() {
+            0 => Ok(()),
+            len => Err(Error::TrailingBytes { len }),
+        }
+    }
+
+    fn peek_tag(&self) -> Result<u8> {
+        self.input.first().copied().ok_or(Error::Eof)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'de [u8]> {
+        if self.input.len() < len {
+            return Err(Error::Eof);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
+
+    fn parse_u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn parse_u16(&mut self) -> Result<u16> {
+        Ok(BigEndian::read_u16(self.take(2)?))
+    }
+
+    fn parse_u32(&mut self) -> Result<u32> {
+        Ok(BigEndian::read_u32(self.take(4)?))
+    }
+
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(Error::DepthLimitExceeded {
+                max_depth: MAX_DEPTH,
+            });
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    /// Returns the name of the atom at the front of the input without
+    /// consuming it, if there is one.
+    fn peek_atom(&self) -> Result<Option<Cow<'de, str>>> {
+        match self.peek_tag()? {
+            ext::ATOM_EXT | ext::SMALL_ATOM_EXT | ext::ATOM_UTF8_EXT | ext::SMALL_ATOM_UTF8_EXT => {
+                Self::new(self.input).parse_atom().map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Cow<'de, str>> {
+        let tag = self.parse_u8()?;
+        let len = match tag {
+            ext::ATOM_EXT | ext::ATOM_UTF8_EXT => self.parse_u16()? as usize,
+            ext::SMALL_ATOM_EXT | ext::SMALL_ATOM_UTF8_EXT => self.parse_u8()? as usize,
+            tag => {
+                return Err(Error::UnexpectedTag {
+                    tag,
+                    expected: "an atom",
+                })
+            }
+        };
+        let bytes = self.take(len)?;
+        match tag {
+            ext::ATOM_UTF8_EXT | ext::SMALL_ATOM_UTF8_EXT => {
+                Ok(Cow::Borrowed(std::str::from_utf8(bytes)?))
+            }
+            _ => Ok(latin1_to_str(bytes)),
+        }
+    }
+
+    fn parse_tuple_header(&mut self) -> Result<usize> {
+        match self.parse_u8()? {
+            ext::SMALL_TUPLE_EXT => Ok(self.parse_u8()? as usize),
+            ext::LARGE_TUPLE_EXT => Ok(self.parse_u32()? as usize),
+            tag => Err(Error::UnexpectedTag {
+                tag,
+                expected: "a tuple",
+            }),
+        }
+    }
+
+    /// Parses an integer which fits in 128 bits, returning whether it is
+    /// negative and its magnitude.
+    fn parse_integer(&mut self) -> Result<(bool, u128)> {
+        match self.parse_u8()? {
+            ext::SMALL_INTEGER_EXT => Ok((false, self.parse_u8()?.into())),
+            ext::INTEGER_EXT => {
+                let value = self.parse_u32()? as i32;
+                Ok((value < 0, value.unsigned_abs().into()))
+            }
+            tag @ (ext::SMALL_BIG_EXT | ext::LARGE_BIG_EXT) => {
+                let n = match tag {
+                    ext::SMALL_BIG_EXT => self.parse_u8()? as usize,
+                    _ => self.parse_u32()? as usize,
+                };
+                let negative = self.parse_u8()? != 0;
+                let digits = self.take(n)?;
+                let mut magnitude = 0u128;
+                for (i, &digit) in digits.iter().enumerate() {
+                    if digit == 0 {
+                        continue;
+                    }
+                    if i >= 16 {
+                        return Err(Error::IntegerOutOfRange);
+                    }
+                    magnitude |= u128::from(digit) << (8 * i);
+                }
+                Ok((negative, magnitude))
+            }
+            tag => Err(Error::UnexpectedTag {
+                tag,
+                expected: "an integer",
+            }),
+        }
+    }
+
+    fn parse_float_ext(&mut self) -> Result<f64> {
+        let bytes = self.take(31)?;
+        let text = std::str::from_utf8(bytes)?.trim_end_matches('\0');
+        text.parse()
+            .map_err(|_| Error::Message(format!("invalid float {:?}", text)))
+    }
+
+    /// Parses a list of character codes into a string.
+    fn parse_charlist(&mut self) -> Result<Cow<'de, str>> {
+        match self.parse_u8()? {
+            ext::NIL_EXT => Ok(Cow::Borrowed("")),
+            ext::STRING_EXT => {
+                let len = self.parse_u16()? as usize;
+                Ok(latin1_to_str(self.take(len)?))
+            }
+            ext::LIST_EXT => {
+                let len = self.parse_u32()? as usize;
+                let mut string = String::new();
+                for _ in 0..len {
+                    let c = match self.parse_integer()? {
+                        (false, code) => u32::try_from(code).ok().and_then(char::from_u32),
+                        (true, _) => None,
+                    };
+                    string.push(c.ok_or_else(|| {
+                        Error::Message("list element is not a character code".to_string())
+                    })?);
+                }
+                self.parse_list_tail()?;
+                Ok(Cow::Owned(string))
+            }
+            tag => Err(Error::UnexpectedTag {
+                tag,
+                expected: "a string",
+            }),
+        }
+    }
+
+    fn parse_list_tail(&mut self) -> Result<()> {
+        match self.parse_u8()? {
+            ext::NIL_EXT => Ok(()),
+            tag => Err(Error::ImproperList { tag }),
+        }
+    }
+
+    /// Skips over the term at the front of the input, including terms such
+    /// as pids which have no serde representation.
+    fn skip_term(&mut self) -> Result<()> {
+        self.enter()?;
+        let tag = self.parse_u8()?;
+        match tag {
+            ext::SMALL_INTEGER_EXT => self.skip(1)?,
+            ext::INTEGER_EXT => self.skip(4)?,
+            ext::FLOAT_EXT => self.skip(31)?,
+            ext::NEW_FLOAT_EXT => self.skip(8)?,
+            ext::ATOM_EXT | ext::ATOM_UTF8_EXT => {
+                let len = self.parse_u16()? as usize;
+                self.skip(len)?;
+            }
+            ext::SMALL_ATOM_EXT | ext::SMALL_ATOM_UTF8_EXT => {
+                let len = self.parse_u8()? as usize;
+                self.skip(len)?;
+            }
+            ext::SMALL_BIG_EXT => {
+                let n = self.parse_u8()? as usize;
+                self.skip(1 + n)?;
+            }
+            ext::LARGE_BIG_EXT => {
+                let n = self.parse_u32()? as usize;
+                self.skip(1 + n)?;
+            }
+            ext::NIL_EXT => {}
+            ext::STRING_EXT => {
+                let len = self.parse_u16()? as usize;
+                self.skip(len)?;
+            }
+            ext::BINARY_EXT => {
+                let len = self.parse_u32()? as usize;
+                self.skip(len)?;
+            }
+            ext::BIT_BINARY_EXT => {
+                let len = self.parse_u32()? as usize;
+                self.skip(1 + len)?;
+            }
+            ext::LIST_EXT => {
+                let len = self.parse_u32()?;
+                for _ in 0..=len {
+                    self.skip_term()?;
+                }
+            }
+            ext::SMALL_TUPLE_EXT | ext::LARGE_TUPLE_EXT => {
+                let arity = match tag {
+                    ext::SMALL_TUPLE_EXT => self.parse_u8()? as u32,
+                    _ => self.parse_u32()?,
+                };
+                for _ in 0..arity {
+                    self.skip_term()?;
+                }
+            }
+            ext::MAP_EXT => {
+                let len = self.parse_u32()?;
+                for _ in 0..len {
+                    self.skip_term()?;
+                    self.skip_term()?;
+                }
+            }
+            ext::PID_EXT => {
+                self.skip_term()?;
+                self.skip(9)?;
+            }
+            ext::NEW_PID_EXT => {
+                self.skip_term()?;
+                self.skip(12)?;
+            }
+            ext::PORT_EXT => {
+                self.skip_term()?;
+                self.skip(5)?;
+            }
+            ext::NEW_PORT_EXT => {
+                self.skip_term()?;
+                self.skip(8)?;
+            }
+            ext::V4_PORT_EXT => {
+                self.skip_term()?;
+                self.skip(12)?;
+            }
+            ext::REFERENCE_EXT => {
+                self.skip_term()?;
+                self.skip(5)?;
+            }
+            ext::NEW_REFERENCE_EXT | ext::NEWER_REFERENCE_EXT => {
+                let len = self.parse_u16()? as usize;
+                self.skip_term()?;
+                let creation = if tag == ext::NEW_REFERENCE_EXT { 1 } else { 4 };
+                self.skip(creation + 4 * len)?;
+            }
+            ext::EXPORT_EXT => {
+                for _ in 0..3 {
+                    self.skip_term()?;
+                }
+            }
+            ext::NEW_FUN_EXT => {
+                let size = self.parse_u32()? as usize;
+                self.skip(size.saturating_sub(4))?;
+            }
+            tag => return Err(Error::UnsupportedTag { tag }),
+        }
+        self.leave();
+        Ok(())
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.take(len).map(|_| ())
+    }
+}
+
+fn latin1_to_str(bytes: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(s) if bytes.is_ascii() => Cow::Borrowed(s),
+        _ => Cow::Owned(bytes.iter().copied().map(char::from).collect()),
+    }
+}
+
+fn visit_str<'de, V: Visitor<'de>>(visitor: V, s: Cow<'de, str>) -> Result<V::Value> {
+    match s {
+        Cow::Borrowed(s) => visitor.visit_borrowed_str(s),
+        Cow::Owned(s) => visitor.visit_string(s),
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut Deserializer<'de> {
+    type Error = Error;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let tag = self.peek_tag()?;
+        match tag {
+            ext::SMALL_INTEGER_EXT => {
+                self.take(1)?;
+                visitor.visit_u8(self.parse_u8()?)
+            }
+            ext::INTEGER_EXT => {
+                self.take(1)?;
+                visitor.visit_i32(self.parse_u32()? as i32)
+            }
+            ext::SMALL_BIG_EXT | ext::LARGE_BIG_EXT => match self.parse_integer()? {
+                (false, n) => match u64::try_from(n) {
+                    Ok(n) => visitor.visit_u64(n),
+                    Err(_) => visitor.visit_u128(n),
+                },
+                (true, n) => match i64::try_from(n) {
+                    Ok(n) => visitor.visit_i64(-n),
+                    Err(_) if n == i64::MIN.unsigned_abs().into() => visitor.visit_i64(i64::MIN),
+                    Err(_) => match i128::try_from(n) {
+                        Ok(n) => visitor.visit_i128(-n),
+                        Err(_) if n == i128::MIN.unsigned_abs() => visitor.visit_i128(i128::MIN),
+                        Err(_) => Err(Error::IntegerOutOfRange),
+                    },
+                },
+            },
+            ext::NEW_FLOAT_EXT => {
+                self.take(1)?;
+                visitor.visit_f64(f64::from_bits(BigEndian::read_u64(self.take(8)?)))
+            }
+            ext::FLOAT_EXT => {
+                self.take(1)?;
+                visitor.visit_f64(self.parse_float_ext()?)
+            }
+            ext::ATOM_EXT | ext::SMALL_ATOM_EXT | ext::ATOM_UTF8_EXT | ext::SMALL_ATOM_UTF8_EXT => {
+                match self.parse_atom()? {
+                    Cow::Borrowed("true") => visitor.visit_bool(true),
+                    Cow::Borrowed("false") => visitor.visit_bool(false),
+                    name => visit_str(visitor, name),
+                }
+            }
+            ext::BINARY_EXT => {
+                self.take(1)?;
+                let len = self.parse_u32()? as usize;
+                visitor.visit_borrowed_bytes(self.take(len)?)
+            }
+            ext::BIT_BINARY_EXT => Err(Error::UnsupportedTag { tag }),
+            ext::NIL_EXT => {
+                self.take(1)?;
+                visitor.visit_seq(SeqDeserializer::new(std::iter::empty::<u8>()))
+            }
+            ext::STRING_EXT => {
+                self.take(1)?;
+                let len = self.parse_u16()? as usize;
+                let mut seq = SeqDeserializer::<_, Error>::new(self.take(len)?.iter().copied());
+                let value = visitor.visit_seq(&mut seq)?;
+                seq.end()?;
+                Ok(value)
+            }
+            ext::LIST_EXT => {
+                self.take(1)?;
+                let len = self.parse_u32()? as usize;
+                self.enter()?;
+                let value = self.visit_elements(len, visitor)?;
+                self.leave();
+                self.parse_list_tail()?;
+                Ok(value)
+            }
+            ext::SMALL_TUPLE_EXT | ext::LARGE_TUPLE_EXT => {
+                let arity = self.parse_tuple_header()?;
+                self.enter()?;
+                let value = self.visit_elements(arity, visitor)?;
+                self.leave();
+                Ok(value)
+            }
+            ext::MAP_EXT => {
+                self.take(1)?;
+                let len = self.parse_u32()? as usize;
+                self.enter()?;
+                let mut entries = Entries { de: self, len };
+                let value = visitor.visit_map(&mut entries)?;
+                if entries.len > 0 {
+                    return Err(de::Error::invalid_length(len, &"fewer map entries"));
+                }
+                self.leave();
+                Ok(value)
+            }
+            ext::PID_EXT
+            | ext::NEW_PID_EXT
+            | ext::PORT_EXT
+            | ext::NEW_PORT_EXT
+            | ext::V4_PORT_EXT
+            | ext::REFERENCE_EXT
+            | ext::NEW_REFERENCE_EXT
+            | ext::NEWER_REFERENCE_EXT
+            | ext::EXPORT_EXT
+            | ext::NEW_FUN_EXT
+            | ext::FUN_EXT
+            | ext::ATOM_CACHE_REF
+            | ext::COMPRESSED => Err(Error::UnsupportedTag { tag }),
+            tag => Err(Error::UnknownTag { tag }),
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    /// Accepts a character code, or a string of one character.
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek_tag()? {
+            ext::SMALL_INTEGER_EXT | ext::INTEGER_EXT => {
+                let (negative, code) = self.parse_integer()?;
+                match u32::try_from(code).ok().and_then(char::from_u32) {
+                    Some(c) if !negative => visitor.visit_char(c),
+                    _ => Err(Error::Message(format!(
+                        "{}{} is not a character code",
+                        if negative { "-" } else { "" },
+                        code
+                    ))),
+                }
+            }
+            _ => self.deserialize_str(visitor),
+        }
+    }
+
+    /// Accepts a UTF-8 binary, an atom or a list of character codes.
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek_tag()? {
+            ext::BINARY_EXT => {
+                self.take(1)?;
+                let len = self.parse_u32()? as usize;
+                visitor.visit_borrowed_str(std::str::from_utf8(self.take(len)?)?)
+            }
+            ext::ATOM_EXT | ext::SMALL_ATOM_EXT | ext::ATOM_UTF8_EXT | ext::SMALL_ATOM_UTF8_EXT => {
+                visit_str(visitor, self.parse_atom()?)
+            }
+            ext::NIL_EXT | ext::STRING_EXT | ext::LIST_EXT => {
+                visit_str(visitor, self.parse_charlist()?)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    /// Accepts a binary, or a list of bytes.
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek_tag()? {
+            ext::NIL_EXT => {
+                self.take(1)?;
+                visitor.visit_borrowed_bytes(&[])
+            }
+            ext::STRING_EXT => {
+                self.take(1)?;
+                let len = self.parse_u16()? as usize;
+                visitor.visit_borrowed_bytes(self.take(len)?)
+            }
+            ext::LIST_EXT => {
+                self.take(1)?;
+                let len = self.parse_u32()? as usize;
+                let mut bytes = Vec::new();
+                for _ in 0..len {
+                    match self.parse_integer()? {
+                        (false, byte) if byte <= 255 => bytes.push(byte as u8),
+                        _ => return Err(Error::Message("list element is not a byte".to_string())),
+                    }
+                }
+                self.parse_list_tail()?;
+                visitor.visit_byte_buf(bytes)
+            }
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    /// Treats the atoms `undefined` and `nil` as `None`.
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek_atom()?.as_deref() {
+            Some("undefined" | "nil") => {
+                self.parse_atom()?;
+                visitor.visit_none()
+            }
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    /// Accepts the empty tuple.
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.parse_tuple_header()? {
+            0 => visitor.visit_unit(),
+            arity => Err(de::Error::invalid_length(arity, &"an empty tuple")),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    /// Accepts an atom for a unit variant, or a tuple tagged with an atom
+    /// for any other variant.
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.peek_tag()? {
+            ext::SMALL_TUPLE_EXT | ext::LARGE_TUPLE_EXT => {
+                let arity = self.parse_tuple_header()?;
+                if arity == 0 {
+                    return Err(de::Error::invalid_length(0, &"a tagged tuple"));
+                }
+                self.enter()?;
+                let value = visitor.visit_enum(Variant { de: self, arity })?;
+                self.leave();
+                Ok(value)
+            }
+            _ => visitor.visit_enum(Variant { de: self, arity: 1 }),
+        }
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        self.skip_term()?;
+        visitor.visit_unit()
+    }
+}
+
+impl<'de> Deserializer<'de> {
+    fn visit_elements<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        let mut elements = Elements { de: self, len };
+        let value = visitor.visit_seq(&mut elements)?;
+        if elements.len > 0 {
+            return Err(de::Error::invalid_length(len, &"fewer elements"));
+        }
+        Ok(value)
+    }
+}
+
+struct Elements<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+}
+impl<'de, 'a> de::SeqAccess<'de> for Elements<'a, 'de> {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+struct Entries<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    len: usize,
+}
+impl<'de, 'a> de::MapAccess<'de> for Entries<'a, 'de> {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        seed.deserialize(&mut *self.de)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+/// An enum variant: a bare atom when `arity` is 1, otherwise the elements
+/// of a tagged tuple after its opening tuple header.
+struct Variant<'a, 'de> {
+    de: &'a mut Deserializer<'de>,
+    arity: usize,
+}
+impl<'de, 'a> de::EnumAccess<'de> for Variant<'a, 'de> {
+    type Error = Error;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let name = self.de.parse_atom()?;
+        let value = seed.deserialize(de::value::CowStrDeserializer::<Error>::new(name))?;
+        Ok((value, self))
+    }
+}
+impl<'de, 'a> de::VariantAccess<'de> for Variant<'a, 'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.arity {
+            1 => Ok(()),
+            arity => Err(de::Error::invalid_length(arity - 1, &"a unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        match self.arity {
+            2 => seed.deserialize(self.de),
+            arity => Err(de::Error::invalid_length(arity - 1, &"a newtype variant")),
+        }
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        if self.arity != len + 1 {
+            return Err(de::Error::invalid_length(self.arity - 1, &visitor));
+        }
+        self.de.visit_elements(len, visitor)
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.arity {
+            2 => de::Deserializer::deserialize_map(self.de, visitor),
+            arity => Err(de::Error::invalid_length(arity - 1, &"a struct variant")),
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt::Display;
+
+/// Errors which can occur when serializing to or deserializing from the
+/// external term format with serde
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Message(String),
+
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+
+    #[error("the format version {version} is unsupported")]
+    UnsupportedVersion { version: u8 },
+
+    #[error("unknown tag {tag}")]
+    UnknownTag { tag: u8 },
+
+    #[error("terms with tag {tag} have no serde representation")]
+    UnsupportedTag { tag: u8 },
+
+    #[error("unexpected end of input")]
+    Eof,
+
+    #[error("{len} bytes remain after the term")]
+    TrailingBytes { len: usize },
+
+    #[error("expected {expected}, found a term with tag {tag}")]
+    UnexpectedTag { tag: u8, expected: &'static str },
+
+    #[error("expected a proper list, found a tail with tag {tag}")]
+    ImproperList { tag: u8 },
+
+    #[error("invalid UTF-8 in a binary or atom")]
+    InvalidUtf8(#[from] std::str::Utf8Error),
+
+    #[error("integer does not fit in 128 bits")]
+    IntegerOutOfRange,
+
+    #[error("tried to convert non-finite float")]
+    NonFiniteFloat,
+
+    #[error("atom of {len} characters exceeds the maximum of 255")]
+    AtomTooLong { len: usize },
+
+    #[error("length {len} exceeds {max}")]
+    TooLarge { len: usize, max: usize },
+
+    #[error("term is nested deeper than the maximum depth of {max_depth}")]
+    DepthLimitExceeded { max_depth: usize },
+}
+
+impl serde::ser::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self::Message(msg.to_string())
+    }
+}
+
+impl serde::de::Error for Error {
+    fn custom<T: Display>(msg: T) -> Self {
+        Self::Message(msg.to_string())
+    }
+}
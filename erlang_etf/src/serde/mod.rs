@@ -0,0 +1,256 @@
+//! Serde support for the external term format.
+//!
+//! [`to_vec`] and [`to_writer`] serialize any [`Serialize`](serde::Serialize)
+//! type straight to external term format bytes, and [`from_slice`] and
+//! [`from_reader`] deserialize them into any
+//! [`Deserialize`](serde::Deserialize) type, without building a [`Term`]
+//! in between.
+//!
+//! Rust values map to terms as follows:
+//!
+//! | Rust                                 | Erlang                           |
+//! |--------------------------------------|----------------------------------|
+//! | `bool`                               | `true` or `false`                |
+//! | integers                             | integers                         |
+//! | `f32`, `f64`                         | floats                           |
+//! | `char`                               | its code point                   |
+//! | `str`, `String`                      | UTF-8 binaries                   |
+//! | bytes (see `serde_bytes`)            | binaries                         |
+//! | `None`                               | `undefined`                      |
+//! | `Some(v)`                            | `v`                              |
+//! | `()`, unit structs                   | `{}`                             |
+//! | newtype structs                      | the inner value                  |
+//! | sequences                            | proper lists                     |
+//! | tuples, tuple structs                | tuples                           |
+//! | maps                                 | maps                             |
+//! | structs                              | maps with atom keys              |
+//! | unit variant `V`                     | the atom `'V'`                   |
+//! | newtype variant `V(a)`               | `{'V', A}`                       |
+//! | tuple variant `V(a, b)`              | `{'V', A, B}`                    |
+//! | struct variant `V { a }`             | `{'V', #{a => A}}`               |
+//!
+//! Field and variant names are used as atoms unchanged, so
+//! `#[serde(rename_all = "snake_case")]` gives the usual Erlang spelling.
+//! [`Serializer::none_as_nil`] writes `None` as `nil` for Elixir peers.
+//!
+//! Deserialization accepts a little more than serialization produces: both
+//! `undefined` and `nil` read as `None`, strings may also be atoms or lists
+//! of character codes, bytes may also be lists, and sequences may also be
+//! tuples. Pids, ports, references, funs and bitstrings which are not a
+//! whole number of bytes have no serde representation and are rejected,
+//! although they can be skipped as ignored fields.
+//!
+//! [`Term`]: crate::Term
+
+mod de;
+mod error;
+mod ser;
+
+pub use de::*;
+pub use error::*;
+pub use ser::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encoder::EncodeOptions;
+    use crate::term::*;
+    use crate::test_util::{atom, decode, encode, encode_with_options, int, tuple};
+    use crate::Process;
+
+    use serde::{Deserialize, Serialize};
+
+    use std::collections::BTreeMap;
+
+    fn map(pairs: Vec<(Term, Term)>) -> Term {
+        Term::from(Map::from(pairs))
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect(u32, u32),
+        Labelled { name: String },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User<'a> {
+        id: u64,
+        name: &'a str,
+        #[serde(with = "serde_bytes_compat")]
+        avatar: Vec<u8>,
+        email: Option<String>,
+        tags: Vec<String>,
+        shapes: Vec<Shape>,
+        scores: BTreeMap<String, i64>,
+    }
+
+    /// Serializes a `Vec<u8>` with `serialize_bytes`, as `serde_bytes` does.
+    mod serde_bytes_compat {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(bytes)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            <&[u8]>::deserialize(deserializer).map(<[u8]>::to_vec)
+        }
+    }
+
+    #[test]
+    fn it_maps_rust_values_to_terms() {
+        let user = User {
+            id: 1 << 40,
+            name: "José",
+            avatar: vec![1, 2, 3],
+            email: None,
+            tags: vec!["admin".to_string()],
+            shapes: vec![
+                Shape::Point,
+                Shape::Circle(1.5),
+                Shape::Rect(2, 3),
+                Shape::Labelled {
+                    name: "a".to_string(),
+                },
+            ],
+            scores: BTreeMap::from([("x".to_string(), -1)]),
+        };
+        let buf = to_vec(&user).unwrap();
+
+        let binary = |s: &str| Term::from(Bitstring::from(s.as_bytes().to_vec()));
+        let list = |elements: Vec<Term>| Term::from(List::from(elements));
+        let expected = map(vec![
+            (
+                atom("id"),
+                Term::from(Number::from(Bignum::from(1i64 << 40))),
+            ),
+            (atom("name"), binary("José")),
+            (atom("avatar"), Term::from(Bitstring::from(vec![1, 2, 3]))),
+            (atom("email"), atom("undefined")),
+            (atom("tags"), list(vec![binary("admin")])),
+            (
+                atom("shapes"),
+                list(vec![
+                    atom("point"),
+                    tuple(vec![
+                        atom("circle"),
+                        Term::from(Number::from(Float::try_from(1.5).unwrap())),
+                    ]),
+                    tuple(vec![atom("rect"), int(2), int(3)]),
+                    tuple(vec![
+                        atom("labelled"),
+                        map(vec![(atom("name"), binary("a"))]),
+                    ]),
+                ]),
+            ),
+            (atom("scores"), map(vec![(binary("x"), int(-1))])),
+        ]);
+        assert_eq!(decode(&buf), expected);
+
+        let decoded: User = from_slice(&buf).unwrap();
+        assert_eq!(decoded, user);
+    }
+
+    #[test]
+    fn it_deserializes_erlang_spellings() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Record {
+            name: String,
+            nickname: Option<String>,
+            initial: char,
+            pair: (i32, bool),
+        }
+
+        let term = map(vec![
+            (
+                atom("name"),
+                Term::from(List::from(vec![int(0x4a), int(0x6f)])),
+            ),
+            (atom("nickname"), atom("nil")),
+            (atom("initial"), int(0x263a)),
+            (
+                atom("pair"),
+                Term::from(List::from(vec![int(-7), atom("true")])),
+            ),
+            (
+                atom("ignored"),
+                Term::from(Pid::new(Atom::from("n@h"), 1, 2, 3)),
+            ),
+        ]);
+        let record: Record = from_slice(&encode(&term)).unwrap();
+        assert_eq!(
+            record,
+            Record {
+                name: "Jo".to_string(),
+                nickname: None,
+                initial: '☺',
+                pair: (-7, true),
+            }
+        );
+
+        let big = Term::from(Number::from(Bignum::from(u128::MAX)));
+        assert_eq!(from_slice::<u128>(&encode(&big)).unwrap(), u128::MAX);
+        assert!(matches!(
+            from_slice::<u64>(&encode(&big)),
+            Err(Error::Message(_))
+        ));
+        assert!(matches!(
+            from_slice::<Shape>(&encode(&tuple(vec![atom("rect"), int(1)]))),
+            Err(Error::Message(_))
+        ));
+        assert!(matches!(
+            from_slice::<i32>(&[131, 97, 1, 0]),
+            Err(Error::TrailingBytes { len: 1 })
+        ));
+    }
+
+    #[test]
+    fn it_round_trips_without_known_lengths() {
+        struct Unsized(Vec<(u8, &'static str)>);
+        impl Serialize for Unsized {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                use serde::ser::SerializeMap;
+                let mut map = serializer.serialize_map(None)?;
+                for (k, v) in &self.0 {
+                    map.serialize_entry(k, v)?;
+                }
+                map.end()
+            }
+        }
+
+        let buf = to_vec(&Unsized(vec![(1, "a"), (2, "b")])).unwrap();
+        let decoded: BTreeMap<u8, String> = from_slice(&buf).unwrap();
+        assert_eq!(
+            decoded,
+            BTreeMap::from([(1, "a".to_string()), (2, "b".to_string())])
+        );
+
+        let mut buf = Vec::new();
+        let mut serializer = Serializer::new(&mut buf).none_as_nil();
+        Option::<u8>::None.serialize(&mut serializer).unwrap();
+        assert_eq!(buf, [119, 3, b'n', b'i', b'l']);
+        assert!(matches!(to_vec(&f64::NAN), Err(Error::NonFiniteFloat)));
+
+        let words = Term::from(List::from(vec![atom("word"); 64]));
+        let options = EncodeOptions::new().compressed(6);
+        let (buf, _) = encode_with_options(&Process::blocking(), options, &words).unwrap();
+        assert!(from_slice::<Vec<String>>(&buf).is_err());
+        assert_eq!(
+            from_reader::<_, Vec<String>>(&buf[..]).unwrap(),
+            vec!["word"; 64]
+        );
+        // A declared size smaller than the inflated term stops inflation
+        // just past it.
+        let mut short = buf.clone();
+        short[2..6].copy_from_slice(&8u32.to_be_bytes());
+        assert!(matches!(
+            from_reader::<_, Vec<String>>(&short[..]),
+            Err(Error::Message(message)) if message.contains("9 bytes instead of 8")
+        ));
+    }
+}
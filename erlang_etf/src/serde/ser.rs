@@ -0,0 +1,562 @@
+use byteorder::{BigEndian, WriteBytesExt};
+use serde::ser::{self, Serialize};
+
+use std::io::Write;
+
+use super::error::Error;
+use crate::codec::external as ext;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Serializes `value` as an external term, including the version byte, into
+/// `writer`.
+pub fn to_writer<W, T>(mut writer: W, value: &T) -> Result<()>
+where
+    W: Write,
+    T: ?Sized + Serialize,
+{
+    writer.write_u8(ext::VERSION_MAGIC)?;
+    value.serialize(&mut Serializer::new(writer))
+}
+
+/// Serializes `value` as an external term, including the version byte.
+pub fn to_vec<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: ?Sized + Serialize,
+{
+    let mut buf = Vec::new();
+    to_writer(&mut buf, value)?;
+    Ok(buf)
+}
+
+/// A serde serializer which writes terms in the external term format,
+/// without the leading version byte.
+///
+/// See the [module documentation](super) for how Rust values map to terms.
+#[derive(Debug)]
+pub struct Serializer<W> {
+    writer: W,
+    none_as_nil: bool,
+}
+impl<W: Write> Serializer<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            none_as_nil: false,
+        }
+    }
+
+    /// Writes `None` as the atom `nil`, as Elixir does, rather than
+    /// `undefined`.
+    pub fn none_as_nil(mut self) -> Self {
+        self.none_as_nil = true;
+        self
+    }
+
+    pub fn is_none_as_nil(&self) -> bool {
+        self.none_as_nil
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Returns a serializer for a nested value which is buffered in memory
+    /// because its length is not known up front.
+    fn buffered(&self) -> Serializer<Vec<u8>> {
+        Serializer {
+            writer: Vec::new(),
+            none_as_nil: self.none_as_nil,
+        }
+    }
+
+    fn write_atom(&mut self, name: &str) -> Result<()> {
+        let len = name.chars().count();
+        if len > 255 {
+            return Err(Error::AtomTooLong { len });
+        }
+        if let Ok(n) = u8::try_from(name.len()) {
+            self.writer.write_u8(ext::SMALL_ATOM_UTF8_EXT)?;
+            self.writer.write_u8(n)?;
+        } else {
+            self.writer.write_u8(ext::ATOM_UTF8_EXT)?;
+            self.writer.write_u16::<BigEndian>(name.len() as u16)?;
+        }
+        self.writer.write_all(name.as_bytes())?;
+        Ok(())
+    }
+
+    fn write_integer(&mut self, value: i128) -> Result<()> {
+        if let Ok(value) = u8::try_from(value) {
+            self.writer.write_u8(ext::SMALL_INTEGER_EXT)?;
+            self.writer.write_u8(value)?;
+        } else if let Ok(value) = i32::try_from(value) {
+            self.writer.write_u8(ext::INTEGER_EXT)?;
+            self.writer.write_i32::<BigEndian>(value)?;
+        } else {
+            self.write_big(value < 0, value.unsigned_abs())?;
+        }
+        Ok(())
+    }
+
+    fn write_big(&mut self, negative: bool, magnitude: u128) -> Result<()> {
+        let digits = magnitude.to_le_bytes();
+        let n = digits.len() - digits.iter().rev().take_while(|&&b| b == 0).count();
+        self.writer.write_u8(ext::SMALL_BIG_EXT)?;
+        self.writer.write_u8(n as u8)?;
+        self.writer.write_u8(negative as u8)?;
+        self.writer.write_all(&digits[..n])?;
+        Ok(())
+    }
+
+    fn write_binary(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_u8(ext::BINARY_EXT)?;
+        self.writer
+            .write_u32::<BigEndian>(check_len(bytes.len())?)?;
+        self.writer.write_all(bytes)?;
+        Ok(())
+    }
+
+    fn write_tuple_header(&mut self, arity: usize) -> Result<()> {
+        if let Ok(n) = u8::try_from(arity) {
+            self.writer.write_u8(ext::SMALL_TUPLE_EXT)?;
+            self.writer.write_u8(n)?;
+        } else {
+            self.writer.write_u8(ext::LARGE_TUPLE_EXT)?;
+            self.writer.write_u32::<BigEndian>(check_len(arity)?)?;
+        }
+        Ok(())
+    }
+
+    fn write_list_header(&mut self, len: usize) -> Result<()> {
+        if len == 0 {
+            self.writer.write_u8(ext::NIL_EXT)?;
+        } else {
+            self.writer.write_u8(ext::LIST_EXT)?;
+            self.writer.write_u32::<BigEndian>(check_len(len)?)?;
+        }
+        Ok(())
+    }
+
+    fn write_list_tail(&mut self, len: usize) -> Result<()> {
+        if len > 0 {
+            self.writer.write_u8(ext::NIL_EXT)?;
+        }
+        Ok(())
+    }
+
+    fn write_map_header(&mut self, len: usize) -> Result<()> {
+        self.writer.write_u8(ext::MAP_EXT)?;
+        self.writer.write_u32::<BigEndian>(check_len(len)?)?;
+        Ok(())
+    }
+}
+
+fn check_len(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| Error::TooLarge {
+        len,
+        max: u32::MAX as usize,
+    })
+}
+
+impl<'a, W: Write> ser::Serializer for &'a mut Serializer<W> {
+    type Ok = ();
+    type Error = Error;
+
+    type SerializeSeq = Compound<'a, W>;
+    type SerializeTuple = Compound<'a, W>;
+    type SerializeTupleStruct = Compound<'a, W>;
+    type SerializeTupleVariant = Compound<'a, W>;
+    type SerializeMap = Compound<'a, W>;
+    type SerializeStruct = Compound<'a, W>;
+    type SerializeStructVariant = Compound<'a, W>;
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.write_atom(if v { "true" } else { "false" })
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.write_integer(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.write_integer(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.write_integer(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.write_integer(v.into())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<()> {
+        self.write_integer(v)
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.write_integer(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.write_integer(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.write_integer(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.write_integer(v.into())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<()> {
+        match i128::try_from(v) {
+            Ok(v) => self.write_integer(v),
+            Err(_) => self.write_big(false, v),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        if !v.is_finite() {
+            return Err(Error::NonFiniteFloat);
+        }
+        self.writer.write_u8(ext::NEW_FLOAT_EXT)?;
+        self.writer.write_f64::<BigEndian>(v)?;
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.write_integer(u32::from(v).into())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.write_binary(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.write_binary(v)
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.write_atom(if self.none_as_nil { "nil" } else { "undefined" })
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        self.write_tuple_header(0)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<()> {
+        self.write_atom(variant)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.write_tuple_header(2)?;
+        self.write_atom(variant)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a, W>> {
+        match len {
+            Some(len) => {
+                self.write_list_header(len)?;
+                Ok(Compound::Direct { ser: self, len })
+            }
+            None => Ok(Compound::Buffered {
+                buf: self.buffered(),
+                ser: self,
+                len: 0,
+            }),
+        }
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a, W>> {
+        self.write_tuple_header(len)?;
+        Ok(Compound::Tuple { ser: self })
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>> {
+        self.serialize_tuple(len)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a, W>> {
+        self.write_tuple_header(len + 1)?;
+        self.write_atom(variant)?;
+        Ok(Compound::Tuple { ser: self })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a, W>> {
+        match len {
+            Some(len) => {
+                self.write_map_header(len)?;
+                Ok(Compound::Map { ser: self })
+            }
+            None => Ok(Compound::BufferedMap {
+                buf: self.buffered(),
+                ser: self,
+                len: 0,
+            }),
+        }
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<Compound<'a, W>> {
+        self.write_map_header(len)?;
+        Ok(Compound::Map { ser: self })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a, W>> {
+        self.write_tuple_header(2)?;
+        self.write_atom(variant)?;
+        self.serialize_struct(variant, len)
+    }
+}
+
+/// The state of a list, tuple or map whose elements are being serialized.
+///
+/// Lists and maps of unknown length are written to a buffer first, since
+/// the external term format puts the length before the elements.
+#[doc(hidden)]
+pub enum Compound<'a, W> {
+    Direct {
+        ser: &'a mut Serializer<W>,
+        len: usize,
+    },
+    Buffered {
+        ser: &'a mut Serializer<W>,
+        buf: Serializer<Vec<u8>>,
+        len: usize,
+    },
+    Tuple {
+        ser: &'a mut Serializer<W>,
+    },
+    Map {
+        ser: &'a mut Serializer<W>,
+    },
+    BufferedMap {
+        ser: &'a mut Serializer<W>,
+        buf: Serializer<Vec<u8>>,
+        len: usize,
+    },
+}
+impl<'a, W: Write> Compound<'a, W> {
+    fn element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Self::Direct { ser, .. } | Self::Tuple { ser } | Self::Map { ser } => {
+                value.serialize(&mut **ser)
+            }
+            Self::Buffered { buf, len, .. } => {
+                *len += 1;
+                value.serialize(buf)
+            }
+            Self::BufferedMap { buf, .. } => value.serialize(buf),
+        }
+    }
+
+    fn field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Self::Map { ser } => {
+                ser.write_atom(key)?;
+                value.serialize(&mut **ser)
+            }
+            _ => unreachable!("struct fields are only written to maps"),
+        }
+    }
+
+    fn end(self) -> Result<()> {
+        match self {
+            Self::Direct { ser, len } => ser.write_list_tail(len),
+            Self::Buffered { ser, buf, len } => {
+                ser.write_list_header(len)?;
+                ser.writer.write_all(&buf.writer)?;
+                ser.write_list_tail(len)
+            }
+            Self::Tuple { .. } | Self::Map { .. } => Ok(()),
+            Self::BufferedMap { ser, buf, len } => {
+                ser.write_map_header(len)?;
+                ser.writer.write_all(&buf.writer)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'a, W: Write> ser::SerializeSeq for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeTuple for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeTupleStruct for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeTupleVariant for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeMap for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        if let Self::BufferedMap { len, .. } = self {
+            *len += 1;
+        }
+        self.element(key)
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeStruct for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
+
+impl<'a, W: Write> ser::SerializeStructVariant for Compound<'a, W> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<()> {
+        Compound::end(self)
+    }
+}
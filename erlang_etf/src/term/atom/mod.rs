@@ -42,6 +42,10 @@ impl Atom {
     pub fn len(&self) -> usize {
         self.0.name.len()
     }
+
+    pub fn name(&self) -> &str {
+        &self.0.name
+    }
 }
 // impl Atom {
 //     fn into_raw_atom_string(this: Self) -> *const AtomString {
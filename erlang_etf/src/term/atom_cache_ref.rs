@@ -7,7 +7,8 @@ use std::sync::{Arc, RwLock};
 
 use crate::codec::external::{ERTS_MAX_INTERNAL_ATOM_CACHE_ENTRIES, ERTS_USE_ATOM_CACHE_SIZE};
 
-use super::atom::{Atom, IndexedAtom, IndexedAtomCache};
+use super::atom::{Atom, AtomCache, IndexedAtom, IndexedAtomCache};
+use crate::dist::{AtomCacheRefEntry, DistHeader};
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
 pub struct AtomCacheRef {
@@ -24,21 +25,6 @@ impl fmt::Display for AtomCacheRef {
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
-pub enum AtomCacheRefEntry {
-    New { index: usize, atom_text: Vec<u8> },
-    Old { index: usize },
-}
-impl AtomCacheRefEntry {
-    pub fn new(index: usize, atom_text: Vec<u8>) -> Self {
-        Self::New { index, atom_text }
-    }
-
-    pub fn old(index: usize) -> Self {
-        Self::Old { index }
-    }
-}
-
 // #[derive(Debug)]
 // pub struct AtomCacheIndex(AtomicUsize);
 // impl Clone for AtomCacheIndex {
@@ -61,6 +47,25 @@ impl AtomCacheRefBuilder {
     pub fn insert(&self, atom: &Atom) -> Option<AtomCacheRef> {
         self.0.write().unwrap().insert(atom)
     }
+
+    pub fn len(&self) -> usize {
+        self.0.read().unwrap().sz
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn long_atoms(&self) -> bool {
+        self.0.read().unwrap().long_atoms
+    }
+
+    /// Builds the atom cache part of a DIST_HEADER for the atoms inserted so
+    /// far. Atoms already in `output_cache` at the same index become old
+    /// entries, the others are new entries and are stored in `output_cache`.
+    pub fn finalize(&self, output_cache: &mut AtomCache) -> DistHeader {
+        self.0.read().unwrap().finalize(output_cache)
+    }
 }
 
 #[derive(Debug)]
@@ -107,6 +112,26 @@ impl AtomCacheRefBuilderInner {
         None
     }
 
+    pub(crate) fn finalize(&self, output_cache: &mut AtomCache) -> DistHeader {
+        let atom_cache_ref_entries = self.cix[..self.sz]
+            .iter()
+            .map(|&ix| {
+                let atom = self.cache.get(ix).unwrap().to_owned_atom();
+                if output_cache.get(ix).as_ref() == Some(&atom) {
+                    AtomCacheRefEntry::old(ix)
+                } else {
+                    let atom_text = atom.name().as_bytes().to_vec();
+                    output_cache.insert(ix, atom);
+                    AtomCacheRefEntry::new(ix, atom_text)
+                }
+            })
+            .collect();
+        DistHeader {
+            long_atoms: self.long_atoms,
+            atom_cache_ref_entries,
+        }
+    }
+
     fn atom2cix(atom: &Atom) -> usize {
         use std::collections::hash_map::DefaultHasher;
         use std::hash::{Hash, Hasher};
@@ -1,8 +1,10 @@
 use std::fmt;
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+use crate::bytes::Bytes;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Bitstring {
-    pub data: Vec<u8>,
+    pub data: Bytes,
     pub bits: u8,
 }
 impl Bitstring {
@@ -42,18 +44,34 @@ impl fmt::Display for Bitstring {
 impl<'a> From<&'a [u8]> for Bitstring {
     fn from(data: &'a [u8]) -> Self {
         Bitstring {
-            data: Vec::from(data),
+            data: Bytes::copy_from_slice(data),
             bits: 0,
         }
     }
 }
 impl From<Vec<u8>> for Bitstring {
     fn from(data: Vec<u8>) -> Self {
-        Bitstring { data, bits: 0 }
+        Bitstring {
+            data: data.into(),
+            bits: 0,
+        }
     }
 }
 impl From<(Vec<u8>, u8)> for Bitstring {
     fn from((data, bits): (Vec<u8>, u8)) -> Self {
+        Bitstring {
+            data: data.into(),
+            bits: bits % 8,
+        }
+    }
+}
+impl From<Bytes> for Bitstring {
+    fn from(data: Bytes) -> Self {
+        Bitstring { data, bits: 0 }
+    }
+}
+impl From<(Bytes, u8)> for Bitstring {
+    fn from((data, bits): (Bytes, u8)) -> Self {
         Bitstring {
             data,
             bits: bits % 8,
@@ -1,5 +1,7 @@
 use super::*;
-use std::convert::TryInto;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
 
 pub trait TryAsRef<T> {
     fn try_as_ref(&self) -> Option<&T>;
@@ -22,56 +24,101 @@ macro_rules! impl_term_try_as_ref {
             }
         }
     };
+    ($to:ident, $variant:ident($inner:ident::$inner_variant:ident)) => {
+        impl TryAsRef<$to> for Term {
+            fn try_as_ref(&self) -> Option<&$to> {
+                match *self {
+                    Term::$variant($inner::$inner_variant(ref x)) => Some(x),
+                    _ => None,
+                }
+            }
+        }
+        impl TryAsRef<$to> for $inner {
+            fn try_as_ref(&self) -> Option<&$to> {
+                match *self {
+                    $inner::$inner_variant(ref x) => Some(x),
+                    _ => None,
+                }
+            }
+        }
+    };
 }
+impl_term_try_as_ref!(Number);
+impl_term_try_as_ref!(FixInteger, Number(Number::FixInteger));
+impl_term_try_as_ref!(Bignum, Number(Number::Bignum));
+impl_term_try_as_ref!(Float, Number(Number::Float));
 impl_term_try_as_ref!(Atom);
-// impl_term_try_as_ref!(FixInteger);
-// impl_term_try_as_ref!(BigInteger);
-// impl_term_try_as_ref!(Float);
+impl_term_try_as_ref!(Reference);
+impl_term_try_as_ref!(Fun);
+impl_term_try_as_ref!(ExternalFun, Fun(Fun::ExternalFun));
+impl_term_try_as_ref!(InternalFun, Fun(Fun::InternalFun));
+impl_term_try_as_ref!(Port);
 impl_term_try_as_ref!(Pid);
-// impl_term_try_as_ref!(Port);
-// impl_term_try_as_ref!(Reference);
-// impl_term_try_as_ref!(ExternalFun);
-// impl_term_try_as_ref!(InternalFun);
-// impl_term_try_as_ref!(Binary);
-// impl_term_try_as_ref!(BitBinary);
-// impl_term_try_as_ref!(List);
-// impl_term_try_as_ref!(ImproperList);
-// impl_term_try_as_ref!(Tuple);
-// impl_term_try_as_ref!(Map);
-
-macro_rules! impl_term_try_into {
+impl_term_try_as_ref!(Tuple);
+impl_term_try_as_ref!(Map);
+impl_term_try_as_ref!(Nil);
+impl_term_try_as_ref!(List);
+impl_term_try_as_ref!(Bitstring);
+
+/// Variant types convert back from a term of that variant, handing back
+/// the term unchanged otherwise.
+macro_rules! impl_term_try_from {
     ($to:ident) => {
-        impl TryInto<$to> for Term {
-            type Error = Self;
-
-            fn try_into(self) -> Result<$to, Self>
-            where
-                Self: Sized,
-            {
-                match self {
+        impl TryFrom<Term> for $to {
+            type Error = Term;
+
+            fn try_from(term: Term) -> Result<$to, Term> {
+                match term {
                     Term::$to(x) => Ok(x),
-                    _ => Err(self),
+                    _ => Err(term),
                 }
             }
         }
     };
 }
-impl_term_try_into!(Atom);
-// impl_term_try_into!(FixInteger);
-// impl_term_try_into!(BigInteger);
-// impl_term_try_into!(Float);
-impl_term_try_into!(Pid);
-// impl_term_try_into!(Port);
-// impl_term_try_into!(Reference);
-// impl_term_try_into!(ExternalFun);
-// impl_term_try_into!(InternalFun);
-// impl_term_try_into!(Binary);
-// impl_term_try_into!(BitBinary);
-// impl_term_try_into!(List);
-// impl_term_try_into!(ImproperList);
-// impl_term_try_into!(Tuple);
-// impl_term_try_into!(Map);
+impl_term_try_from!(Number);
+impl_term_try_from!(Atom);
+impl_term_try_from!(Reference);
+impl_term_try_from!(Fun);
+impl_term_try_from!(Port);
+impl_term_try_from!(Pid);
+impl_term_try_from!(Tuple);
+impl_term_try_from!(Map);
+impl_term_try_from!(Nil);
+impl_term_try_from!(List);
+impl_term_try_from!(Bitstring);
 
+/// Rust types convert to and from terms with their [`IntoTerm`] and
+/// [`FromTerm`] implementations.
+macro_rules! impl_std_conversions {
+    ($($ty:ty),*) => {
+        $(
+            impl From<$ty> for Term {
+                fn from(value: $ty) -> Self {
+                    value.into_term()
+                }
+            }
+            impl TryFrom<Term> for $ty {
+                type Error = FromTermError;
+
+                fn try_from(term: Term) -> Result<Self, FromTermError> {
+                    <$ty>::from_term(term)
+                }
+            }
+        )*
+    };
+}
+impl_std_conversions!(i8, i16, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+impl_std_conversions!(f64, bool, String);
+
+impl From<i32> for Term {
+    fn from(value: i32) -> Self {
+        value.into_term()
+    }
+}
+
+// Kept from before `FromTerm` existed, so that its error still hands back the
+// term. Use `i32::from_term` for a `FromTermError`.
 impl TryInto<i32> for Term {
     type Error = Self;
 
@@ -86,6 +133,72 @@ impl TryInto<i32> for Term {
     }
 }
 
+impl From<&str> for Term {
+    fn from(value: &str) -> Self {
+        binary::into_term(value)
+    }
+}
+impl<T: IntoTerm> From<Vec<T>> for Term {
+    fn from(value: Vec<T>) -> Self {
+        value.into_term()
+    }
+}
+impl<T: FromTerm> TryFrom<Term> for Vec<T> {
+    type Error = FromTermError;
+
+    fn try_from(term: Term) -> Result<Self, FromTermError> {
+        Self::from_term(term)
+    }
+}
+impl<T: IntoTerm> From<Option<T>> for Term {
+    fn from(value: Option<T>) -> Self {
+        value.into_term()
+    }
+}
+// `TryFrom<Term> for Option<T>` would overlap with `From<T> for Option<T>`,
+// so options convert back with `FromTerm` only.
+impl<T: IntoTerm, E: IntoTerm> From<Result<T, E>> for Term {
+    fn from(value: Result<T, E>) -> Self {
+        value.into_term()
+    }
+}
+impl<T: FromTerm, E: FromTerm> TryFrom<Term> for Result<T, E> {
+    type Error = FromTermError;
+
+    fn try_from(term: Term) -> Result<Self, FromTermError> {
+        Self::from_term(term)
+    }
+}
+impl<K: IntoTerm, V: IntoTerm, S> From<HashMap<K, V, S>> for Term {
+    fn from(value: HashMap<K, V, S>) -> Self {
+        value.into_term()
+    }
+}
+impl<K, V, S> TryFrom<Term> for HashMap<K, V, S>
+where
+    K: FromTerm + Eq + Hash,
+    V: FromTerm,
+    S: BuildHasher + Default,
+{
+    type Error = FromTermError;
+
+    fn try_from(term: Term) -> Result<Self, FromTermError> {
+        Self::from_term(term)
+    }
+}
+impl<K: IntoTerm, V: IntoTerm> From<BTreeMap<K, V>> for Term {
+    fn from(value: BTreeMap<K, V>) -> Self {
+        value.into_term()
+    }
+}
+impl<K: FromTerm + Ord, V: FromTerm> TryFrom<Term> for BTreeMap<K, V> {
+    type Error = FromTermError;
+
+    fn try_from(term: Term) -> Result<Self, FromTermError> {
+        Self::from_term(term)
+    }
+}
+
 pub trait AsOption {
     fn as_option(&self) -> Option<&Self>;
 }
@@ -99,6 +212,540 @@ impl AsOption for bool {
     }
 }
 
+/// Converts a value into a term.
+///
+/// Derive it with `#[derive(IntoTerm)]` when the `derive` feature is
+/// enabled.
+pub trait IntoTerm {
+    fn into_term(self) -> Term;
+}
+
+/// Converts a term into a value, or fails with an error naming where in the
+/// term the conversion went wrong.
+///
+/// Derive it with `#[derive(FromTerm)]` when the `derive` feature is
+/// enabled.
+pub trait FromTerm: Sized {
+    fn from_term(term: Term) -> Result<Self, FromTermError>;
+}
+
+#[cfg(feature = "derive")]
+pub use erlang_etf_derive::{FromTerm, IntoTerm};
+
+/// One step of the path to the part of a term which failed to convert.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PathSegment {
+    /// A struct field or enum variant.
+    Field(&'static str),
+    /// An element of a list or tuple.
+    Index(usize),
+    /// The value of a map entry.
+    Key(Term),
+}
+
+/// Why a term failed to convert.
+#[derive(Debug, thiserror::Error)]
+pub enum FromTermErrorKind {
+    #[error("{value} is not {expected}")]
+    UnexpectedType { value: Term, expected: String },
+
+    #[error("missing map key")]
+    MissingKey,
+}
+
+/// Errors which can occur when converting a term with [`FromTerm`]
+#[derive(Debug, thiserror::Error)]
+#[error("{}{kind}", PathDisplay(path))]
+pub struct FromTermError {
+    path: Vec<PathSegment>,
+    kind: Box<FromTermErrorKind>,
+}
+impl FromTermError {
+    pub fn new(kind: FromTermErrorKind) -> Self {
+        Self {
+            path: Vec::new(),
+            kind: Box::new(kind),
+        }
+    }
+
+    pub fn unexpected<S: Into<String>>(value: Term, expected: S) -> Self {
+        Self::new(FromTermErrorKind::UnexpectedType {
+            value,
+            expected: expected.into(),
+        })
+    }
+
+    pub fn missing_key(field: &'static str) -> Self {
+        Self::new(FromTermErrorKind::MissingKey).in_field(field)
+    }
+
+    /// Records that the error happened inside the field or variant `name`.
+    pub fn in_field(mut self, name: &'static str) -> Self {
+        self.path.insert(0, PathSegment::Field(name));
+        self
+    }
+
+    /// Records that the error happened inside the element at `index`.
+    pub fn at_index(mut self, index: usize) -> Self {
+        self.path.insert(0, PathSegment::Index(index));
+        self
+    }
+
+    /// Records that the error happened inside the value for `key`.
+    pub fn at_key(mut self, key: Term) -> Self {
+        self.path.insert(0, PathSegment::Key(key));
+        self
+    }
+
+    /// Returns the path from the outermost term to the one which failed.
+    pub fn get_path(&self) -> &[PathSegment] {
+        &self.path
+    }
+
+    pub fn get_kind(&self) -> &FromTermErrorKind {
+        &self.kind
+    }
+}
+
+struct PathDisplay<'a>(&'a [PathSegment]);
+impl fmt::Display for PathDisplay<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                PathSegment::Field(name) if i == 0 => write!(f, "{}", name)?,
+                PathSegment::Field(name) => write!(f, ".{}", name)?,
+                PathSegment::Index(index) => write!(f, "[{}]", index)?,
+                PathSegment::Key(key) => write!(f, "[{}]", key)?,
+            }
+        }
+        if !self.0.is_empty() {
+            write!(f, ": ")?;
+        }
+        Ok(())
+    }
+}
+
+impl IntoTerm for Term {
+    fn into_term(self) -> Term {
+        self
+    }
+}
+impl FromTerm for Term {
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        Ok(term)
+    }
+}
+
+macro_rules! impl_term_conversions {
+    ($to:ident, $expected:expr) => {
+        impl IntoTerm for $to {
+            fn into_term(self) -> Term {
+                Term::$to(self)
+            }
+        }
+        impl FromTerm for $to {
+            fn from_term(term: Term) -> Result<Self, FromTermError> {
+                match term {
+                    Term::$to(x) => Ok(x),
+                    term => Err(FromTermError::unexpected(term, $expected)),
+                }
+            }
+        }
+    };
+}
+impl_term_conversions!(Number, "a number");
+impl_term_conversions!(Atom, "an atom");
+impl_term_conversions!(Reference, "a reference");
+impl_term_conversions!(Fun, "a fun");
+impl_term_conversions!(Port, "a port");
+impl_term_conversions!(Pid, "a pid");
+impl_term_conversions!(Tuple, "a tuple");
+impl_term_conversions!(Map, "a map");
+impl_term_conversions!(Nil, "nil");
+impl_term_conversions!(List, "a non-empty list");
+impl_term_conversions!(Bitstring, "a bitstring");
+
+macro_rules! impl_integer_conversions {
+    ($($ty:ident),*) => {
+        $(
+            impl IntoTerm for $ty {
+                fn into_term(self) -> Term {
+                    match i32::try_from(self) {
+                        Ok(value) => Term::from(Number::from(FixInteger::from(value))),
+                        Err(_) => Term::from(Number::from(Bignum::from(self))),
+                    }
+                }
+            }
+            impl FromTerm for $ty {
+                fn from_term(term: Term) -> Result<Self, FromTermError> {
+                    let value = match term {
+                        Term::Number(Number::FixInteger(ref x)) => $ty::try_from(x.value).ok(),
+                        Term::Number(Number::Bignum(ref x)) => $ty::try_from(&x.value).ok(),
+                        _ => None,
+                    };
+                    value.ok_or_else(|| {
+                        FromTermError::unexpected(
+                            term,
+                            concat!("an integer in the range of ", stringify!($ty)),
+                        )
+                    })
+                }
+            }
+        )*
+    };
+}
+impl_integer_conversions!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/// Non-finite values are kept as they are, although they cannot be encoded.
+impl IntoTerm for f64 {
+    fn into_term(self) -> Term {
+        Term::from(Number::from(Float { value: self }))
+    }
+}
+impl FromTerm for f64 {
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        match term {
+            Term::Number(Number::Float(x)) => Ok(x.value),
+            term => Err(FromTermError::unexpected(term, "a float")),
+        }
+    }
+}
+
+impl IntoTerm for bool {
+    fn into_term(self) -> Term {
+        Term::from(Atom::from(if self { "true" } else { "false" }))
+    }
+}
+impl FromTerm for bool {
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        match term {
+            Term::Atom(ref x) if x.name() == "true" => Ok(true),
+            Term::Atom(ref x) if x.name() == "false" => Ok(false),
+            term => Err(FromTermError::unexpected(term, "a boolean")),
+        }
+    }
+}
+
+/// Strings are binaries by default, but convert back from either binaries
+/// or lists of character codes; see [`binary`], [`charlist`] and [`atom`]
+/// to pick one representation.
+impl IntoTerm for String {
+    fn into_term(self) -> Term {
+        binary::into_term(self)
+    }
+}
+impl FromTerm for String {
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        match term {
+            Term::Bitstring(_) => binary::from_term(term),
+            Term::Nil(_) | Term::List(_) => charlist::from_term(term),
+            term => Err(FromTermError::unexpected(
+                term,
+                "a UTF-8 binary or a list of character codes",
+            )),
+        }
+    }
+}
+
+/// `None` is the atom `undefined`, which is also what `from_term` accepts
+/// along with `nil`.
+impl<T: IntoTerm> IntoTerm for Option<T> {
+    fn into_term(self) -> Term {
+        match self {
+            Some(value) => value.into_term(),
+            None => Term::from(Atom::from("undefined")),
+        }
+    }
+}
+impl<T: FromTerm> FromTerm for Option<T> {
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        match term {
+            Term::Atom(ref x) if x.name() == "undefined" || x.name() == "nil" => Ok(None),
+            term => T::from_term(term).map(Some),
+        }
+    }
+}
+
+impl<T: IntoTerm> IntoTerm for Vec<T> {
+    fn into_term(self) -> Term {
+        if self.is_empty() {
+            return Term::from(Nil);
+        }
+        Term::from(List::from(
+            self.into_iter()
+                .map(IntoTerm::into_term)
+                .collect::<Vec<_>>(),
+        ))
+    }
+}
+impl<T: FromTerm> FromTerm for Vec<T> {
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        match term {
+            Term::Nil(_) => Ok(Vec::new()),
+            Term::List(list) if !list.is_improper_list() => list
+                .elements
+                .into_iter()
+                .enumerate()
+                .map(|(i, element)| T::from_term(element).map_err(|e| e.at_index(i)))
+                .collect(),
+            term => Err(FromTermError::unexpected(term, "a proper list")),
+        }
+    }
+}
+
+impl IntoTerm for &str {
+    fn into_term(self) -> Term {
+        binary::into_term(self)
+    }
+}
+
+/// `Ok(T)` is `{ok, T}` and `Err(E)` is `{error, E}`.
+impl<T: IntoTerm, E: IntoTerm> IntoTerm for Result<T, E> {
+    fn into_term(self) -> Term {
+        let (tag, value) = match self {
+            Ok(value) => ("ok", value.into_term()),
+            Err(error) => ("error", error.into_term()),
+        };
+        Term::from(Tuple::from(vec![Term::from(Atom::from(tag)), value]))
+    }
+}
+impl<T: FromTerm, E: FromTerm> FromTerm for Result<T, E> {
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        const EXPECTED: &str = "{ok, _} or {error, _}";
+        match variant_tag(&term) {
+            Some(("ok", 1)) => {
+                let [value] = tuple_fields::<1>(term, Some("ok"), EXPECTED)?;
+                Ok(Ok(T::from_term(value).map_err(|e| e.in_field("Ok"))?))
+            }
+            Some(("error", 1)) => {
+                let [error] = tuple_fields::<1>(term, Some("error"), EXPECTED)?;
+                Ok(Err(E::from_term(error).map_err(|e| e.in_field("Err"))?))
+            }
+            _ => Err(FromTermError::unexpected(term, EXPECTED)),
+        }
+    }
+}
+
+impl<K: IntoTerm, V: IntoTerm, S> IntoTerm for HashMap<K, V, S> {
+    fn into_term(self) -> Term {
+        map_into_term(self)
+    }
+}
+impl<K, V, S> FromTerm for HashMap<K, V, S>
+where
+    K: FromTerm + Eq + Hash,
+    V: FromTerm,
+    S: BuildHasher + Default,
+{
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        map_from_term(term)
+    }
+}
+
+impl<K: IntoTerm, V: IntoTerm> IntoTerm for BTreeMap<K, V> {
+    fn into_term(self) -> Term {
+        map_into_term(self)
+    }
+}
+impl<K: FromTerm + Ord, V: FromTerm> FromTerm for BTreeMap<K, V> {
+    fn from_term(term: Term) -> Result<Self, FromTermError> {
+        map_from_term(term)
+    }
+}
+
+fn map_into_term<K, V, I>(pairs: I) -> Term
+where
+    K: IntoTerm,
+    V: IntoTerm,
+    I: IntoIterator<Item = (K, V)>,
+{
+    Term::from(Map::from(
+        pairs
+            .into_iter()
+            .map(|(key, value)| (key.into_term(), value.into_term()))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+fn map_from_term<K, V, C>(term: Term) -> Result<C, FromTermError>
+where
+    K: FromTerm,
+    V: FromTerm,
+    C: FromIterator<(K, V)>,
+{
+    match term {
+        Term::Map(map) => map
+            .pairs
+            .into_iter()
+            .map(|(key, value)| {
+                let value = V::from_term(value).map_err(|e| e.at_key(key.clone()))?;
+                Ok((K::from_term(key)?, value))
+            })
+            .collect(),
+        term => Err(FromTermError::unexpected(term, "a map")),
+    }
+}
+
+macro_rules! impl_tuple_conversions {
+    ($arity:literal => $($ty:ident $var:ident $index:tt),+) => {
+        impl<$($ty: IntoTerm),+> IntoTerm for ($($ty,)+) {
+            fn into_term(self) -> Term {
+                Term::from(Tuple::from(vec![$(self.$index.into_term()),+]))
+            }
+        }
+        impl<$($ty: FromTerm),+> FromTerm for ($($ty,)+) {
+            fn from_term(term: Term) -> Result<Self, FromTermError> {
+                let [$($var),+] = tuple_fields::<$arity>(
+                    term,
+                    None,
+                    concat!("a tuple of arity ", $arity),
+                )?;
+                Ok(($($ty::from_term($var).map_err(|e| e.at_index($index))?,)+))
+            }
+        }
+        impl<$($ty: IntoTerm),+> From<($($ty,)+)> for Term {
+            fn from(value: ($($ty,)+)) -> Self {
+                value.into_term()
+            }
+        }
+        impl<$($ty: FromTerm),+> TryFrom<Term> for ($($ty,)+) {
+            type Error = FromTermError;
+
+            fn try_from(term: Term) -> Result<Self, FromTermError> {
+                Self::from_term(term)
+            }
+        }
+    };
+}
+impl_tuple_conversions!(1 => A a 0);
+impl_tuple_conversions!(2 => A a 0, B b 1);
+impl_tuple_conversions!(3 => A a 0, B b 1, C c 2);
+impl_tuple_conversions!(4 => A a 0, B b 1, C c 2, D d 3);
+impl_tuple_conversions!(5 => A a 0, B b 1, C c 2, D d 3, E e 4);
+impl_tuple_conversions!(6 => A a 0, B b 1, C c 2, D d 3, E e 4, F f 5);
+impl_tuple_conversions!(7 => A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6);
+impl_tuple_conversions!(8 => A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7);
+impl_tuple_conversions!(9 => A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7, I i 8);
+impl_tuple_conversions!(10 => A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7, I i 8, J j 9);
+impl_tuple_conversions!(11 => A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7, I i 8, J j 9, K k 10);
+impl_tuple_conversions!(12 => A a 0, B b 1, C c 2, D d 3, E e 4, F f 5, G g 6, H h 7, I i 8, J j 9, K k 10, L l 11);
+
+/// Strings as UTF-8 binaries, for use with `#[etf(with = "...")]`.
+pub mod binary {
+    use super::*;
+
+    pub fn into_term<S: AsRef<str>>(value: S) -> Term {
+        Term::from(Bitstring::from(value.as_ref().as_bytes()))
+    }
+
+    pub fn from_term<S: From<String>>(term: Term) -> Result<S, FromTermError> {
+        match term {
+            Term::Bitstring(ref x) if x.is_binary() => match std::str::from_utf8(&x.data) {
+                Ok(s) => Ok(S::from(s.to_owned())),
+                Err(_) => Err(FromTermError::unexpected(term, "a UTF-8 binary")),
+            },
+            term => Err(FromTermError::unexpected(term, "a UTF-8 binary")),
+        }
+    }
+}
+
+/// Strings as lists of character codes, for use with `#[etf(with = "...")]`.
+pub mod charlist {
+    use super::*;
+
+    pub fn into_term<S: AsRef<str>>(value: S) -> Term {
+        let elements: Vec<Term> = value
+            .as_ref()
+            .chars()
+            .map(|c| u32::from(c).into_term())
+            .collect();
+        if elements.is_empty() {
+            return Term::from(Nil);
+        }
+        Term::from(List::from(elements))
+    }
+
+    pub fn from_term<S: From<String>>(term: Term) -> Result<S, FromTermError> {
+        let string = match term {
+            Term::Nil(_) => Some(String::new()),
+            Term::List(ref list) if !list.is_improper_list() => list
+                .elements
+                .iter()
+                .map(|element| match element {
+                    Term::Number(Number::FixInteger(x)) => {
+                        u32::try_from(x.value).ok().and_then(char::from_u32)
+                    }
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        };
+        string
+            .map(S::from)
+            .ok_or_else(|| FromTermError::unexpected(term, "a list of character codes"))
+    }
+}
+
+/// Strings as atoms, for use with `#[etf(with = "...")]`.
+pub mod atom {
+    use super::*;
+
+    pub fn into_term<S: AsRef<str>>(value: S) -> Term {
+        Term::from(Atom::from(value.as_ref()))
+    }
+
+    pub fn from_term<S: From<String>>(term: Term) -> Result<S, FromTermError> {
+        match term {
+            Term::Atom(x) => Ok(S::from(x.name().to_owned())),
+            term => Err(FromTermError::unexpected(term, "an atom")),
+        }
+    }
+}
+
+/// Returns the fields of a tuple of exactly `N` elements, or of `N + 1`
+/// elements whose first is the atom `tag`. Used by derived [`FromTerm`]
+/// implementations.
+#[doc(hidden)]
+pub fn tuple_fields<const N: usize>(
+    term: Term,
+    tag: Option<&str>,
+    expected: &str,
+) -> Result<[Term; N], FromTermError> {
+    let offset = usize::from(tag.is_some());
+    match term {
+        Term::Tuple(tuple)
+            if tuple.elements.len() == N + offset
+                && tag.is_none_or(|tag| is_atom(&tuple.elements[0], tag)) =>
+        {
+            let mut elements = tuple.elements;
+            elements.drain(..offset);
+            Ok(elements
+                .try_into()
+                .unwrap_or_else(|_| unreachable!("the arity was checked")))
+        }
+        term => Err(FromTermError::unexpected(term, expected)),
+    }
+}
+
+/// Returns the tag and number of fields of an enum variant: an atom on its
+/// own, or a tuple whose first element is an atom. Used by derived
+/// [`FromTerm`] implementations.
+#[doc(hidden)]
+pub fn variant_tag(term: &Term) -> Option<(&str, usize)> {
+    match term {
+        Term::Atom(x) => Some((x.name(), 0)),
+        Term::Tuple(x) => match x.elements.first() {
+            Some(Term::Atom(tag)) => Some((tag.name(), x.elements.len() - 1)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_atom(term: &Term, name: &str) -> bool {
+    matches!(term, Term::Atom(x) if x.name() == name)
+}
+
 // impl num::traits::ToPrimitive for FixInteger {
 //     fn to_i64(&self) -> Option<i64> {
 //         Some(i64::from(self.value))
@@ -196,3 +843,261 @@ impl AsOption for bool {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{atom, int, tuple};
+
+    fn binary(s: &str) -> Term {
+        Term::from(Bitstring::from(s.as_bytes()))
+    }
+
+    #[test]
+    fn it_names_the_path_of_failed_conversions() {
+        let term = Term::from(List::from(vec![int(1), int(300)]));
+        let error = Vec::<u8>::from_term(term).unwrap_err();
+        assert_eq!(error.get_path(), &[PathSegment::Index(1)]);
+        assert_eq!(
+            error.in_field("sizes").in_field("config").to_string(),
+            "config.sizes[1]: 300 is not an integer in the range of u8"
+        );
+
+        assert_eq!(
+            u64::MAX.into_term(),
+            Term::from(Number::from(Bignum::from(u64::MAX)))
+        );
+        assert_eq!(u64::from_term(u64::MAX.into_term()).unwrap(), u64::MAX);
+        assert_eq!(Option::<bool>::from_term(atom("nil")).unwrap(), None);
+        assert_eq!(
+            charlist::from_term::<String>(charlist::into_term("héllo")).unwrap(),
+            "héllo"
+        );
+    }
+
+    #[test]
+    fn it_converts_rust_values_both_ways() {
+        let big = Term::from(i64::MIN);
+        assert_eq!(big, Term::from(Number::from(Bignum::from(i64::MIN))));
+        assert_eq!(i64::try_from(big.clone()).unwrap(), i64::MIN);
+        assert!(i32::from_term(big.clone()).is_err());
+        let result: Result<i32, Term> = big.clone().try_into();
+        assert_eq!(result.unwrap_err(), big);
+        assert_eq!(u8::try_from(Term::from(200u64)).unwrap(), 200);
+        assert!(u8::try_from(Term::from(-1)).is_err());
+        assert_eq!(u128::try_from(Term::from(u128::MAX)).unwrap(), u128::MAX);
+
+        assert_eq!(f64::try_from(Term::from(0.5)).unwrap(), 0.5);
+        assert_eq!(Term::from(true), atom("true"));
+        assert!(!bool::try_from(atom("false")).unwrap());
+        assert_eq!(Term::from("hi"), binary("hi"));
+        assert_eq!(String::try_from(binary("hi")).unwrap(), "hi");
+        assert_eq!(String::try_from(charlist::into_term("hi")).unwrap(), "hi");
+        assert_eq!(String::try_from(Term::from(Nil)).unwrap(), "");
+
+        let pair = Term::from((atom("ok"), vec![1u8, 2]));
+        assert_eq!(
+            pair,
+            tuple(vec![
+                atom("ok"),
+                Term::from(List::from(vec![int(1), int(2)]))
+            ])
+        );
+        let (tag, bytes): (Atom, Vec<u8>) = pair.try_into().unwrap();
+        assert_eq!((tag.name(), bytes), ("ok", vec![1, 2]));
+        let twelve = (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, "twelve");
+        let term = Term::from(twelve);
+        assert_eq!(
+            <(u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, String)>::try_from(term)
+                .unwrap()
+                .11,
+            "twelve"
+        );
+
+        assert_eq!(
+            Term::from(Ok::<_, String>(1)),
+            tuple(vec![atom("ok"), int(1)])
+        );
+        let error = tuple(vec![atom("error"), atom("enoent")]);
+        assert_eq!(
+            Result::<u8, Atom>::try_from(error)
+                .unwrap()
+                .unwrap_err()
+                .name(),
+            "enoent"
+        );
+        assert_eq!(Term::from(None::<u8>), atom("undefined"));
+        assert_eq!(Option::<u8>::from_term(int(3)).unwrap(), Some(3));
+
+        let scores = BTreeMap::from([("a".to_string(), 1), ("b".to_string(), 2)]);
+        let term = Term::from(scores.clone());
+        assert_eq!(
+            BTreeMap::<String, i32>::try_from(term.clone()).unwrap(),
+            scores
+        );
+        let hashed = HashMap::<String, i32>::try_from(term).unwrap();
+        assert_eq!(hashed.len(), 2);
+        let bad = Term::from(Map::from(vec![(binary("a"), atom("x"))]));
+        assert_eq!(
+            HashMap::<String, i32>::try_from(bad)
+                .unwrap_err()
+                .to_string(),
+            "[<<97>>]: 'x' is not an integer in the range of i32"
+        );
+    }
+
+    #[test]
+    fn it_projects_terms_onto_their_variants() {
+        let term = Term::from(1.5);
+        let float: Option<&Float> = term.try_as_ref();
+        assert_eq!(float.unwrap().value, 1.5);
+        assert!(TryAsRef::<FixInteger>::try_as_ref(&term).is_none());
+        assert!(TryAsRef::<Number>::try_as_ref(&term).is_some());
+
+        let list = Term::from(vec![1]);
+        assert_eq!(
+            TryAsRef::<List>::try_as_ref(&list).unwrap().elements,
+            [int(1)]
+        );
+        assert!(TryAsRef::<Nil>::try_as_ref(&Term::from(Vec::<u8>::new())).is_some());
+        assert!(Tuple::try_from(list.clone()).is_err());
+        assert_eq!(List::try_from(list).unwrap().elements.len(), 1);
+    }
+
+    #[cfg(feature = "derive")]
+    mod derive {
+        use super::*;
+
+        #[derive(Clone, Debug, PartialEq, IntoTerm, FromTerm)]
+        #[etf(record = "user")]
+        struct User {
+            name: String,
+            #[etf(charlist)]
+            nickname: String,
+            #[etf(atom)]
+            role: String,
+            age: Option<u8>,
+        }
+
+        #[derive(Clone, Debug, PartialEq, IntoTerm, FromTerm)]
+        struct Config<T> {
+            users: Vec<User>,
+            default: T,
+            shapes: Vec<Shape>,
+        }
+
+        #[derive(Clone, Debug, PartialEq, IntoTerm, FromTerm)]
+        enum Shape {
+            Point,
+            Circle(f64),
+            #[etf(tag = "rect")]
+            Rectangle(u32, u32),
+            Labelled {
+                #[etf(atom)]
+                label: String,
+            },
+        }
+
+        #[derive(Clone, Debug, PartialEq, IntoTerm, FromTerm)]
+        struct Meters(u32);
+
+        #[derive(Clone, Debug, PartialEq, IntoTerm, FromTerm)]
+        struct Token {
+            r#type: u8,
+        }
+
+        fn user() -> User {
+            User {
+                name: "Ann".to_string(),
+                nickname: "annie".to_string(),
+                role: "admin".to_string(),
+                age: None,
+            }
+        }
+
+        #[test]
+        fn it_derives_records_maps_and_tagged_tuples() {
+            let user_term = tuple(vec![
+                atom("user"),
+                binary("Ann"),
+                charlist::into_term("annie"),
+                atom("admin"),
+                atom("undefined"),
+            ]);
+            assert_eq!(user().into_term(), user_term);
+            assert_eq!(User::from_term(user_term).unwrap(), user());
+
+            let config = Config {
+                users: vec![user()],
+                default: Meters(5),
+                shapes: vec![
+                    Shape::Point,
+                    Shape::Circle(1.5),
+                    Shape::Rectangle(2, 3),
+                    Shape::Labelled {
+                        label: "x".to_string(),
+                    },
+                ],
+            };
+            let term = config.clone().into_term();
+            let Term::Map(ref map) = term else {
+                panic!("{} is not a map", term)
+            };
+            assert_eq!(map.pairs[1], (atom("default"), int(5)));
+            assert_eq!(
+                map.pairs[2].1,
+                Term::from(List::from(vec![
+                    atom("point"),
+                    tuple(vec![
+                        atom("circle"),
+                        Term::from(Number::from(Float::try_from(1.5).unwrap()))
+                    ]),
+                    tuple(vec![atom("rect"), int(2), int(3)]),
+                    tuple(vec![atom("labelled"), atom("x")]),
+                ]))
+            );
+            assert_eq!(Config::from_term(term).unwrap(), config);
+        }
+
+        #[test]
+        fn it_reports_the_field_path_of_derived_errors() {
+            let term = Term::from(Map::from(vec![
+                (atom("users"), Term::from(Nil)),
+                (atom("default"), int(1)),
+                (
+                    atom("shapes"),
+                    Term::from(List::from(vec![
+                        atom("point"),
+                        tuple(vec![atom("rect"), int(2), int(-3)]),
+                    ])),
+                ),
+            ]));
+            let error = Config::<Meters>::from_term(term).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "shapes[1].Rectangle[1]: -3 is not an integer in the range of u32"
+            );
+
+            let term = Term::from(Map::from(vec![(atom("users"), Term::from(Nil))]));
+            let error = Config::<Meters>::from_term(term).unwrap_err();
+            assert_eq!(error.to_string(), "default: missing map key");
+
+            let term = tuple(vec![atom("admin"), binary("Ann")]);
+            let error = User::from_term(term).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "{'admin',<<65,110,110>>} is not a user record"
+            );
+            assert!(Shape::from_term(tuple(vec![atom("point")])).is_err());
+        }
+
+        #[test]
+        fn it_strips_raw_identifier_prefixes() {
+            let term = Term::from(Map::from(vec![(atom("type"), int(1))]));
+            assert_eq!(Token { r#type: 1 }.into_term(), term);
+            assert_eq!(Token::from_term(term).unwrap(), Token { r#type: 1 });
+            let error = Token::from_term(Term::from(Map::from(vec![]))).unwrap_err();
+            assert_eq!(error.to_string(), "type: missing map key");
+        }
+    }
+}
@@ -0,0 +1,562 @@
+//! Formatting of a format string and arguments as
+//! [`io_lib:format/2`](https://www.erlang.org/doc/man/io_lib.html#format-2)
+//! does, such as the format and arguments of a message sent by a logger.
+//!
+//! A control sequence is written `~F.P.PadModC`, where the field width
+//! `F`, precision `P` and padding character `Pad` are all optional, and
+//! `F` and `P` may be `*` to take them from the arguments. A negative field
+//! width left-justifies the field. The modifier `t` allows Unicode text and
+//! `l` turns off the detection of strings by `~p` and `~P`.
+//!
+//! The control characters are `~c`, `~f`, `~e`, `~g`, `~s`, `~w`, `~p`,
+//! `~W`, `~P`, `~B`, `~X`, `~#`, `~b`, `~x`, `~+`, `~i`, `~n` and `~~`.
+
+use std::iter::Peekable;
+use std::slice::Iter;
+use std::str::Chars;
+
+use crate::num_bigint::BigInt;
+use crate::term::*;
+
+/// Formats `args` according to `format`, as `io_lib:format(Format, Args)`
+/// does.
+pub fn format(format: &str, args: &[Term]) -> Result<String, FormatError> {
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    let mut args = args.iter();
+    while let Some(c) = chars.next() {
+        if c == '~' {
+            let spec = Spec::parse(&mut chars, &mut args)?;
+            spec.write(&mut text, &mut args)?;
+        } else {
+            text.push(c);
+        }
+    }
+    match args.len() {
+        0 => Ok(text),
+        count => Err(FormatError::TooManyArguments { count }),
+    }
+}
+
+/// Errors which can occur when formatting, for all of which
+/// `io_lib:format/2` fails with `badarg`.
+#[derive(Debug, thiserror::Error)]
+pub enum FormatError {
+    #[error("format string ends inside a control sequence")]
+    Unterminated,
+
+    #[error("unknown control sequence ~{0}")]
+    UnknownControl(char),
+
+    #[error("no argument left for ~{control}")]
+    MissingArgument { control: char },
+
+    #[error("{count} arguments left over")]
+    TooManyArguments { count: usize },
+
+    #[error("bad argument for ~{control}: {argument}")]
+    BadArgument { control: char, argument: Term },
+
+    #[error("invalid field width, precision or padding for ~{0}")]
+    BadField(char),
+}
+
+/// A parsed control sequence.
+struct Spec {
+    width: Option<usize>,
+    left: bool,
+    precision: Option<usize>,
+    pad: char,
+    unicode: bool,
+    strings: bool,
+    control: char,
+}
+impl Spec {
+    fn parse(chars: &mut Peekable<Chars>, args: &mut Iter<Term>) -> Result<Self, FormatError> {
+        let mut spec = Spec {
+            width: None,
+            left: false,
+            precision: None,
+            pad: ' ',
+            unicode: false,
+            strings: true,
+            control: '~',
+        };
+        let negated = chars.next_if_eq(&'-').is_some();
+        if let Some(width) = field_value(chars, args)? {
+            spec.left = (width < 0) != negated;
+            spec.width = Some(width.unsigned_abs() as usize);
+        }
+        if chars.next_if_eq(&'.').is_some() {
+            match field_value(chars, args)? {
+                Some(precision) if precision < 0 => return Err(FormatError::BadField('.')),
+                precision => spec.precision = precision.map(|p| p as usize),
+            }
+            if chars.next_if_eq(&'.').is_some() {
+                spec.pad = match chars.next() {
+                    Some('*') => match args.next() {
+                        Some(Term::Number(Number::FixInteger(x))) => u32::try_from(x.value)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or(FormatError::BadField('*'))?,
+                        _ => return Err(FormatError::BadField('*')),
+                    },
+                    Some(pad) => pad,
+                    None => return Err(FormatError::Unterminated),
+                };
+            }
+        }
+        loop {
+            match chars.next() {
+                Some('t') => spec.unicode = true,
+                Some('l') => spec.strings = false,
+                // Maps are always written with their keys in order.
+                Some('k') => {}
+                Some(control) => {
+                    spec.control = control;
+                    return Ok(spec);
+                }
+                None => return Err(FormatError::Unterminated),
+            }
+        }
+    }
+
+    fn arg<'a>(&self, args: &mut Iter<'a, Term>) -> Result<&'a Term, FormatError> {
+        args.next().ok_or(FormatError::MissingArgument {
+            control: self.control,
+        })
+    }
+
+    fn bad_argument(&self, argument: &Term) -> FormatError {
+        FormatError::BadArgument {
+            control: self.control,
+            argument: argument.clone(),
+        }
+    }
+
+    fn write(&self, text: &mut String, args: &mut Iter<Term>) -> Result<(), FormatError> {
+        let field = match self.control {
+            '~' => self.chars('~')?,
+            'n' => self.chars('\n')?,
+            'i' => {
+                self.arg(args)?;
+                String::new()
+            }
+            'c' => {
+                let arg = self.arg(args)?;
+                let c = match arg {
+                    Term::Number(Number::FixInteger(x)) if self.unicode => {
+                        u32::try_from(x.value).ok().and_then(char::from_u32)
+                    }
+                    Term::Number(Number::FixInteger(x)) => Some(char::from(x.value as u8)),
+                    _ => None,
+                };
+                self.chars(c.ok_or_else(|| self.bad_argument(arg))?)?
+            }
+            's' => {
+                let arg = self.arg(args)?;
+                let mut string = String::new();
+                match arg {
+                    Term::Atom(x) if self.unicode || x.name().chars().all(|c| c <= '\u{ff}') => {
+                        string.push_str(x.name())
+                    }
+                    Term::Atom(_) => return Err(self.bad_argument(arg)),
+                    _ => chardata(arg, self.unicode, &mut string)
+                        .ok_or_else(|| self.bad_argument(arg))?,
+                }
+                self.string(string)?
+            }
+            'w' | 'W' => {
+                let arg = self.arg(args)?;
+                let mut options = PrettyOptions::new()
+                    .line_length(0)
+                    .strings(false)
+                    .unicode(self.unicode);
+                if self.control == 'W' {
+                    if let Some(depth) = self.depth(args)? {
+                        options = options.depth(depth);
+                    }
+                }
+                self.term(pretty(arg, &options))
+            }
+            'p' | 'P' => {
+                let arg = self.arg(args)?;
+                if self.left {
+                    return Err(FormatError::BadField(self.control));
+                }
+                let column = self.precision.unwrap_or_else(|| indentation(text) + 1);
+                let mut options = PrettyOptions::new()
+                    .line_length(self.width.unwrap_or(80))
+                    .column(column)
+                    .strings(self.strings)
+                    .unicode(self.unicode);
+                if self.control == 'P' {
+                    if let Some(depth) = self.depth(args)? {
+                        options = options.depth(depth);
+                    }
+                }
+                pretty(arg, &options)
+            }
+            'B' | 'b' | 'X' | 'x' | '#' | '+' => {
+                let arg = self.arg(args)?;
+                let value = match arg {
+                    Term::Number(Number::FixInteger(x)) => BigInt::from(x.value),
+                    Term::Number(Number::Bignum(x)) => x.value.clone(),
+                    _ => return Err(self.bad_argument(arg)),
+                };
+                let base = match self.precision {
+                    None => 10,
+                    Some(base @ 2..=36) => base as u32,
+                    Some(_) => return Err(FormatError::BadField(self.control)),
+                };
+                let prefix = match self.control {
+                    'X' | 'x' => {
+                        let prefix = self.arg(args)?;
+                        let mut string = String::new();
+                        match prefix {
+                            Term::Atom(x) => string.push_str(x.name()),
+                            _ => chardata(prefix, true, &mut string)
+                                .ok_or_else(|| self.bad_argument(prefix))?,
+                        }
+                        string
+                    }
+                    '#' | '+' => format!("{}#", base),
+                    _ => String::new(),
+                };
+                let mut digits = value.magnitude().to_str_radix(base);
+                if matches!(self.control, 'B' | 'X' | '#') {
+                    digits.make_ascii_uppercase();
+                }
+                let sign = if value < BigInt::from(0) { "-" } else { "" };
+                self.number(format!("{}{}{}", sign, prefix, digits))
+            }
+            'f' | 'e' | 'g' => {
+                let arg = self.arg(args)?;
+                let value = match arg {
+                    Term::Number(Number::Float(x)) => x.value,
+                    _ => return Err(self.bad_argument(arg)),
+                };
+                let minimum = if self.control == 'e' { 2 } else { 1 };
+                let precision = self.precision.unwrap_or(6);
+                if precision < minimum {
+                    return Err(FormatError::BadField(self.control));
+                }
+                let text = match self.control {
+                    'f' => write_fixed(value, precision),
+                    'e' => write_exponent(value, precision),
+                    _ => write_general(value, precision),
+                };
+                self.number(text)
+            }
+            control => return Err(FormatError::UnknownControl(control)),
+        };
+        text.push_str(&field);
+        Ok(())
+    }
+
+    /// Reads the depth argument of `~W` and `~P`, where a negative depth
+    /// means no limit.
+    fn depth(&self, args: &mut Iter<Term>) -> Result<Option<usize>, FormatError> {
+        let arg = self.arg(args)?;
+        match arg {
+            Term::Number(Number::FixInteger(x)) => Ok(usize::try_from(x.value).ok()),
+            _ => Err(self.bad_argument(arg)),
+        }
+    }
+
+    fn adjust(&self, text: String, padding: usize) -> String {
+        let padding: String = std::iter::repeat_n(self.pad, padding).collect();
+        if self.left {
+            text + &padding
+        } else {
+            padding + &text
+        }
+    }
+
+    /// Lays out a character repeated by the precision, or by the field
+    /// width if there is no precision.
+    fn chars(&self, c: char) -> Result<String, FormatError> {
+        let repeat = |n| std::iter::repeat_n(c, n).collect::<String>();
+        match (self.width, self.precision) {
+            (None, None) => Ok(c.to_string()),
+            (Some(width), None) => Ok(repeat(width)),
+            (None, Some(precision)) => Ok(repeat(precision)),
+            (Some(width), Some(precision)) if width >= precision => {
+                Ok(self.adjust(repeat(precision), width - precision))
+            }
+            _ => Err(FormatError::BadField(self.control)),
+        }
+    }
+
+    /// Lays out a string cut to the precision and padded to the field
+    /// width.
+    fn string(&self, string: String) -> Result<String, FormatError> {
+        let len = string.chars().count();
+        let cut = |n| string.chars().take(n).collect::<String>();
+        Ok(match (self.width, self.precision) {
+            (None, None) => string,
+            (Some(width), None) => match len {
+                len if len > width => cut(width),
+                len => self.adjust(string, width - len),
+            },
+            (None, Some(precision)) => match len {
+                len if len > precision => cut(precision),
+                len => Spec {
+                    left: true,
+                    ..*self
+                }
+                .adjust(string, precision - len),
+            },
+            (Some(width), Some(precision)) if width >= precision => {
+                let string = match len {
+                    len if len > precision => cut(precision),
+                    len => {
+                        let padding: String =
+                            std::iter::repeat_n(self.pad, precision - len).collect();
+                        string + &padding
+                    }
+                };
+                self.adjust(string, width - precision)
+            }
+            _ => return Err(FormatError::BadField(self.control)),
+        })
+    }
+
+    /// Lays out a number, whose precision is its base or number of digits
+    /// rather than a limit on its length.
+    fn number(&self, text: String) -> String {
+        Spec {
+            precision: None,
+            ..*self
+        }
+        .term(text)
+    }
+
+    /// Lays out a term padded to the field width, or as `*`s filling the
+    /// field if it does not fit.
+    fn term(&self, text: String) -> String {
+        let (width, precision) = match (self.width, self.precision) {
+            (None, None) => return text,
+            (None, Some(precision)) => (precision, precision),
+            (Some(width), precision) => (width, precision.map_or(width, |p| p.min(width))),
+        };
+        let len = text.chars().count();
+        let shown = len.min(precision);
+        if len > shown {
+            self.adjust("*".repeat(shown), width - shown)
+        } else {
+            self.adjust(text, width - len)
+        }
+    }
+}
+
+/// Reads a field width or precision, which is either digits or `*` for
+/// the next argument.
+fn field_value(
+    chars: &mut Peekable<Chars>,
+    args: &mut Iter<Term>,
+) -> Result<Option<i64>, FormatError> {
+    if chars.next_if_eq(&'*').is_some() {
+        return match args.next() {
+            Some(Term::Number(Number::FixInteger(x))) => Ok(Some(i64::from(x.value))),
+            _ => Err(FormatError::BadField('*')),
+        };
+    }
+    let mut value: Option<i64> = None;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        chars.next();
+        value = Some(
+            value
+                .unwrap_or(0)
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(i64::from(digit)))
+                .ok_or(FormatError::BadField('*'))?,
+        );
+    }
+    Ok(value)
+}
+
+/// Returns the width of the last line of `text`, with tabs stopping at
+/// every 8 columns.
+fn indentation(text: &str) -> usize {
+    let line = text.rsplit('\n').next().unwrap_or_default();
+    line.chars().fold(0, |column, c| match c {
+        '\t' => (column + 8) / 8 * 8,
+        _ => column + 1,
+    })
+}
+
+/// Appends the characters of an iolist or chardata: a binary, or a
+/// possibly deep list of characters and binaries. Returns `None` if it is
+/// not one, or holds characters above 255 without `unicode`.
+fn chardata(term: &Term, unicode: bool, string: &mut String) -> Option<()> {
+    match term {
+        Term::Nil(_) => Some(()),
+        Term::Bitstring(x) if !x.is_binary() => None,
+        Term::Bitstring(x) if unicode => {
+            string.push_str(std::str::from_utf8(&x.data).ok()?);
+            Some(())
+        }
+        Term::Bitstring(x) => {
+            string.extend(x.data.iter().map(|&b| char::from(b)));
+            Some(())
+        }
+        Term::List(x) => {
+            for element in &x.elements {
+                match element {
+                    Term::Number(Number::FixInteger(c)) => {
+                        let c = u32::try_from(c.value).ok().and_then(char::from_u32)?;
+                        if c > '\u{ff}' && !unicode {
+                            return None;
+                        }
+                        string.push(c);
+                    }
+                    Term::List(_) | Term::Nil(_) | Term::Bitstring(_) => {
+                        chardata(element, unicode, string)?
+                    }
+                    _ => return None,
+                }
+            }
+            match &*x.tail {
+                Term::Nil(_) | Term::Bitstring(_) => chardata(&x.tail, unicode, string),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Writes `value` as `~.Pf` does, with `precision` digits after the point.
+fn write_fixed(value: f64, precision: usize) -> String {
+    // `-0.0` is not less than `0.0`, so Erlang writes it without a sign.
+    let value = if value == 0.0 { 0.0 } else { value };
+    format!("{:.*}", precision, value)
+}
+
+/// Writes `value` as `~.Pe` does, with `precision` significant digits.
+fn write_exponent(value: f64, precision: usize) -> String {
+    let value = if value == 0.0 { 0.0 } else { value };
+    let text = format!("{:.*e}", precision - 1, value);
+    match text.split_once('e') {
+        Some((mantissa, exponent)) if !exponent.starts_with('-') => {
+            format!("{}e+{}", mantissa, exponent)
+        }
+        _ => text,
+    }
+}
+
+/// Writes `value` as `~.Pg` does: as `~f` if it is at least 0.1 and less
+/// than 10,000, and as `~e` otherwise.
+fn write_general(value: f64, precision: usize) -> String {
+    let magnitude = value.abs();
+    let exponent: i64 = match magnitude {
+        m if m < 1.0e-1 => -2,
+        m if m < 1.0e0 => -1,
+        m if m < 1.0e1 => 0,
+        m if m < 1.0e2 => 1,
+        m if m < 1.0e3 => 2,
+        m if m < 1.0e4 => 3,
+        _ => i64::MAX,
+    };
+    let precision = precision as i64;
+    if (precision <= 1 && exponent == -1) || (precision - 1 > exponent && exponent >= -1) {
+        write_fixed(value, (precision - 1 - exponent) as usize)
+    } else if precision <= 1 {
+        write_exponent(value, 2)
+    } else {
+        write_exponent(value, precision as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(text: &str) -> Vec<Term> {
+        match parse_term(text).unwrap() {
+            Term::List(list) => list.elements,
+            _ => Vec::new(),
+        }
+    }
+
+    fn f(format_string: &str, text: &str) -> String {
+        format(format_string, &args(text)).unwrap()
+    }
+
+    #[test]
+    fn it_formats_like_io_lib() {
+        assert_eq!(
+            f(
+                "~p: ~w ~s~n",
+                "[{ok, \"x\"}, \"x\", [\"ab\", <<\"c\">>, $d]]"
+            ),
+            "{ok,\"x\"}: [120] abcd\n"
+        );
+        assert_eq!(f("~~ ~i~c~5c ~tc", "[skipped, $a, $b, 9786]"), "~ abbbbb ☺");
+        assert_eq!(f("~ts ~s", "[<<\"é\"/utf8>>, <<\"é\"/utf8>>]"), "é Ã©");
+        assert_eq!(
+            f("[~5s|~-5s|~.2s|~5.2s]", "[abc, abc, abc, abc]"),
+            "[  abc|abc  |ab|   ab]"
+        );
+        assert_eq!(
+            f(
+                "~B ~.16B ~.16b ~8.2.0B ~.16# ~.16+",
+                "[-42, 255, 255, 5, -31, 31]"
+            ),
+            "-42 FF ff 00000101 -16#1F 16#1f"
+        );
+        assert_eq!(f("~.16X ~.16x", "[255, \"0x\", 255, '#']"), "0xFF #ff");
+        assert_eq!(f("~*.*.0B|~-*w|", "[6, 2, 5, 4, ab]"), "000101|ab  |");
+        assert_eq!(f("~3w ~6w", "[12345, 1.5]"), "***    1.5");
+        assert_eq!(
+            f("~f ~.2f ~e ~.3e", "[3.14159, 3.14159, 1.0, 123456.0]"),
+            "3.141590 3.14 1.00000e+0 1.23e+5"
+        );
+        assert_eq!(
+            f("~g ~g ~g", "[0.5, 12345.0, 0.01]"),
+            "0.500000 1.23450e+4 1.00000e-2"
+        );
+        assert_eq!(
+            f("~W ~P", "[[a, b, c, d], 3, [a, b, c, d], 3]"),
+            "[a,b|...] [a,b|...]"
+        );
+        assert_eq!(f("~lp ~p", "[\"ab\", \"ab\"]"), "[97,98] \"ab\"");
+    }
+
+    #[test]
+    fn it_indents_pretty_terms_from_the_column_they_start_at() {
+        let items: Vec<String> = (1..=6)
+            .map(|i| format!("{{item, {}, \"a name\"}}", i))
+            .collect();
+        let text = format!("[[{}]]", items.join(", "));
+        assert_eq!(
+            f("items: ~p", &text),
+            "items: [{item,1,\"a name\"},\n        {item,2,\"a name\"},\n        {item,3,\"a name\"},\n        {item,4,\"a name\"},\n        {item,5,\"a name\"},\n        {item,6,\"a name\"}]"
+        );
+    }
+
+    #[test]
+    fn it_rejects_bad_arguments() {
+        assert!(matches!(
+            format("~s ~s", &args("[a]")),
+            Err(FormatError::MissingArgument { control: 's' })
+        ));
+        assert!(matches!(
+            format("~s", &args("[a, b]")),
+            Err(FormatError::TooManyArguments { count: 1 })
+        ));
+        assert!(matches!(
+            format("~z", &[]),
+            Err(FormatError::UnknownControl('z'))
+        ));
+        assert!(matches!(format("~5", &[]), Err(FormatError::Unterminated)));
+        assert!(matches!(
+            format("~f", &args("[1]")),
+            Err(FormatError::BadArgument { control: 'f', .. })
+        ));
+        assert!(format("~s", &args("[[300]]")).is_err());
+        assert_eq!(format("~ts", &args("[[300]]")).unwrap(), "Ĭ");
+        let error = format("~s", &args("[{a}]")).unwrap_err();
+        assert_eq!(error.to_string(), "bad argument for ~s: {'a'}");
+    }
+}
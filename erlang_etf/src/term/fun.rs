@@ -2,7 +2,7 @@ use std::fmt;
 
 use crate::term::{Atom, Pid, Term};
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Fun {
     InternalFun(InternalFun),
     ExternalFun(ExternalFun),
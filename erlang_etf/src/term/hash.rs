@@ -0,0 +1,465 @@
+//! The portable hash of
+//! [`erlang:phash2/1,2`](https://www.erlang.org/doc/man/erlang.html#phash2-1),
+//! computed as `make_hash2()` in `erts/emulator/beam/utils.c` does on a
+//! 64-bit emulator.
+
+use crate::term::*;
+
+const HCONST: u32 = 0x9e3779b9;
+const HCONST_2: u32 = 0x3c6ef372;
+const HCONST_3: u32 = 0xdaa66d2b;
+const HCONST_4: u32 = 0x78dde6e4;
+const HCONST_5: u32 = 0x1715609d;
+const HCONST_6: u32 = 0xb54cda56;
+const HCONST_7: u32 = 0x5384540f;
+const HCONST_9: u32 = 0x8ff34781;
+const HCONST_10: u32 = 0x2e2ac13a;
+const HCONST_11: u32 = 0xcc623af3;
+const HCONST_12: u32 = 0x6a99b4ac;
+const HCONST_13: u32 = 0x08d12e65;
+const HCONST_14: u32 = 0xa708a81e;
+const HCONST_15: u32 = 0x454021d7;
+const HCONST_16: u32 = 0xe3779b90;
+const HCONST_19: u32 = 0xbe1e08bb;
+
+/// The type tag the emulator mixes in for `[]`.
+const NIL_DEF: u32 = 2;
+
+/// The hash of `[]` when it is the first thing hashed, which is what mixing
+/// in [`NIL_DEF`] would give.
+const NIL_HASH: u32 = 3468870702;
+
+/// Returns `erlang:phash2(Term)`, a hash in `0..2^27`.
+pub fn phash2(term: &Term) -> u32 {
+    make_hash2(term) & ((1 << 27) - 1)
+}
+
+/// Returns `erlang:phash2(Term, Range)`, a hash in `0..range`.
+///
+/// # Panics
+///
+/// Panics if `range` is not in `1..=2^32`, where `erlang:phash2/2` fails
+/// with `badarg`.
+pub fn phash2_range(term: &Term, range: u64) -> u32 {
+    assert!(
+        range > 0 && range <= 1 << 32,
+        "phash2 range must be in 1..=2^32, got {}",
+        range
+    );
+    let hash = make_hash2(term);
+    match u32::try_from(range) {
+        Ok(range) => hash % range,
+        Err(_) => hash,
+    }
+}
+
+/// Work left on the stack, like the `ESTACK` of `make_hash2()`.
+enum Work<'a> {
+    Term(&'a Term),
+    /// The list cells from the `usize`th element of a list on.
+    Cells(&'a List, usize),
+    /// The end of a map pair, whose hash is folded into the pairs hashed so
+    /// far.
+    MapPair,
+    /// The end of a map, with the state from before it.
+    MapTail {
+        hash: u32,
+        xor_pairs: u32,
+    },
+}
+
+fn make_hash2(term: &Term) -> u32 {
+    let mut hasher = Hasher {
+        hash: 0,
+        xor_pairs: 0,
+    };
+    let mut stack = vec![Work::Term(term)];
+    while let Some(work) = stack.pop() {
+        match work {
+            Work::Term(term) => hasher.term(term, &mut stack),
+            Work::Cells(list, index) => hasher.cells(list, index, &mut stack),
+            Work::MapPair => {
+                hasher.xor_pairs ^= hasher.hash;
+                hasher.hash = 0;
+            }
+            Work::MapTail { hash, xor_pairs } => {
+                hasher.hash = hash;
+                hasher.uint32_hash(hasher.xor_pairs, HCONST_19);
+                hasher.xor_pairs = xor_pairs;
+            }
+        }
+    }
+    hasher.hash
+}
+
+struct Hasher {
+    hash: u32,
+    /// The xor of the hashes of the pairs of the innermost map, so that
+    /// their order does not matter.
+    xor_pairs: u32,
+}
+impl Hasher {
+    fn uint32_hash_2(&mut self, x: u32, y: u32, constant: u32) {
+        let a = constant.wrapping_add(x);
+        let b = constant.wrapping_add(y);
+        (_, _, self.hash) = mix(a, b, self.hash);
+    }
+
+    fn uint32_hash(&mut self, x: u32, constant: u32) {
+        self.uint32_hash_2(x, 0, constant);
+    }
+
+    fn term<'a>(&mut self, term: &'a Term, stack: &mut Vec<Work<'a>>) {
+        match skip_empty_lists(term) {
+            Term::Number(x) => self.number(x),
+            Term::Atom(x) => {
+                let atom_hash = atom_hash(x);
+                if self.hash == 0 {
+                    self.hash = atom_hash;
+                } else {
+                    self.uint32_hash(atom_hash, HCONST_3);
+                }
+            }
+            Term::Reference(x) => {
+                self.uint32_hash(x.id.first().copied().unwrap_or(0), HCONST_7);
+            }
+            Term::Fun(x) => self.fun(x, stack),
+            // Both halves of the 64-bit port number, where a zero high word
+            // hashes as `UINT32_HASH` of the low word alone would.
+            Term::Port(x) => self.uint32_hash_2(x.id as u32, (x.id >> 32) as u32, HCONST_6),
+            Term::Pid(x) => self.uint32_hash(x.id, HCONST_5),
+            Term::Tuple(x) => {
+                self.uint32_hash(x.elements.len() as u32, HCONST_9);
+                stack.extend(x.elements.iter().rev().map(Work::Term));
+            }
+            Term::Map(x) => {
+                self.uint32_hash(x.pairs.len() as u32, HCONST_16);
+                if x.pairs.is_empty() {
+                    return;
+                }
+                stack.push(Work::MapTail {
+                    hash: self.hash,
+                    xor_pairs: self.xor_pairs,
+                });
+                self.hash = 0;
+                self.xor_pairs = 0;
+                for (key, val) in x.pairs.iter().rev() {
+                    stack.push(Work::MapPair);
+                    stack.push(Work::Term(val));
+                    stack.push(Work::Term(key));
+                }
+            }
+            Term::Nil(_) => {
+                if self.hash == 0 {
+                    self.hash = NIL_HASH;
+                } else {
+                    self.uint32_hash(NIL_DEF, HCONST_2);
+                }
+            }
+            Term::List(x) => self.cells(x, 0, stack),
+            Term::Bitstring(x) => self.bitstring(x),
+        }
+    }
+
+    /// Hashes runs of byte elements four at a time, like strings, and pushes
+    /// whatever follows the run.
+    fn cells<'a>(&mut self, mut list: &'a List, mut index: usize, stack: &mut Vec<Work<'a>>) {
+        let mut count = 0;
+        let mut packed: u32 = 0;
+        loop {
+            let element = &list.elements[index];
+            let next = match next_cell(list, index) {
+                Ok(cell) => Work::Cells(cell.0, cell.1),
+                Err(tail) => Work::Term(tail),
+            };
+            let byte = match small_integer(element).and_then(|x| u8::try_from(x).ok()) {
+                Some(byte) => byte,
+                None => {
+                    if count > 0 {
+                        self.uint32_hash(packed, HCONST_4);
+                    }
+                    stack.push(next);
+                    stack.push(Work::Term(element));
+                    return;
+                }
+            };
+            packed = (packed << 8) + u32::from(byte);
+            if count == 3 {
+                self.uint32_hash(packed, HCONST_4);
+                count = 0;
+                packed = 0;
+            } else {
+                count += 1;
+            }
+            match next {
+                Work::Cells(next_list, next_index) => {
+                    list = next_list;
+                    index = next_index;
+                }
+                tail => {
+                    if count > 0 {
+                        self.uint32_hash(packed, HCONST_4);
+                    }
+                    stack.push(tail);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn number(&mut self, number: &Number) {
+        if let Some(value) = small_integer_in(number) {
+            // Integers of at most 28 bits are hashed as 32-bit integers, and
+            // negative ones are mixed twice.
+            if (-(1 << 27)..(1 << 27)).contains(&value) {
+                let value = value as i32;
+                if value < 0 {
+                    self.uint32_hash(value.unsigned_abs(), HCONST);
+                }
+                self.uint32_hash(value as u32, HCONST);
+                return;
+            }
+        }
+        match number {
+            Number::FixInteger(x) => {
+                let constant = if x.value < 0 { HCONST_10 } else { HCONST_11 };
+                self.uint32_hash_2(x.value.unsigned_abs(), 0, constant);
+            }
+            Number::Bignum(x) => {
+                // Larger integers are hashed by their 64-bit digits, least
+                // significant first.
+                let constant = match x.value.sign() {
+                    Sign::Minus => HCONST_10,
+                    _ => HCONST_11,
+                };
+                for digit in x.value.magnitude().to_u64_digits() {
+                    self.uint32_hash_2(digit as u32, (digit >> 32) as u32, constant);
+                }
+            }
+            Number::Float(x) => {
+                // -0.0 hashes like 0.0. The emulator hashes the two words
+                // of the double in memory order, low word first.
+                let value = if x.value == 0.0 { 0.0 } else { x.value };
+                let bits = value.to_bits();
+                self.uint32_hash_2(bits as u32, (bits >> 32) as u32, HCONST_12);
+            }
+        }
+    }
+
+    fn fun<'a>(&mut self, fun: &'a Fun, stack: &mut Vec<Work<'a>>) {
+        let (module, index, uniq, free_vars) = match fun {
+            Fun::ExternalFun(x) => {
+                self.uint32_hash_2(u32::from(x.arity), atom_hash(&x.module), HCONST);
+                self.uint32_hash(atom_hash(&x.function), HCONST_14);
+                return;
+            }
+            Fun::InternalFun(InternalFun::New {
+                module,
+                index,
+                old_uniq,
+                free_vars,
+                ..
+            }) => (module, *index, *old_uniq, free_vars),
+            Fun::InternalFun(InternalFun::Old {
+                module,
+                index,
+                uniq,
+                free_vars,
+                ..
+            }) => (module, *index as u32, *uniq, free_vars),
+        };
+        self.uint32_hash_2(free_vars.len() as u32, atom_hash(module), HCONST);
+        self.uint32_hash_2(index, uniq as u32, HCONST);
+        stack.extend(free_vars.iter().rev().map(Work::Term));
+    }
+
+    fn bitstring(&mut self, bitstring: &Bitstring) {
+        let constant = HCONST_13.wrapping_add(self.hash);
+        let tail_bits = bitstring.bits % 8;
+        let (bytes, tail) = match bitstring.data.split_last() {
+            Some((&last, init)) if tail_bits != 0 => (init, Some(last)),
+            _ => (&bitstring.data[..], None),
+        };
+        if bytes.is_empty() && tail.is_none() {
+            self.hash = constant;
+            return;
+        }
+        self.hash = block_hash(bytes, constant);
+        if let Some(last) = tail {
+            // The decoder keeps the trailing bits right-aligned.
+            let last = last & ((1 << tail_bits) - 1);
+            self.uint32_hash_2(u32::from(tail_bits), u32::from(last), HCONST_15);
+        }
+    }
+}
+
+/// Returns the cell after the `index`th element of `list`, or the tail of
+/// the list if there is none.
+fn next_cell(list: &List, index: usize) -> Result<(&List, usize), &Term> {
+    if index + 1 < list.elements.len() {
+        return Ok((list, index + 1));
+    }
+    match skip_empty_lists(&list.tail) {
+        Term::List(next) => Ok((next, 0)),
+        tail => Err(tail),
+    }
+}
+
+/// Returns the value of an integer that the emulator would keep as a small
+/// integer, whichever way it is held.
+fn small_integer_in(number: &Number) -> Option<i64> {
+    match number {
+        Number::FixInteger(x) => Some(i64::from(x.value)),
+        Number::Bignum(x) => i64::try_from(&x.value).ok(),
+        Number::Float(_) => None,
+    }
+}
+
+fn small_integer(term: &Term) -> Option<i64> {
+    match term {
+        Term::Number(x) => small_integer_in(x),
+        _ => None,
+    }
+}
+
+/// The hash the atom table keeps for an atom, `hashpjw` over its name, with
+/// two-byte UTF-8 sequences for Latin-1 characters taken as one byte.
+fn atom_hash(atom: &Atom) -> u32 {
+    let mut name = atom.name().as_bytes();
+    let mut hash: u32 = 0;
+    while let Some((&first, rest)) = name.split_first() {
+        let mut byte = first;
+        name = rest;
+        if let Some((&next, rest)) = name.split_first() {
+            if first & 0xfe == 0xc2 && next & 0xc0 == 0x80 {
+                byte = (first << 6) | (next & 0x3f);
+                name = rest;
+            }
+        }
+        hash = (hash << 4).wrapping_add(u32::from(byte));
+        let g = hash & 0xf000_0000;
+        if g != 0 {
+            hash ^= g >> 24;
+            hash ^= g;
+        }
+    }
+    hash
+}
+
+/// Bob Jenkins' lookup2 hash over `bytes`, starting from `initval`.
+fn block_hash(bytes: &[u8], initval: u32) -> u32 {
+    let word = |k: &[u8]| u32::from_le_bytes([k[0], k[1], k[2], k[3]]);
+    let mut a = HCONST;
+    let mut b = HCONST;
+    let mut c = initval;
+    let mut chunks = bytes.chunks_exact(12);
+    for k in chunks.by_ref() {
+        a = a.wrapping_add(word(&k[0..4]));
+        b = b.wrapping_add(word(&k[4..8]));
+        c = c.wrapping_add(word(&k[8..12]));
+        (a, b, c) = mix(a, b, c);
+    }
+    let k = chunks.remainder();
+    c = c.wrapping_add(bytes.len() as u32);
+    // The lowest byte of `c` is taken by the length.
+    for (i, &byte) in k.iter().enumerate() {
+        let byte = u32::from(byte);
+        match i {
+            0..=3 => a = a.wrapping_add(byte << (8 * i)),
+            4..=7 => b = b.wrapping_add(byte << (8 * (i - 4))),
+            _ => c = c.wrapping_add(byte << (8 * (i - 7))),
+        }
+    }
+    (_, _, c) = mix(a, b, c);
+    c
+}
+
+fn mix(mut a: u32, mut b: u32, mut c: u32) -> (u32, u32, u32) {
+    a = a.wrapping_sub(b).wrapping_sub(c) ^ (c >> 13);
+    b = b.wrapping_sub(c).wrapping_sub(a) ^ (a << 8);
+    c = c.wrapping_sub(a).wrapping_sub(b) ^ (b >> 13);
+    a = a.wrapping_sub(b).wrapping_sub(c) ^ (c >> 12);
+    b = b.wrapping_sub(c).wrapping_sub(a) ^ (a << 16);
+    c = c.wrapping_sub(a).wrapping_sub(b) ^ (b >> 5);
+    a = a.wrapping_sub(b).wrapping_sub(c) ^ (c >> 3);
+    b = b.wrapping_sub(c).wrapping_sub(a) ^ (a << 10);
+    c = c.wrapping_sub(a).wrapping_sub(b) ^ (b >> 15);
+    (a, b, c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{atom, big, float, int};
+
+    #[test]
+    fn it_matches_otp() {
+        // erlang:phash2([]), erlang:phash2([], 1 bsl 32) and
+        // erlang:phash2(<<>>), which the emulator computes from constants.
+        assert_eq!(phash2(&Term::from(Nil)), 113427502);
+        assert_eq!(phash2_range(&Term::from(Nil), 1 << 32), 3468870702);
+        assert_eq!(phash2(&Term::from(Bitstring::from(vec![]))), 13708901);
+        // A lone atom hashes to its atom table hash, with 'é' as Latin-1.
+        assert_eq!(phash2(&atom("a")), 97);
+        assert_eq!(phash2(&atom("ab")), 97 * 16 + 98);
+        assert_eq!(phash2(&atom("\u{e9}")), 0xe9);
+    }
+
+    #[test]
+    fn it_hashes_equal_terms_alike() {
+        assert_eq!(phash2(&int(5)), phash2(&big(5)));
+        assert_eq!(phash2(&int(-1 << 30)), phash2(&big(-1 << 30)));
+        assert_ne!(phash2(&int(1)), phash2(&float(1.0)));
+        assert_eq!(phash2(&float(0.0)), phash2(&float(-0.0)));
+
+        let a = Term::from(Map::from(vec![(atom("a"), int(1)), (atom("b"), int(2))]));
+        let b = Term::from(Map::from(vec![(atom("b"), int(2)), (atom("a"), int(1))]));
+        assert_eq!(phash2(&a), phash2(&b));
+
+        // [104, 105 | [106]] is "hij", whichever way its cells are held.
+        let split = Term::from(List::from((
+            vec![int(104), int(105)],
+            Term::from(List::from(vec![int(106)])),
+        )));
+        let joined = Term::from(List::from(vec![int(104), int(105), int(106)]));
+        assert_eq!(phash2(&split), phash2(&joined));
+        assert_ne!(phash2(&joined), phash2(&Term::from(Nil)));
+    }
+
+    #[test]
+    fn it_hashes_into_ranges() {
+        let terms = [
+            int(42),
+            big(1 << 100),
+            float(2.5),
+            Term::from(Tuple::from(vec![atom("ok"), int(1)])),
+            Term::from(Bitstring::from((vec![1, 2, 0b101], 3))),
+            Term::from(Pid::new("a@b", 1, 2, 3)),
+        ];
+        for term in terms.iter() {
+            assert_eq!(phash2_range(term, 1), 0);
+            assert!(phash2_range(term, 7) < 7);
+            assert!(phash2(term) < 1 << 27);
+            assert_eq!(phash2_range(term, 1 << 32) & ((1 << 27) - 1), phash2(term));
+        }
+    }
+
+    #[test]
+    fn it_hashes_both_halves_of_port_numbers() {
+        let port = |id| phash2(&Term::from(Port::new("a@b", id, 3)));
+        let mut hasher = Hasher {
+            hash: 0,
+            xor_pairs: 0,
+        };
+        hasher.uint32_hash(5, HCONST_6);
+        assert_eq!(port(5), hasher.hash & ((1 << 27) - 1));
+        assert_ne!(port(5), port(5 | 1 << 32));
+        assert_ne!(port(1 << 32), port(1 << 33));
+    }
+
+    #[test]
+    #[should_panic]
+    fn it_rejects_an_empty_range() {
+        phash2_range(&Term::from(Nil), 0);
+    }
+}
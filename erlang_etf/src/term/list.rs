@@ -2,7 +2,7 @@ use std::fmt;
 
 use crate::term::{Nil, Term};
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 pub struct List {
     pub elements: Vec<Term>,
     pub tail: Box<Term>,
@@ -2,7 +2,7 @@ use std::fmt;
 
 use crate::term::Term;
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 pub struct Map {
     pub pairs: Vec<(Term, Term)>,
 }
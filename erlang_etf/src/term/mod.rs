@@ -5,34 +5,44 @@ pub mod convert;
 mod atom;
 mod atom_cache_ref;
 mod bitstring;
-mod dist;
+mod format;
 mod fun;
+mod hash;
 mod list;
 mod map;
 mod nil;
 mod number;
+mod order;
+mod parse;
+mod pretty;
 mod pid;
 mod port;
 mod reference;
+mod term_ref;
 mod tuple;
 
 pub use atom::*;
 pub use atom_cache_ref::*;
 pub use bitstring::*;
-pub use dist::*;
+pub use format::*;
 pub use fun::*;
+pub use hash::*;
 pub use list::*;
 pub use map::*;
 pub use nil::*;
 pub use number::*;
+pub use parse::*;
+pub use pretty::*;
+pub(crate) use order::*;
 pub use pid::*;
 pub use port::*;
 pub use reference::*;
+pub use term_ref::*;
 pub use tuple::*;
 
 /// See [Term Comparisons](https://www.erlang.org/doc/reference_manual/expressions.html#term-comparisons) in the Erlang docs.
 /// Ordering: `number < atom < reference < fun < port < pid < tuple < map < nil < list < bit string`
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 pub enum Term {
     Number(Number),
     Atom(Atom),
@@ -45,7 +55,6 @@ pub enum Term {
     Nil(Nil),
     List(List),
     Bitstring(Bitstring),
-    Dist(Dist),
 }
 impl Term {
     /// Returns `true` if it is fun term, otherwise `false`.
@@ -119,7 +128,6 @@ impl fmt::Display for Term {
             Term::Nil(ref x) => x.fmt(f),
             Term::List(ref x) => x.fmt(f),
             Term::Bitstring(ref x) => x.fmt(f),
-            Term::Dist(ref x) => x.fmt(f),
         }
     }
 }
@@ -133,11 +141,6 @@ impl From<Bitstring> for Term {
         Term::Bitstring(x)
     }
 }
-impl From<Dist> for Term {
-    fn from(x: Dist) -> Self {
-        Term::Dist(x)
-    }
-}
 impl From<Fun> for Term {
     fn from(x: Fun) -> Self {
         Term::Fun(x)
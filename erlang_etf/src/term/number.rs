@@ -3,7 +3,7 @@ use std::fmt;
 use crate::codec::error::DecodeError;
 pub use crate::num_bigint::{BigInt, Sign};
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug)]
 pub enum Number {
     FixInteger(FixInteger),
     Bignum(Bignum),
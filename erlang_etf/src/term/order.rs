@@ -0,0 +1,653 @@
+//! Erlang term order, see
+//! [Term Comparisons](https://www.erlang.org/doc/reference_manual/expressions.html#term-comparisons)
+//! in the Erlang docs.
+//!
+//! The [`Ord`] and [`Eq`] implementations of terms follow the exact
+//! comparisons `=:=` and `=/=`, so `1` and `1.0` are different terms. The
+//! arithmetic comparisons `==` and `<` are available as [`Term::arith_eq`]
+//! and [`Term::arith_cmp`].
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+use crate::term::*;
+
+/// How numbers of different types compare.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    /// Every integer is less than every float, as for map keys.
+    MapKey,
+    /// Numbers compare by value, and an integer is less than a float of the
+    /// same value, as for `=:=`.
+    Exact,
+    /// Numbers compare by value only, as for `==`.
+    Arith,
+}
+
+impl Term {
+    /// Compares two terms like Erlang's `<`, `==` and `>`, under which
+    /// `1 == 1.0`.
+    ///
+    /// This is [`Ord::cmp`], except that integers and floats of the same
+    /// value are equal.
+    pub fn arith_cmp(&self, other: &Self) -> Ordering {
+        cmp_terms(self, other, Mode::Arith)
+    }
+
+    /// Equivalent to `==`: `1 == 1.0` is true.
+    pub fn arith_eq(&self, other: &Self) -> bool {
+        self.arith_cmp(other).is_eq()
+    }
+
+    /// Equivalent to `=:=`: `1 =:= 1.0` is false. This is the same as `==`
+    /// on terms in Rust.
+    pub fn exact_eq(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+/// Implements `Ord` and `PartialOrd` in exact term order with `$cmp`.
+macro_rules! impl_ord {
+    ($ty:ty, $cmp:ident) => {
+        impl Ord for $ty {
+            fn cmp(&self, other: &Self) -> Ordering {
+                $cmp(self, other, Mode::Exact)
+            }
+        }
+        impl PartialOrd for $ty {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+    };
+}
+
+/// Implements `Eq` and `PartialEq` in terms of `Ord`, for types with more
+/// than one representation of the same value.
+macro_rules! impl_eq {
+    ($ty:ty) => {
+        impl Eq for $ty {}
+        impl PartialEq for $ty {
+            fn eq(&self, other: &Self) -> bool {
+                self.cmp(other).is_eq()
+            }
+        }
+    };
+}
+
+impl_ord!(Term, cmp_terms);
+impl_ord!(Number, cmp_numbers);
+impl_ord!(Reference, cmp_references);
+impl_ord!(Fun, cmp_funs);
+impl_ord!(Port, cmp_ports);
+impl_ord!(Pid, cmp_pids);
+impl_ord!(Tuple, cmp_tuples);
+impl_ord!(Map, cmp_maps);
+impl_ord!(List, cmp_lists);
+impl_ord!(Bitstring, cmp_bitstrings);
+
+impl_eq!(Term);
+impl_eq!(Number);
+impl_eq!(Map);
+impl_eq!(List);
+
+impl Hash for Term {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let term = skip_empty_lists(self);
+        type_rank(term).hash(state);
+        match term {
+            Term::Number(x) => x.hash(state),
+            Term::Atom(x) => x.hash(state),
+            Term::Reference(x) => x.hash(state),
+            Term::Fun(x) => x.hash(state),
+            Term::Port(x) => x.hash(state),
+            Term::Pid(x) => x.hash(state),
+            Term::Tuple(x) => x.hash(state),
+            Term::Map(x) => x.hash(state),
+            Term::Nil(_) => {}
+            Term::List(x) => x.hash(state),
+            Term::Bitstring(x) => x.hash(state),
+        }
+    }
+}
+
+/// Integers hash the same whether they are held in a [`FixInteger`] or a
+/// [`Bignum`].
+impl Hash for Number {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Number::FixInteger(x) => (0u8, x.value).hash(state),
+            Number::Bignum(x) => match i32::try_from(&x.value) {
+                Ok(value) => (0u8, value).hash(state),
+                Err(_) => (1u8, &x.value).hash(state),
+            },
+            Number::Float(x) => (2u8, x).hash(state),
+        }
+    }
+}
+
+/// Maps hash the same whatever the order of their pairs.
+impl Hash for Map {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.pairs.len().hash(state);
+        for pair in sorted_pairs(self) {
+            pair.hash(state);
+        }
+    }
+}
+
+/// Lists hash the same whether their cells are held in one `List` or split
+/// across the tails of nested ones.
+impl Hash for List {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut list = self;
+        loop {
+            for element in list.elements.iter() {
+                element.hash(state);
+            }
+            match skip_empty_lists(&list.tail) {
+                Term::List(next) => list = next,
+                tail => return tail.hash(state),
+            }
+        }
+    }
+}
+
+/// Compares two terms in map key order, the order in which
+/// `term_to_binary(T, [deterministic])` writes map keys.
+///
+/// This is term order, except that every integer is less than every float,
+/// so that `1` and `1.0` are distinct keys.
+pub(crate) fn cmp_map_keys(a: &Term, b: &Term) -> Ordering {
+    cmp_terms(a, b, Mode::MapKey)
+}
+
+fn cmp_terms(a: &Term, b: &Term, mode: Mode) -> Ordering {
+    let (a, b) = (skip_empty_lists(a), skip_empty_lists(b));
+    match (a, b) {
+        (Term::Number(a), Term::Number(b)) => cmp_numbers(a, b, mode),
+        (Term::Atom(a), Term::Atom(b)) => cmp_atoms(a, b),
+        (Term::Reference(a), Term::Reference(b)) => cmp_references(a, b, mode),
+        (Term::Fun(a), Term::Fun(b)) => cmp_funs(a, b, mode),
+        (Term::Port(a), Term::Port(b)) => cmp_ports(a, b, mode),
+        (Term::Pid(a), Term::Pid(b)) => cmp_pids(a, b, mode),
+        (Term::Tuple(a), Term::Tuple(b)) => cmp_tuples(a, b, mode),
+        (Term::Map(a), Term::Map(b)) => cmp_maps(a, b, mode),
+        (Term::Nil(_), Term::Nil(_)) => Ordering::Equal,
+        (Term::List(a), Term::List(b)) => cmp_lists(a, b, mode),
+        (Term::Bitstring(a), Term::Bitstring(b)) => cmp_bitstrings(a, b, mode),
+        (a, b) => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+/// A list without elements is encoded, and compared, as its tail.
+pub(crate) fn skip_empty_lists(mut term: &Term) -> &Term {
+    while let Term::List(list) = term {
+        if !list.elements.is_empty() {
+            break;
+        }
+        term = &list.tail;
+    }
+    term
+}
+
+const LIST_RANK: u8 = 9;
+
+fn type_rank(term: &Term) -> u8 {
+    match term {
+        Term::Number(_) => 0,
+        Term::Atom(_) => 1,
+        Term::Reference(_) => 2,
+        Term::Fun(_) => 3,
+        Term::Port(_) => 4,
+        Term::Pid(_) => 5,
+        Term::Tuple(_) => 6,
+        Term::Map(_) => 7,
+        Term::Nil(_) => 8,
+        Term::List(_) => LIST_RANK,
+        Term::Bitstring(_) => 10,
+    }
+}
+
+fn cmp_numbers(a: &Number, b: &Number, mode: Mode) -> Ordering {
+    match (a, b) {
+        (Number::Float(a), Number::Float(b)) if mode == Mode::MapKey => a.value.total_cmp(&b.value),
+        (Number::Float(a), Number::Float(b)) => a.cmp_value(b),
+        (Number::Float(_), _) if mode == Mode::MapKey => Ordering::Greater,
+        (_, Number::Float(_)) if mode == Mode::MapKey => Ordering::Less,
+        (Number::Float(a), b) => cmp_integer_float(b, a, mode).reverse(),
+        (a, Number::Float(b)) => cmp_integer_float(a, b, mode),
+        (a, b) => cmp_integers(a, b),
+    }
+}
+
+fn cmp_integers(a: &Number, b: &Number) -> Ordering {
+    match (a, b) {
+        (Number::FixInteger(a), Number::FixInteger(b)) => a.value.cmp(&b.value),
+        (Number::FixInteger(a), Number::Bignum(b)) => BigInt::from(a.value).cmp(&b.value),
+        (Number::Bignum(a), Number::FixInteger(b)) => a.value.cmp(&BigInt::from(b.value)),
+        (Number::Bignum(a), Number::Bignum(b)) => a.value.cmp(&b.value),
+        _ => unreachable!(),
+    }
+}
+
+/// Compares an integer with a float by their exact values. In exact mode,
+/// an integer is less than a float of the same value.
+fn cmp_integer_float(a: &Number, b: &Float, mode: Mode) -> Ordering {
+    let ordering = match a {
+        // Every i32 is exactly representable as an f64.
+        Number::FixInteger(a) => Float {
+            value: f64::from(a.value),
+        }
+        .cmp_value(b),
+        Number::Bignum(a) => {
+            let floor = b.value.floor();
+            match a.value.cmp(&integral_float_to_bigint(floor)) {
+                Ordering::Equal if floor < b.value => Ordering::Less,
+                ordering => ordering,
+            }
+        }
+        Number::Float(_) => unreachable!(),
+    };
+    match mode {
+        Mode::Exact => ordering.then(Ordering::Less),
+        _ => ordering,
+    }
+}
+
+/// Converts a float without a fractional part to the integer of the same
+/// value.
+fn integral_float_to_bigint(value: f64) -> BigInt {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i64;
+    let fraction = bits & ((1 << 52) - 1);
+    let (mantissa, exponent) = if exponent == 0 {
+        (fraction, -1074)
+    } else {
+        (fraction | (1 << 52), exponent - 1075)
+    };
+    let magnitude = if exponent >= 0 {
+        BigInt::from(mantissa) << exponent as usize
+    } else {
+        BigInt::from(mantissa) >> (-exponent) as usize
+    };
+    if value.is_sign_negative() {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+impl Float {
+    /// Compares by value, so that `-0.0` and `0.0` are equal, just like
+    /// [`Float`]'s `Eq`.
+    fn cmp_value(&self, other: &Self) -> Ordering {
+        ordered_float::OrderedFloat(self.value).cmp(&ordered_float::OrderedFloat(other.value))
+    }
+}
+
+fn cmp_atoms(a: &Atom, b: &Atom) -> Ordering {
+    // UTF-8 byte order is code point order.
+    a.name().as_bytes().cmp(b.name().as_bytes())
+}
+
+fn cmp_references(a: &Reference, b: &Reference, _mode: Mode) -> Ordering {
+    cmp_atoms(&a.node, &b.node)
+        .then(a.creation.cmp(&b.creation))
+        .then(a.id.len().cmp(&b.id.len()))
+        .then_with(|| a.id.iter().rev().cmp(b.id.iter().rev()))
+}
+
+fn cmp_ports(a: &Port, b: &Port, _mode: Mode) -> Ordering {
+    cmp_atoms(&a.node, &b.node)
+        .then(a.creation.cmp(&b.creation))
+        .then(a.id.cmp(&b.id))
+}
+
+fn cmp_pids(a: &Pid, b: &Pid, _mode: Mode) -> Ordering {
+    cmp_atoms(&a.node, &b.node)
+        .then(a.creation.cmp(&b.creation))
+        .then(a.serial.cmp(&b.serial))
+        .then(a.id.cmp(&b.id))
+}
+
+/// Funs that Erlang considers equal may still differ in fields that it
+/// ignores, such as the creating pid, so those break ties to keep the order
+/// total.
+fn cmp_funs(a: &Fun, b: &Fun, mode: Mode) -> Ordering {
+    match (a, b) {
+        (Fun::InternalFun(a), Fun::InternalFun(b)) => {
+            let (a_module, a_index, a_uniq, a_free_vars) = internal_fun_key(a);
+            let (b_module, b_index, b_uniq, b_free_vars) = internal_fun_key(b);
+            cmp_atoms(a_module, b_module)
+                .then(a_index.cmp(&b_index))
+                .then(a_uniq.cmp(&b_uniq))
+                .then(a_free_vars.len().cmp(&b_free_vars.len()))
+                .then_with(|| cmp_slices(a_free_vars, b_free_vars, mode))
+                .then_with(|| cmp_internal_fun_rest(a, b))
+        }
+        (Fun::ExternalFun(a), Fun::ExternalFun(b)) => cmp_atoms(&a.module, &b.module)
+            .then_with(|| cmp_atoms(&a.function, &b.function))
+            .then(a.arity.cmp(&b.arity)),
+        (Fun::InternalFun(_), Fun::ExternalFun(_)) => Ordering::Less,
+        (Fun::ExternalFun(_), Fun::InternalFun(_)) => Ordering::Greater,
+    }
+}
+
+fn internal_fun_key(fun: &InternalFun) -> (&Atom, i64, u128, &[Term]) {
+    match fun {
+        InternalFun::Old {
+            module,
+            index,
+            uniq,
+            free_vars,
+            ..
+        } => (module, *index as i64, *uniq as u32 as u128, free_vars),
+        InternalFun::New {
+            module,
+            index,
+            uniq,
+            free_vars,
+            ..
+        } => (module, *index as i64, u128::from_be_bytes(*uniq), free_vars),
+    }
+}
+
+fn cmp_internal_fun_rest(a: &InternalFun, b: &InternalFun) -> Ordering {
+    match (a, b) {
+        (InternalFun::Old { pid: a_pid, .. }, InternalFun::Old { pid: b_pid, .. }) => {
+            a_pid.cmp(b_pid)
+        }
+        (
+            InternalFun::New {
+                arity: a_arity,
+                pid: a_pid,
+                old_index: a_old_index,
+                old_uniq: a_old_uniq,
+                ..
+            },
+            InternalFun::New {
+                arity: b_arity,
+                pid: b_pid,
+                old_index: b_old_index,
+                old_uniq: b_old_uniq,
+                ..
+            },
+        ) => a_arity
+            .cmp(b_arity)
+            .then(a_old_index.cmp(b_old_index))
+            .then(a_old_uniq.cmp(b_old_uniq))
+            .then_with(|| a_pid.cmp(b_pid)),
+        (InternalFun::Old { .. }, InternalFun::New { .. }) => Ordering::Less,
+        (InternalFun::New { .. }, InternalFun::Old { .. }) => Ordering::Greater,
+    }
+}
+
+fn cmp_tuples(a: &Tuple, b: &Tuple, mode: Mode) -> Ordering {
+    a.elements
+        .len()
+        .cmp(&b.elements.len())
+        .then_with(|| cmp_slices(&a.elements, &b.elements, mode))
+}
+
+fn cmp_slices(a: &[Term], b: &[Term], mode: Mode) -> Ordering {
+    for (a, b) in a.iter().zip(b) {
+        match cmp_terms(a, b, mode) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Maps are ordered by size, then by their keys in map key order, then by
+/// the values of those keys.
+fn cmp_maps(a: &Map, b: &Map, mode: Mode) -> Ordering {
+    a.pairs.len().cmp(&b.pairs.len()).then_with(|| {
+        let (a, b) = (sorted_pairs(a), sorted_pairs(b));
+        a.iter()
+            .zip(&b)
+            .map(|((a, _), (b, _))| cmp_map_keys(a, b))
+            .chain(
+                a.iter()
+                    .zip(&b)
+                    .map(|((_, a), (_, b))| cmp_terms(a, b, mode)),
+            )
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    })
+}
+
+pub(crate) fn sorted_pairs(map: &Map) -> Vec<&(Term, Term)> {
+    let mut pairs: Vec<_> = map.pairs.iter().collect();
+    pairs.sort_by(|(a, _), (b, _)| cmp_map_keys(a, b));
+    pairs
+}
+
+/// Lists are compared cell by cell, so an improper tail is compared with
+/// whatever is in the same position in the other list.
+fn cmp_lists(a: &List, b: &List, mode: Mode) -> Ordering {
+    let n = a.elements.len().min(b.elements.len());
+    match cmp_slices(&a.elements[..n], &b.elements[..n], mode) {
+        Ordering::Equal => {}
+        ordering => return ordering,
+    }
+    match (a.elements.len() > n, b.elements.len() > n) {
+        (false, false) => cmp_terms(&a.tail, &b.tail, mode),
+        (false, true) => cmp_tail_with_cells(&a.tail, &b.elements[n..], &b.tail, mode),
+        (true, false) => cmp_tail_with_cells(&b.tail, &a.elements[n..], &a.tail, mode).reverse(),
+        (true, true) => unreachable!(),
+    }
+}
+
+fn cmp_tail_with_cells(tail: &Term, elements: &[Term], rest: &Term, mode: Mode) -> Ordering {
+    match skip_empty_lists(tail) {
+        Term::List(list) => {
+            let cells = List {
+                elements: elements.to_vec(),
+                tail: Box::new(rest.clone()),
+            };
+            cmp_lists(list, &cells, mode)
+        }
+        tail => type_rank(tail).cmp(&LIST_RANK),
+    }
+}
+
+/// Bitstrings with the same bits compare by their representation last, so
+/// that only identical bitstrings are equal.
+fn cmp_bitstrings(a: &Bitstring, b: &Bitstring, _mode: Mode) -> Ordering {
+    let (a_full, a_bits) = split_tail_bits(a);
+    let (b_full, b_bits) = split_tail_bits(b);
+    let n = a_full.len().min(b_full.len());
+    match a_full[..n].cmp(&b_full[..n]) {
+        Ordering::Equal => {}
+        ordering => return ordering,
+    }
+    // Compare what is left bit by bit, most significant first.
+    let a_rest = bits(&a_full[n..], a_bits);
+    let b_rest = bits(&b_full[n..], b_bits);
+    a_rest
+        .cmp(b_rest)
+        .then_with(|| (&a.data, a.bits).cmp(&(&b.data, b.bits)))
+}
+
+/// Splits a bitstring into its whole bytes and its trailing bits, which the
+/// decoder keeps right-aligned in the last byte.
+fn split_tail_bits(bitstring: &Bitstring) -> (&[u8], Option<(u8, u8)>) {
+    let tail_bits = bitstring.bits % 8;
+    match bitstring.data.split_last() {
+        Some((&last, init)) if tail_bits != 0 => (init, Some((last, tail_bits))),
+        _ => (&bitstring.data[..], None),
+    }
+}
+
+fn bits(bytes: &[u8], tail: Option<(u8, u8)>) -> impl Iterator<Item = bool> + '_ {
+    let whole = bytes
+        .iter()
+        .flat_map(|&byte| (0..8).rev().map(move |i| byte >> i & 1 == 1));
+    let tail = tail
+        .into_iter()
+        .flat_map(|(byte, n)| (0..n).rev().map(move |i| byte >> i & 1 == 1));
+    whole.chain(tail)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{atom, big, float, int};
+    use std::collections::hash_map::DefaultHasher;
+
+    fn hash(term: &Term) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        term.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn it_orders_map_keys_like_otp() {
+        let mut keys = vec![
+            Term::from(Bitstring::from(vec![1])),
+            Term::from(List::from(vec![int(1)])),
+            Term::from(Nil),
+            Term::from(Map::empty()),
+            Term::from(Tuple::from(vec![int(1)])),
+            Term::from(Tuple::empty()),
+            atom("b"),
+            atom("a"),
+            float(0.5),
+            int(2),
+            float(1.0),
+            int(1),
+        ];
+        keys.sort_by(cmp_map_keys);
+        assert_eq!(
+            keys,
+            vec![
+                int(1),
+                int(2),
+                float(0.5),
+                float(1.0),
+                atom("a"),
+                atom("b"),
+                Term::from(Tuple::empty()),
+                Term::from(Tuple::from(vec![int(1)])),
+                Term::from(Map::empty()),
+                Term::from(Nil),
+                Term::from(List::from(vec![int(1)])),
+                Term::from(Bitstring::from(vec![1])),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_orders_lists_and_bitstrings_cell_by_cell() {
+        let short = Term::from(List::from(vec![int(1)]));
+        let long = Term::from(List::from(vec![int(1), int(0)]));
+        let improper = Term::from(List::from((vec![int(1)], int(5))));
+        assert_eq!(cmp_map_keys(&short, &long), Ordering::Less);
+        // The tail 5 is a number, which is less than the list [0].
+        assert_eq!(cmp_map_keys(&improper, &long), Ordering::Less);
+
+        let bits = Term::from(Bitstring::from((vec![1, 0b1], 1)));
+        let bytes = Term::from(Bitstring::from(vec![1, 0]));
+        // <<1, 1:1>> is greater than <<1, 0>> and less than <<1, 128>>.
+        assert_eq!(cmp_map_keys(&bits, &bytes), Ordering::Greater);
+        let larger = Term::from(Bitstring::from(vec![1, 128, 0]));
+        assert_eq!(cmp_map_keys(&bits, &larger), Ordering::Less);
+    }
+
+    #[test]
+    fn it_sorts_terms_in_term_order() {
+        let mut terms = vec![
+            Term::from(Bitstring::from(vec![1])),
+            Term::from(List::from(vec![int(1)])),
+            Term::from(Nil),
+            Term::from(Map::from(vec![(int(1), int(1))])),
+            Term::from(Map::empty()),
+            Term::from(Tuple::from(vec![int(1), int(2)])),
+            Term::from(Tuple::from(vec![int(9)])),
+            Term::from(Pid::new("a", 1, 0, 0)),
+            Term::from(Port::new("a", 1, 0)),
+            Term::from(Reference::new("a", vec![1], 0)),
+            atom("a"),
+            big(1 << 40),
+            float(1.5),
+            int(2),
+            float(1.0),
+            int(1),
+            float(-0.5),
+        ];
+        terms.sort();
+        assert_eq!(
+            terms,
+            vec![
+                float(-0.5),
+                int(1),
+                float(1.0),
+                float(1.5),
+                int(2),
+                big(1 << 40),
+                atom("a"),
+                Term::from(Reference::new("a", vec![1], 0)),
+                Term::from(Port::new("a", 1, 0)),
+                Term::from(Pid::new("a", 1, 0, 0)),
+                Term::from(Tuple::from(vec![int(9)])),
+                Term::from(Tuple::from(vec![int(1), int(2)])),
+                Term::from(Map::empty()),
+                Term::from(Map::from(vec![(int(1), int(1))])),
+                Term::from(Nil),
+                Term::from(List::from(vec![int(1)])),
+                Term::from(Bitstring::from(vec![1])),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_compares_exactly_and_arithmetically() {
+        assert!(int(1).arith_eq(&float(1.0)));
+        assert!(!int(1).exact_eq(&float(1.0)));
+        assert_eq!(int(1).arith_cmp(&float(1.5)), Ordering::Less);
+        assert_eq!(
+            big(1 << 60).arith_cmp(&float((1u64 << 60) as f64)),
+            Ordering::Equal
+        );
+        assert_eq!(
+            big((1 << 60) + 1).arith_cmp(&float((1u64 << 60) as f64)),
+            Ordering::Greater
+        );
+
+        // Integers are equal whatever their representation.
+        assert_eq!(big(5), int(5));
+        assert_eq!(hash(&big(5)), hash(&int(5)));
+
+        let tuple = |x| Term::from(Tuple::from(vec![atom("a"), x]));
+        assert!(tuple(int(2)).arith_eq(&tuple(float(2.0))));
+        assert_ne!(tuple(int(2)), tuple(float(2.0)));
+
+        // Maps are equal whatever the order of their pairs.
+        let a = Term::from(Map::from(vec![(atom("a"), int(1)), (atom("b"), int(2))]));
+        let b = Term::from(Map::from(vec![(atom("b"), int(2)), (atom("a"), int(1))]));
+        assert_eq!(a, b);
+        assert_eq!(hash(&a), hash(&b));
+        let c = Term::from(Map::from(vec![
+            (atom("a"), float(1.0)),
+            (atom("b"), int(2)),
+        ]));
+        assert!(a.arith_eq(&c));
+        assert_ne!(a, c);
+
+        // Keys are always compared exactly.
+        let d = Term::from(Map::from(vec![(float(1.0), int(1))]));
+        let e = Term::from(Map::from(vec![(int(1), int(1))]));
+        assert!(!d.arith_eq(&e));
+
+        // [1 | [2]] is [1, 2].
+        let split = Term::from(List::from((
+            vec![int(1)],
+            Term::from(List::from(vec![int(2)])),
+        )));
+        let joined = Term::from(List::from(vec![int(1), int(2)]));
+        assert_eq!(split, joined);
+        assert_eq!(hash(&split), hash(&joined));
+    }
+}
@@ -0,0 +1,1070 @@
+//! A parser for Erlang term literals, such as the contents of `sys.config`,
+//! `.app` files and other files read with
+//! [`file:consult/1`](https://www.erlang.org/doc/man/file.html#consult-1).
+//!
+//! Only literal terms are accepted: there are no variables, operators other
+//! than a sign on numbers, records or funs.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::num_bigint::{BigInt, Sign};
+use crate::term::*;
+
+/// How deeply tuples, lists, maps and binaries may be nested.
+const MAX_DEPTH: usize = 128;
+
+/// The widest integer segment of a binary, in bits.
+const MAX_SEGMENT_BITS: usize = 1 << 24;
+
+/// Parses a single term, optionally followed by a `.`.
+pub fn parse_term(text: &str) -> Result<Term, ParseError> {
+    let mut parser = Parser::new(text)?;
+    let term = parser.term()?;
+    if parser.peek().kind == TokenKind::Dot {
+        parser.next();
+    }
+    parser.expect_eof()?;
+    Ok(term)
+}
+
+/// Parses a sequence of terms, each followed by a `.`, as `file:consult/1`
+/// does.
+pub fn parse_terms(text: &str) -> Result<Vec<Term>, ParseError> {
+    let mut parser = Parser::new(text)?;
+    let mut terms = Vec::new();
+    while parser.peek().kind != TokenKind::Eof {
+        terms.push(parser.term()?);
+        parser.expect(TokenKind::Dot, "`.`")?;
+    }
+    Ok(terms)
+}
+
+/// Why a term failed to parse.
+#[derive(Debug, thiserror::Error)]
+pub enum ParseErrorKind {
+    #[error("unexpected character {0:?}")]
+    UnexpectedChar(char),
+
+    #[error("unexpected {found}, expected {expected}")]
+    UnexpectedToken {
+        found: String,
+        expected: &'static str,
+    },
+
+    #[error("unterminated {0}")]
+    Unterminated(&'static str),
+
+    #[error("invalid escape sequence")]
+    InvalidEscape,
+
+    #[error("invalid number {0:?}")]
+    InvalidNumber(String),
+
+    #[error("atom of {len} characters exceeds the maximum of 255")]
+    AtomTooLong { len: usize },
+
+    #[error("variable {0} is not allowed in a term")]
+    Variable(String),
+
+    #[error("reserved word {0} is not allowed in a term")]
+    ReservedWord(String),
+
+    #[error("invalid binary segment: {0}")]
+    InvalidSegment(String),
+
+    #[error("term is nested deeper than the maximum depth of {max_depth}")]
+    DepthLimitExceeded { max_depth: usize },
+}
+
+/// Errors which can occur when parsing terms, with the 1-based line and
+/// column where the problem was found
+#[derive(Debug, thiserror::Error)]
+#[error("{line}:{column}: {kind}")]
+pub struct ParseError {
+    line: usize,
+    column: usize,
+    kind: ParseErrorKind,
+}
+impl ParseError {
+    fn new(position: Position, kind: ParseErrorKind) -> Self {
+        Self {
+            line: position.line,
+            column: position.column,
+            kind,
+        }
+    }
+
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    pub fn get_column(&self) -> usize {
+        self.column
+    }
+
+    pub fn get_kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+}
+
+/// Words which must be quoted to be read as atoms.
+pub(crate) const RESERVED_WORDS: &[&str] = &[
+    "after", "and", "andalso", "band", "begin", "bnot", "bor", "bsl", "bsr", "bxor", "case",
+    "catch", "cond", "div", "else", "end", "fun", "if", "let", "maybe", "not", "of", "or",
+    "orelse", "receive", "rem", "try", "when", "xor",
+];
+
+#[derive(Clone, Copy, Debug)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    Atom(String),
+    Integer(BigInt),
+    Float(f64),
+    String(String),
+    /// Punctuation such as `{` or `=>`.
+    Punct(&'static str),
+    /// The `.` which ends a term.
+    Dot,
+    Eof,
+}
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Atom(name) => write!(f, "atom {}", name),
+            Self::Integer(value) => write!(f, "integer {}", value),
+            Self::Float(value) => write!(f, "float {}", value),
+            Self::String(value) => write!(f, "string {:?}", value),
+            Self::Punct(punct) => write!(f, "`{}`", punct),
+            Self::Dot => write!(f, "`.`"),
+            Self::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    kind: TokenKind,
+    position: Position,
+}
+
+/// Punctuation, longest first so that `<<` is not read as two `<`.
+const PUNCTUATION: &[&str] = &[
+    "=>", "<<", ">>", "{", "}", "[", "]", "(", ")", ",", "|", "#", ":", "/", "-", "+",
+];
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    rest: &'a str,
+    position: Position,
+}
+impl<'a> Lexer<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+            rest: text,
+            position: Position { line: 1, column: 1 },
+        }
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, kind: ParseErrorKind) -> Result<T, ParseError> {
+        Err(ParseError::new(self.position, kind))
+    }
+
+    fn tokens(mut self) -> Result<Vec<Token>, ParseError> {
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let position = self.position;
+            let kind = self.token()?;
+            let eof = kind == TokenKind::Eof;
+            tokens.push(Token { kind, position });
+            if eof {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek_char() {
+            if c == '%' {
+                while !matches!(self.next_char(), Some('\n') | None) {}
+            } else if c.is_whitespace() {
+                self.next_char();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn token(&mut self) -> Result<TokenKind, ParseError> {
+        let start = self.position;
+        let c = match self.peek_char() {
+            Some(c) => c,
+            None => return Ok(TokenKind::Eof),
+        };
+        match c {
+            '0'..='9' => self.number(),
+            '$' => {
+                self.next_char();
+                match self.next_char() {
+                    Some('\\') => Ok(TokenKind::Integer(BigInt::from(self.escape()? as u32))),
+                    Some(c) => Ok(TokenKind::Integer(BigInt::from(c as u32))),
+                    None => self.error(ParseErrorKind::Unterminated("character literal")),
+                }
+            }
+            '"' => {
+                self.next_char();
+                Ok(TokenKind::String(self.quoted('"', "string")?))
+            }
+            '\'' => {
+                self.next_char();
+                let name = self.quoted('\'', "quoted atom")?;
+                Ok(TokenKind::Atom(name))
+            }
+            '.' => {
+                self.next_char();
+                match self.peek_char() {
+                    None | Some('%') => Ok(TokenKind::Dot),
+                    Some(c) if c.is_whitespace() => Ok(TokenKind::Dot),
+                    Some(c) => self.error(ParseErrorKind::UnexpectedChar(c)),
+                }
+            }
+            c if is_atom_start(c) => {
+                let name = self.name();
+                if RESERVED_WORDS.contains(&name.as_str()) {
+                    return Err(ParseError::new(start, ParseErrorKind::ReservedWord(name)));
+                }
+                Ok(TokenKind::Atom(name))
+            }
+            c if c == '_' || is_uppercase(c) => {
+                let name = self.name();
+                Err(ParseError::new(start, ParseErrorKind::Variable(name)))
+            }
+            c => match PUNCTUATION
+                .iter()
+                .find(|punct| self.rest.starts_with(**punct))
+            {
+                Some(punct) => {
+                    for _ in 0..punct.len() {
+                        self.next_char();
+                    }
+                    Ok(TokenKind::Punct(punct))
+                }
+                None => self.error(ParseErrorKind::UnexpectedChar(c)),
+            },
+        }
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek_char() {
+            if !is_name_char(c) {
+                break;
+            }
+            name.push(c);
+            self.next_char();
+        }
+        name
+    }
+
+    fn digits(&mut self, radix: u32) -> String {
+        let mut digits = String::new();
+        while let Some(c) = self.peek_char() {
+            if c.is_digit(radix) {
+                digits.push(c);
+            } else if c != '_' {
+                break;
+            }
+            self.next_char();
+        }
+        digits
+    }
+
+    fn number(&mut self) -> Result<TokenKind, ParseError> {
+        let position = self.position;
+        let invalid = |text: String| {
+            Err(ParseError::new(
+                position,
+                ParseErrorKind::InvalidNumber(text),
+            ))
+        };
+        let digits = self.digits(10);
+        if self.peek_char() == Some('#') {
+            self.next_char();
+            let radix = match digits.parse::<u32>() {
+                Ok(radix @ 2..=36) => radix,
+                _ => return invalid(format!("{}#", digits)),
+            };
+            let value = self.digits(radix);
+            return match BigInt::parse_bytes(value.as_bytes(), radix) {
+                Some(value) => Ok(TokenKind::Integer(value)),
+                None => invalid(format!("{}#{}", radix, value)),
+            };
+        }
+        let mut after = self.chars.clone();
+        let is_fraction =
+            after.next() == Some('.') && after.next().is_some_and(|c| c.is_ascii_digit());
+        if !is_fraction {
+            return Ok(TokenKind::Integer(digits.parse().expect("decimal digits")));
+        }
+        self.next_char();
+        let mut text = format!("{}.{}", digits, self.digits(10));
+        if let Some(e @ ('e' | 'E')) = self.peek_char() {
+            self.next_char();
+            text.push(e);
+            if let Some(sign @ ('-' | '+')) = self.peek_char() {
+                self.next_char();
+                text.push(sign);
+            }
+            let exponent = self.digits(10);
+            if exponent.is_empty() {
+                return invalid(text);
+            }
+            text.push_str(&exponent);
+        }
+        match text.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(TokenKind::Float(value)),
+            _ => invalid(text),
+        }
+    }
+
+    /// Reads the rest of a string or quoted atom whose opening quote has
+    /// been consumed.
+    fn quoted(&mut self, quote: char, what: &'static str) -> Result<String, ParseError> {
+        let mut text = String::new();
+        loop {
+            match self.next_char() {
+                Some(c) if c == quote => return Ok(text),
+                Some('\\') => text.push(self.escape()?),
+                Some(c) => text.push(c),
+                None => return self.error(ParseErrorKind::Unterminated(what)),
+            }
+        }
+    }
+
+    /// Reads the rest of an escape sequence whose `\` has been consumed.
+    fn escape(&mut self) -> Result<char, ParseError> {
+        let c = match self.next_char() {
+            Some(c) => c,
+            None => return self.error(ParseErrorKind::InvalidEscape),
+        };
+        let code = match c {
+            'b' => 8,
+            'd' => 127,
+            'e' => 27,
+            'f' => 12,
+            'n' => 10,
+            'r' => 13,
+            's' => 32,
+            't' => 9,
+            'v' => 11,
+            '^' => match self.next_char() {
+                Some(c) if c.is_ascii_alphabetic() || "@[\\]^_".contains(c) => c as u32 & 0x1f,
+                _ => return self.error(ParseErrorKind::InvalidEscape),
+            },
+            'x' if self.peek_char() == Some('{') => {
+                self.next_char();
+                let digits = self.digits(16);
+                if self.next_char() != Some('}') {
+                    return self.error(ParseErrorKind::InvalidEscape);
+                }
+                u32::from_str_radix(&digits, 16).unwrap_or(u32::MAX)
+            }
+            'x' => {
+                let mut code = 0;
+                for _ in 0..2 {
+                    match self.next_char().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => code = code * 16 + digit,
+                        None => return self.error(ParseErrorKind::InvalidEscape),
+                    }
+                }
+                code
+            }
+            '0'..='7' => {
+                let mut code = c.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match self.peek_char().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            self.next_char();
+                            code = code * 8 + digit;
+                        }
+                        None => break,
+                    }
+                }
+                code
+            }
+            c => c as u32,
+        };
+        match char::from_u32(code) {
+            Some(c) => Ok(c),
+            None => self.error(ParseErrorKind::InvalidEscape),
+        }
+    }
+}
+
+pub(crate) fn is_atom_start(c: char) -> bool {
+    c.is_ascii_lowercase() || (('ß'..='ÿ').contains(&c) && c != '÷')
+}
+
+fn is_uppercase(c: char) -> bool {
+    c.is_ascii_uppercase() || (('À'..='Þ').contains(&c) && c != '×')
+}
+
+pub(crate) fn is_name_char(c: char) -> bool {
+    is_atom_start(c) || is_uppercase(c) || c.is_ascii_digit() || c == '_' || c == '@'
+}
+
+struct Parser {
+    tokens: std::vec::IntoIter<Token>,
+    peeked: Token,
+    depth: usize,
+}
+impl Parser {
+    fn new(text: &str) -> Result<Self, ParseError> {
+        let mut tokens = Lexer::new(text).tokens()?.into_iter();
+        let peeked = tokens.next().expect("the lexer always ends with Eof");
+        Ok(Self {
+            tokens,
+            peeked,
+            depth: 0,
+        })
+    }
+
+    fn peek(&self) -> &Token {
+        &self.peeked
+    }
+
+    fn next(&mut self) -> Token {
+        let next = match self.tokens.next() {
+            Some(token) => token,
+            None => self.peeked.clone(),
+        };
+        std::mem::replace(&mut self.peeked, next)
+    }
+
+    fn unexpected<T>(&self, token: &Token, expected: &'static str) -> Result<T, ParseError> {
+        Err(ParseError::new(
+            token.position,
+            ParseErrorKind::UnexpectedToken {
+                found: token.kind.to_string(),
+                expected,
+            },
+        ))
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &'static str) -> Result<Token, ParseError> {
+        let token = self.next();
+        if token.kind != kind {
+            return self.unexpected(&token, expected);
+        }
+        Ok(token)
+    }
+
+    fn expect_eof(&mut self) -> Result<(), ParseError> {
+        self.expect(TokenKind::Eof, "end of input").map(|_| ())
+    }
+
+    fn eat(&mut self, punct: &'static str) -> bool {
+        if self.peek().kind == TokenKind::Punct(punct) {
+            self.next();
+            return true;
+        }
+        false
+    }
+
+    fn term(&mut self) -> Result<Term, ParseError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ParseError::new(
+                self.peek().position,
+                ParseErrorKind::DepthLimitExceeded {
+                    max_depth: MAX_DEPTH,
+                },
+            ));
+        }
+        let term = self.nested_term();
+        self.depth -= 1;
+        term
+    }
+
+    fn nested_term(&mut self) -> Result<Term, ParseError> {
+        let token = self.next();
+        match token.kind {
+            TokenKind::Atom(name) => {
+                let len = name.chars().count();
+                if len > 255 {
+                    return Err(ParseError::new(
+                        token.position,
+                        ParseErrorKind::AtomTooLong { len },
+                    ));
+                }
+                Ok(Term::from(Atom::from(name)))
+            }
+            TokenKind::Integer(value) => Ok(integer(value)),
+            TokenKind::Float(value) => Ok(float(value)),
+            TokenKind::String(mut string) => {
+                while let TokenKind::String(more) = &self.peek().kind {
+                    string.push_str(more);
+                    self.next();
+                }
+                Ok(charlist(&string))
+            }
+            TokenKind::Punct(sign @ ("-" | "+")) => {
+                let token = self.next();
+                match token.kind {
+                    TokenKind::Integer(value) if sign == "-" => Ok(integer(-value)),
+                    TokenKind::Integer(value) => Ok(integer(value)),
+                    TokenKind::Float(value) if sign == "-" => Ok(float(-value)),
+                    TokenKind::Float(value) => Ok(float(value)),
+                    _ => self.unexpected(&token, "a number"),
+                }
+            }
+            TokenKind::Punct("{") => {
+                let elements = self.sequence("}")?;
+                Ok(Term::from(Tuple::from(elements)))
+            }
+            TokenKind::Punct("[") => self.list(),
+            TokenKind::Punct("#") => {
+                self.expect(TokenKind::Punct("{"), "`{`")?;
+                self.map()
+            }
+            TokenKind::Punct("<<") => self.binary(),
+            _ => self.unexpected(&token, "a term"),
+        }
+    }
+
+    /// Parses comma-separated terms up to the closing `close`.
+    fn sequence(&mut self, close: &'static str) -> Result<Vec<Term>, ParseError> {
+        let mut elements = Vec::new();
+        if self.eat(close) {
+            return Ok(elements);
+        }
+        loop {
+            elements.push(self.term()?);
+            if self.eat(close) {
+                return Ok(elements);
+            }
+            let token = self.next();
+            if token.kind != TokenKind::Punct(",") {
+                return self.unexpected(&token, "`,` or a closing bracket");
+            }
+        }
+    }
+
+    fn list(&mut self) -> Result<Term, ParseError> {
+        let mut elements = Vec::new();
+        if self.eat("]") {
+            return Ok(Term::from(Nil));
+        }
+        loop {
+            elements.push(self.term()?);
+            let token = self.next();
+            match token.kind {
+                TokenKind::Punct(",") => continue,
+                TokenKind::Punct("]") => return Ok(Term::from(List::from(elements))),
+                TokenKind::Punct("|") => {
+                    let tail = self.term()?;
+                    self.expect(TokenKind::Punct("]"), "`]`")?;
+                    return Ok(match tail {
+                        Term::Nil(_) => Term::from(List::from(elements)),
+                        Term::List(list) => {
+                            elements.extend(list.elements);
+                            Term::from(List::from((elements, *list.tail)))
+                        }
+                        tail => Term::from(List::from((elements, tail))),
+                    });
+                }
+                _ => return self.unexpected(&token, "`,`, `|` or `]`"),
+            }
+        }
+    }
+
+    fn map(&mut self) -> Result<Term, ParseError> {
+        let mut pairs: Vec<(Term, Term)> = Vec::new();
+        if self.eat("}") {
+            return Ok(Term::from(Map::from(pairs)));
+        }
+        loop {
+            let key = self.term()?;
+            self.expect(TokenKind::Punct("=>"), "`=>`")?;
+            let value = self.term()?;
+            // Later associations replace earlier ones with the same key.
+            match pairs.iter_mut().find(|(k, _)| *k == key) {
+                Some(pair) => pair.1 = value,
+                None => pairs.push((key, value)),
+            }
+            let token = self.next();
+            match token.kind {
+                TokenKind::Punct(",") => continue,
+                TokenKind::Punct("}") => return Ok(Term::from(Map::from(pairs))),
+                _ => return self.unexpected(&token, "`,` or `}`"),
+            }
+        }
+    }
+
+    fn binary(&mut self) -> Result<Term, ParseError> {
+        let mut bits = BitWriter::default();
+        if self.eat(">>") {
+            return Ok(Term::from(bits.finish()));
+        }
+        loop {
+            let position = self.peek().position;
+            let segment = self.segment()?;
+            segment.write(&mut bits).map_err(|reason| {
+                ParseError::new(position, ParseErrorKind::InvalidSegment(reason))
+            })?;
+            let token = self.next();
+            match token.kind {
+                TokenKind::Punct(",") => continue,
+                TokenKind::Punct(">>") => return Ok(Term::from(bits.finish())),
+                _ => return self.unexpected(&token, "`,` or `>>`"),
+            }
+        }
+    }
+
+    fn segment(&mut self) -> Result<Segment, ParseError> {
+        let token = self.peek().clone();
+        let value = match &token.kind {
+            TokenKind::String(_) => {
+                let mut string = String::new();
+                while let TokenKind::String(more) = &self.peek().kind {
+                    string.push_str(more);
+                    self.next();
+                }
+                SegmentValue::String(string)
+            }
+            TokenKind::Integer(_) | TokenKind::Float(_) | TokenKind::Punct("-" | "+" | "<<") => {
+                match self.term()? {
+                    Term::Number(Number::FixInteger(x)) => SegmentValue::Integer(x.value.into()),
+                    Term::Number(Number::Bignum(x)) => SegmentValue::Integer(x.value),
+                    Term::Number(Number::Float(x)) => SegmentValue::Float(x.value),
+                    Term::Bitstring(x) => SegmentValue::Bitstring(x),
+                    _ => unreachable!("only numbers and binaries start this way"),
+                }
+            }
+            _ => return self.unexpected(&token, "a binary segment"),
+        };
+        let mut segment = Segment {
+            value,
+            size: None,
+            kind: None,
+            little: false,
+            unit: None,
+        };
+        if self.eat(":") {
+            let token = self.next();
+            match &token.kind {
+                TokenKind::Integer(size) => match usize::try_from(size) {
+                    Ok(size) => segment.size = Some(size),
+                    Err(_) => return self.unexpected(&token, "a segment size"),
+                },
+                _ => return self.unexpected(&token, "a segment size"),
+            }
+        }
+        if self.eat("/") {
+            loop {
+                let token = self.next();
+                let name = match &token.kind {
+                    TokenKind::Atom(name) => name.as_str(),
+                    _ => return self.unexpected(&token, "a type specifier"),
+                };
+                match name {
+                    "integer" | "float" | "binary" | "bytes" | "bitstring" | "bits" | "utf8"
+                    | "utf16" | "utf32" => segment.kind = Some(name.to_owned()),
+                    "signed" | "unsigned" | "big" => {}
+                    "little" => segment.little = true,
+                    "native" => segment.little = cfg!(target_endian = "little"),
+                    "unit" => {
+                        self.expect(TokenKind::Punct(":"), "`:`")?;
+                        let token = self.next();
+                        match &token.kind {
+                            TokenKind::Integer(unit) => match u8::try_from(unit) {
+                                Ok(unit @ 1..=255) => segment.unit = Some(unit as usize),
+                                _ => return self.unexpected(&token, "a unit from 1 to 255"),
+                            },
+                            _ => return self.unexpected(&token, "a unit from 1 to 255"),
+                        }
+                    }
+                    _ => return self.unexpected(&token, "a type specifier"),
+                }
+                if !self.eat("-") {
+                    break;
+                }
+            }
+        }
+        Ok(segment)
+    }
+}
+
+fn integer(value: BigInt) -> Term {
+    match i32::try_from(&value) {
+        Ok(value) => Term::from(Number::from(FixInteger::from(value))),
+        Err(_) => Term::from(Number::from(Bignum { value })),
+    }
+}
+
+fn float(value: f64) -> Term {
+    Term::from(Number::from(Float { value }))
+}
+
+fn charlist(string: &str) -> Term {
+    if string.is_empty() {
+        return Term::from(Nil);
+    }
+    Term::from(List::from(
+        string
+            .chars()
+            .map(|c| integer(BigInt::from(c as u32)))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+enum SegmentValue {
+    Integer(BigInt),
+    Float(f64),
+    String(String),
+    Bitstring(Bitstring),
+}
+
+/// One `Value:Size/Type` segment of a binary.
+struct Segment {
+    value: SegmentValue,
+    size: Option<usize>,
+    kind: Option<String>,
+    little: bool,
+    unit: Option<usize>,
+}
+impl Segment {
+    fn write(self, bits: &mut BitWriter) -> Result<(), String> {
+        match self.value {
+            SegmentValue::String(ref string) => {
+                for c in string.chars() {
+                    Segment {
+                        value: SegmentValue::Integer(BigInt::from(c as u32)),
+                        kind: self.kind.clone(),
+                        ..self
+                    }
+                    .write(bits)?;
+                }
+                Ok(())
+            }
+            SegmentValue::Integer(ref value) => match self.kind.as_deref() {
+                None | Some("integer") => {
+                    let len = self.len(8, 1)?;
+                    if len > MAX_SEGMENT_BITS {
+                        return Err(format!(
+                            "integers wider than {} bits are not supported",
+                            MAX_SEGMENT_BITS
+                        ));
+                    }
+                    bits.write_integer(value, len, self.little)
+                }
+                Some("float") => self.write_float(bits, float_of(value)),
+                Some(kind @ ("utf8" | "utf16" | "utf32")) => {
+                    if self.size.is_some() {
+                        return Err("utf segments cannot have a size".to_string());
+                    }
+                    let c = u32::try_from(value)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("{} is not a code point", value))?;
+                    bits.write_char(c, kind, self.little);
+                    Ok(())
+                }
+                Some(kind) => Err(format!("an integer cannot be a {} segment", kind)),
+            },
+            SegmentValue::Float(value) => match self.kind.as_deref() {
+                None | Some("float") => self.write_float(bits, value),
+                Some(kind) => Err(format!("a float cannot be a {} segment", kind)),
+            },
+            SegmentValue::Bitstring(ref value) => match self.kind.as_deref() {
+                Some("binary" | "bytes" | "bitstring" | "bits") => {
+                    let unit = match self.kind.as_deref() {
+                        Some("binary" | "bytes") => self.unit.unwrap_or(8),
+                        _ => self.unit.unwrap_or(1),
+                    };
+                    let available = bit_len(value);
+                    let len = match self.size {
+                        Some(_) => self.len(0, unit)?,
+                        None => available,
+                    };
+                    if len > available || len % unit != 0 {
+                        return Err(format!("{} does not fit {} bits", value, len));
+                    }
+                    bits.write_bitstring(value, len);
+                    Ok(())
+                }
+                _ => Err("a binary segment needs the binary or bitstring type".to_string()),
+            },
+        }
+    }
+
+    /// Returns the segment's size in bits, from `size * unit`.
+    fn len(&self, size: usize, unit: usize) -> Result<usize, String> {
+        let size = self.size.unwrap_or(size);
+        let unit = self.unit.unwrap_or(unit);
+        size.checked_mul(unit)
+            .ok_or_else(|| format!("a size of {}*{} bits is too large", size, unit))
+    }
+
+    fn write_float(&self, bits: &mut BitWriter, value: f64) -> Result<(), String> {
+        let mut bytes = match self.len(64, 1)? {
+            64 => value.to_be_bytes().to_vec(),
+            32 => (value as f32).to_be_bytes().to_vec(),
+            len => return Err(format!("floats of {} bits are not supported", len)),
+        };
+        if self.little {
+            bytes.reverse();
+        }
+        bits.write_bits(&bytes, 0, bytes.len() * 8);
+        Ok(())
+    }
+}
+
+fn float_of(value: &BigInt) -> f64 {
+    value.to_string().parse().unwrap_or(f64::INFINITY)
+}
+
+fn bit_len(bitstring: &Bitstring) -> usize {
+    match bitstring.bits % 8 {
+        0 => bitstring.data.len() * 8,
+        tail => (bitstring.data.len() - 1) * 8 + tail as usize,
+    }
+}
+
+/// Accumulates the bits of a binary, most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+impl BitWriter {
+    /// Appends `len` bits of `bytes`, starting `skip` bits in.
+    fn write_bits(&mut self, bytes: &[u8], skip: usize, len: usize) {
+        for i in skip..skip + len {
+            let bit = bytes[i / 8] >> (7 - i % 8) & 1;
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.len % 8);
+            self.len += 1;
+        }
+    }
+
+    /// Appends the low `len` bits of `value` in two's complement.
+    fn write_integer(&mut self, value: &BigInt, len: usize, little: bool) -> Result<(), String> {
+        if little && !len.is_multiple_of(8) {
+            return Err("little-endian integers must be a whole number of bytes".to_string());
+        }
+        let width = len.div_ceil(8);
+        let mut bytes = value.to_signed_bytes_be();
+        let fill = if value.sign() == Sign::Minus { 0xff } else { 0 };
+        if bytes.len() < width {
+            let mut padded = vec![fill; width - bytes.len()];
+            padded.extend(bytes);
+            bytes = padded;
+        } else {
+            bytes.drain(..bytes.len() - width);
+        }
+        if little {
+            bytes.reverse();
+        }
+        self.write_bits(&bytes, width * 8 - len, len);
+        Ok(())
+    }
+
+    fn write_char(&mut self, c: char, kind: &str, little: bool) {
+        let mut bytes = match kind {
+            "utf8" => c.to_string().into_bytes(),
+            "utf16" => {
+                let mut units = [0; 2];
+                c.encode_utf16(&mut units)
+                    .iter()
+                    .flat_map(|unit| unit.to_be_bytes())
+                    .collect()
+            }
+            _ => (c as u32).to_be_bytes().to_vec(),
+        };
+        if little {
+            match kind {
+                "utf16" => bytes.chunks_mut(2).for_each(|unit| unit.reverse()),
+                "utf32" => bytes.reverse(),
+                _ => {}
+            }
+        }
+        self.write_bits(&bytes, 0, bytes.len() * 8);
+    }
+
+    fn write_bitstring(&mut self, bitstring: &Bitstring, len: usize) {
+        let mut bytes = bitstring.data.to_vec();
+        if let (Some(last), tail @ 1..=7) = (bytes.last_mut(), bitstring.bits % 8) {
+            *last <<= 8 - tail;
+        }
+        self.write_bits(&bytes, 0, len);
+    }
+
+    fn finish(mut self) -> Bitstring {
+        let tail = self.len % 8;
+        if let (Some(last), 1..=7) = (self.bytes.last_mut(), tail) {
+            *last >>= 8 - tail;
+        }
+        Bitstring::from((self.bytes, tail as u8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{atom, int, tuple};
+
+    #[test]
+    fn it_parses_term_literals() {
+        assert_eq!(parse_term("foo").unwrap(), atom("foo"));
+        assert_eq!(parse_term("'Hello world'.").unwrap(), atom("Hello world"));
+        assert_eq!(parse_term("node@host").unwrap(), atom("node@host"));
+        assert_eq!(parse_term("16#FF").unwrap(), int(255));
+        assert_eq!(parse_term("-2#1010").unwrap(), int(-10));
+        assert_eq!(parse_term("1_000_000").unwrap(), int(1_000_000));
+        assert_eq!(parse_term("$a").unwrap(), int(97));
+        assert_eq!(parse_term("$\\n").unwrap(), int(10));
+        assert_eq!(
+            parse_term("36#ZZZZZZZZZZZZZZ").unwrap(),
+            Term::from(Number::from(Bignum {
+                value: BigInt::parse_bytes(b"ZZZZZZZZZZZZZZ", 36).unwrap(),
+            }))
+        );
+        assert_eq!(parse_term("-1.5e3").unwrap(), float(-1500.0));
+        assert_eq!(parse_term("\"hi\" \"\\x{263a}\"").unwrap(), charlist("hi☺"));
+        assert_eq!(parse_term("\"\"").unwrap(), Term::from(Nil));
+        assert_eq!(
+            parse_term("[1, 2 | [3 | tail]]").unwrap(),
+            Term::from(List::from((vec![int(1), int(2), int(3)], atom("tail"))))
+        );
+        assert_eq!(
+            parse_term("#{a => 1, \"k\" => {}, a => 2}").unwrap(),
+            Term::from(Map::from(vec![
+                (atom("a"), int(2)),
+                (charlist("k"), tuple(vec![])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn it_parses_binaries() {
+        let binary = |bytes: &[u8]| Term::from(Bitstring::from(bytes));
+        assert_eq!(parse_term("<<>>").unwrap(), binary(&[]));
+        assert_eq!(
+            parse_term("<<\"a\", 1:8, 258:16>>").unwrap(),
+            binary(b"a\x01\x01\x02")
+        );
+        assert_eq!(
+            parse_term("<<-1:16/little>>").unwrap(),
+            binary(&[0xff, 0xff])
+        );
+        assert_eq!(parse_term("<<258:16/little>>").unwrap(), binary(&[2, 1]));
+        assert_eq!(
+            parse_term("<<\"é\"/utf8>>").unwrap(),
+            binary("é".as_bytes())
+        );
+        assert_eq!(
+            parse_term("<<1.0/float>>").unwrap(),
+            binary(&1.0f64.to_be_bytes())
+        );
+        assert_eq!(
+            parse_term("<<1:1, 0:2, <<5:3>>/bits>>").unwrap(),
+            Term::from(Bitstring::from((vec![0b100101], 6)))
+        );
+        assert_eq!(
+            parse_term("<<<<1, 2, 3>>:2/binary, 7:4>>").unwrap(),
+            Term::from(Bitstring::from((vec![1, 2, 7], 4)))
+        );
+    }
+
+    #[test]
+    fn it_parses_consult_files() {
+        let text = "
+            %% sys.config
+            [{kernel, [{logger_level, info}]},   % trailing comment
+             {app, [{port, 8080}, {name, <<\"svc\">>}]}].
+            {version, \"1.0\"}.
+        ";
+        let terms = parse_terms(text).unwrap();
+        assert_eq!(terms.len(), 2);
+        assert_eq!(terms[1], tuple(vec![atom("version"), charlist("1.0")]));
+        assert_eq!(parse_terms("  % nothing\n").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn it_reports_error_positions() {
+        let error = parse_terms("{a, b}.\n{c, X}.").unwrap_err();
+        assert_eq!((error.get_line(), error.get_column()), (2, 5));
+        assert!(matches!(error.get_kind(), ParseErrorKind::Variable(name) if name == "X"));
+
+        let error = parse_terms("[1, 2\n  3].").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "2:3: unexpected integer 3, expected `,`, `|` or `]`"
+        );
+        let error = parse_terms("{a}").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1:4: unexpected end of input, expected `.`"
+        );
+        let error = parse_term("\"open").unwrap_err();
+        assert!(matches!(
+            error.get_kind(),
+            ParseErrorKind::Unterminated("string")
+        ));
+        assert!(parse_term("fun").is_err());
+        assert!(parse_term("{a} {b}").is_err());
+    }
+
+    #[test]
+    fn it_limits_nesting_and_segment_sizes() {
+        let nested = "[".repeat(100_000) + &"]".repeat(100_000);
+        let error = parse_term(&nested).unwrap_err();
+        assert!(matches!(
+            error.get_kind(),
+            ParseErrorKind::DepthLimitExceeded { max_depth: 128 }
+        ));
+        let nested = "{".repeat(MAX_DEPTH) + &"}".repeat(MAX_DEPTH);
+        assert!(parse_term(&nested).is_ok());
+
+        for text in [
+            "<<0:99999999999>>",
+            "<<0:18446744073709551615/unit:2>>",
+            "<<\"ab\":18446744073709551615/binary>>",
+            "<<1.0:18446744073709551615/float-unit:255>>",
+        ] {
+            let error = parse_term(text).unwrap_err();
+            assert!(
+                matches!(error.get_kind(), ParseErrorKind::InvalidSegment(_)),
+                "{}",
+                text
+            );
+        }
+    }
+}
@@ -2,7 +2,7 @@ use std::fmt;
 
 use crate::term::Atom;
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Port {
     pub node: Atom,
     pub id: u64,
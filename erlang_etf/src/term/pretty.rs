@@ -0,0 +1,953 @@
+//! A pretty printer which lays terms out as `io_lib:format("~p", [Term])`
+//! does, following the rules of `io_lib_pretty` in `stdlib`.
+//!
+//! A term is written on one line when it fits. Otherwise the elements of a
+//! list or tuple are broken across lines, with elements which are not
+//! themselves lists, tuples, maps or binaries filling each line. A tuple
+//! whose first element is an atom, such as `{error, Reason}`, keeps that
+//! tag on the first line.
+
+use crate::term::*;
+
+/// Options for [`pretty`], which correspond to the control sequences of
+/// `io_lib:format/2` as follows:
+///
+/// | Control | Options                                          |
+/// |---------|--------------------------------------------------|
+/// | `~p`    | `PrettyOptions::new()`                           |
+/// | `~tp`   | `PrettyOptions::new().unicode(true)`             |
+/// | `~lp`   | `PrettyOptions::new().strings(false)`            |
+/// | `~P`    | `PrettyOptions::new().depth(depth)`              |
+/// | `~w`    | `PrettyOptions::new().line_length(0).strings(false)` |
+/// | `~W`    | the same as `~w` with `.depth(depth)`            |
+#[derive(Clone, Debug)]
+pub struct PrettyOptions {
+    line_length: usize,
+    column: usize,
+    depth: Option<usize>,
+    unicode: bool,
+    strings: bool,
+}
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        Self {
+            line_length: 80,
+            column: 1,
+            depth: None,
+            unicode: false,
+            strings: true,
+        }
+    }
+}
+impl PrettyOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The width terms are broken to fit, `80` by default. `0` writes every
+    /// term on one line.
+    pub fn line_length(mut self, line_length: usize) -> Self {
+        self.line_length = line_length;
+        self
+    }
+
+    /// The 1-based column the term starts at, which is also where broken
+    /// lines are indented from. Defaults to `1`.
+    pub fn column(mut self, column: usize) -> Self {
+        self.column = column.max(1);
+        self
+    }
+
+    /// Replaces what lies deeper than `depth` with `...`. Each element of a
+    /// list, tuple or map counts one level deeper than the one before it,
+    /// as with `~P`.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Equivalent to the `t` modifier of `~tp`: atoms and binaries may hold
+    /// any Unicode character, and lists of Unicode characters print as
+    /// strings as under `+pc unicode`.
+    pub fn unicode(mut self, unicode: bool) -> Self {
+        self.unicode = unicode;
+        self
+    }
+
+    /// Whether lists and binaries of printable characters print as strings.
+    /// Defaults to `true`; `false` is equivalent to the `l` modifier of
+    /// `~lp`.
+    pub fn strings(mut self, strings: bool) -> Self {
+        self.strings = strings;
+        self
+    }
+
+    pub fn get_line_length(&self) -> usize {
+        self.line_length
+    }
+
+    pub fn get_column(&self) -> usize {
+        self.column
+    }
+
+    pub fn get_depth(&self) -> Option<usize> {
+        self.depth
+    }
+
+    pub fn is_unicode(&self) -> bool {
+        self.unicode
+    }
+
+    pub fn get_strings(&self) -> bool {
+        self.strings
+    }
+}
+
+/// Formats `term` as `io_lib:format/2` does for the control sequence
+/// described by `options`.
+pub fn pretty(term: &Term, options: &PrettyOptions) -> String {
+    let depth = options.depth.map_or(-1, |depth| depth as i64);
+    let item = Builder { options }.item(term, depth);
+    let (line_length, column) = (options.line_length, options.column);
+    if line_length == 0 || item.len + column < line_length {
+        return write(&item);
+    }
+    // Keep elements beside the tag of tagged tuples if that leaves them
+    // room, else indent them by 4, else by 1, as `io_lib_pretty` does.
+    for tag_indent in [None, Some(4)] {
+        let printer = Printer {
+            line_length,
+            max: item.len,
+            tag_indent,
+            check: true,
+        };
+        if let Ok(text) = printer.pp(&item, column, 0) {
+            return text;
+        }
+    }
+    let printer = Printer {
+        line_length,
+        max: item.len,
+        tag_indent: Some(1),
+        check: false,
+    };
+    printer.pp(&item, column, 0).unwrap_or_default()
+}
+
+/// Returns the text of `value` as `io_lib:format("~p", [Value])` writes
+/// floats: the shortest digits which read back as the same float, in
+/// scientific notation when that is shorter.
+pub(crate) fn write_float(value: f64) -> String {
+    if value == 0.0 {
+        return if value.is_sign_negative() {
+            "-0.0"
+        } else {
+            "0.0"
+        }
+        .to_string();
+    }
+    let sign = if value < 0.0 { "-" } else { "" };
+    let scientific = format!("{:e}", value.abs());
+    let (mantissa, exponent) = scientific.split_once('e').expect("exponent");
+    let digits = mantissa.replace('.', "");
+    let len = digits.len() as i64;
+    // The number of digits before the decimal point.
+    let place = exponent.parse::<i64>().expect("exponent") + 1;
+    let exponential = || {
+        let (first, rest) = digits.split_at(1);
+        let rest = if rest.is_empty() { "0" } else { rest };
+        format!("{}.{}e{}", first, rest, place - 1)
+    };
+    let text = if place == 0 {
+        format!("0.{}", digits)
+    } else if place < 0 || place >= len {
+        let dot_cost = if len == 1 { 2 } else { 1 };
+        let exponent_cost = (place - 1).to_string().len() as i64 + 1 + dot_cost;
+        if place < 0 && 2 - place <= exponent_cost {
+            format!("0.{}{}", "0".repeat(-place as usize), digits)
+        } else if place > 0 && place - len + 2 <= exponent_cost {
+            format!("{}{}.0", digits, "0".repeat((place - len) as usize))
+        } else {
+            exponential()
+        }
+    } else {
+        let (whole, fraction) = digits.split_at(place as usize);
+        format!("{}.{}", whole, fraction)
+    };
+    format!("{}{}", sign, text)
+}
+
+/// Returns the text of an atom, quoted only if it would not read back as
+/// the same atom otherwise.
+pub(crate) fn write_atom(name: &str, unicode: bool) -> String {
+    let mut chars = name.chars();
+    let bare = chars.next().is_some_and(is_atom_start)
+        && chars.all(is_name_char)
+        && !RESERVED_WORDS.contains(&name);
+    if bare {
+        name.to_string()
+    } else {
+        write_string(name.chars(), '\'', unicode)
+    }
+}
+
+/// Returns `chars` quoted with `quote` and escaped as `io_lib:write_string/2`
+/// does. Without `unicode`, characters above 255 are written as `\x{...}`.
+pub(crate) fn write_string(
+    chars: impl Iterator<Item = char>,
+    quote: char,
+    unicode: bool,
+) -> String {
+    let mut text = String::new();
+    text.push(quote);
+    for c in chars {
+        match c {
+            c if c == quote => {
+                text.push('\\');
+                text.push(c);
+            }
+            '\\' => text.push_str("\\\\"),
+            ' '..='~' => text.push(c),
+            '\u{a0}'..='\u{ff}' => text.push(c),
+            c if c > '\u{ff}' && unicode => text.push(c),
+            c if c > '\u{ff}' => text.push_str(&format!("\\x{{{:X}}}", c as u32)),
+            '\n' => text.push_str("\\n"),
+            '\r' => text.push_str("\\r"),
+            '\t' => text.push_str("\\t"),
+            '\u{b}' => text.push_str("\\v"),
+            '\u{8}' => text.push_str("\\b"),
+            '\u{c}' => text.push_str("\\f"),
+            '\u{1b}' => text.push_str("\\e"),
+            '\u{7f}' => text.push_str("\\d"),
+            c => text.push_str(&format!("\\{:03o}", c as u32)),
+        }
+    }
+    text.push(quote);
+    text
+}
+
+fn is_printable_latin1(c: u32) -> bool {
+    matches!(c, 32..=126 | 160..=255 | 8..=13 | 27)
+}
+
+fn is_printable_unicode(c: u32) -> bool {
+    is_printable_latin1(c) || matches!(c, 256..=0xd7ff | 0xe000..=0xfffd | 0x10000..=0x10ffff)
+}
+
+/// A term laid out for printing, with the length it has on one line.
+struct Item {
+    node: Node,
+    len: usize,
+}
+
+enum Node {
+    /// Text which is never broken across lines, such as a number, an atom
+    /// or a string.
+    Leaf(String),
+    List(Vec<Item>, Tail),
+    Tuple {
+        /// Whether the first element is an atom, which is kept on the first
+        /// line.
+        tagged: bool,
+        elements: Vec<Item>,
+        tail: Tail,
+    },
+    Map(Vec<(Item, Item)>, Tail),
+    /// The comma-separated segments of a binary which is not a string.
+    Bits(Vec<String>),
+}
+
+/// What follows the last element of a list, tuple or map.
+enum Tail {
+    None,
+    /// Elements left out because of the depth limit.
+    Dots,
+    /// The tail of an improper list.
+    Improper(Box<Item>),
+}
+impl Tail {
+    fn len(&self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Dots => 4,
+            Self::Improper(item) => 1 + item.len,
+        }
+    }
+}
+
+impl Item {
+    fn leaf(text: String) -> Self {
+        Self {
+            len: text.chars().count(),
+            node: Node::Leaf(text),
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        matches!(self.node, Node::Leaf(_))
+    }
+}
+
+/// The length of elements of lengths `lens` separated by commas and
+/// followed by `tail`, plus `brackets` characters around them.
+fn seq_len(lens: impl Iterator<Item = usize>, tail: &Tail, brackets: usize) -> usize {
+    let (count, sum) = lens.fold((0, 0), |(count, sum), len| (count + 1, sum + len));
+    brackets + sum + count.max(1) - 1 + tail.len()
+}
+
+struct Builder<'a> {
+    options: &'a PrettyOptions,
+}
+impl Builder<'_> {
+    /// Lays out `term`, leaving out what lies deeper than `depth`, or
+    /// nothing if `depth` is negative.
+    fn item(&self, term: &Term, depth: i64) -> Item {
+        if depth == 0 {
+            return Item::leaf("...".to_string());
+        }
+        let unicode = self.options.unicode;
+        match term {
+            Term::Number(Number::FixInteger(x)) => Item::leaf(x.value.to_string()),
+            Term::Number(Number::Bignum(x)) => Item::leaf(x.value.to_string()),
+            Term::Number(Number::Float(x)) => Item::leaf(write_float(x.value)),
+            Term::Atom(x) => Item::leaf(write_atom(x.name(), unicode)),
+            Term::Fun(Fun::ExternalFun(x)) => Item::leaf(format!(
+                "fun {}:{}/{}",
+                write_atom(x.module.name(), unicode),
+                write_atom(x.function.name(), unicode),
+                x.arity
+            )),
+            Term::Fun(Fun::InternalFun(x)) => {
+                let (module, id) = match x {
+                    InternalFun::Old {
+                        module,
+                        index,
+                        uniq,
+                        ..
+                    } => (module, format!("{}.{}", index, uniq)),
+                    InternalFun::New {
+                        module,
+                        index,
+                        uniq,
+                        ..
+                    } => (module, format!("{}.{}", index, u128::from_be_bytes(*uniq))),
+                };
+                let module = write_atom(module.name(), unicode);
+                Item::leaf(format!("#Fun<{}.{}>", module, id))
+            }
+            Term::Reference(x) => Item::leaf(x.to_string()),
+            Term::Port(x) => Item::leaf(x.to_string()),
+            Term::Pid(x) => Item::leaf(x.to_string()),
+            Term::Nil(_) => Item::leaf("[]".to_string()),
+            Term::Tuple(x) if x.elements.is_empty() => Item::leaf("{}".to_string()),
+            Term::Tuple(_) if depth == 1 => Item::leaf("{...}".to_string()),
+            Term::Tuple(x) => {
+                let (elements, tail) = self.elements(x.elements.iter(), None, depth);
+                let tagged = x.elements.len() > 1 && matches!(x.elements[0], Term::Atom(_));
+                Item {
+                    len: seq_len(elements.iter().map(|e| e.len), &tail, 2),
+                    node: Node::Tuple {
+                        tagged,
+                        elements,
+                        tail,
+                    },
+                }
+            }
+            Term::Map(x) if x.pairs.is_empty() => Item::leaf("#{}".to_string()),
+            Term::Map(_) if depth == 1 => Item::leaf("#{...}".to_string()),
+            Term::Map(x) => {
+                // Map key order, as maps:to_list/1 returns the pairs.
+                let pairs = sorted_pairs(x);
+                let shown = if depth < 0 {
+                    pairs.len()
+                } else {
+                    depth as usize - 1
+                };
+                let tail = if pairs.len() > shown {
+                    Tail::Dots
+                } else {
+                    Tail::None
+                };
+                let pairs: Vec<(Item, Item)> = pairs
+                    .into_iter()
+                    .take(shown)
+                    .map(|(k, v)| (self.item(k, depth - 1), self.item(v, depth - 1)))
+                    .collect();
+                Item {
+                    len: seq_len(pairs.iter().map(|(k, v)| k.len + 4 + v.len), &tail, 3),
+                    node: Node::Map(pairs, tail),
+                }
+            }
+            Term::List(x) if x.elements.is_empty() => self.item(&x.tail, depth),
+            Term::List(x) => {
+                if depth != 1 && self.options.strings && !x.is_improper_list() {
+                    if let Some(chars) = self.printable_list(&x.elements) {
+                        return Item::leaf(write_string(chars.into_iter(), '"', unicode));
+                    }
+                }
+                if depth == 1 {
+                    return Item::leaf("[...]".to_string());
+                }
+                let (elements, tail) = self.elements(x.elements.iter(), Some(&x.tail), depth);
+                Item {
+                    len: seq_len(elements.iter().map(|e| e.len), &tail, 2),
+                    node: Node::List(elements, tail),
+                }
+            }
+            Term::Bitstring(x) if x.data.is_empty() => Item::leaf("<<>>".to_string()),
+            Term::Bitstring(_) if depth == 1 => Item::leaf("<<...>>".to_string()),
+            Term::Bitstring(x) => {
+                if x.is_binary() && self.options.strings {
+                    if let Some(text) = self.printable_binary(&x.data, depth - 1) {
+                        return Item::leaf(text);
+                    }
+                }
+                let parts = bits_parts(x, depth);
+                Item {
+                    len: seq_len(parts.iter().map(|p| p.len()), &Tail::None, 4),
+                    node: Node::Bits(parts),
+                }
+            }
+        }
+    }
+
+    /// Lays out the elements of a tuple or list and the tail of a list,
+    /// each element one level deeper than the one before it.
+    fn elements<'t>(
+        &self,
+        terms: impl ExactSizeIterator<Item = &'t Term>,
+        tail: Option<&Term>,
+        depth: i64,
+    ) -> (Vec<Item>, Tail) {
+        let mut elements = Vec::with_capacity(terms.len());
+        let mut depth = depth;
+        for term in terms {
+            if depth == 1 {
+                return (elements, Tail::Dots);
+            }
+            elements.push(self.item(term, depth - 1));
+            depth -= 1;
+        }
+        let tail = match tail {
+            Some(tail) if !tail.is_nil() => {
+                if depth == 1 {
+                    Tail::Dots
+                } else {
+                    Tail::Improper(Box::new(self.item(tail, depth - 1)))
+                }
+            }
+            _ => Tail::None,
+        };
+        (elements, tail)
+    }
+
+    fn printable_list(&self, elements: &[Term]) -> Option<Vec<char>> {
+        let printable = if self.options.unicode {
+            is_printable_unicode
+        } else {
+            is_printable_latin1
+        };
+        elements
+            .iter()
+            .map(|element| match element {
+                Term::Number(Number::FixInteger(x)) => u32::try_from(x.value)
+                    .ok()
+                    .filter(|&c| printable(c))
+                    .and_then(char::from_u32),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the text of a binary as a string, such as `<<"abc">>`, if
+    /// its first `limit` bytes (or all of them, if `limit` is negative) are
+    /// printable.
+    fn printable_binary(&self, data: &[u8], limit: i64) -> Option<String> {
+        let take = |len: usize| {
+            if limit < 0 {
+                len
+            } else {
+                len.min(limit as usize)
+            }
+        };
+        if self.options.unicode && !data.is_ascii() {
+            if let Ok(text) = std::str::from_utf8(data) {
+                let count = text.chars().count();
+                let shown = take(count);
+                if text
+                    .chars()
+                    .take(shown)
+                    .all(|c| is_printable_unicode(c as u32))
+                {
+                    let string = write_string(text.chars().take(shown), '"', true);
+                    let dots = if shown < count { "..." } else { "" };
+                    return Some(format!("<<{}/utf8{}>>", string, dots));
+                }
+            }
+        }
+        let shown = take(data.len());
+        let bytes = &data[..shown];
+        if !bytes.iter().all(|&b| is_printable_latin1(u32::from(b))) {
+            return None;
+        }
+        let string = write_string(bytes.iter().map(|&b| char::from(b)), '"', false);
+        let dots = if shown < data.len() { "..." } else { "" };
+        Some(format!("<<{}{}>>", string, dots))
+    }
+}
+
+/// Returns the segments of a binary as `io_lib:write/2` writes them, with
+/// `Value:Size` for trailing bits.
+fn bits_parts(bitstring: &Bitstring, depth: i64) -> Vec<String> {
+    let data = &bitstring.data;
+    let tail_bits = bitstring.bits % 8;
+    let mut parts = Vec::new();
+    let mut depth = depth;
+    for (i, byte) in data.iter().enumerate() {
+        if depth == 1 {
+            parts.push("...".to_string());
+            break;
+        }
+        if i + 1 == data.len() && tail_bits != 0 {
+            parts.push(format!("{}:{}", byte, tail_bits));
+        } else {
+            parts.push(byte.to_string());
+        }
+        depth -= 1;
+    }
+    parts
+}
+
+/// Writes `item` on one line.
+fn write(item: &Item) -> String {
+    let mut text = String::with_capacity(item.len);
+    write_into(item, &mut text);
+    text
+}
+
+fn write_into(item: &Item, text: &mut String) {
+    match &item.node {
+        Node::Leaf(leaf) => text.push_str(leaf),
+        Node::List(elements, tail) => {
+            text.push('[');
+            write_elements(elements, tail, '|', text);
+            text.push(']');
+        }
+        Node::Tuple { elements, tail, .. } => {
+            text.push('{');
+            write_elements(elements, tail, ',', text);
+            text.push('}');
+        }
+        Node::Map(pairs, tail) => {
+            text.push_str("#{");
+            for (i, pair) in pairs.iter().enumerate() {
+                if i > 0 {
+                    text.push(',');
+                }
+                write_pair(pair, text);
+            }
+            if let Tail::Dots = tail {
+                text.push_str(",...");
+            }
+            text.push('}');
+        }
+        Node::Bits(parts) => {
+            text.push_str("<<");
+            text.push_str(&parts.join(","));
+            text.push_str(">>");
+        }
+    }
+}
+
+fn write_elements(elements: &[Item], tail: &Tail, separator: char, text: &mut String) {
+    for (i, element) in elements.iter().enumerate() {
+        if i > 0 {
+            text.push(',');
+        }
+        write_into(element, text);
+    }
+    match tail {
+        Tail::None => {}
+        Tail::Dots => {
+            text.push(separator);
+            text.push_str("...");
+        }
+        Tail::Improper(item) => {
+            text.push(separator);
+            write_into(item, text);
+        }
+    }
+}
+
+fn write_pair((key, value): &(Item, Item), text: &mut String) {
+    write_into(key, text);
+    text.push_str(" => ");
+    write_into(value, text);
+}
+
+fn indent(column: usize) -> String {
+    format!("\n{}", " ".repeat(column - 1))
+}
+
+/// Raised while trying a tag indentation which pushes elements too far
+/// right.
+struct NoGood;
+
+/// Breaks terms across lines. Columns are 1-based, and `last` is the
+/// number of closing brackets which will follow on the same line.
+struct Printer {
+    line_length: usize,
+    /// The length of the whole term on one line.
+    max: usize,
+    /// How far past the `{` the elements of a tagged tuple are indented
+    /// when the tag is longer than this, or `None` to always keep them
+    /// beside the tag.
+    tag_indent: Option<usize>,
+    /// Whether to give up with [`NoGood`] when elements of tagged tuples
+    /// start past the middle of the line.
+    check: bool,
+}
+impl Printer {
+    fn fits(&self, len: usize, column: usize, last: usize) -> bool {
+        len + column + last < self.line_length
+    }
+
+    fn pp(&self, item: &Item, column: usize, last: usize) -> Result<String, NoGood> {
+        if self.fits(item.len, column, last) {
+            return Ok(write(item));
+        }
+        Ok(match &item.node {
+            Node::Leaf(leaf) => leaf.clone(),
+            Node::List(elements, tail) => {
+                format!("[{}]", self.pp_list(elements, tail, column + 1, last, '|')?)
+            }
+            Node::Tuple {
+                tagged: true,
+                elements,
+                tail,
+            } => format!("{{{}}}", self.pp_tag_tuple(elements, tail, column, last)?),
+            Node::Tuple { elements, tail, .. } => {
+                format!(
+                    "{{{}}}",
+                    self.pp_list(elements, tail, column + 1, last, ',')?
+                )
+            }
+            Node::Map(pairs, tail) => {
+                format!("#{{{}}}", self.pp_map(pairs, tail, column + 2, last)?)
+            }
+            Node::Bits(parts) => self.pp_bits(parts, column + 2, last),
+        })
+    }
+
+    /// The number of closing brackets after an element, given whether
+    /// more elements follow it.
+    fn last_depth(more: bool, last: usize) -> usize {
+        if more {
+            0
+        } else {
+            last + 1
+        }
+    }
+
+    /// Lays out an element, returning its text and the width it takes up
+    /// on its last line. Anything other than a leaf takes up the whole
+    /// line, so the next element starts on a new one.
+    fn pp_element(
+        &self,
+        item: &Item,
+        column: usize,
+        last: usize,
+    ) -> Result<(String, usize), NoGood> {
+        if item.is_leaf() && self.fits(item.len, column, last) {
+            Ok((write(item), item.len))
+        } else {
+            Ok((self.pp(item, column, last)?, self.line_length))
+        }
+    }
+
+    fn pp_list(
+        &self,
+        elements: &[Item],
+        tail: &Tail,
+        column: usize,
+        last: usize,
+        separator: char,
+    ) -> Result<String, NoGood> {
+        let (first, rest) = match elements.split_first() {
+            Some(split) => split,
+            None => return Ok("...".to_string()),
+        };
+        let more = !rest.is_empty();
+        let (mut text, width) = self.pp_element(first, column, Self::last_depth(more, last))?;
+        text.push_str(&self.pp_tail(rest, tail, column, column + width, last, separator)?);
+        Ok(text)
+    }
+
+    /// Lays out the elements after the first, continuing at `at` on the
+    /// current line while leaves fit and indenting new lines to `column`.
+    fn pp_tail(
+        &self,
+        elements: &[Item],
+        tail: &Tail,
+        column: usize,
+        mut at: usize,
+        last: usize,
+        separator: char,
+    ) -> Result<String, NoGood> {
+        let mut text = String::new();
+        for (i, element) in elements.iter().enumerate() {
+            let element_last = Self::last_depth(i + 1 < elements.len(), last);
+            let len = 1 + element.len;
+            let inline = element.is_leaf()
+                && if element_last == 0 {
+                    len + 1 + at < self.line_length
+                } else {
+                    len + at + element_last < self.line_length
+                };
+            if inline {
+                text.push(',');
+                write_into(element, &mut text);
+                at += len;
+            } else {
+                let (element, width) = self.pp_element(element, column, element_last)?;
+                text.push(',');
+                text.push_str(&indent(column));
+                text.push_str(&element);
+                at = column + width;
+            }
+        }
+        match tail {
+            Tail::None => {}
+            Tail::Dots => {
+                text.push(separator);
+                text.push_str("...");
+            }
+            Tail::Improper(item) => {
+                text.push(separator);
+                if item.is_leaf() && item.len + 1 + at + last + 1 < self.line_length {
+                    write_into(item, &mut text);
+                } else {
+                    text.push_str(&indent(column));
+                    text.push_str(&self.pp(item, column, last + 1)?);
+                }
+            }
+        }
+        Ok(text)
+    }
+
+    fn pp_tag_tuple(
+        &self,
+        elements: &[Item],
+        tail: &Tail,
+        column: usize,
+        last: usize,
+    ) -> Result<String, NoGood> {
+        let (tag, rest) = elements.split_first().expect("a tagged tuple");
+        let tag_indent = tag.len + 2;
+        let tag_column = column + tag_indent;
+        let half = self.line_length / 2;
+        let mut text = write(tag);
+        match self.tag_indent {
+            Some(indent) if tag_indent > indent => {
+                let column = column + indent;
+                if self.check && self.max + column > self.line_length && column > half {
+                    return Err(NoGood);
+                }
+                text.push_str(&self.pp_tail(rest, tail, column, tag_column, last, ',')?);
+            }
+            _ => {
+                if self.check && self.max + tag_column >= self.line_length && tag_column >= half {
+                    return Err(NoGood);
+                }
+                text.push(',');
+                text.push_str(&self.pp_list(rest, tail, tag_column, last, ',')?);
+            }
+        }
+        Ok(text)
+    }
+
+    fn pp_map(
+        &self,
+        pairs: &[(Item, Item)],
+        tail: &Tail,
+        column: usize,
+        last: usize,
+    ) -> Result<String, NoGood> {
+        let mut text = String::new();
+        let mut at = column;
+        for (i, pair) in pairs.iter().enumerate() {
+            let pair_last = Self::last_depth(i + 1 < pairs.len(), last);
+            let len = pair.0.len + 4 + pair.1.len;
+            let leaves = pair.0.is_leaf() && pair.1.is_leaf();
+            if i == 0 {
+                let (first, width) = self.pp_pair(pair, column, pair_last)?;
+                text.push_str(&first);
+                at = column + width;
+                continue;
+            }
+            let inline = leaves
+                && if pair_last == 0 {
+                    len + 2 + at < self.line_length
+                } else {
+                    len + 1 + at + pair_last < self.line_length
+                };
+            if inline {
+                text.push(',');
+                write_pair(pair, &mut text);
+                at += len + 1;
+            } else {
+                let (pair, width) = self.pp_pair(pair, column, pair_last)?;
+                text.push(',');
+                text.push_str(&indent(column));
+                text.push_str(&pair);
+                at = column + width;
+            }
+        }
+        if let Tail::Dots = tail {
+            text.push_str(",...");
+        }
+        Ok(text)
+    }
+
+    /// Lays out a key and value, putting the value on the next line if the
+    /// pair does not fit on one.
+    fn pp_pair(
+        &self,
+        pair: &(Item, Item),
+        column: usize,
+        last: usize,
+    ) -> Result<(String, usize), NoGood> {
+        let len = pair.0.len + 4 + pair.1.len;
+        if self.fits(len, column, last) {
+            let mut text = String::new();
+            write_pair(pair, &mut text);
+            let leaves = pair.0.is_leaf() && pair.1.is_leaf();
+            return Ok((text, if leaves { len } else { self.line_length }));
+        }
+        let value_indent = self.tag_indent.filter(|&indent| indent > 0).unwrap_or(4);
+        let key = self.pp(&pair.0, column, last)?;
+        let value = self.pp(&pair.1, column + value_indent, last)?;
+        let text = format!("{} =>{}{}", key, indent(column + value_indent), value);
+        Ok((text, self.line_length))
+    }
+
+    /// Fills lines with the segments of a binary.
+    fn pp_bits(&self, parts: &[String], column: usize, last: usize) -> String {
+        let room = self.line_length.saturating_sub(column + last).max(8);
+        let mut left = room;
+        let mut text = String::from("<<");
+        for (i, part) in parts.iter().enumerate() {
+            if i + 1 < parts.len() {
+                let len = part.len() + 1;
+                if len > left {
+                    text.push_str(&indent(column));
+                    left = room.saturating_sub(len);
+                } else {
+                    left -= len;
+                }
+                text.push_str(part);
+                text.push(',');
+            } else {
+                if part.len() > left {
+                    text.push_str(&indent(column));
+                }
+                text.push_str(part);
+            }
+        }
+        text.push_str(">>");
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::term;
+
+    fn p(text: &str) -> String {
+        pretty(&term(text), &PrettyOptions::new())
+    }
+
+    #[test]
+    fn it_writes_terms_like_io_lib() {
+        assert_eq!(
+            p("{ok, 'Hello', 'end', 'a b', node@host}"),
+            "{ok,'Hello','end','a b',node@host}"
+        );
+        assert_eq!(
+            p("[\"abc\", [1, 2], [10, 0], <<\"bin\">>, <<1, 2:3>>]"),
+            "[\"abc\",[1,2],[10,0],<<\"bin\">>,<<1,2:3>>]"
+        );
+        assert_eq!(p("#{b => 1, a => [x | y]}"), "#{a => [x|y],b => 1}");
+        assert_eq!(
+            p("#{2.0 => b, 3 => c, 1 => a}"),
+            "#{1 => a,3 => c,2.0 => b}"
+        );
+        assert_eq!(p("\"tab\\there\\\"\""), "\"tab\\there\\\"\"");
+        let floats = [
+            "1.0",
+            "0.1",
+            "100.0",
+            "1.0e3",
+            "0.0001",
+            "1.0e-5",
+            "-1.5",
+            "1.2345e20",
+        ];
+        for float in floats {
+            assert_eq!(p(float), float);
+        }
+        let unicode = PrettyOptions::new().unicode(true);
+        let term = parse_term("{<<\"é\"/utf8>>, \"☺\", '☺'}").unwrap();
+        assert_eq!(pretty(&term, &unicode), "{<<\"é\"/utf8>>,\"☺\",'☺'}");
+        assert_eq!(
+            pretty(&term, &PrettyOptions::new()),
+            "{<<\"Ã©\">>,[9786],'\\x{263A}'}"
+        );
+        let term = parse_term("[\"abc\", <<\"d\">>]").unwrap();
+        let w = PrettyOptions::new().line_length(0).strings(false);
+        assert_eq!(pretty(&term, &w), "[[97,98,99],<<100>>]");
+    }
+
+    #[test]
+    fn it_limits_depth() {
+        let term = parse_term("[1, 2, {a, b, c}, [x, y], 5]").unwrap();
+        let depth = |depth| pretty(&term, &PrettyOptions::new().depth(depth));
+        assert_eq!(depth(1), "[...]");
+        assert_eq!(depth(3), "[1,2|...]");
+        assert_eq!(depth(4), "[1,2,{...}|...]");
+        assert_eq!(depth(5), "[1,2,{a,...},[...]|...]");
+        let term = parse_term("{#{a => 1, b => 2, c => 3}, <<1, 2, 3>>, <<\"abcdef\">>}").unwrap();
+        let depth = |depth| pretty(&term, &PrettyOptions::new().depth(depth));
+        assert_eq!(depth(3), "{#{a => 1,...},<<...>>,...}");
+        assert_eq!(
+            depth(5),
+            "{#{a => 1,b => 2,c => 3},<<1,2,...>>,<<\"a\"...>>}"
+        );
+    }
+
+    #[test]
+    fn it_breaks_long_terms_across_lines() {
+        let numbers: Vec<String> = (1..=40).map(|i| i.to_string()).collect();
+        assert_eq!(
+            p(&format!("[{}]", numbers.join(","))),
+            "[1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,\n 29,30,31,32,33,34,35,36,37,38,39,40]"
+        );
+        assert_eq!(
+            p("[{application, kernel}, {application, stdlib}, {application, sasl}, {env, [{port, 80}]}]"),
+            "[{application,kernel},\n {application,stdlib},\n {application,sasl},\n {env,[{port,80}]}]"
+        );
+        assert_eq!(
+            p("{error, {badmatch, [\"a long string which does not fit here\", \"another one\"]}, extra}"),
+            "{error,{badmatch,[\"a long string which does not fit here\",\"another one\"]},\n       extra}"
+        );
+        assert_eq!(
+            p("{a_rather_long_tag_for_a_tuple_to_have, [{one, 1}, {two, 2}, {three, 3}, {four, 4}, {five, 5}]}"),
+            "{a_rather_long_tag_for_a_tuple_to_have,\n    [{one,1},{two,2},{three,3},{four,4},{five,5}]}"
+        );
+        assert_eq!(
+            p("#{config => #{listen => \"0.0.0.0\", port => 8080}, name => <<\"a service with a name\">>}"),
+            "#{config => #{listen => \"0.0.0.0\",port => 8080},\n  name => <<\"a service with a name\">>}"
+        );
+    }
+}
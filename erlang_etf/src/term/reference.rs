@@ -2,7 +2,7 @@ use std::fmt;
 
 use crate::term::Atom;
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Reference {
     pub node: Atom,
     pub id: Vec<u32>,
@@ -0,0 +1,456 @@
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::codec::decoder::{ReadContext, YieldableDecoder};
+use crate::codec::error::DecodeError;
+use crate::codec::external as ext;
+use crate::dist::AtomCache;
+use crate::term::Term;
+use crate::{pin_mut, Cassette, Process};
+
+/// Encodings of the integers `0..=255`, for the elements of a `STRING_EXT`.
+static SMALL_INTEGERS: [[u8; 2]; 256] = {
+    let mut table = [[0; 2]; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = [ext::SMALL_INTEGER_EXT, i as u8];
+        i += 1;
+    }
+    table
+};
+static NIL: [u8; 1] = [ext::NIL_EXT];
+
+/// A borrowed view of an encoded term.
+///
+/// Only the layout of the term is checked when the view is created. Elements
+/// are located as they are iterated, and atoms and binaries borrow the
+/// encoded bytes. Use [`to_term`](Self::to_term) for an owned [`Term`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TermRef<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> TermRef<'a> {
+    /// Creates a view of the term in `buf`, which starts with the version
+    /// byte. Compressed terms cannot be borrowed and are rejected.
+    pub fn from_external(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        match buf.first() {
+            Some(&ext::VERSION_MAGIC) => {}
+            Some(&version) => return Err(DecodeError::UnsupportedVersion { version }),
+            None => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+        }
+        if buf.get(1) == Some(&ext::COMPRESSED) {
+            return Err(DecodeError::UnknownTag {
+                tag: ext::COMPRESSED,
+            });
+        }
+        Self::from_internal(&buf[1..])
+    }
+
+    /// Creates a view of the term at the start of `buf`, which has no
+    /// version byte. Bytes after the term are ignored.
+    pub fn from_internal(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        let len = term_len(buf)?;
+        Ok(Self { bytes: &buf[..len] })
+    }
+
+    /// Returns the encoding of the term, without a version byte.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    fn tag(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    /// Returns the name of an atom, in the encoding given by its tag.
+    pub fn as_atom(&self) -> Option<&'a [u8]> {
+        match self.tag() {
+            ext::ATOM_EXT | ext::ATOM_UTF8_EXT => Some(&self.bytes[3..]),
+            ext::SMALL_ATOM_EXT | ext::SMALL_ATOM_UTF8_EXT => Some(&self.bytes[2..]),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an integer that fits in an `i32`.
+    pub fn as_integer(&self) -> Option<i32> {
+        match self.tag() {
+            ext::SMALL_INTEGER_EXT => Some(self.bytes[1] as i32),
+            ext::INTEGER_EXT => Some(BigEndian::read_i32(&self.bytes[1..])),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self.tag() {
+            ext::NEW_FLOAT_EXT => Some(BigEndian::read_f64(&self.bytes[1..])),
+            ext::FLOAT_EXT => std::str::from_utf8(&self.bytes[1..])
+                .ok()?
+                .trim_end_matches('\0')
+                .parse()
+                .ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_binary(&self) -> Option<&'a [u8]> {
+        match self.tag() {
+            ext::BINARY_EXT => Some(&self.bytes[5..]),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of a bit binary and the number of bits used in its
+    /// last byte. The used bits are the most significant ones, as on the
+    /// wire.
+    pub fn as_bit_binary(&self) -> Option<(&'a [u8], u8)> {
+        match self.tag() {
+            ext::BIT_BINARY_EXT => Some((&self.bytes[6..], self.bytes[5])),
+            _ => None,
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        self.tag() == ext::NIL_EXT
+    }
+
+    pub fn as_tuple(&self) -> Option<TupleRefIterator<'a>> {
+        let (arity, offset) = match self.tag() {
+            ext::SMALL_TUPLE_EXT => (self.bytes[1] as usize, 2),
+            ext::LARGE_TUPLE_EXT => (BigEndian::read_u32(&self.bytes[1..]) as usize, 5),
+            _ => return None,
+        };
+        Some(TupleRefIterator {
+            rest: &self.bytes[offset..],
+            remaining: arity,
+        })
+    }
+
+    /// Returns an iterator over the cells of a list, including `[]`. A
+    /// string is iterated as a list of small integers.
+    pub fn as_list(&self) -> Option<ListRefIterator<'a>> {
+        let (rest, remaining) = match self.tag() {
+            ext::NIL_EXT => (&NIL[..0], 0),
+            ext::LIST_EXT => (
+                &self.bytes[5..],
+                BigEndian::read_u32(&self.bytes[1..]) as usize,
+            ),
+            ext::STRING_EXT => (
+                &self.bytes[3..],
+                BigEndian::read_u16(&self.bytes[1..]) as usize,
+            ),
+            _ => return None,
+        };
+        Some(ListRefIterator {
+            string: self.tag() == ext::STRING_EXT,
+            rest,
+            remaining,
+        })
+    }
+
+    pub fn as_map(&self) -> Option<MapRefIterator<'a>> {
+        match self.tag() {
+            ext::MAP_EXT => Some(MapRefIterator {
+                elements: TupleRefIterator {
+                    rest: &self.bytes[5..],
+                    remaining: 2 * BigEndian::read_u32(&self.bytes[1..]) as usize,
+                },
+            }),
+            _ => None,
+        }
+    }
+
+    /// Decodes the term into an owned [`Term`].
+    pub fn to_term(&self) -> Result<Term, DecodeError> {
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let mut decoder = YieldableDecoder::new(self.bytes);
+        let future = decoder.read_internal_term(&ctx);
+        pin_mut!(future);
+        Cassette::new(future).block_on()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TupleRefIterator<'a> {
+    rest: &'a [u8],
+    remaining: usize,
+}
+impl<'a> Iterator for TupleRefIterator<'a> {
+    type Item = TermRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        // The layout was checked when the outer term was created.
+        let len = term_len(self.rest).ok()?;
+        let (bytes, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        self.remaining -= 1;
+        Some(TermRef { bytes })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+impl ExactSizeIterator for TupleRefIterator<'_> {}
+
+#[derive(Clone, Debug)]
+pub struct ListRefIterator<'a> {
+    string: bool,
+    rest: &'a [u8],
+    remaining: usize,
+}
+impl<'a> ListRefIterator<'a> {
+    /// Returns the tail of the list, skipping any elements not yet iterated.
+    pub fn tail(mut self) -> TermRef<'a> {
+        if self.string {
+            return TermRef { bytes: &NIL };
+        }
+        while self.next().is_some() {}
+        if self.rest.is_empty() {
+            return TermRef { bytes: &NIL };
+        }
+        TermRef { bytes: self.rest }
+    }
+}
+impl<'a> Iterator for ListRefIterator<'a> {
+    type Item = TermRef<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let len = if self.string {
+            1
+        } else {
+            term_len(self.rest).ok()?
+        };
+        let (bytes, rest) = self.rest.split_at(len);
+        self.rest = rest;
+        self.remaining -= 1;
+        if self.string {
+            Some(TermRef {
+                bytes: &SMALL_INTEGERS[bytes[0] as usize],
+            })
+        } else {
+            Some(TermRef { bytes })
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+impl ExactSizeIterator for ListRefIterator<'_> {}
+
+#[derive(Clone, Debug)]
+pub struct MapRefIterator<'a> {
+    elements: TupleRefIterator<'a>,
+}
+impl<'a> Iterator for MapRefIterator<'a> {
+    type Item = (TermRef<'a>, TermRef<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some((self.elements.next()?, self.elements.next()?))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.elements.remaining / 2;
+        (len, Some(len))
+    }
+}
+impl ExactSizeIterator for MapRefIterator<'_> {}
+
+/// Returns the length of the encoded term at the start of `buf`.
+fn term_len(buf: &[u8]) -> Result<usize, DecodeError> {
+    let u8_at = |pos: usize| -> Result<usize, DecodeError> {
+        buf.get(pos).map(|&b| b as usize).ok_or_else(eof)
+    };
+    let u16_at = |pos: usize| -> Result<usize, DecodeError> {
+        let bytes = buf.get(pos..pos + 2).ok_or_else(eof)?;
+        Ok(BigEndian::read_u16(bytes) as usize)
+    };
+    let u32_at = |pos: usize| -> Result<usize, DecodeError> {
+        let bytes = buf.get(pos..pos + 4).ok_or_else(eof)?;
+        Ok(BigEndian::read_u32(bytes) as usize)
+    };
+    // Nodes are always atoms, so their length is read directly rather than
+    // by recursing, which would let the input choose the recursion depth.
+    let node_len = |pos: usize| -> Result<usize, DecodeError> {
+        match u8_at(pos)? as u8 {
+            ext::ATOM_EXT | ext::ATOM_UTF8_EXT => Ok(3 + u16_at(pos + 1)?),
+            ext::SMALL_ATOM_EXT | ext::SMALL_ATOM_UTF8_EXT => Ok(2 + u8_at(pos + 1)?),
+            ext::ATOM_CACHE_REF => Ok(2),
+            _ => Err(DecodeError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "expected internal atom",
+            ))),
+        }
+    };
+
+    let mut pos = 0;
+    let mut pending: u64 = 1;
+    while pending > 0 {
+        pending -= 1;
+        let tag = u8_at(pos)? as u8;
+        pos += 1;
+        match tag {
+            ext::SMALL_INTEGER_EXT | ext::ATOM_CACHE_REF => pos += 1,
+            ext::INTEGER_EXT => pos += 4,
+            ext::FLOAT_EXT => pos += 31,
+            ext::NEW_FLOAT_EXT => pos += 8,
+            ext::ATOM_EXT | ext::ATOM_UTF8_EXT | ext::STRING_EXT => pos += 2 + u16_at(pos)?,
+            ext::SMALL_ATOM_EXT | ext::SMALL_ATOM_UTF8_EXT => pos += 1 + u8_at(pos)?,
+            ext::NIL_EXT => {}
+            ext::PID_EXT => pos += node_len(pos)? + 9,
+            ext::NEW_PID_EXT | ext::V4_PORT_EXT => pos += node_len(pos)? + 12,
+            ext::PORT_EXT | ext::REFERENCE_EXT => pos += node_len(pos)? + 5,
+            ext::NEW_PORT_EXT => pos += node_len(pos)? + 8,
+            ext::NEW_REFERENCE_EXT => {
+                let n = u16_at(pos)?;
+                pos += 2;
+                pos += node_len(pos)? + 1 + 4 * n;
+            }
+            ext::NEWER_REFERENCE_EXT => {
+                let n = u16_at(pos)?;
+                pos += 2;
+                pos += node_len(pos)? + 4 + 4 * n;
+            }
+            ext::SMALL_TUPLE_EXT => {
+                pending += u8_at(pos)? as u64;
+                pos += 1;
+            }
+            ext::LARGE_TUPLE_EXT => {
+                pending += u32_at(pos)? as u64;
+                pos += 4;
+            }
+            ext::LIST_EXT => {
+                pending += u32_at(pos)? as u64 + 1;
+                pos += 4;
+            }
+            ext::MAP_EXT => {
+                pending += 2 * u32_at(pos)? as u64;
+                pos += 4;
+            }
+            ext::BINARY_EXT => pos += 4 + u32_at(pos)?,
+            ext::BIT_BINARY_EXT => pos += 5 + u32_at(pos)?,
+            ext::SMALL_BIG_EXT => pos += 2 + u8_at(pos)?,
+            ext::LARGE_BIG_EXT => pos += 5 + u32_at(pos)?,
+            ext::NEW_FUN_EXT => {
+                // The size includes the size field itself.
+                let size = u32_at(pos)?;
+                if size < 4 {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "invalid fun size",
+                    )
+                    .into());
+                }
+                pos += size;
+            }
+            ext::EXPORT_EXT => pending += 3,
+            ext::FUN_EXT => {
+                pending += 4 + u32_at(pos)? as u64;
+                pos += 4;
+            }
+            tag => return Err(DecodeError::UnknownTag { tag }),
+        }
+        if pos > buf.len() {
+            return Err(eof());
+        }
+    }
+    Ok(pos)
+}
+
+fn eof() -> DecodeError {
+    std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // {call, <<"data">>, [1, 2 | x], #{a => "hi"}, 3.5}
+    fn message() -> Vec<u8> {
+        let mut buf = vec![131, 104, 5];
+        buf.extend_from_slice(&[119, 4, b'c', b'a', b'l', b'l']);
+        buf.extend_from_slice(&[109, 0, 0, 0, 4, b'd', b'a', b't', b'a']);
+        buf.extend_from_slice(&[108, 0, 0, 0, 2, 97, 1, 97, 2, 119, 1, b'x']);
+        buf.extend_from_slice(&[116, 0, 0, 0, 1, 119, 1, b'a', 107, 0, 2, b'h', b'i']);
+        buf.extend_from_slice(&[70, 64, 12, 0, 0, 0, 0, 0, 0]);
+        buf
+    }
+
+    #[test]
+    fn it_borrows_elements() {
+        let buf = message();
+        let term = TermRef::from_external(&buf).unwrap();
+        let mut elements = term.as_tuple().unwrap();
+        assert_eq!(elements.len(), 5);
+        assert_eq!(elements.next().unwrap().as_atom(), Some(&b"call"[..]));
+        assert_eq!(elements.next().unwrap().as_binary(), Some(&b"data"[..]));
+
+        let list = elements.next().unwrap().as_list().unwrap();
+        let cells: Vec<_> = list.clone().map(|cell| cell.as_integer()).collect();
+        assert_eq!(cells, [Some(1), Some(2)]);
+        assert_eq!(list.tail().as_atom(), Some(&b"x"[..]));
+
+        let mut pairs = elements.next().unwrap().as_map().unwrap();
+        let (key, value) = pairs.next().unwrap();
+        assert_eq!(key.as_atom(), Some(&b"a"[..]));
+        let chars: Vec<_> = value
+            .as_list()
+            .unwrap()
+            .map(|c| c.as_integer().unwrap())
+            .collect();
+        assert_eq!(chars, [b'h' as i32, b'i' as i32]);
+        assert!(value.as_list().unwrap().tail().is_nil());
+        assert!(pairs.next().is_none());
+
+        assert_eq!(elements.next().unwrap().as_float(), Some(3.5));
+        assert!(elements.next().is_none());
+    }
+
+    #[test]
+    fn it_converts_to_owned_terms() {
+        let buf = message();
+        let term = TermRef::from_external(&buf).unwrap();
+        let process = Process::blocking();
+        let atom_cache = AtomCache::new();
+        let ctx = ReadContext::new(&process, &atom_cache);
+        let mut decoder = YieldableDecoder::new(buf.as_slice());
+        let future = decoder.read_external_term(&ctx);
+        pin_mut!(future);
+        let expected = Cassette::new(future).block_on().unwrap();
+        assert_eq!(term.to_term().unwrap(), expected);
+        assert_eq!(term.as_bytes().len(), buf.len() - 1);
+    }
+
+    #[test]
+    fn it_checks_the_layout_up_front() {
+        let buf = message();
+        assert!(matches!(
+            TermRef::from_external(&buf[..buf.len() - 1]),
+            Err(DecodeError::Io(_))
+        ));
+        assert!(matches!(
+            TermRef::from_external(&[131, 104, 1, 200]),
+            Err(DecodeError::UnknownTag { tag: 200 })
+        ));
+    }
+
+    #[test]
+    fn it_only_accepts_atoms_as_nodes() {
+        let mut nested = vec![131];
+        nested.resize(2_000_000, ext::PID_EXT);
+        assert!(matches!(
+            TermRef::from_external(&nested),
+            Err(DecodeError::Io(_))
+        ));
+        let mut pid = vec![131, ext::PID_EXT, ext::SMALL_ATOM_UTF8_EXT, 1, b'n'];
+        pid.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 2, 3]);
+        assert!(TermRef::from_external(&pid).is_ok());
+    }
+}
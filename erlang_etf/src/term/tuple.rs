@@ -2,7 +2,7 @@ use std::fmt;
 
 use crate::term::Term;
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Tuple {
     pub elements: Vec<Term>,
 }
@@ -0,0 +1,75 @@
+//! Fixtures and encode/decode helpers shared by the unit tests.
+
+use crate::codec::decoder::{ReadContext, YieldableDecoder};
+use crate::codec::encoder::{EncodeOptions, WriteContext, YieldableEncoder};
+use crate::codec::error::EncodeError;
+use crate::dist::AtomCache;
+use crate::term::*;
+use crate::{pin_mut, Cassette, Process};
+
+pub fn atom(name: &str) -> Term {
+    Term::from(Atom::from(name))
+}
+
+pub fn int(value: i32) -> Term {
+    Term::from(Number::from(FixInteger::from(value)))
+}
+
+pub fn big(value: i128) -> Term {
+    Term::from(Number::from(Bignum::from(value)))
+}
+
+pub fn float(value: f64) -> Term {
+    Term::from(Number::from(Float { value }))
+}
+
+pub fn tuple(elements: Vec<Term>) -> Term {
+    Term::from(Tuple::from(elements))
+}
+
+/// Parses `text` as an Erlang term literal.
+pub fn term(text: &str) -> Term {
+    parse_term(text).unwrap()
+}
+
+/// Encodes `term` in external term format, also returning how many times
+/// the encoder yielded to `process`.
+pub fn encode_with_options(
+    process: &Process,
+    options: EncodeOptions,
+    term: &Term,
+) -> Result<(Vec<u8>, usize), EncodeError> {
+    let ctx = WriteContext::with_options(process, options);
+    let mut encoder = YieldableEncoder::new(Vec::new());
+    let mut yields = 0;
+    {
+        let future = encoder.write_external_term(&ctx, term);
+        pin_mut!(future);
+        let mut cm = Cassette::new(future);
+        loop {
+            match cm.poll_on() {
+                Some(result) => break result?,
+                None => yields += 1,
+            }
+        }
+    }
+    Ok((encoder.into_inner(), yields))
+}
+
+pub fn encode_with(process: &Process, term: &Term) -> (Vec<u8>, usize) {
+    encode_with_options(process, EncodeOptions::new(), term).unwrap()
+}
+
+pub fn encode(term: &Term) -> Vec<u8> {
+    encode_with(&Process::blocking(), term).0
+}
+
+pub fn decode(buf: &[u8]) -> Term {
+    let process = Process::blocking();
+    let atom_cache = AtomCache::new();
+    let ctx = ReadContext::new(&process, &atom_cache);
+    let mut decoder = YieldableDecoder::new(buf);
+    let future = decoder.read_external_term(&ctx);
+    pin_mut!(future);
+    Cassette::new(future).block_on().unwrap()
+}
@@ -0,0 +1,116 @@
+use syn::{Attribute, LitStr, Path};
+
+/// Options set with `#[etf(...)]` on a struct or enum.
+#[derive(Default)]
+pub struct ContainerAttrs {
+    /// `record = "name"`: a tuple tagged with the atom `name`.
+    pub record: Option<String>,
+    /// `map`: a map with atom keys. This is the default for structs with
+    /// named fields.
+    pub map: bool,
+}
+impl ContainerAttrs {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+        for attr in etf_attrs(attrs) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("record") {
+                    let name: LitStr = meta.value()?.parse()?;
+                    parsed.record = Some(name.value());
+                } else if meta.path.is_ident("map") {
+                    parsed.map = true;
+                } else {
+                    return Err(meta.error("expected `record` or `map`"));
+                }
+                Ok(())
+            })?;
+            if parsed.record.is_some() && parsed.map {
+                return Err(syn::Error::new_spanned(
+                    attr,
+                    "`record` and `map` cannot be combined",
+                ));
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+/// Options set with `#[etf(...)]` on an enum variant.
+#[derive(Default)]
+pub struct VariantAttrs {
+    /// `tag = "name"`: the atom which tags the variant, instead of its name
+    /// in snake case.
+    pub tag: Option<String>,
+}
+impl VariantAttrs {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+        for attr in etf_attrs(attrs) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag") {
+                    let name: LitStr = meta.value()?.parse()?;
+                    parsed.tag = Some(name.value());
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `tag`"))
+                }
+            })?;
+        }
+        Ok(parsed)
+    }
+}
+
+/// Options set with `#[etf(...)]` on a field.
+#[derive(Default)]
+pub struct FieldAttrs {
+    /// A module with `into_term` and `from_term` functions which convert the
+    /// field instead of its `IntoTerm` and `FromTerm` implementations.
+    /// `binary`, `charlist` and `atom` are shorthands for the string modules
+    /// in `erlang_etf::term::convert`.
+    pub with: Option<Path>,
+}
+impl FieldAttrs {
+    pub fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+        for attr in etf_attrs(attrs) {
+            attr.parse_nested_meta(|meta| {
+                let with = if meta.path.is_ident("with") {
+                    let path: LitStr = meta.value()?.parse()?;
+                    path.parse()?
+                } else if let Some(module) = ["binary", "charlist", "atom"]
+                    .into_iter()
+                    .find(|module| meta.path.is_ident(module))
+                {
+                    syn::parse_str(&format!("::erlang_etf::term::convert::{}", module))?
+                } else {
+                    return Err(meta.error("expected `binary`, `charlist`, `atom` or `with`"));
+                };
+                if parsed.with.replace(with).is_some() {
+                    return Err(meta.error("a field can only be converted one way"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(parsed)
+    }
+}
+
+fn etf_attrs(attrs: &[Attribute]) -> impl Iterator<Item = &Attribute> {
+    attrs.iter().filter(|attr| attr.path().is_ident("etf"))
+}
+
+/// Converts a variant name such as `NotFound` into `not_found`.
+pub fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
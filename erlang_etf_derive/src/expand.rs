@@ -0,0 +1,379 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::ext::IdentExt;
+use syn::{Data, DeriveInput, Fields, Generics, Ident};
+
+use crate::attr::{snake_case, ContainerAttrs, FieldAttrs, VariantAttrs};
+
+/// The shape of the term a struct or enum variant converts to.
+enum Form {
+    /// `#{field => Value}`
+    Map,
+    /// `{tag, Value...}`, or `{Value...}` without a tag.
+    Tuple(Option<String>),
+    /// The single field's own term.
+    Transparent,
+}
+
+struct Field {
+    /// The field's name, or its index for tuple fields.
+    name: String,
+    /// The local variable the field is bound to.
+    binding: Ident,
+    named: bool,
+    attrs: FieldAttrs,
+}
+
+fn fields(fields: &Fields) -> syn::Result<Vec<Field>> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            Ok(match &field.ident {
+                Some(ident) => Field {
+                    name: ident.unraw().to_string(),
+                    binding: ident.clone(),
+                    named: true,
+                    attrs: FieldAttrs::parse(&field.attrs)?,
+                },
+                None => Field {
+                    name: i.to_string(),
+                    binding: format_ident!("__f{}", i),
+                    named: false,
+                    attrs: FieldAttrs::parse(&field.attrs)?,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Returns a pattern which binds every field of `path`.
+fn pattern(path: TokenStream, kind: &Fields, fields: &[Field]) -> TokenStream {
+    let bindings = fields.iter().map(|field| &field.binding);
+    match kind {
+        Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        Fields::Unnamed(_) => quote!(#path ( #(#bindings),* )),
+        Fields::Unit => path,
+    }
+}
+
+fn struct_form(input: &DeriveInput, attrs: &ContainerAttrs, kind: &Fields) -> syn::Result<Form> {
+    if attrs.map && !matches!(kind, Fields::Named(_)) {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "`map` requires a struct with named fields",
+        ));
+    }
+    Ok(match (&attrs.record, kind) {
+        (Some(record), _) => Form::Tuple(Some(record.clone())),
+        (None, Fields::Named(_)) => Form::Map,
+        (None, Fields::Unnamed(unnamed)) if unnamed.unnamed.len() == 1 => Form::Transparent,
+        (None, _) => Form::Tuple(None),
+    })
+}
+
+fn add_bounds(generics: &Generics, bound: TokenStream) -> Generics {
+    let mut generics = generics.clone();
+    let params: Vec<Ident> = generics
+        .type_params()
+        .map(|param| param.ident.clone())
+        .collect();
+    let where_clause = generics.make_where_clause();
+    for param in params {
+        where_clause
+            .predicates
+            .push(syn::parse_quote!(#param: #bound));
+    }
+    generics
+}
+
+fn atom(name: &str) -> TokenStream {
+    quote!(::erlang_etf::term::Term::from(::erlang_etf::term::Atom::from(#name)))
+}
+
+pub fn into_term(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let attrs = ContainerAttrs::parse(&input.attrs)?;
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = fields(&data.fields)?;
+            let form = struct_form(input, &attrs, &data.fields)?;
+            let pattern = pattern(quote!(Self), &data.fields, &fields);
+            let term = into_form(&form, &fields);
+            quote! {
+                let #pattern = self;
+                #term
+            }
+        }
+        Data::Enum(data) => {
+            check_enum_attrs(input, &attrs)?;
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let variant_attrs = VariantAttrs::parse(&variant.attrs)?;
+                    let tag = variant_tag(&variant.ident, &variant_attrs);
+                    let fields = fields(&variant.fields)?;
+                    let ident = &variant.ident;
+                    let pattern = pattern(quote!(Self::#ident), &variant.fields, &fields);
+                    let term = match variant.fields {
+                        Fields::Unit => atom(&tag),
+                        _ => into_form(&Form::Tuple(Some(tag)), &fields),
+                    };
+                    Ok(quote!(#pattern => #term,))
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "IntoTerm cannot be derived for unions",
+            ))
+        }
+    };
+
+    let name = &input.ident;
+    let generics = add_bounds(
+        &input.generics,
+        quote!(::erlang_etf::term::convert::IntoTerm),
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::erlang_etf::term::convert::IntoTerm for #name #ty_generics #where_clause {
+            fn into_term(self) -> ::erlang_etf::term::Term {
+                #body
+            }
+        }
+    })
+}
+
+fn into_field(field: &Field) -> TokenStream {
+    let binding = &field.binding;
+    match &field.attrs.with {
+        Some(with) => quote!(#with::into_term(#binding)),
+        None => quote!(::erlang_etf::term::convert::IntoTerm::into_term(#binding)),
+    }
+}
+
+fn into_form(form: &Form, fields: &[Field]) -> TokenStream {
+    match form {
+        Form::Map => {
+            let pairs = fields.iter().map(|field| {
+                let key = atom(&field.name);
+                let value = into_field(field);
+                quote!((#key, #value))
+            });
+            quote! {
+                ::erlang_etf::term::Term::from(::erlang_etf::term::Map::from(vec![#(#pairs),*]))
+            }
+        }
+        Form::Tuple(tag) => {
+            let tag = tag.iter().map(|tag| atom(tag));
+            let elements = fields.iter().map(into_field);
+            quote! {
+                ::erlang_etf::term::Term::from(::erlang_etf::term::Tuple::from(vec![#(#tag,)* #(#elements),*]))
+            }
+        }
+        Form::Transparent => into_field(&fields[0]),
+    }
+}
+
+pub fn from_term(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let attrs = ContainerAttrs::parse(&input.attrs)?;
+    let name = &input.ident;
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = fields(&data.fields)?;
+            let form = struct_form(input, &attrs, &data.fields)?;
+            let expected = match &form {
+                Form::Map => format!("a {} map", name),
+                Form::Tuple(Some(record)) => format!("a {} record", record),
+                Form::Tuple(None) => format!("a tuple of {} elements", fields.len()),
+                Form::Transparent => String::new(),
+            };
+            let construct = pattern(quote!(Self), &data.fields, &fields);
+            let convert = from_form(&form, &fields, &expected, &[]);
+            quote! {
+                #convert
+                Ok(#construct)
+            }
+        }
+        Data::Enum(data) => {
+            check_enum_attrs(input, &attrs)?;
+            let expected = format!("a {} variant", name);
+            let arms = data
+                .variants
+                .iter()
+                .map(|variant| {
+                    let variant_attrs = VariantAttrs::parse(&variant.attrs)?;
+                    let tag = variant_tag(&variant.ident, &variant_attrs);
+                    let fields = fields(&variant.fields)?;
+                    let ident = &variant.ident;
+                    let construct = pattern(quote!(Self::#ident), &variant.fields, &fields);
+                    let arity = fields.len();
+                    Ok(match variant.fields {
+                        Fields::Unit => quote! {
+                            Some((#tag, 0)) if !__term.is_tuple() => Ok(#construct),
+                        },
+                        _ => {
+                            let convert = from_form(
+                                &Form::Tuple(Some(tag.clone())),
+                                &fields,
+                                &expected,
+                                &[ident.unraw().to_string()],
+                            );
+                            quote! {
+                                Some((#tag, #arity)) if __term.is_tuple() => {
+                                    #convert
+                                    Ok(#construct)
+                                }
+                            }
+                        }
+                    })
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                match ::erlang_etf::term::convert::variant_tag(&__term) {
+                    #(#arms)*
+                    _ => Err(::erlang_etf::term::convert::FromTermError::unexpected(__term, #expected)),
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                input,
+                "FromTerm cannot be derived for unions",
+            ))
+        }
+    };
+
+    let generics = add_bounds(
+        &input.generics,
+        quote!(::erlang_etf::term::convert::FromTerm),
+    );
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::erlang_etf::term::convert::FromTerm for #name #ty_generics #where_clause {
+            fn from_term(
+                __term: ::erlang_etf::term::Term,
+            ) -> Result<Self, ::erlang_etf::term::convert::FromTermError> {
+                #body
+            }
+        }
+    })
+}
+
+/// Returns statements which bind every field of `fields`, converted from
+/// `__term`, to its binding. `outer` names the enum variant, if any, so
+/// errors carry the whole path to the field.
+fn from_form(form: &Form, fields: &[Field], expected: &str, outer: &[String]) -> TokenStream {
+    match form {
+        Form::Map => {
+            let slots: Vec<Ident> = fields
+                .iter()
+                .map(|field| format_ident!("__slot_{}", field.binding))
+                .collect();
+            let names = fields.iter().map(|field| &field.name);
+            let converted = fields.iter().zip(&slots).map(|(field, slot)| {
+                let binding = &field.binding;
+                let name = &field.name;
+                let convert = from_field(field, quote!(__value), outer);
+                quote! {
+                    let #binding = match #slot {
+                        Some(__value) => #convert,
+                        None => {
+                            return Err(::erlang_etf::term::convert::FromTermError::missing_key(#name)
+                                #(.in_field(#outer))*)
+                        }
+                    };
+                }
+            });
+            quote! {
+                let __pairs = match __term {
+                    ::erlang_etf::term::Term::Map(__map) => __map.pairs,
+                    __term => {
+                        return Err(::erlang_etf::term::convert::FromTermError::unexpected(__term, #expected))
+                    }
+                };
+                #(let mut #slots = None;)*
+                for (__key, __value) in __pairs {
+                    if let ::erlang_etf::term::Term::Atom(ref __key) = __key {
+                        match __key.name() {
+                            #(#names => #slots = Some(__value),)*
+                            _ => {}
+                        }
+                    }
+                }
+                #(#converted)*
+            }
+        }
+        Form::Tuple(tag) => {
+            let arity = fields.len();
+            let tag = match tag {
+                Some(tag) => quote!(Some(#tag)),
+                None => quote!(None),
+            };
+            let bindings = fields.iter().map(|field| &field.binding);
+            let converted = fields.iter().map(|field| {
+                let binding = &field.binding;
+                let convert = from_field(field, quote!(#binding), outer);
+                quote!(let #binding = #convert;)
+            });
+            quote! {
+                let [#(#bindings),*] = ::erlang_etf::term::convert::tuple_fields::<#arity>(
+                    __term,
+                    #tag,
+                    #expected,
+                )?;
+                #(#converted)*
+            }
+        }
+        Form::Transparent => {
+            let field = &fields[0];
+            let binding = &field.binding;
+            let convert = match &field.attrs.with {
+                Some(with) => quote!(#with::from_term(__term)?),
+                None => quote!(::erlang_etf::term::convert::FromTerm::from_term(__term)?),
+            };
+            quote!(let #binding = #convert;)
+        }
+    }
+}
+
+fn from_field(field: &Field, value: TokenStream, outer: &[String]) -> TokenStream {
+    let convert = match &field.attrs.with {
+        Some(with) => quote!(#with::from_term(#value)),
+        None => quote!(::erlang_etf::term::convert::FromTerm::from_term(#value)),
+    };
+    let segment = if field.named {
+        let name = &field.name;
+        quote!(.in_field(#name))
+    } else {
+        let index: usize = field.name.parse().unwrap();
+        quote!(.at_index(#index))
+    };
+    quote! {
+        #convert.map_err(|__error| __error #segment #(.in_field(#outer))*)?
+    }
+}
+
+fn check_enum_attrs(input: &DeriveInput, attrs: &ContainerAttrs) -> syn::Result<()> {
+    if attrs.record.is_some() || attrs.map {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "enum variants are always tagged tuples; use `#[etf(tag = \"...\")]` on a variant to rename it",
+        ));
+    }
+    Ok(())
+}
+
+fn variant_tag(ident: &Ident, attrs: &VariantAttrs) -> String {
+    attrs
+        .tag
+        .clone()
+        .unwrap_or_else(|| snake_case(&ident.unraw().to_string()))
+}
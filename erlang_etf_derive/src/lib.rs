@@ -0,0 +1,51 @@
+//! `#[derive(IntoTerm, FromTerm)]` for the conversion traits in
+//! `erlang_etf::term::convert`. Enable the `derive` feature of `erlang_etf`
+//! rather than depending on this crate directly.
+//!
+//! Structs with named fields convert to maps with atom keys, or to records
+//! with `#[etf(record = "name")]`:
+//!
+//! ```ignore
+//! #[derive(IntoTerm, FromTerm)]
+//! #[etf(record = "user")]
+//! struct User {
+//!     name: String,
+//!     #[etf(charlist)]
+//!     nickname: String,
+//! }
+//! // {user, <<"Ann">>, "annie"}
+//! ```
+//!
+//! Tuple structs convert to tuples, except that a struct with a single
+//! field converts to that field's term. Unit structs convert to `{}`.
+//!
+//! Enum variants convert to tagged tuples such as `{not_found, Path}`, or to
+//! a bare atom for unit variants. Tags are variant names in snake case
+//! unless renamed with `#[etf(tag = "name")]`.
+//!
+//! Fields convert with their own `IntoTerm` and `FromTerm` implementations
+//! unless marked `#[etf(binary)]`, `#[etf(charlist)]` or `#[etf(atom)]` to
+//! pick a string representation, or `#[etf(with = "module")]` to use the
+//! `into_term` and `from_term` functions of another module.
+
+use proc_macro::TokenStream;
+use syn::{parse_macro_input, DeriveInput};
+
+mod attr;
+mod expand;
+
+#[proc_macro_derive(IntoTerm, attributes(etf))]
+pub fn derive_into_term(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand::into_term(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(FromTerm, attributes(etf))]
+pub fn derive_from_term(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand::from_term(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}